@@ -0,0 +1,130 @@
+//! Benchmarks of the hot paths of a crawl: finding the links in a page,
+//! resolving them and crawling a whole site.
+//!
+//! Run with `cargo bench`, or `cargo bench -- extract` for some of them.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use croolr::bench::Site;
+use croolr::fetch::{extract_page, resolve_link};
+use croolr::settings::SiteScope;
+use croolr::urlinfo::{Domain, Page};
+use croolr::{Crawler, Settings};
+use url::Url;
+
+/// A page of a news site: navigation, an article with inline links and
+/// entities, a list of related articles and a footer.
+fn news_page(links: usize) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html lang=\"en\"><head>\n<meta charset=\"utf-8\">\n\
+         <title>  Markets rally as rates hold &amp; earnings beat  </title>\n\
+         <link rel=\"canonical\" href=\"https://news.example.com/business/markets-rally\">\n\
+         <link rel=\"stylesheet\" href=\"/static/site.css?v=42\">\n\
+         <script src=\"https://cdn.example.net/analytics.js\" async></script>\n\
+         </head><body>\n<nav><ul>\n",
+    );
+    for section in ["world", "business", "tech", "science", "sport", "culture"] {
+        html += &format!(
+            "<li><a class=\"nav\" href=\"/{0}/\">{0}</a></li>\n",
+            section
+        );
+    }
+    html += "</ul></nav>\n<article>\n";
+    for i in 0..links {
+        html += &format!(
+            "<p>Paragraph {0} of the story with <em>emphasis</em>, quoting &quot;sources&quot; \
+             and <a href=\"../business/story-{0}?ref=inline&amp;pos={0}#top\">a related story</a>, \
+             <a href='https://other.example.org/report/{0}' rel=\"nofollow noopener\">a report</a> \
+             and <abbr title=\"note\">a note</abbr>.</p>\n",
+            i
+        );
+    }
+    html += "</article>\n<aside><ul>\n";
+    for i in 0..links / 4 {
+        html += &format!(
+            "<li><a href=\"/business/related-{}\">Related</a> \
+             <a href=\"mailto:tips@example.com\">tip</a></li>\n",
+            i
+        );
+    }
+    html += "</ul></aside>\n<footer><a href=\"/about\">About</a> \
+             <a href=\"/privacy#cookies\">Privacy</a></footer>\n</body></html>\n";
+    html
+}
+
+fn bench_extract(c: &mut Criterion) {
+    let url = Url::parse("https://news.example.com/business/markets-rally").unwrap();
+    let mut group = c.benchmark_group("extract");
+    for links in [10, 100, 1000] {
+        let html = news_page(links);
+        group.throughput(Throughput::Bytes(html.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(links), &html, |b, html| {
+            b.iter(|| {
+                let mut page = Page::default();
+                extract_page(&url, html, Vec::new(), SiteScope::default(), &mut page)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_resolve(c: &mut Criterion) {
+    let base = Url::parse("https://news.example.com/business/markets/rally.html").unwrap();
+    let mut group = c.benchmark_group("resolve");
+    let links = [
+        ("absolute", "https://other.example.org/report/1"),
+        ("root", "/business/story-1"),
+        ("relative", "../world/story-1?ref=inline&pos=1#top"),
+        ("dot_segments", "./a/../b/./c/../../d/story-1"),
+        ("unicode", "https://BÜCHER.example/Straße?q=ä#x"),
+        ("rejected", "mailto:tips@example.com"),
+    ];
+    for (name, link) in links {
+        group.bench_with_input(BenchmarkId::from_parameter(name), link, |b, link| {
+            b.iter(|| resolve_link(&base, link))
+        });
+    }
+    group.finish();
+}
+
+fn bench_crawl(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let origin = "http://bench.example.com";
+    let seed = Url::parse(origin).unwrap();
+    let domain = Domain::from_url(&seed).unwrap();
+    let mut group = c.benchmark_group("crawl");
+    group.sample_size(10);
+    for site in [
+        Site {
+            fan_out: 10,
+            depth: 2,
+        },
+        Site {
+            fan_out: 10,
+            depth: 3,
+        },
+    ] {
+        let pages = site.pages().unwrap();
+        let fetcher = site.mock_fetcher(origin).unwrap();
+        group.throughput(Throughput::Elements(pages as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(pages),
+            &fetcher,
+            |b, fetcher| {
+                b.to_async(&runtime).iter(|| async {
+                    let crawler = Crawler::builder()
+                        .settings(Settings::default())
+                        .fetcher(fetcher.clone())
+                        .build()
+                        .unwrap();
+                    crawler.crawl_seed(seed.clone()).await.unwrap();
+                    crawler.wait(domain.clone()).await.unwrap();
+                    crawler.shutdown().await.unwrap();
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_extract, bench_resolve, bench_crawl);
+criterion_main!(benches);
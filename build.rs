@@ -0,0 +1,14 @@
+//! Generate the gRPC service code. The proto files are compiled by protox so
+//! that no protoc installation is needed.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+    #[cfg(feature = "grpc")]
+    {
+        let descriptors = protox::compile(["croolr.proto"], ["proto"])?;
+        tonic_build::configure()
+            .build_client(false)
+            .compile_fds(descriptors)?;
+    }
+    Ok(())
+}
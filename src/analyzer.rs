@@ -0,0 +1,255 @@
+//! Checks run on each fetched page, reporting what may be wrong with it.
+//!
+//! An [`Analyzer`] is given the URL, the response headers and the body of
+//! each page, and its findings are kept with the page, listed under
+//! `findings` by `/url`. Analyzers are added with
+//! [`CrawlerBuilder::analyzer`], the built-in ones also by name in the
+//! settings. They run as the last step of the fetch pipeline, on the pages as
+//! left by the middleware.
+//!
+//! [`CrawlerBuilder::analyzer`]: super::crawler::CrawlerBuilder::analyzer
+
+use super::fetcher::{Request, Response};
+use super::middleware::Middleware;
+use super::urlinfo::Error;
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use url::Url;
+
+/// A check of the fetched pages.
+pub trait Analyzer: Send + Sync + 'static {
+    /// Name the findings are reported under.
+    fn name(&self) -> &str;
+
+    /// Look given page over, returning a message for each problem found.
+    fn analyze(&self, url: &Url, headers: &[(String, String)], body: &str) -> Vec<String>;
+}
+
+/// A problem found in a page by an analyzer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Finding {
+    pub analyzer: String,
+    pub message: String,
+}
+
+/// The analyzers shipped with croolr, as named in the settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BuiltinAnalyzer {
+    /// See [`AltText`].
+    AltText,
+    /// See [`Headings`].
+    Headings,
+}
+
+impl BuiltinAnalyzer {
+    pub fn analyzer(self) -> Arc<dyn Analyzer> {
+        match self {
+            BuiltinAnalyzer::AltText => Arc::new(AltText),
+            BuiltinAnalyzer::Headings => Arc::new(Headings),
+        }
+    }
+}
+
+impl FromStr for BuiltinAnalyzer {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alt-text" => Ok(BuiltinAnalyzer::AltText),
+            "headings" => Ok(BuiltinAnalyzer::Headings),
+            _ => Err(format!("unknown analyzer: {}", s)),
+        }
+    }
+}
+
+/// Counts the images without alt text.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AltText;
+
+impl Analyzer for AltText {
+    fn name(&self) -> &str {
+        "alt-text"
+    }
+
+    fn analyze(&self, _url: &Url, _headers: &[(String, String)], body: &str) -> Vec<String> {
+        let images: Vec<&str> = tags(body, "img").collect();
+        let missing = images
+            .iter()
+            .filter(|tag| !has_attribute(tag, "alt"))
+            .count();
+        match missing {
+            0 => Vec::new(),
+            _ => vec![format!(
+                "{} of {} images without alt text",
+                missing,
+                images.len()
+            )],
+        }
+    }
+}
+
+/// Checks the page has a single `<h1>` heading and that the levels of the
+/// headings do not skip any, e.g. from `<h2>` to `<h4>`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Headings;
+
+impl Analyzer for Headings {
+    fn name(&self) -> &str {
+        "headings"
+    }
+
+    fn analyze(&self, _url: &Url, _headers: &[(String, String)], body: &str) -> Vec<String> {
+        let lower = body.to_ascii_lowercase();
+        let levels: Vec<u8> = lower
+            .match_indices("<h")
+            .filter_map(|(start, _)| {
+                let rest = &lower.as_bytes()[start + 2..];
+                match rest {
+                    [level @ b'1'..=b'6', next, ..]
+                        if next.is_ascii_whitespace() || *next == b'>' =>
+                    {
+                        Some(level - b'0')
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        let mut findings = Vec::new();
+        match levels.iter().filter(|&&l| l == 1).count() {
+            0 => findings.push("no h1 heading".to_string()),
+            1 => (),
+            n => findings.push(format!("{} h1 headings", n)),
+        }
+        for pair in levels.windows(2) {
+            if pair[1] > pair[0] + 1 {
+                let skip = format!("h{} followed by h{}", pair[0], pair[1]);
+                if !findings.contains(&skip) {
+                    findings.push(skip);
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// The contents of the opening tags of given name, e.g. `img src="a.png"`.
+fn tags<'a>(body: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    // ASCII lowercasing preserves byte offsets into the original body.
+    let lower = body.to_ascii_lowercase();
+    let open = format!("<{}", name);
+    let found: Vec<(usize, usize)> = lower
+        .match_indices(&open)
+        .filter_map(|(start, _)| {
+            let end = start + lower[start..].find('>')?;
+            let next = lower[start + open.len()..].chars().next()?;
+            (next.is_whitespace() || next == '>' || next == '/').then_some((start + 1, end))
+        })
+        .collect();
+    found.into_iter().map(move |(start, end)| &body[start..end])
+}
+
+/// Whether the tag has given attribute, set to any value.
+fn has_attribute(tag: &str, name: &str) -> bool {
+    let lower = tag.to_ascii_lowercase();
+    lower.match_indices(name).any(|(start, _)| {
+        let before = lower[..start].chars().next_back();
+        let after = lower[start + name.len()..].trim_start().chars().next();
+        before.is_some_and(char::is_whitespace) && matches!(after, Some('=' | '/') | None)
+    })
+}
+
+/// Middleware running the analyzers on the pages.
+pub(crate) struct Analyzers(pub Vec<Arc<dyn Analyzer>>);
+
+impl Middleware for Analyzers {
+    fn after_response(&self, _request: &Request, result: &mut Result<Response, Error>) {
+        let response = match result {
+            Ok(response) => response,
+            Err(_) => return,
+        };
+        let body = String::from_utf8_lossy(&response.body);
+        let page = &mut response.page;
+        for analyzer in &self.0 {
+            let messages = analyzer.analyze(&response.url, &page.headers, &body);
+            page.findings
+                .extend(messages.into_iter().map(|message| Finding {
+                    analyzer: analyzer.name().to_string(),
+                    message,
+                }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::crawler::Crawler;
+    use super::super::fetcher::MockFetcher;
+    use super::super::urlinfo::Domain;
+    use super::*;
+
+    fn analyze(analyzer: impl Analyzer, body: &str) -> Vec<String> {
+        let url = Url::parse("http://example.com/").unwrap();
+        analyzer.analyze(&url, &[], body)
+    }
+
+    #[test]
+    fn unit_alt_text() {
+        let body = r#"<img src="a.png" alt="A"><IMG SRC="b.png"><img src=c.png alt>
+            <img data-alt="d" src="d.png"/><imgx>"#;
+        assert_eq!(analyze(AltText, body), ["2 of 4 images without alt text"]);
+        assert!(analyze(AltText, r#"<img alt="" src="a.png">"#).is_empty());
+    }
+
+    #[test]
+    fn unit_headings() {
+        let body = "<h1>A</h1><h2>B</h2><H4 id=c>C</h4><h2>D</h2><h4>E</h4><hr><header>";
+        assert_eq!(analyze(Headings, body), ["h2 followed by h4"]);
+        assert_eq!(analyze(Headings, "<h2>A</h2><h3>B</h3>"), ["no h1 heading"]);
+        assert_eq!(analyze(Headings, "<h1>A</h1><h1>B</h1>"), ["2 h1 headings"]);
+    }
+
+    #[tokio::test]
+    async fn test_analyzers_crawl() {
+        /// Reports the pages served without a content language.
+        struct Language;
+        impl Analyzer for Language {
+            fn name(&self) -> &str {
+                "language"
+            }
+            fn analyze(&self, _url: &Url, headers: &[(String, String)], _: &str) -> Vec<String> {
+                let set = headers.iter().any(|(n, _)| n == "content-language");
+                (!set)
+                    .then(|| "no content language".to_string())
+                    .into_iter()
+                    .collect()
+            }
+        }
+
+        let fetcher = MockFetcher::new().page("http://example.com/", "<h1>A</h1><img src=a>");
+        let crawler = Crawler::builder()
+            .fetcher(fetcher)
+            .analyzer(AltText)
+            .analyzer(Language)
+            .build()
+            .unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        assert_eq!(crawler.wait(domain).await, Ok(true));
+        let info = crawler.url_info(Url::parse("http://example.com/").unwrap());
+        let page = info.await.unwrap().unwrap().result.unwrap();
+        let findings: Vec<(&str, &str)> = page
+            .findings
+            .iter()
+            .map(|f| (&*f.analyzer, &*f.message))
+            .collect();
+        assert_eq!(
+            findings,
+            [
+                ("alt-text", "1 of 1 images without alt text"),
+                ("language", "no content language"),
+            ]
+        );
+    }
+}
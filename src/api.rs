@@ -0,0 +1,313 @@
+//! Response bodies and errors of the versioned HTTP API.
+//!
+//! These are part of the public interface of the server. Fields may be added
+//! but existing ones should not be renamed or removed within an API version.
+
+use super::audit::Audit;
+use super::crawler::{CrawlReply, CrawlState, DomainStatus, QueueStatus};
+use super::error::CroolrError;
+use super::linkcheck::BrokenLink;
+use super::outlinks::Outlinks;
+use super::robots::RobotsReport;
+use super::search::SearchResults;
+use super::settings::Settings;
+use super::stats::Stats;
+use super::summary::Report;
+use super::urlinfo::UrlRecord;
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use url::Url;
+use warp::http::StatusCode;
+
+/// Reply to /crawl-batch.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResponse {
+    /// Number of seeds queued for crawling.
+    pub accepted: usize,
+    /// Number of seeds rejected.
+    pub rejected: usize,
+    /// Outcome for each seed, in the order they were submitted.
+    pub results: Vec<BatchResult>,
+}
+
+/// Outcome of crawling a single seed of a batch.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub seed: String,
+    /// URL the crawl starts at, unless the seed is invalid.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<Url>,
+    pub accepted: bool,
+    /// Why the seed has been rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+/// Reply to /pause/domain.com, /resume/domain.com and /stop/domain.com.
+#[derive(Debug, Clone, Serialize)]
+pub struct ControlResponse {
+    /// State after the command.
+    pub state: CrawlState,
+}
+
+/// Reply to /count/domain.com.
+#[derive(Debug, Clone, Serialize)]
+pub struct CountResponse {
+    /// Number of URLs discovered.
+    pub count: usize,
+    /// Number of responses by status class, e.g. `2xx`.
+    pub status_classes: BTreeMap<String, usize>,
+    /// Number of responses by media type.
+    pub content_types: BTreeMap<String, usize>,
+}
+
+/// Reply to /domains.
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainsResponse {
+    pub domains: Vec<DomainStatus>,
+}
+
+/// Reply to `/outlinks/domain.com?page=http://domain.com/page`.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutlinksResponse {
+    pub outlinks: Outlinks,
+}
+
+/// Reply to /robots/domain.com.
+#[derive(Debug, Clone, Serialize)]
+pub struct RobotsResponse {
+    pub robots: RobotsReport,
+}
+
+/// Reply to /search?q=substring.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResponse {
+    pub search: SearchResults,
+}
+
+/// Reply to /admin/config.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigResponse {
+    pub config: Settings,
+}
+
+/// Reply to /stats.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsResponse {
+    pub stats: Stats,
+}
+
+/// Reply to /summary/domain.com.
+#[derive(Debug, Clone, Serialize)]
+pub struct SummaryResponse {
+    pub summary: Report,
+}
+
+/// Reply to /audit/domain.com.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditResponse {
+    pub audit: Audit,
+}
+
+/// Reply to /broken/domain.com.
+#[derive(Debug, Clone, Serialize)]
+pub struct BrokenLinksResponse {
+    pub broken: Vec<BrokenLink>,
+}
+
+/// Reply to /wait/domain.com.
+#[derive(Debug, Clone, Serialize)]
+pub struct WaitResponse {
+    /// False if the crawl has not finished before the timeout.
+    pub finished: bool,
+    pub summary: Report,
+}
+
+/// Reply to /queue/domain.com.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueResponse {
+    pub queue: QueueStatus,
+}
+
+/// Reply to `/url?u=http://domain.com/page`.
+#[derive(Debug, Clone, Serialize)]
+pub struct UrlResponse {
+    /// Everything known about the URL.
+    pub url: UrlRecord,
+}
+
+/// Body of all error replies.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+/// Description of an API error.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorBody {
+    /// Stable identifier of the kind of the error.
+    pub code: &'static str,
+    /// Human-readable description of the error.
+    pub message: &'static str,
+    /// More information about this particular occurrence, if available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+/// Failure of an API request.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request is malformed, e.g. it has invalid query parameters.
+    BadRequest(String),
+    /// The domain name in the request path is malformed.
+    InvalidDomain(String),
+    /// The domain is not being crawled.
+    UnknownDomain,
+    /// The URL has not been fetched.
+    UnknownUrl,
+    /// No screenshot has been taken of the page.
+    NoScreenshot,
+    /// The domain is already being crawled.
+    AlreadyCrawling,
+    /// The request lacks an API key granting sufficient access.
+    Unauthorized,
+    /// The client made too many requests, it may retry after given time.
+    RateLimited(Duration),
+    /// The crawler failed to handle the request.
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::InvalidDomain(_) => StatusCode::BAD_REQUEST,
+            ApiError::UnknownDomain | ApiError::UnknownUrl | ApiError::NoScreenshot => {
+                StatusCode::NOT_FOUND
+            }
+            ApiError::AlreadyCrawling => StatusCode::CONFLICT,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidDomain(_) => "invalid_domain",
+            ApiError::UnknownDomain => "unknown_domain",
+            ApiError::UnknownUrl => "unknown_url",
+            ApiError::NoScreenshot => "no_screenshot",
+            ApiError::AlreadyCrawling => "already_crawling",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "malformed request",
+            ApiError::InvalidDomain(_) => "invalid domain name",
+            ApiError::UnknownDomain => "domain is not being crawled",
+            ApiError::UnknownUrl => "URL has not been fetched",
+            ApiError::NoScreenshot => "no screenshot has been taken of the page",
+            ApiError::AlreadyCrawling => "domain is already being crawled",
+            ApiError::Unauthorized => "missing or invalid API key",
+            ApiError::RateLimited(_) => "rate limit exceeded",
+            ApiError::Internal(_) => "internal error",
+        }
+    }
+
+    pub fn details(&self) -> Option<String> {
+        match self {
+            ApiError::BadRequest(details)
+            | ApiError::InvalidDomain(details)
+            | ApiError::Internal(details) => Some(details.clone()),
+            ApiError::RateLimited(retry_after) => Some(format!(
+                "retry after {} seconds",
+                retry_after_secs(*retry_after)
+            )),
+            _ => None,
+        }
+    }
+
+    pub fn response(&self) -> ErrorResponse {
+        ErrorResponse {
+            error: ErrorBody {
+                code: self.code(),
+                message: self.message(),
+                details: self.details(),
+            },
+        }
+    }
+}
+
+impl warp::reject::Reject for ApiError {}
+
+impl From<CroolrError> for ApiError {
+    fn from(err: CroolrError) -> Self {
+        ApiError::Internal(err.to_string())
+    }
+}
+
+/// Check the crawler reply to a crawl request, the replies other than
+/// [`CrawlReply::Queued`] being API errors. The reply to /crawl/domain.com is
+/// the serialized [`CrawlReply`].
+pub fn crawl_response(reply: CrawlReply) -> Result<CrawlReply, ApiError> {
+    match reply {
+        CrawlReply::Queued => Ok(reply),
+        CrawlReply::MalformedHostName(e) => Err(ApiError::InvalidDomain(e.to_string())),
+        CrawlReply::AlreadyCrawling => Err(ApiError::AlreadyCrawling),
+    }
+}
+
+/// Whole number of seconds to wait, rounded up so that retrying succeeds.
+pub fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + (retry_after.subsec_nanos() > 0) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_error_response() {
+        let err = ApiError::InvalidDomain("invalid domain character".to_string());
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        let body = serde_json::to_value(err.response()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": {
+                    "code": "invalid_domain",
+                    "message": "invalid domain name",
+                    "details": "invalid domain character",
+                }
+            })
+        );
+        let body = serde_json::to_value(ApiError::UnknownDomain.response()).unwrap();
+        assert!(body["error"].get("details").is_none());
+        let limited = ApiError::RateLimited(Duration::from_millis(1500));
+        assert_eq!(limited.details().unwrap(), "retry after 2 seconds");
+    }
+
+    #[test]
+    fn unit_crawl_reply() {
+        let json = |reply| serde_json::to_value(reply).unwrap();
+        assert_eq!(
+            json(CrawlReply::Queued),
+            serde_json::json!({ "status": "Queued" })
+        );
+        assert_eq!(
+            json(CrawlReply::MalformedHostName(url::ParseError::EmptyHost)),
+            serde_json::json!({ "status": "MalformedHostName", "error": "empty host" })
+        );
+        let body = serde_json::to_value(crawl_response(CrawlReply::Queued).unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({ "status": "Queued" }));
+        let err = crawl_response(CrawlReply::AlreadyCrawling).unwrap_err();
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+    }
+}
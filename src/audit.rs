@@ -0,0 +1,238 @@
+//! SEO audit of the crawl results of a domain.
+//!
+//! The audit goes over the fetched pages of a domain looking for what search
+//! engines are known to frown upon: pages without a title or a description,
+//! titles shared by several pages, the same content under several URLs with
+//! no canonical one declared, pages many links away from the seed and long
+//! redirect chains. The findings list the URLs concerned in order.
+
+use super::urlinfo::UrlSet;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use url::Url;
+
+/// Default number of links from the seed beyond which pages are too deep.
+const MAX_DEPTH: u32 = 5;
+
+/// Default number of redirects beyond which a chain is too long.
+const MAX_REDIRECTS: usize = 1;
+
+/// Thresholds of the audit.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuditOptions {
+    /// Pages found more links away from the seed are reported.
+    pub max_depth: u32,
+    /// URLs redirected more times are reported.
+    pub max_redirects: usize,
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        AuditOptions {
+            max_depth: MAX_DEPTH,
+            max_redirects: MAX_REDIRECTS,
+        }
+    }
+}
+
+/// Findings of the audit of a domain.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Audit {
+    /// Number of pages fetched successfully, those audited.
+    pub pages: usize,
+    pub missing_titles: Vec<Url>,
+    pub missing_descriptions: Vec<Url>,
+    /// Titles shared by several pages, with the pages. Pages declaring
+    /// another canonical URL are expected to share it and left out.
+    pub duplicate_titles: BTreeMap<String, Vec<Url>>,
+    /// Groups of pages with the same content not declaring the same
+    /// canonical URL.
+    pub non_canonical_duplicates: Vec<Vec<Url>>,
+    pub deep_pages: Vec<DeepPage>,
+    pub redirect_chains: Vec<RedirectChain>,
+}
+
+/// A page found too many links away from the seed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeepPage {
+    pub url: Url,
+    pub depth: u32,
+}
+
+/// A URL redirected too many times, with the redirects in order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RedirectChain {
+    pub url: Url,
+    pub redirects: Vec<Url>,
+}
+
+/// Audit the results of a domain.
+pub fn audit(urls: &UrlSet, opts: &AuditOptions) -> Audit {
+    let mut sorted: Vec<_> = urls.iter().collect();
+    sorted.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    let mut audit = Audit::default();
+    let mut titles: BTreeMap<&str, Vec<Url>> = BTreeMap::new();
+    // Pages by the hash of their content, with their canonical URLs.
+    let mut contents: HashMap<u64, Vec<(&Url, &Url)>> = HashMap::new();
+    for (url, info) in sorted {
+        if info.redirects.len() > opts.max_redirects {
+            audit.redirect_chains.push(RedirectChain {
+                url: (**url).clone(),
+                redirects: info.redirects.clone(),
+            });
+        }
+        let page = match &info.result {
+            Ok(page) => page,
+            Err(_) => continue,
+        };
+        audit.pages += 1;
+        if info.discovery.depth > opts.max_depth {
+            audit.deep_pages.push(DeepPage {
+                url: (**url).clone(),
+                depth: info.discovery.depth,
+            });
+        }
+        let canonical = page.canonical.as_ref().unwrap_or(url);
+        match &page.title {
+            None => audit.missing_titles.push((**url).clone()),
+            Some(title) if canonical == &**url => {
+                titles.entry(title).or_default().push((**url).clone())
+            }
+            Some(_) => (),
+        }
+        if page.description.is_none() {
+            audit.missing_descriptions.push((**url).clone());
+        }
+        if let Some(hash) = page.content_hash {
+            contents.entry(hash).or_default().push((url, canonical));
+        }
+    }
+    audit.duplicate_titles = titles
+        .into_iter()
+        .filter(|(_, pages)| pages.len() > 1)
+        .map(|(title, pages)| (title.to_string(), pages))
+        .collect();
+    audit.non_canonical_duplicates = contents
+        .into_values()
+        .filter(|pages| pages.iter().any(|(_, canonical)| *canonical != pages[0].1))
+        .map(|pages| pages.into_iter().map(|(url, _)| url.clone()).collect())
+        .collect();
+    audit.non_canonical_duplicates.sort_unstable();
+    audit
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::urlinfo::*;
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn parse(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn unit_audit() {
+        let page = |title: Option<&str>, canonical: Option<&str>, hash| Page {
+            size: 10,
+            canonical: canonical.map(parse),
+            title: title.map(str::to_string),
+            description: Some("Fish".to_string()),
+            content_hash: Some(hash),
+            ..Page::default()
+        };
+        let mut urls = UrlSet::new();
+        let mut add = |url: &str, result, depth, redirects: &[&str]| {
+            let mut info = UrlInfo::from_result(result, Duration::ZERO);
+            info.discovery.depth = depth;
+            info.redirects = redirects.iter().map(|r| parse(r)).collect();
+            urls.insert(Arc::new(parse(url)), info);
+        };
+        add(
+            "http://example.com/",
+            Ok(page(Some("Home"), None, 1)),
+            0,
+            &[],
+        );
+        add("http://example.com/a", Ok(page(Some("A"), None, 2)), 1, &[]);
+        add("http://example.com/b", Ok(page(Some("A"), None, 3)), 6, &[]);
+        // Duplicates of the home page, only the second declares it canonical.
+        add(
+            "http://example.com/?x",
+            Ok(page(Some("Home"), None, 1)),
+            1,
+            &[],
+        );
+        let canonical = Some("http://example.com/");
+        add(
+            "http://example.com/?y",
+            Ok(page(Some("Home"), canonical, 1)),
+            1,
+            &[],
+        );
+        let mut untitled = page(None, None, 4);
+        untitled.description = None;
+        add("http://example.com/c", Ok(untitled), 1, &[]);
+        let chain = ["http://example.com/d", "http://example.com/e"];
+        let moved = Err(Error::Status(reqwest::StatusCode::NOT_FOUND));
+        add("http://example.com/old", moved, 1, &chain);
+        add(
+            "http://example.com/new",
+            Ok(page(Some("New"), None, 5)),
+            1,
+            &chain[..1],
+        );
+
+        let audit = audit(&urls, &AuditOptions::default());
+        assert_eq!(audit.pages, 7);
+        assert_eq!(audit.missing_titles, [parse("http://example.com/c")]);
+        assert_eq!(audit.missing_descriptions, audit.missing_titles);
+        let titles: Vec<_> = audit.duplicate_titles.iter().collect();
+        let pages = |urls: &[&str]| urls.iter().map(|u| parse(u)).collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            [
+                (
+                    &"A".to_string(),
+                    &pages(&["http://example.com/a", "http://example.com/b"])
+                ),
+                (
+                    &"Home".to_string(),
+                    &pages(&["http://example.com/", "http://example.com/?x"])
+                ),
+            ]
+        );
+        assert_eq!(
+            audit.non_canonical_duplicates,
+            [pages(&[
+                "http://example.com/",
+                "http://example.com/?x",
+                "http://example.com/?y"
+            ])]
+        );
+        let deep = DeepPage {
+            url: parse("http://example.com/b"),
+            depth: 6,
+        };
+        assert_eq!(audit.deep_pages, [deep]);
+        let chains: Vec<&str> = audit
+            .redirect_chains
+            .iter()
+            .map(|c| c.url.as_str())
+            .collect();
+        assert_eq!(chains, ["http://example.com/old"]);
+
+        let opts = AuditOptions {
+            max_depth: 0,
+            max_redirects: 0,
+        };
+        let audit = super::audit(&urls, &opts);
+        assert_eq!(
+            (audit.deep_pages.len(), audit.redirect_chains.len()),
+            (6, 2)
+        );
+    }
+}
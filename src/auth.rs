@@ -0,0 +1,144 @@
+//! API key authentication.
+//!
+//! Keys are passed in the `x-api-key` request header. Write keys grant access
+//! to everything, read keys only to the query endpoints. If no write keys are
+//! configured, the mutating endpoints are open to everyone. Likewise for read
+//! keys and the query endpoints.
+
+use super::api::ApiError;
+
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use warp::{Filter, Rejection};
+
+/// Name of the request header carrying the API key.
+pub const HEADER: &str = "x-api-key";
+
+/// Level of access required by an endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Querying the crawl results.
+    Read,
+    /// Starting and controlling crawls.
+    Write,
+}
+
+/// The configured API keys.
+#[derive(Debug, Clone, Default)]
+pub struct Keys {
+    pub write: HashSet<String>,
+    pub read: HashSet<String>,
+}
+
+impl Keys {
+    /// Check whether given key is one of the configured ones.
+    pub fn contains(&self, key: &str) -> bool {
+        self.write.contains(key) || self.read.contains(key)
+    }
+
+    /// Check whether given key (if any) grants the requested access.
+    pub fn allows(&self, key: Option<&str>, access: Access) -> bool {
+        let is_write_key = key.is_some_and(|k| self.write.contains(k));
+        match access {
+            Access::Write => self.write.is_empty() || is_write_key,
+            Access::Read => {
+                self.read.is_empty() || is_write_key || key.is_some_and(|k| self.read.contains(k))
+            }
+        }
+    }
+}
+
+/// The API keys in effect, shared by all the endpoints. They are replaced
+/// when the configuration is reloaded.
+#[derive(Debug, Clone, Default)]
+pub struct SharedKeys(Arc<RwLock<Keys>>);
+
+impl SharedKeys {
+    pub fn new(keys: Keys) -> Self {
+        SharedKeys(Arc::new(RwLock::new(keys)))
+    }
+
+    /// Check whether given key is one of the configured ones.
+    pub fn contains(&self, key: &str) -> bool {
+        self.0.read().unwrap().contains(key)
+    }
+
+    /// Check whether given key (if any) grants the requested access.
+    pub fn allows(&self, key: Option<&str>, access: Access) -> bool {
+        self.0.read().unwrap().allows(key, access)
+    }
+
+    /// Put given keys in effect instead of the current ones.
+    pub fn replace(&self, keys: Keys) {
+        *self.0.write().unwrap() = keys;
+    }
+}
+
+/// Warp filter rejecting requests that lack the requested access.
+pub fn require(
+    keys: &SharedKeys,
+    access: Access,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let keys = keys.clone();
+    warp::header::optional::<String>(HEADER)
+        .and_then(move |key: Option<String>| {
+            let allowed = keys.allows(key.as_deref(), access);
+            async move {
+                match allowed {
+                    true => Ok(()),
+                    false => Err(warp::reject::custom(ApiError::Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys(write: &[&str], read: &[&str]) -> Keys {
+        let set = |ks: &[&str]| ks.iter().map(|k| k.to_string()).collect();
+        Keys {
+            write: set(write),
+            read: set(read),
+        }
+    }
+
+    #[test]
+    fn unit_auth_open_by_default() {
+        let keys = Keys::default();
+        assert!(keys.allows(None, Access::Write));
+        assert!(keys.allows(Some("anything"), Access::Read));
+    }
+
+    #[test]
+    fn unit_auth_keys() {
+        let keys = keys(&["w"], &["r"]);
+        assert!(keys.allows(Some("w"), Access::Write));
+        assert!(keys.allows(Some("w"), Access::Read));
+        assert!(keys.allows(Some("r"), Access::Read));
+        assert!(!keys.allows(Some("r"), Access::Write));
+        assert!(!keys.allows(Some("x"), Access::Read));
+        assert!(!keys.allows(None, Access::Read));
+        assert!(!keys.allows(None, Access::Write));
+    }
+
+    #[test]
+    fn unit_auth_replace_keys() {
+        let shared = SharedKeys::new(keys(&["w"], &[]));
+        let other = shared.clone();
+        other.replace(keys(&["v"], &[]));
+        assert!(!shared.allows(Some("w"), Access::Write));
+        assert!(shared.allows(Some("v"), Access::Write));
+        assert!(shared.contains("v"));
+    }
+
+    #[test]
+    fn unit_auth_write_keys_only() {
+        let keys = keys(&["w"], &[]);
+        assert!(keys.allows(None, Access::Read));
+        assert!(!keys.allows(None, Access::Write));
+        assert!(keys.allows(Some("w"), Access::Write));
+    }
+}
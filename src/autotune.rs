@@ -0,0 +1,128 @@
+//! Lowering of the fetch limit while the process runs short of open files or
+//! memory.
+//!
+//! Every few seconds the tuner checks how much of the open files limit and of
+//! the configured memory limit the process uses. Under pressure it halves the
+//! number of fetches allowed at once, then lets it grow back gradually up to
+//! the configured fetch limit once the pressure is gone. The usage is read
+//! from `/proc`, where it is not available the limit is left alone.
+
+use super::crawler::Crawler;
+
+use std::time::Duration;
+
+/// How often the usage is checked.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// Share of a limit in use above which the fetches are cut down.
+const HIGH_WATER: f64 = 0.8;
+
+/// Share of a limit in use below which the fetches may grow again.
+const LOW_WATER: f64 = 0.6;
+
+/// Start tuning the fetch limit of given crawler, keeping the resident memory
+/// below given number of bytes if any. Stops with the crawler.
+pub fn spawn(crawler: Crawler, memory_limit: Option<u64>) {
+    tokio::task::spawn(async move {
+        let mut cap = None;
+        let mut ticks = tokio::time::interval(INTERVAL);
+        loop {
+            ticks.tick().await;
+            let limit = match crawler.settings().await {
+                Ok(settings) => settings.fetch_limit,
+                Err(_) => break,
+            };
+            let usage = match usage(memory_limit) {
+                Some(usage) => usage,
+                None => continue,
+            };
+            let next = next_cap(cap, limit, usage);
+            if next != cap {
+                let fetch_limit = next.unwrap_or(limit);
+                tracing::info!(usage, fetch_limit, "Fetch limit adjusted");
+                cap = next;
+                if crawler.cap_fetch_limit(cap).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// The cap on the fetch limit for given usage, given the current cap and
+/// the configured limit. None if the fetches need not be capped.
+fn next_cap(cap: Option<u32>, limit: u32, usage: f64) -> Option<u32> {
+    let current = cap.map_or(limit, |c| c.min(limit));
+    if usage > HIGH_WATER {
+        return Some((current / 2).max(1));
+    }
+    match cap {
+        Some(cap) if usage < LOW_WATER => {
+            let grown = cap + (cap / 4).max(1);
+            (grown < limit).then_some(grown)
+        }
+        cap => cap,
+    }
+}
+
+/// Share of the most used limit in use, None if it cannot be told.
+fn usage(memory_limit: Option<u64>) -> Option<f64> {
+    let files = open_files().map(|(open, max)| open as f64 / max as f64);
+    let memory = memory_limit
+        .zip(resident_memory())
+        .map(|(limit, rss)| rss as f64 / limit as f64);
+    match (files, memory) {
+        (Some(files), Some(memory)) => Some(files.max(memory)),
+        (files, memory) => files.or(memory),
+    }
+}
+
+/// Number of open files of the process and the limit on them.
+fn open_files() -> Option<(u64, u64)> {
+    let open = std::fs::read_dir("/proc/self/fd").ok()?.count() as u64;
+    let limits = std::fs::read_to_string("/proc/self/limits").ok()?;
+    let max = limits
+        .lines()
+        .find_map(|l| l.strip_prefix("Max open files"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+    Some((open, max))
+}
+
+/// Resident memory of the process in bytes.
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb: u64 = status
+        .lines()
+        .find_map(|l| l.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_next_cap() {
+        // Halved under pressure, down to a single fetch.
+        assert_eq!(next_cap(None, 16, 0.9), Some(8));
+        assert_eq!(next_cap(Some(8), 16, 0.9), Some(4));
+        assert_eq!(next_cap(Some(1), 16, 0.9), Some(1));
+        // Kept while the usage is moderate.
+        assert_eq!(next_cap(Some(4), 16, 0.7), Some(4));
+        assert_eq!(next_cap(None, 16, 0.7), None);
+        // Grown back once the pressure is gone, then lifted.
+        assert_eq!(next_cap(Some(4), 16, 0.1), Some(5));
+        assert_eq!(next_cap(Some(8), 16, 0.1), Some(10));
+        assert_eq!(next_cap(Some(14), 16, 0.1), None);
+        // A cap above a lowered fetch limit is halved from the limit.
+        assert_eq!(next_cap(Some(8), 4, 0.9), Some(2));
+    }
+}
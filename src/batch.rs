@@ -0,0 +1,136 @@
+//! Parsing of crawl seed lists submitted in bulk.
+
+use super::urlinfo::Domain;
+
+use serde::Deserialize;
+use std::path::Path;
+use url::Url;
+
+/// Max number of seeds accepted in a single batch.
+pub const MAX_SEEDS: usize = 1000;
+
+/// Max size of the request body in bytes.
+pub const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// JSON body of a batch crawl request.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchRequest {
+    pub domains: Vec<String>,
+}
+
+/// Parse a plain text seed list.
+///
+/// There is one seed per line, blank lines and lines starting with `#` are
+/// skipped.
+pub fn parse_seed_list(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Get the domain to crawl given a seed, either a domain name or a URL.
+fn seed_domain(seed: &str) -> Result<Domain, String> {
+    if seed.contains("://") {
+        let url = Url::parse(seed).map_err(|e| e.to_string())?;
+        Domain::from_url(&url).ok_or_else(|| "URL has no host".to_string())
+    } else {
+        seed.parse().map_err(|e: url::ParseError| e.to_string())
+    }
+}
+
+/// Get the URL to start crawling at given a seed, either a URL or a domain
+/// name, crawled from its root over HTTP.
+pub fn seed_url(seed: &str) -> Result<Url, String> {
+    if seed.contains("://") {
+        let url = Url::parse(seed).map_err(|e| e.to_string())?;
+        match url.host() {
+            Some(_) => Ok(url),
+            None => Err("URL has no host".to_string()),
+        }
+    } else {
+        let domain = seed_domain(seed)?;
+        let mut url = Url::parse("http://localhost/").unwrap();
+        url.set_host(Some(&domain)).map_err(|e| e.to_string())?;
+        Ok(url)
+    }
+}
+
+/// Read the URLs to start crawling at from a seed file in the plain text
+/// format.
+///
+/// Seeds given multiple times are crawled once. All the seeds have to be
+/// valid.
+pub fn read_seed_file(path: &Path) -> Result<Vec<Url>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    seed_urls(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// URLs to start crawling at given a plain text seed list, without
+/// duplicates.
+fn seed_urls(text: &str) -> Result<Vec<Url>, String> {
+    let mut urls = Vec::new();
+    for seed in parse_seed_list(text) {
+        let url = seed_url(&seed).map_err(|e| format!("invalid seed {}: {}", seed, e))?;
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    Ok(urls)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_parse_seed_list() {
+        let text = "# seeds\nexample.com\n\n  https://Example.org/blog \r\n";
+        let seeds = parse_seed_list(text);
+        assert_eq!(seeds, ["example.com", "https://Example.org/blog"]);
+        let domains: Vec<_> = seeds.iter().map(|s| seed_domain(s).unwrap()).collect();
+        assert_eq!(&*domains[1], "example.org");
+        assert!(seed_domain("http://").is_err());
+        assert!(seed_domain("exa mple.com").is_err());
+    }
+
+    #[test]
+    fn unit_seed_url() {
+        let url = seed_url("https://example.com:8443/blog/").unwrap();
+        assert_eq!(url.as_str(), "https://example.com:8443/blog/");
+        let url = seed_url("Example.com").unwrap();
+        assert_eq!(url.as_str(), "http://example.com/");
+        assert!(seed_url("file:///etc/hosts").is_err());
+        assert!(seed_url("exa mple.com").is_err());
+    }
+
+    #[test]
+    fn unit_seed_urls() {
+        let urls = seed_urls(
+            "example.com
+http://example.com/
+http://example.com:8080/blog/
+example.org
+",
+        )
+        .unwrap();
+        let urls: Vec<&str> = urls.iter().map(Url::as_str).collect();
+        assert_eq!(
+            urls,
+            [
+                "http://example.com/",
+                "http://example.com:8080/blog/",
+                "http://example.org/"
+            ]
+        );
+        let err = seed_urls(
+            "example.com
+exa mple.com
+",
+        )
+        .unwrap_err();
+        assert!(err.contains("exa mple.com"), "{}", err);
+    }
+}
@@ -0,0 +1,282 @@
+//! Benchmarking the crawler against a built-in mock site.
+//!
+//! The site is served in-process on a random local port. Its pages form a
+//! tree: the home page links to `fan_out` pages, each of which links to
+//! `fan_out` pages one level deeper, down to given depth. Every page also links
+//! back to its parent and to the home page.
+
+use super::crawler::{CrawlReply, Crawler};
+use super::fetcher::{HttpFetcher, MockFetcher};
+use super::listing::ListOptions;
+use super::settings::Settings;
+
+use serde::Serialize;
+use std::fmt;
+use std::time::{Duration, Instant};
+use url::Url;
+use warp::Filter;
+
+/// Max number of pages of the mock site.
+const MAX_PAGES: usize = 1_000_000;
+
+/// Shape of the mock site.
+#[derive(Debug, Clone, Copy)]
+pub struct Site {
+    /// Number of links to deeper pages on each page.
+    pub fan_out: usize,
+    /// Number of links to follow from the home page to get to the deepest pages.
+    pub depth: u32,
+}
+
+/// Results of a benchmark run.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// Number of pages fetched.
+    pub pages: usize,
+    /// Number of fetches that failed.
+    pub errors: usize,
+    pub elapsed_secs: f64,
+    pub pages_per_sec: f64,
+    /// Fetch latency percentiles in milliseconds.
+    pub latency_ms: Percentiles,
+}
+
+/// Percentiles of a set of measurements.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Site {
+    /// Total number of pages, None if there are too many.
+    pub fn pages(&self) -> Option<usize> {
+        let mut level = 1usize;
+        let mut total = 1usize;
+        for _ in 0..self.depth {
+            level = level.checked_mul(self.fan_out)?;
+            total = total.checked_add(level).filter(|&t| t <= MAX_PAGES)?;
+        }
+        Some(total)
+    }
+
+    /// The site served by a mock fetcher under given origin, e.g.
+    /// `http://example.com`. None if there are too many pages.
+    pub fn mock_fetcher(&self, origin: &str) -> Option<MockFetcher> {
+        let pages = self.pages()?;
+        let fetcher = (0..pages).fold(MockFetcher::new(), |fetcher, id| {
+            let path = Self::path(id);
+            let html = self.page(&path[1..], pages).expect("page within the site");
+            fetcher.page(&format!("{}{}", origin, path), &html)
+        });
+        Some(fetcher)
+    }
+
+    /// Path of the page with given number, pages are numbered level by level.
+    fn path(id: usize) -> String {
+        match id {
+            0 => "/".to_string(),
+            id => format!("/page/{}", id),
+        }
+    }
+
+    /// Content of the page at given path, None if there is no such page.
+    fn page(&self, path: &str, pages: usize) -> Option<String> {
+        let id = match path {
+            "" => 0,
+            path => path.strip_prefix("page/")?.parse().ok()?,
+        };
+        if id >= pages {
+            return None;
+        }
+        let children = (1..=self.fan_out).map(|i| id * self.fan_out + i);
+        let mut links: Vec<usize> = children.take_while(|&c| c < pages).collect();
+        if id > 0 {
+            links.push((id - 1) / self.fan_out);
+            links.push(0);
+        }
+        let links: String = links
+            .into_iter()
+            .map(|l| format!("<a href=\"{}\">Page {}</a>\n", Self::path(l), l))
+            .collect();
+        Some(format!(
+            "<html><head><title>Page {}</title></head><body>\n{}</body></html>\n",
+            id, links
+        ))
+    }
+}
+
+/// Serve the mock site, crawl it with given settings and report how it went.
+///
+/// Without `reuse_connections`, every page is fetched over a new connection,
+/// which shows what keeping the connections open is worth.
+pub async fn run(
+    settings: Settings,
+    site: Site,
+    reuse_connections: bool,
+) -> Result<Report, String> {
+    let pages = site
+        .pages()
+        .ok_or_else(|| format!("the site would have over {} pages", MAX_PAGES))?;
+    let routes = warp::get()
+        .and(warp::path::tail())
+        .map(
+            move |tail: warp::path::Tail| match site.page(tail.as_str(), pages) {
+                Some(html) => {
+                    warp::reply::with_status(warp::reply::html(html), warp::http::StatusCode::OK)
+                }
+                None => warp::reply::with_status(
+                    warp::reply::html(String::new()),
+                    warp::http::StatusCode::NOT_FOUND,
+                ),
+            },
+        );
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let (addr, server) = warp::serve(routes)
+        .try_bind_with_graceful_shutdown(([127, 0, 0, 1], 0), async {
+            stopped.await.ok();
+        })
+        .map_err(|e| format!("cannot start the mock site: {}", e))?;
+    tokio::task::spawn(server);
+    tracing::info!(%addr, pages, "Mock site started");
+
+    let seed = Url::parse(&format!("http://{}/", addr)).map_err(|e| e.to_string())?;
+    let domain = super::urlinfo::Domain::from_url(&seed).ok_or("mock site has no host")?;
+    let gone = |_| "crawler stopped unexpectedly".to_string();
+    let pool_size = match reuse_connections {
+        true => settings.pool_size(),
+        false => 0,
+    };
+    let fetcher = HttpFetcher::with_pool(pool_size, settings.pool_idle_timeout());
+    let crawler = Crawler::builder()
+        .settings(settings)
+        .fetcher(fetcher)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let started = Instant::now();
+    match crawler.crawl_seed(seed).await.map_err(gone)? {
+        CrawlReply::Queued => (),
+        reply => return Err(format!("cannot crawl the mock site: {}", reply.status())),
+    }
+    crawler.wait(domain.clone()).await.map_err(gone)?;
+    let elapsed = started.elapsed();
+    let _ = stop.send(());
+
+    let listing = crawler
+        .list_urls(domain, ListOptions::default())
+        .await
+        .map_err(gone)?
+        .unwrap_or_default();
+    crawler.shutdown().await.map_err(gone)?;
+    let latencies: Vec<Duration> = listing.urls.iter().map(|(_, i)| i.elapsed).collect();
+    let fetched = listing.urls.len();
+    Ok(Report {
+        pages: fetched,
+        errors: listing
+            .urls
+            .iter()
+            .filter(|(_, i)| i.result.is_err())
+            .count(),
+        elapsed_secs: elapsed.as_secs_f64(),
+        pages_per_sec: fetched as f64 / elapsed.as_secs_f64(),
+        latency_ms: Percentiles::new(latencies),
+    })
+}
+
+impl Percentiles {
+    /// Percentiles of given durations in milliseconds, by the nearest rank.
+    fn new(mut durations: Vec<Duration>) -> Self {
+        durations.sort();
+        let ms = |q: f64| {
+            let rank = ((q * durations.len() as f64).ceil() as usize).max(1);
+            durations
+                .get(rank - 1)
+                .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+        };
+        Percentiles {
+            p50: ms(0.5),
+            p90: ms(0.9),
+            p99: ms(0.99),
+            max: ms(1.0),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Fetched {} pages ({} errors) in {:.3} s, {:.1} pages/s",
+            self.pages, self.errors, self.elapsed_secs, self.pages_per_sec
+        )?;
+        let l = &self.latency_ms;
+        writeln!(
+            f,
+            "Latency: p50 {:.3} ms, p90 {:.3} ms, p99 {:.3} ms, max {:.3} ms",
+            l.p50, l.p90, l.p99, l.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::metrics::METRICS;
+    use super::*;
+
+    #[test]
+    fn unit_mock_site() {
+        let site = Site {
+            fan_out: 2,
+            depth: 2,
+        };
+        assert_eq!(site.pages(), Some(7));
+        let home = site.page("", 7).unwrap();
+        assert!(home.contains("href=\"/page/1\"") && home.contains("href=\"/page/2\""));
+        let leaf = site.page("page/6", 7).unwrap();
+        assert!(leaf.contains("href=\"/page/2\"") && leaf.contains("href=\"/\""));
+        assert!(!leaf.contains("/page/13"));
+        assert_eq!(site.page("page/7", 7), None);
+        assert_eq!(site.page("other", 7), None);
+
+        let huge = Site {
+            fan_out: 1000,
+            depth: 3,
+        };
+        assert_eq!(huge.pages(), None);
+    }
+
+    #[test]
+    fn unit_percentiles() {
+        let durations = (1..=100).map(Duration::from_millis).collect();
+        let p = Percentiles::new(durations);
+        assert_eq!((p.p50, p.p90, p.p99, p.max), (50.0, 90.0, 99.0, 100.0));
+    }
+
+    #[tokio::test]
+    async fn test_bench() {
+        let site = Site {
+            fan_out: 3,
+            depth: 2,
+        };
+        for reuse_connections in [true, false] {
+            let (opened, reused) = (
+                METRICS.connections_opened.get(),
+                METRICS.connections_reused.get(),
+            );
+            let report = run(Settings::default(), site, reuse_connections)
+                .await
+                .unwrap();
+            assert_eq!((report.pages, report.errors), (13, 0));
+            assert!(report.latency_ms.max >= report.latency_ms.p50);
+            // Other tests may open connections too, but never fewer.
+            assert!(METRICS.connections_opened.get() > opened);
+            if reuse_connections {
+                assert!(METRICS.connections_reused.get() > reused);
+            } else {
+                assert!(METRICS.connections_opened.get() >= opened + 13);
+            }
+        }
+    }
+}
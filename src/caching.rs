@@ -0,0 +1,68 @@
+//! Conditional requests on the domain data endpoints.
+
+use super::crawler::DataVersion;
+use super::timefmt::http_date;
+
+use warp::http::{header, StatusCode};
+use warp::reply::Response;
+
+/// Check whether an If-None-Match header matches given entity tag.
+///
+/// Uses the weak comparison, i.e. ignores the `W/` prefix.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Reply with 304 Not Modified if the client has the current version,
+/// None if the full reply has to be sent.
+pub fn not_modified(if_none_match: Option<&str>, etag: &str) -> Option<Response> {
+    if !if_none_match.is_some_and(|inm| etag_matches(inm, etag)) {
+        return None;
+    }
+    let mut reply = Response::default();
+    *reply.status_mut() = StatusCode::NOT_MODIFIED;
+    insert_header(&mut reply, header::ETAG, etag);
+    Some(reply)
+}
+
+/// Attach the validators of given data version to the reply.
+///
+/// Clients are asked to revalidate every time since the data keep changing
+/// while the domain is being crawled.
+pub fn with_validators(mut reply: Response, version: &DataVersion, etag: &str) -> Response {
+    insert_header(&mut reply, header::ETAG, etag);
+    insert_header(
+        &mut reply,
+        header::LAST_MODIFIED,
+        &http_date(version.modified),
+    );
+    insert_header(&mut reply, header::CACHE_CONTROL, "no-cache");
+    reply
+}
+
+fn insert_header(reply: &mut Response, name: header::HeaderName, value: &str) {
+    if let Ok(value) = header::HeaderValue::from_str(value) {
+        reply.headers_mut().insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_etag_matches() {
+        assert!(etag_matches("\"a-1\"", "\"a-1\""));
+        assert!(etag_matches("\"x\", W/\"a-1\"", "\"a-1\""));
+        assert!(etag_matches("*", "\"a-1\""));
+        assert!(!etag_matches("\"a-2\"", "\"a-1\""));
+        assert!(not_modified(None, "\"a-1\"").is_none());
+        let reply = not_modified(Some("\"a-1\""), "\"a-1\"").unwrap();
+        assert_eq!(reply.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(reply.headers()[header::ETAG], "\"a-1\"");
+    }
+}
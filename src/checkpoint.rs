@@ -0,0 +1,145 @@
+//! Progress of a crawl saved to a file so that it can be resumed later.
+//!
+//! The checkpoint holds the results fetched so far and the frontier, i.e. the
+//! URLs discovered but not fetched yet. URLs being fetched when it is taken
+//! are part of the frontier and get fetched again on resume.
+
+use super::urlinfo::{Discovery, Domain, FetchResult, UrlInfo};
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// Saved state of the crawler.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Domains being crawled.
+    pub domains: Vec<Domain>,
+    /// URLs fetched so far along with their results.
+    pub fetched: Vec<Fetched>,
+    /// URLs waiting to be fetched in the order of discovery, followed by
+    /// those being fetched.
+    pub frontier: Vec<Queued>,
+}
+
+/// A URL fetched before the checkpoint has been taken.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fetched {
+    pub url: Url,
+    pub result: FetchResult,
+    pub redirects: Vec<Url>,
+    pub fetched_at: SystemTime,
+    pub elapsed: Duration,
+    pub discovery: Discovery,
+}
+
+/// A URL waiting to be fetched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Queued {
+    pub url: Url,
+    pub discovery: Discovery,
+}
+
+impl Fetched {
+    pub fn new(url: &Url, info: &UrlInfo) -> Self {
+        Fetched {
+            url: url.clone(),
+            result: info.result.clone(),
+            redirects: info.redirects.clone(),
+            fetched_at: info.fetched_at,
+            elapsed: info.elapsed,
+            discovery: info.discovery.clone(),
+        }
+    }
+
+    pub fn into_info(self) -> (Url, UrlInfo) {
+        let info = UrlInfo {
+            result: self.result,
+            redirects: self.redirects,
+            fetched_at: self.fetched_at,
+            elapsed: self.elapsed,
+            discovery: self.discovery,
+        };
+        (self.url, info)
+    }
+}
+
+impl Checkpoint {
+    /// Read a checkpoint from given file, None if there is no such file.
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        let text = match std::fs::read(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+        };
+        serde_json::from_slice(&text)
+            .map(Some)
+            .map_err(|e| format!("invalid checkpoint {}: {}", path.display(), e))
+    }
+
+    /// Write the checkpoint to given file.
+    ///
+    /// The file is replaced at once so that an interruption does not leave a
+    /// partially written checkpoint behind.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let tmp = path.with_extension("tmp");
+        let text = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        std::fs::write(&tmp, text)
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::urlinfo::{Error, Page};
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn unit_checkpoint_roundtrip() {
+        let url = |s: &str| Url::parse(s).unwrap();
+        let page = Page {
+            size: 10,
+            headers: vec![("server".to_string(), "test".to_string())],
+            title: Some("Home".to_string()),
+            links: vec![url("http://example.com/a")].into(),
+            ..Page::default()
+        };
+        let info = |r| UrlInfo::from_result(r, Duration::from_millis(5));
+        let checkpoint = Checkpoint {
+            domains: vec!["example.com".parse().unwrap()],
+            fetched: vec![
+                Fetched::new(&url("http://example.com/"), &info(Ok(page))),
+                Fetched::new(
+                    &url("http://example.com/b"),
+                    &info(Err(Error::Status(reqwest::StatusCode::NOT_FOUND))),
+                ),
+            ],
+            frontier: vec![Queued {
+                url: url("http://example.com/a"),
+                discovery: Discovery {
+                    seq: 1,
+                    parent: Some(Arc::new(url("http://example.com/"))),
+                    depth: 1,
+                    discovered_at: None,
+                },
+            }],
+        };
+        let path = std::env::temp_dir().join(format!("croolr-test-{}.json", std::process::id()));
+        assert!(Checkpoint::load(&path).unwrap().is_none());
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.domains, checkpoint.domains);
+        let (home, info) = loaded.fetched[0].clone().into_info();
+        assert_eq!(home, url("http://example.com/"));
+        assert_eq!(info.elapsed, Duration::from_millis(5));
+        assert_eq!(info.result.unwrap().title.as_deref(), Some("Home"));
+        let (_, info) = loaded.fetched[1].clone().into_info();
+        assert_eq!(info.status(), Some(reqwest::StatusCode::NOT_FOUND));
+        assert_eq!(loaded.frontier[0].discovery.depth, 1);
+    }
+}
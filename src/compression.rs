@@ -0,0 +1,113 @@
+//! Compression of API responses negotiated by the Accept-Encoding header.
+
+use warp::filters::BoxedFilter;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+/// Supported content encodings, most preferred first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    const ALL: [Encoding; 2] = [Encoding::Brotli, Encoding::Gzip];
+
+    fn token(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+/// Pick the encoding to use given the Accept-Encoding header.
+///
+/// Encodings are picked by server preference, ignoring the client weights
+/// apart from `q=0` which marks an encoding as not acceptable.
+pub fn negotiate(accept_encoding: Option<&str>) -> Option<Encoding> {
+    let accepted: Vec<&str> = accept_encoding
+        .unwrap_or("")
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let token = parts.next()?;
+            let refused = parts.any(|p| {
+                p.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (!refused && !token.is_empty()).then_some(token)
+        })
+        .collect();
+    Encoding::ALL.iter().copied().find(|enc| {
+        accepted
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(enc.token()) || *t == "*")
+    })
+}
+
+/// Compress responses of given routes if the client accepts it.
+///
+/// Requests for which `is_streaming` returns true are never compressed since
+/// the compressor would hold back the streamed data.
+pub fn compress(
+    routes: BoxedFilter<(Response,)>,
+    is_streaming: fn(&str) -> bool,
+) -> BoxedFilter<(Response,)> {
+    // Exactly one of the branches is selected for each request so that the
+    // routes are evaluated just once.
+    let selected = move |expected: Option<Encoding>| {
+        warp::header::optional::<String>("accept-encoding")
+            .and(warp::path::full())
+            .and_then(
+                move |accept: Option<String>, path: warp::path::FullPath| async move {
+                    let encoding = match is_streaming(path.as_str()) {
+                        true => None,
+                        false => negotiate(accept.as_deref()),
+                    };
+                    match encoding == expected {
+                        true => Ok(()),
+                        false => Err(warp::reject::not_found()),
+                    }
+                },
+            )
+            .untuple_one()
+    };
+    let vary = warp::reply::with::header("vary", "accept-encoding");
+    let brotli = selected(Some(Encoding::Brotli))
+        .and(routes.clone())
+        .with(warp::compression::brotli())
+        .map(Reply::into_response);
+    let gzip = selected(Some(Encoding::Gzip))
+        .and(routes.clone())
+        .with(warp::compression::gzip())
+        .map(Reply::into_response);
+    let plain = selected(None).and(routes);
+    brotli
+        .or(gzip)
+        .unify()
+        .or(plain)
+        .unify()
+        .with(vary)
+        .map(Reply::into_response)
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_negotiate_encoding() {
+        assert_eq!(negotiate(None), None);
+        assert_eq!(negotiate(Some("identity")), None);
+        assert_eq!(negotiate(Some("gzip")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("gzip, deflate, br")), Some(Encoding::Brotli));
+        assert_eq!(negotiate(Some("br;q=0, gzip;q=0.5")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("GZIP")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("*")), Some(Encoding::Brotli));
+        assert_eq!(negotiate(Some("gzip;q=0")), None);
+    }
+}
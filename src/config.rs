@@ -0,0 +1,109 @@
+//! Configuration file, re-read when the server is asked to reload.
+//!
+//! The file is in the TOML format and holds the settings which can be changed
+//! while the server is running:
+//!
+//! ```toml
+//! fetch_limit = 8
+//! host_delay_ms = 500
+//! host_burst = 4
+//! user_agent = "croolr (+https://example.com/bot)"
+//! api_keys = ["secret"]
+//! read_keys = ["reader"]
+//! ```
+//!
+//! Values in the file take precedence over the command line options. Values
+//! left out of it are taken from the command line, also when the file is
+//! reloaded.
+
+use super::auth::Keys;
+use super::settings::{Settings, SettingsUpdate};
+
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Contents of the configuration file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub fetch_limit: Option<u32>,
+    pub host_delay_ms: Option<u64>,
+    pub host_burst: Option<u32>,
+    pub user_agent: Option<String>,
+    pub api_keys: Option<Vec<String>>,
+    pub read_keys: Option<Vec<String>>,
+}
+
+/// The configuration file along with the command line options it overrides.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub path: PathBuf,
+    /// Settings given on the command line.
+    pub settings: Settings,
+    /// Keys given on the command line.
+    pub keys: Keys,
+}
+
+impl ConfigFile {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+}
+
+impl Source {
+    /// Read the file and work out the settings and keys in effect.
+    pub fn load(&self) -> Result<(Settings, Keys), String> {
+        let text = std::fs::read_to_string(&self.path)
+            .map_err(|e| format!("cannot read {}: {}", self.path.display(), e))?;
+        let file = ConfigFile::parse(&text)
+            .map_err(|e| format!("invalid {}: {}", self.path.display(), e))?;
+        self.apply(file)
+    }
+
+    fn apply(&self, file: ConfigFile) -> Result<(Settings, Keys), String> {
+        let mut settings = self.settings.clone();
+        let update = SettingsUpdate {
+            fetch_limit: file.fetch_limit,
+            host_delay_ms: file.host_delay_ms,
+            host_burst: file.host_burst,
+            user_agent: file.user_agent,
+        };
+        update.apply(&mut settings)?;
+        let keys = Keys {
+            write: file
+                .api_keys
+                .map_or_else(|| self.keys.write.clone(), |k| k.into_iter().collect()),
+            read: file
+                .read_keys
+                .map_or_else(|| self.keys.read.clone(), |k| k.into_iter().collect()),
+        };
+        Ok((settings, keys))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_config_file() {
+        let source = Source {
+            path: PathBuf::from("croolr.toml"),
+            settings: Settings::default(),
+            keys: Keys {
+                write: vec!["cli".to_string()].into_iter().collect(),
+                read: Default::default(),
+            },
+        };
+        let file = ConfigFile::parse("fetch_limit = 2\nread_keys = [\"r\"]\n").unwrap();
+        let (settings, keys) = source.apply(file).unwrap();
+        assert_eq!(settings.fetch_limit, 2);
+        assert_eq!(settings.host_delay_ms, 0);
+        assert!(keys.write.contains("cli"));
+        assert!(keys.read.contains("r"));
+
+        assert!(ConfigFile::parse("unknown = 1").is_err());
+        let file = ConfigFile::parse("fetch_limit = 0").unwrap();
+        assert!(source.apply(file).is_err());
+    }
+}
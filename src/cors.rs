@@ -0,0 +1,62 @@
+//! Cross-origin resource sharing for browser-based clients.
+
+use super::auth;
+
+use warp::http::Method;
+
+/// Origins and methods allowed for cross-origin requests.
+#[derive(Debug, Clone, Default)]
+pub struct CorsConfig {
+    /// Allowed origins as serialized by `parse_origin`, `*` for any origin.
+    pub origins: Vec<String>,
+    /// Allowed request methods.
+    pub methods: Vec<Method>,
+}
+
+impl CorsConfig {
+    /// Build the warp CORS wrapper, None if cross-origin requests are not allowed.
+    pub fn build(&self) -> Option<warp::cors::Builder> {
+        if self.origins.is_empty() {
+            return None;
+        }
+        let cors = warp::cors()
+            .allow_methods(self.methods.iter().cloned())
+            .allow_headers(vec![auth::HEADER, "content-type"]);
+        Some(match self.origins.iter().any(|o| o == "*") {
+            true => cors.allow_any_origin(),
+            false => cors.allow_origins(self.origins.iter().map(String::as_str)),
+        })
+    }
+}
+
+/// Parse an allowed origin given either as `*` or as a URL.
+pub fn parse_origin(s: &str) -> Result<String, String> {
+    if s == "*" {
+        return Ok(s.to_string());
+    }
+    let url = url::Url::parse(s).map_err(|e| e.to_string())?;
+    match url.origin() {
+        origin @ url::Origin::Tuple(..) => Ok(origin.ascii_serialization()),
+        url::Origin::Opaque(_) => Err(format!("not a valid origin: {}", s)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_parse_origin() {
+        assert_eq!(parse_origin("*").unwrap(), "*");
+        assert_eq!(
+            parse_origin("https://dash.example.com/").unwrap(),
+            "https://dash.example.com"
+        );
+        assert_eq!(
+            parse_origin("http://localhost:8080").unwrap(),
+            "http://localhost:8080"
+        );
+        assert!(parse_origin("dash.example.com").is_err());
+        assert!(parse_origin("data:text/plain,hi").is_err());
+    }
+}
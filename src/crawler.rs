@@ -0,0 +1,2709 @@
+//! The main crawler module.
+
+use super::analyzer::{Analyzer, Analyzers};
+use super::audit::{self, Audit, AuditOptions};
+use super::autotune;
+use super::checkpoint::{Checkpoint, Fetched, Queued};
+use super::discovery;
+use super::error::CroolrError;
+use super::event::CrawlEvent;
+use super::fetch;
+use super::fetcher::{Fetcher, HttpFetcher};
+use super::frontier::{Frontier, MakeFrontier, MemoryFrontier};
+#[cfg(feature = "headless")]
+use super::headless::{Browser, HeadlessChrome, HeadlessFetcher};
+use super::linkcheck::{self, BrokenLink, LinkChecker};
+use super::listing::{ListOptions, Listing};
+use super::metrics::METRICS;
+use super::middleware::{Chain, Middleware, ResponseCache};
+use super::outlinks::Outlinks;
+#[cfg(feature = "wasm")]
+use super::plugin::Plugin;
+use super::robots::{RobotsReport, RobotsTxt};
+#[cfg(feature = "headless")]
+use super::screenshot::{ScreenshotStore, Screenshots};
+#[cfg(feature = "scripting")]
+use super::script::Script;
+use super::search::{SearchQuery, SearchResults};
+use super::seen::SeenSet;
+use super::sessions::SessionParams;
+use super::settings::{
+    PortPolicy, RedirectPolicy, Settings, SettingsUpdate, SiteScope, TrailingSlash,
+};
+use super::shard::{ShardMessage, ShardStats, Shards};
+use super::stats::{self, MemoryEstimate, Stats};
+use super::summary::{Buckets, Counts, Report, Summary};
+use super::urlinfo::*;
+use super::workers::WorkerPool;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::Instant;
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
+use tokio_stream::{Stream, StreamExt};
+use url::Url;
+
+/// A handle to the crawler process. Used to send messages to it.
+#[derive(Clone)]
+pub struct Crawler {
+    channel: mpsc::Sender<Message>,
+    events: broadcast::Sender<CrawlEvent>,
+    fetcher: Arc<dyn Fetcher>,
+    on_discover: Option<DiscoverHook>,
+    /// The discovered URLs, added to by the crawler task only.
+    seen: Arc<SeenSet>,
+    /// Runs the fetches of the pages.
+    workers: WorkerPool,
+    /// Which hosts make up a site, fixed at start.
+    scope: SiteScope,
+    /// Checks the links to other sites, if asked to.
+    links: Option<Arc<LinkChecker>>,
+    /// Takes the screenshots of the pages, if asked to.
+    #[cfg(feature = "headless")]
+    screenshots: Option<Arc<Screenshots>>,
+}
+
+/// Callback given each discovered URL and the page linking to it, if any,
+/// before the URL is scheduled.
+///
+/// Returns the URL to schedule, possibly rewritten, or None to skip it.
+pub type DiscoverHook = Arc<dyn Fn(Url, Option<&Url>) -> Option<Url> + Send + Sync>;
+
+/// Number of events buffered for each subscriber before it starts lagging.
+const EVENT_BUFFER_SIZE: usize = 1024;
+
+/// Identifier of a crawl, unique across crawler restarts, e.g. `5f1e2d3c4b5a6-0`.
+///
+/// It is attached to the log lines concerning the crawl.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
+pub struct CrawlId(String);
+
+impl std::fmt::Display for CrawlId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Reply to a request to crawl given domain.
+///
+/// Serialized as an object with the `status` name of the variant, and the
+/// `error` for malformed host names.
+#[derive(Debug)]
+pub enum CrawlReply {
+    /// Request has been queued.
+    Queued,
+    /// THe requested host name is malformed.
+    MalformedHostName(url::ParseError),
+    /// This domain has already been crawled or is crawling.
+    AlreadyCrawling,
+}
+
+impl CrawlReply {
+    /// Stable name of the reply, e.g. `Queued`.
+    pub fn status(&self) -> &'static str {
+        match self {
+            CrawlReply::Queued => "Queued",
+            CrawlReply::MalformedHostName(_) => "MalformedHostName",
+            CrawlReply::AlreadyCrawling => "AlreadyCrawling",
+        }
+    }
+}
+
+impl Serialize for CrawlReply {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let error = match self {
+            CrawlReply::MalformedHostName(e) => Some(e.to_string()),
+            _ => None,
+        };
+        let mut reply = serializer.serialize_struct("CrawlReply", 1 + error.is_some() as usize)?;
+        reply.serialize_field("status", self.status())?;
+        match error {
+            Some(error) => reply.serialize_field("error", &error)?,
+            None => reply.skip_field("error")?,
+        }
+        reply.end()
+    }
+}
+
+/// Reply to URL listing.
+pub type ListUrlsReply = Option<Listing>;
+
+/// Reply to a domain audit request.
+pub type AuditReply = Option<Audit>;
+
+/// Reply to a broken-link report request.
+pub type BrokenLinksReply = Option<Vec<BrokenLink>>;
+
+/// Reply to URL count request.
+pub type CountUrlsReply = Option<usize>;
+
+/// Reply to URL counts request, None if there are no results for the domain.
+pub type UrlCountsReply = Option<Counts>;
+
+/// Reply to domain summary request.
+pub type SummaryReply = Option<Summary>;
+
+/// Reply to a request for the result statistics of all the domains, ordered
+/// by domain name.
+pub type SummariesReply = Vec<(Domain, Report)>;
+
+/// Reply to URL detail request.
+pub type UrlInfoReply = Option<UrlInfo>;
+
+/// Reply to a page outlinks request, None if the page has not been fetched.
+pub type OutlinksReply = Option<Outlinks>;
+
+/// Reply to a domain listing request, ordered by domain name.
+pub type DomainsReply = Vec<DomainStatus>;
+
+/// Reply to a data version request, None if the domain has neither data nor a
+/// crawl.
+pub type DataVersionReply = Option<DataVersion>;
+
+/// Version of the data collected for a domain, changes with every fetch result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataVersion {
+    /// Identifies the crawler instance so that versions are not reused
+    /// after a restart.
+    pub epoch: u64,
+    /// Sequence number of the latest fetch result.
+    pub seq: u64,
+    /// When the latest fetch result has been stored.
+    pub modified: SystemTime,
+}
+
+impl DataVersion {
+    /// Version of the data of a crawl with no result yet. Its epoch, unlike
+    /// those of the crawler instances, is 0, so that the first result changes
+    /// it even though the sequence numbers start at 0.
+    fn queued() -> Self {
+        DataVersion {
+            epoch: 0,
+            seq: 0,
+            modified: SystemTime::now(),
+        }
+    }
+
+    /// Entity tag of a representation of the data.
+    pub fn etag(&self, variant: &str) -> String {
+        format!("\"{:x}-{:x}-{}\"", self.epoch, self.seq, variant)
+    }
+}
+
+/// Reply to a robots.txt request, None if the domain is not being crawled.
+pub type RobotsReply = Option<RobotsReport>;
+
+/// Reply to a request to wait for a crawl to finish, false if the domain is
+/// not being crawled.
+pub type WaitReply = bool;
+
+/// Reply to a drain request, the number of queued URLs left unfetched.
+pub type DrainReply = usize;
+
+/// State of the crawl of a domain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CrawlState {
+    /// URLs of the domain are being fetched.
+    Running,
+    /// The crawl waits for one of the others to finish, as many are running
+    /// as the max number of active domains allows.
+    Pending,
+    /// Queued URLs of the domain are held back until the crawl is resumed.
+    Paused,
+    /// Queued URLs of the domain have been dropped and new ones are ignored.
+    Stopped,
+}
+
+/// Command changing the state of a crawl.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Control {
+    Pause,
+    Resume,
+    Stop,
+}
+
+impl std::str::FromStr for Control {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pause" => Ok(Control::Pause),
+            "resume" => Ok(Control::Resume),
+            "stop" => Ok(Control::Stop),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Reply to a crawl control command. The new crawl state or None if the
+/// domain is not being crawled.
+pub type ControlReply = Option<CrawlState>;
+
+/// Fetch queue status for a domain.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueueStatus {
+    /// State of the crawl, None if the domain is not being crawled.
+    pub state: Option<CrawlState>,
+    /// Number of URLs waiting to be fetched.
+    pub queued: usize,
+    /// A few of the queued URLs, the ones to be fetched next first.
+    pub sample: Vec<Url>,
+    /// URLs being fetched right now.
+    pub in_flight: Vec<Url>,
+}
+
+/// Overview of a domain the crawler has data for.
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainStatus {
+    pub domain: Domain,
+    /// Number of URLs fetched so far.
+    pub urls: usize,
+    /// State of the crawl, None if the domain has not been crawled itself,
+    /// e.g. when it has only been reached by a redirect.
+    pub state: Option<CrawlState>,
+    /// Identifier of the crawl, None if the domain has not been crawled itself.
+    pub crawl_id: Option<CrawlId>,
+}
+
+/// Messages the main crawler is capable reacting to.
+#[derive(Debug)]
+enum Message {
+    /// Notify that (possibly) new URLs have been found in a web page code.
+    /// The second field is the page the links have been found on, if any.
+    LinksFound(Vec<Url>, Option<Arc<Url>>),
+    /// Notify that a web page has been processed with given result.
+    Processed(Url, Box<UrlInfo>),
+    /// Crawl given domain, starting at given URL or the domain root.
+    Crawl(Domain, Option<Url>, oneshot::Sender<CrawlReply>),
+    /// Get a snapshot of the results for given domain, to be listed by the
+    /// caller.
+    ListUrls(Domain, oneshot::Sender<Option<Arc<UrlSet>>>),
+    /// Store the robots.txt file fetched for given domain.
+    RobotsFetched(Domain, Box<RobotsTxt>),
+    /// Get the robots.txt status of given domain, optionally with the decision
+    /// on given path.
+    Robots(Domain, Option<String>, oneshot::Sender<RobotsReply>),
+    /// Get the version of the data collected for given domain.
+    DataVersion(Domain, oneshot::Sender<DataVersionReply>),
+    /// Get the numbers of urls for given domain.
+    CountUrls(Domain, oneshot::Sender<UrlCountsReply>),
+    /// Get the result summary for given domain.
+    Summary(Domain, oneshot::Sender<SummaryReply>),
+    /// Get the result statistics of all the domains.
+    Summaries(oneshot::Sender<SummariesReply>),
+    /// Get everything known about given URL.
+    UrlInfo(Url, oneshot::Sender<UrlInfoReply>),
+    /// Get the links found on given page.
+    Outlinks(Url, oneshot::Sender<OutlinksReply>),
+    /// Get all the known domains.
+    Domains(oneshot::Sender<DomainsReply>),
+    /// Find URLs matching a search across all domains.
+    Search(SearchQuery, oneshot::Sender<SearchResults>),
+    /// Get the current settings.
+    GetSettings(oneshot::Sender<Settings>),
+    /// Change the settings, reply with the new ones or why the change is invalid.
+    UpdateSettings(SettingsUpdate, oneshot::Sender<Result<Settings, String>>),
+    /// Allow fewer fetches at once than the fetch limit, or lift the cap.
+    CapFetchLimit(Option<u32>, oneshot::Sender<()>),
+    /// Get the global crawler statistics.
+    Stats(oneshot::Sender<Stats>),
+    /// Get the fetch queue status for given domain.
+    Queue(Domain, oneshot::Sender<QueueStatus>),
+    /// Pause, resume or stop crawling given domain.
+    Control(Domain, Control, oneshot::Sender<ControlReply>),
+    /// Reply once the crawl of given domain has nothing more to fetch.
+    Wait(Domain, oneshot::Sender<WaitReply>),
+    /// The crawl of given domain has had nothing more to fetch, or has been
+    /// stopped, making room for a pending one.
+    CrawlFinished(Domain),
+    /// Stop starting new fetches, reply once all the fetches in flight finish.
+    /// The crawler task stops then if the flag is set.
+    Drain(bool, oneshot::Sender<DrainReply>),
+    /// The shards have drained, leaving given number of URLs queued.
+    Drained(DrainReply),
+    /// Get the progress of the crawls to save.
+    Checkpoint(oneshot::Sender<Checkpoint>),
+    /// Load saved progress and continue the crawls.
+    Restore(Box<Checkpoint>, oneshot::Sender<()>),
+}
+
+/// Configuration of a new crawler, see [`Crawler::builder`].
+#[derive(Default)]
+pub struct CrawlerBuilder {
+    settings: Settings,
+    fetcher: Option<Arc<dyn Fetcher>>,
+    frontier: Option<MakeFrontier>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    analyzers: Vec<Arc<dyn Analyzer>>,
+    #[cfg(feature = "headless")]
+    browser: Option<Arc<dyn Browser>>,
+    on_discover: Option<DiscoverHook>,
+    #[cfg(feature = "scripting")]
+    script: Option<Arc<Script>>,
+}
+
+impl CrawlerBuilder {
+    /// Start from given settings, replacing everything set so far.
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Max number of concurrent fetches, 16 by default.
+    pub fn fetch_limit(mut self, fetch_limit: u32) -> Self {
+        self.settings.fetch_limit = fetch_limit;
+        self
+    }
+
+    /// Min delay between starting fetches from the same host, none by default.
+    pub fn host_delay(mut self, delay: Duration) -> Self {
+        self.settings.host_delay_ms = delay.as_millis() as u64;
+        self
+    }
+
+    /// Number of fetches from the same host that may start at once, spaced by
+    /// the host delay from then on, 1 by default.
+    pub fn host_burst(mut self, burst: u32) -> Self {
+        self.settings.host_burst = burst;
+        self
+    }
+
+    /// User agent header sent with the fetches.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.settings.user_agent = user_agent.into();
+        self
+    }
+
+    /// Histogram buckets of the per-domain statistics.
+    pub fn buckets(mut self, buckets: Buckets) -> Self {
+        self.settings.buckets = buckets;
+        self
+    }
+
+    /// Max number of links followed from the seed, unlimited by default.
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.settings.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Number of messages the crawler task buffers, 32 by default. Fetches
+    /// finding links faster than the crawler takes them wait once it is full.
+    pub fn channel_capacity(mut self, capacity: usize) -> Self {
+        self.settings.channel_capacity = capacity;
+        self
+    }
+
+    /// Max number of idle connections the default fetcher keeps open to each
+    /// host, the fetch limit by default. Zero closes them after each request.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.settings.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Lower the fetch limit while the process runs short of open files or of
+    /// memory, if given in MiB. Off by default.
+    pub fn auto_fetch_limit(mut self, memory_limit_mb: Option<u64>) -> Self {
+        self.settings.auto_fetch_limit = true;
+        self.settings.memory_limit_mb = memory_limit_mb;
+        self
+    }
+
+    /// How long the default fetcher keeps idle connections open, 90 seconds
+    /// by default.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.pool_idle_timeout_secs = timeout.as_secs();
+        self
+    }
+
+    /// Answer the requests for pages fetched less than given time ago from
+    /// memory, keeping up to given number of pages. Off by default.
+    pub fn response_cache(mut self, size: usize, ttl: Duration) -> Self {
+        self.settings.response_cache_size = size;
+        self.settings.response_cache_ttl_secs = ttl.as_secs();
+        self
+    }
+
+    /// What to do with the pages redirecting to another host, following
+    /// their links there by default.
+    pub fn cross_host_redirects(mut self, policy: RedirectPolicy) -> Self {
+        self.settings.cross_host_redirects = policy;
+        self
+    }
+
+    /// Crawl and report `www.example.com` and `example.com` as one site,
+    /// keyed by the latter. Off by default.
+    pub fn merge_www(mut self, merge: bool) -> Self {
+        self.settings.merge_www = merge;
+        self
+    }
+
+    /// Max number of domains crawled at once, the crawls asked for beyond it
+    /// waiting for the others to finish. Unlimited by default.
+    pub fn max_active_domains(mut self, max: u32) -> Self {
+        self.settings.max_active_domains = Some(max);
+        self
+    }
+
+    /// Render the pages in a headless browser before extracting their links,
+    /// see [`headless`](super::headless).
+    #[cfg(feature = "headless")]
+    pub fn headless(mut self) -> Self {
+        self.settings.headless = true;
+        self
+    }
+
+    /// Keep a screenshot of each page fetched in given directory, see
+    /// [`screenshot`](super::screenshot).
+    #[cfg(feature = "headless")]
+    pub fn screenshots(mut self, dir: impl Into<std::path::PathBuf>) -> Self {
+        self.settings.screenshot_dir = Some(dir.into());
+        self
+    }
+
+    /// Render the pages and take the screenshots with given browser rather
+    /// than the headless Chrome set in the settings.
+    #[cfg(feature = "headless")]
+    pub fn browser(mut self, browser: impl Browser) -> Self {
+        self.browser = Some(Arc::new(browser));
+        self
+    }
+
+    /// Check the links to other sites found on the pages, reporting those not
+    /// working along with the broken links within the sites.
+    pub fn check_external(mut self) -> Self {
+        self.settings.check_external = true;
+        self
+    }
+
+    /// Whether to find the query parameters carrying sessions, which are then
+    /// dropped from the URLs found, as they are by default.
+    pub fn detect_session_params(mut self, detect: bool) -> Self {
+        self.settings.detect_session_params = detect;
+        self
+    }
+
+    /// Turn the paths of the discovered URLs into given form, leaving them as
+    /// they are found by default.
+    pub fn trailing_slash(mut self, form: TrailingSlash) -> Self {
+        self.settings.trailing_slash = form;
+        self
+    }
+
+    /// Whether the links to the other ports of a host are followed, as they
+    /// are by default.
+    pub fn port_policy(mut self, policy: PortPolicy) -> Self {
+        self.settings.port_policy = policy;
+        self
+    }
+
+    /// Backend doing the requests, by default an [`HttpFetcher`] keeping as
+    /// many connections to a host open as the fetch limit.
+    pub fn fetcher(mut self, fetcher: impl Fetcher) -> Self {
+        self.fetcher = Some(Arc::new(fetcher));
+        self
+    }
+
+    /// Scheduling policy, given as the function creating the frontier of
+    /// each crawled domain. [`MemoryFrontier`] by default.
+    pub fn frontier<F, T>(mut self, make_frontier: F) -> Self
+    where
+        F: Fn(&Domain) -> T + Send + Sync + 'static,
+        T: Frontier,
+    {
+        self.frontier = Some(Arc::new(move |domain| Box::new(make_frontier(domain))));
+        self
+    }
+
+    /// Pass the page fetches through given middleware, after the one added
+    /// before.
+    pub fn middleware(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Run given analyzer on the fetched pages, after the built-in ones set
+    /// in the settings and those added before.
+    pub fn analyzer(mut self, analyzer: impl Analyzer) -> Self {
+        self.analyzers.push(Arc::new(analyzer));
+        self
+    }
+
+    /// Let given callback skip or rewrite the discovered URLs, see
+    /// [`DiscoverHook`].
+    ///
+    /// Rewritten URLs should stay within the crawled domains.
+    pub fn on_discover<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(Url, Option<&Url>) -> Option<Url> + Send + Sync + 'static,
+    {
+        self.on_discover = Some(Arc::new(hook));
+        self
+    }
+
+    /// Let given script decide which links to follow and extract custom
+    /// fields from the pages, instead of the script file in the settings.
+    #[cfg(feature = "scripting")]
+    pub fn script(mut self, script: Script) -> Self {
+        self.script = Some(Arc::new(script));
+        self
+    }
+
+    /// Record assets and pages matching given patterns without fetching them.
+    pub fn discovery_only(mut self, leaf_patterns: Vec<String>) -> Self {
+        self.settings.discovery_only = true;
+        self.settings.leaf_patterns = leaf_patterns;
+        self
+    }
+
+    /// Record the URLs matching given patterns without fetching them, as they
+    /// look like they change some state, instead of [the default ones]. No
+    /// patterns fetch every URL.
+    ///
+    /// [the default ones]: discovery::DEFAULT_UNSAFE_PATTERNS
+    pub fn unsafe_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.settings.unsafe_patterns = patterns;
+        self
+    }
+
+    /// Check the configuration and start the crawler on the current tokio
+    /// runtime.
+    ///
+    /// Returns a handle that can be used to communicate with the crawler.
+    pub fn build(self) -> Result<Crawler, CroolrError> {
+        let settings = self.settings;
+        let check = SettingsUpdate {
+            fetch_limit: Some(settings.fetch_limit),
+            host_burst: Some(settings.host_burst),
+            user_agent: Some(settings.user_agent.clone()),
+            ..SettingsUpdate::default()
+        };
+        check.validate().map_err(CroolrError::InvalidConfig)?;
+        if settings.channel_capacity == 0 {
+            let e = "channel capacity must be at least 1".to_string();
+            return Err(CroolrError::InvalidConfig(e));
+        }
+        for pattern in settings
+            .leaf_patterns
+            .iter()
+            .chain(&settings.unsafe_patterns)
+        {
+            discovery::parse_pattern(pattern).map_err(CroolrError::InvalidConfig)?;
+        }
+        // Extended by the optional features.
+        #[allow(unused_mut)]
+        let (mut layers, mut on_discover) = (self.middleware, self.on_discover);
+        #[cfg(feature = "scripting")]
+        {
+            let script = match (self.script, &settings.script) {
+                (Some(script), _) => Some(script),
+                (None, Some(path)) => Some(Arc::new(
+                    Script::load(path).map_err(CroolrError::InvalidConfig)?,
+                )),
+                (None, None) => None,
+            };
+            if let Some(script) = script {
+                layers.push(script.clone());
+                on_discover = Some(follow_hook(on_discover, script));
+            }
+        }
+        #[cfg(not(feature = "scripting"))]
+        if settings.script.is_some() {
+            let e = "scripts need croolr built with the scripting feature".to_string();
+            return Err(CroolrError::InvalidConfig(e));
+        }
+        #[cfg(feature = "wasm")]
+        for path in &settings.plugins {
+            let plugin = Plugin::load(path).map_err(CroolrError::InvalidConfig)?;
+            layers.push(Arc::new(plugin));
+        }
+        #[cfg(not(feature = "wasm"))]
+        if !settings.plugins.is_empty() {
+            let e = "plugins need croolr built with the wasm feature".to_string();
+            return Err(CroolrError::InvalidConfig(e));
+        }
+        // The analyzers see the pages as left by all the other middleware.
+        let builtin = settings.analyzers.iter().map(|a| a.analyzer());
+        let analyzers: Vec<_> = builtin.chain(self.analyzers).collect();
+        if !analyzers.is_empty() {
+            layers.insert(0, Arc::new(Analyzers(analyzers)));
+        }
+        // Cached pages are those processed by all the other middleware.
+        if settings.response_cache_size > 0 {
+            let (size, ttl) = (settings.response_cache_size, settings.response_cache_ttl());
+            layers.insert(0, Arc::new(ResponseCache::new(size, ttl)));
+        }
+        let mut fetcher = self.fetcher.unwrap_or_else(|| {
+            let (pool_size, idle_timeout) = (settings.pool_size(), settings.pool_idle_timeout());
+            Arc::new(HttpFetcher::with_pool(pool_size, idle_timeout))
+        });
+        // The middleware gets the pages as rendered.
+        #[cfg(feature = "headless")]
+        let browser = self
+            .browser
+            .unwrap_or_else(|| Arc::new(HeadlessChrome::new(&settings.browser)));
+        #[cfg(feature = "headless")]
+        if settings.headless {
+            fetcher = Arc::new(HeadlessFetcher::new(fetcher, browser.clone()));
+        }
+        #[cfg(not(feature = "headless"))]
+        if settings.headless {
+            let e = "headless rendering needs croolr built with the headless feature".to_string();
+            return Err(CroolrError::InvalidConfig(e));
+        }
+        if !layers.is_empty() {
+            fetcher = Arc::new(Chain { fetcher, layers });
+        }
+        let make_frontier = self
+            .frontier
+            .unwrap_or_else(|| Arc::new(|_| Box::new(MemoryFrontier::new())));
+        #[cfg(feature = "headless")]
+        let screenshots = match &settings.screenshot_dir {
+            Some(_) if !settings.headless => {
+                let e = "screenshots need the headless browser backend".to_string();
+                return Err(CroolrError::InvalidConfig(e));
+            }
+            Some(dir) => {
+                let store = ScreenshotStore::new(dir).map_err(CroolrError::InvalidConfig)?;
+                Some(Arc::new(Screenshots::new(browser, store)))
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "headless"))]
+        if settings.screenshot_dir.is_some() {
+            let e = "screenshots need croolr built with the headless feature".to_string();
+            return Err(CroolrError::InvalidConfig(e));
+        }
+        let memory_limit = settings.memory_limit_mb.map(|mb| mb << 20);
+        let auto_fetch_limit = settings.auto_fetch_limit;
+        #[cfg(feature = "headless")]
+        let crawler = Crawler::spawn(settings, fetcher, make_frontier, on_discover, screenshots);
+        #[cfg(not(feature = "headless"))]
+        let crawler = Crawler::spawn(settings, fetcher, make_frontier, on_discover);
+        if auto_fetch_limit {
+            autotune::spawn(crawler.clone(), memory_limit);
+        }
+        Ok(crawler)
+    }
+}
+
+/// Chain the script deciding which links to follow after given hook.
+#[cfg(feature = "scripting")]
+fn follow_hook(hook: Option<DiscoverHook>, script: Arc<Script>) -> DiscoverHook {
+    Arc::new(move |url, parent| {
+        let url = match &hook {
+            Some(hook) => hook(url, parent)?,
+            None => url,
+        };
+        script.follow(&url, parent).then_some(url)
+    })
+}
+
+// Crawler agent implementation.
+impl Crawler {
+    /// Configure a new crawler, starting from the default settings.
+    pub fn builder() -> CrawlerBuilder {
+        CrawlerBuilder::default()
+    }
+
+    /// Start a new crawler with given initial settings.
+    ///
+    /// Panics if the fetch limit is 0.
+    fn spawn(
+        settings: Settings,
+        fetcher: Arc<dyn Fetcher>,
+        make_frontier: MakeFrontier,
+        on_discover: Option<DiscoverHook>,
+        #[cfg(feature = "headless")] screenshots: Option<Arc<Screenshots>>,
+    ) -> Crawler {
+        assert!(settings.fetch_limit >= 1, "Fetch limit must be at least 1");
+        let (sx, rx) = mpsc::channel(settings.channel_capacity);
+        let (events, _) = broadcast::channel(EVENT_BUFFER_SIZE);
+        let crawler = Crawler {
+            channel: sx,
+            events,
+            on_discover,
+            seen: Arc::new(SeenSet::new()),
+            workers: WorkerPool::new(settings.fetch_limit as usize),
+            scope: settings.site_scope(),
+            links: settings
+                .check_external
+                .then(|| Arc::new(LinkChecker::new(fetcher.clone()))),
+            fetcher,
+            #[cfg(feature = "headless")]
+            screenshots,
+        };
+        tokio::task::spawn(crawler.clone().run(rx, settings, make_frontier));
+        crawler
+    }
+
+    /// Subscribe to the events published by the crawler.
+    ///
+    /// Subscribers that do not keep up with the crawler miss some events.
+    pub fn subscribe(&self) -> broadcast::Receiver<CrawlEvent> {
+        self.events.subscribe()
+    }
+
+    /// Stream of the events published by the crawler, ending when it shuts
+    /// down.
+    ///
+    /// Events missed by not keeping up with the crawler are skipped.
+    pub fn events(&self) -> impl Stream<Item = CrawlEvent> {
+        BroadcastStream::new(self.subscribe())
+            .take_while(|event| !matches!(event, Ok(CrawlEvent::ShuttingDown)))
+            .filter_map(Result::ok)
+    }
+
+    /// The domain given one is crawled and reported as, see
+    /// [`Domain::site`].
+    pub fn site(&self, domain: Domain) -> Domain {
+        self.scope.site(domain)
+    }
+
+    /// The domain the results of given URL are kept under.
+    pub fn domain_of(&self, url: &Url) -> Option<Domain> {
+        self.scope.domain_of(url)
+    }
+
+    /// Instruct the crawler to crawl given domain.
+    pub async fn crawl(&self, domain: Domain) -> Result<CrawlReply, CroolrError> {
+        let domain = self.site(domain);
+        self.send_and_wait_reply(|r| Message::Crawl(domain, None, r))
+            .await
+    }
+
+    /// Crawl given domain, streaming the results as its URLs are fetched.
+    ///
+    /// The stream ends once the crawl has nothing more to fetch, so a crawl
+    /// in progress only streams the rest of its results. Results missed by
+    /// not keeping up with the crawler are skipped.
+    pub async fn crawl_stream(
+        &self,
+        domain: Domain,
+    ) -> Result<impl Stream<Item = (Url, UrlInfo)>, CroolrError> {
+        // Subscribed first not to miss the results of a quick crawl.
+        let mut events = self.subscribe();
+        let domain = self.site(domain);
+        self.crawl(domain.clone()).await?;
+        let (sx, rx) = mpsc::channel(EVENT_BUFFER_SIZE);
+        let crawler = self.clone();
+        tokio::task::spawn(async move {
+            let finished = crawler.wait(domain.clone());
+            tokio::pin!(finished);
+            let mut done = false;
+            loop {
+                // The results are published before the crawl is reported to
+                // be finished, so they are all buffered by then.
+                let event = match done {
+                    true => match events.try_recv() {
+                        Ok(event) => event,
+                        Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                        Err(_) => break,
+                    },
+                    false => tokio::select! {
+                        event = events.recv() => match event {
+                            Ok(event) => event,
+                            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        },
+                        _ = &mut finished => {
+                            done = true;
+                            continue;
+                        }
+                    },
+                };
+                let url = match event {
+                    CrawlEvent::Fetched { url, .. }
+                        if crawler.domain_of(&url) == Some(domain.clone()) =>
+                    {
+                        url
+                    }
+                    _ => continue,
+                };
+                if let Ok(Some(info)) = crawler.url_info(url.clone()).await {
+                    if sx.send((url, info)).await.is_err() {
+                        // Nobody is listening anymore.
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(ReceiverStream::new(rx))
+    }
+
+    /// Instruct the crawler to crawl the domain of given URL, starting at the
+    /// URL instead of the root of the domain.
+    ///
+    /// Like the links found, the seed is stored without its credentials and
+    /// session parameters, see [`fetch::strip_secrets`].
+    pub async fn crawl_seed(&self, mut seed: Url) -> Result<CrawlReply, CroolrError> {
+        fetch::strip_secrets(&mut seed);
+        let domain = match self.domain_of(&seed) {
+            Some(domain) => domain,
+            None => return Ok(CrawlReply::MalformedHostName(url::ParseError::EmptyHost)),
+        };
+        self.send_and_wait_reply(|r| Message::Crawl(domain, Some(seed), r))
+            .await
+    }
+
+    /// Instruct the crawler to send a list of URLs for given domain.
+    pub async fn list_urls(
+        &self,
+        domain: Domain,
+        opts: ListOptions,
+    ) -> Result<ListUrlsReply, CroolrError> {
+        // Listed here so that the crawl is not held up by large listings.
+        let domain = self.site(domain);
+        let urls = self
+            .send_and_wait_reply(|r| Message::ListUrls(domain, r))
+            .await?;
+        Ok(urls.map(|urls| opts.apply(&urls)))
+    }
+
+    /// Instruct the crawler to send the SEO audit of given domain.
+    pub async fn audit(
+        &self,
+        domain: Domain,
+        opts: AuditOptions,
+    ) -> Result<AuditReply, CroolrError> {
+        // Audited here so that the crawl is not held up by large domains.
+        let domain = self.site(domain);
+        let urls = self
+            .send_and_wait_reply(|r| Message::ListUrls(domain, r))
+            .await?;
+        Ok(urls.map(|urls| audit::audit(&urls, &opts)))
+    }
+
+    /// Instruct the crawler to send the broken links found on the pages of
+    /// given domain, see [`linkcheck::broken_links`].
+    pub async fn broken_links(&self, domain: Domain) -> Result<BrokenLinksReply, CroolrError> {
+        let domain = self.site(domain);
+        let urls = self
+            .send_and_wait_reply(|r| Message::ListUrls(domain, r))
+            .await?;
+        let links = self.links.as_deref();
+        Ok(urls.map(|urls| linkcheck::broken_links(&urls, &self.scope, links)))
+    }
+
+    /// The screenshot of given page as PNG, None if screenshots are not taken
+    /// or none has been taken of the page (yet).
+    #[cfg(feature = "headless")]
+    pub async fn screenshot(&self, url: Url) -> Result<Option<Vec<u8>>, String> {
+        match &self.screenshots {
+            Some(screenshots) => screenshots.get(&url).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Instruct the crawler to send the number of URLs for given domain.
+    pub async fn count_urls(&self, domain: Domain) -> Result<CountUrlsReply, CroolrError> {
+        let counts = self.url_counts(domain).await?;
+        Ok(counts.map(|c| c.total))
+    }
+
+    /// Instruct the crawler to send the numbers of URLs for given domain,
+    /// also by status class and content type.
+    pub async fn url_counts(&self, domain: Domain) -> Result<UrlCountsReply, CroolrError> {
+        let domain = self.site(domain);
+        self.send_and_wait_reply(|r| Message::CountUrls(domain, r))
+            .await
+    }
+
+    /// Instruct the crawler to send the robots.txt status of given domain.
+    pub async fn robots(
+        &self,
+        domain: Domain,
+        path: Option<String>,
+    ) -> Result<RobotsReply, CroolrError> {
+        let domain = self.site(domain);
+        self.send_and_wait_reply(|r| Message::Robots(domain, path, r))
+            .await
+    }
+
+    /// Instruct the crawler to send the version of the data for given domain.
+    pub async fn data_version(&self, domain: Domain) -> Result<DataVersionReply, CroolrError> {
+        let domain = self.site(domain);
+        self.send_and_wait_reply(|r| Message::DataVersion(domain, r))
+            .await
+    }
+
+    /// Instruct the crawler to send result summary for given domain.
+    pub async fn summary(&self, domain: Domain) -> Result<SummaryReply, CroolrError> {
+        let domain = self.site(domain);
+        self.send_and_wait_reply(|r| Message::Summary(domain, r))
+            .await
+    }
+
+    /// Instruct the crawler to send the result statistics of all the domains.
+    pub async fn summaries(&self) -> Result<SummariesReply, CroolrError> {
+        self.send_and_wait_reply(Message::Summaries).await
+    }
+
+    /// Instruct the crawler to send information about given URL.
+    pub async fn url_info(&self, url: Url) -> Result<UrlInfoReply, CroolrError> {
+        self.send_and_wait_reply(|r| Message::UrlInfo(url, r)).await
+    }
+
+    /// Instruct the crawler to send the links found on given page.
+    pub async fn outlinks(&self, page: Url) -> Result<OutlinksReply, CroolrError> {
+        self.send_and_wait_reply(|r| Message::Outlinks(page, r))
+            .await
+    }
+
+    /// Instruct the crawler to send an overview of all the known domains.
+    pub async fn domains(&self) -> Result<DomainsReply, CroolrError> {
+        self.send_and_wait_reply(Message::Domains).await
+    }
+
+    /// Instruct the crawler to search URLs of all the domains.
+    pub async fn search(&self, query: SearchQuery) -> Result<SearchResults, CroolrError> {
+        self.send_and_wait_reply(|r| Message::Search(query, r))
+            .await
+    }
+
+    /// Instruct the crawler to send its current settings.
+    pub async fn settings(&self) -> Result<Settings, CroolrError> {
+        self.send_and_wait_reply(Message::GetSettings).await
+    }
+
+    /// Instruct the crawler to change its settings.
+    pub async fn update_settings(
+        &self,
+        update: SettingsUpdate,
+    ) -> Result<Result<Settings, String>, CroolrError> {
+        self.send_and_wait_reply(|r| Message::UpdateSettings(update, r))
+            .await
+    }
+
+    /// Instruct the crawler to allow at most given number of fetches at once,
+    /// below the fetch limit. None lifts the cap.
+    pub(crate) async fn cap_fetch_limit(&self, cap: Option<u32>) -> Result<(), CroolrError> {
+        self.send_and_wait_reply(|r| Message::CapFetchLimit(cap, r))
+            .await
+    }
+
+    /// Instruct the crawler to send global statistics.
+    pub async fn stats(&self) -> Result<Stats, CroolrError> {
+        self.send_and_wait_reply(Message::Stats).await
+    }
+
+    /// Instruct the crawler to send fetch queue status for given domain.
+    pub async fn queue(&self, domain: Domain) -> Result<QueueStatus, CroolrError> {
+        let domain = self.site(domain);
+        self.send_and_wait_reply(|r| Message::Queue(domain, r))
+            .await
+    }
+
+    /// Instruct the crawler to pause, resume or stop crawling given domain.
+    pub async fn control(
+        &self,
+        domain: Domain,
+        control: Control,
+    ) -> Result<ControlReply, CroolrError> {
+        let domain = self.site(domain);
+        self.send_and_wait_reply(|r| Message::Control(domain, control, r))
+            .await
+    }
+
+    /// Wait until the crawl of given domain has nothing more to fetch.
+    ///
+    /// A paused crawl with URLs still queued is not finished.
+    pub async fn wait(&self, domain: Domain) -> Result<WaitReply, CroolrError> {
+        let domain = self.site(domain);
+        self.send_and_wait_reply(|r| Message::Wait(domain, r)).await
+    }
+
+    /// Stop fetching new URLs and wait for the fetches in progress to finish.
+    ///
+    /// Subscribers are notified that the crawler is shutting down.
+    pub async fn drain(&self) -> Result<DrainReply, CroolrError> {
+        self.send_and_wait_reply(|r| Message::Drain(false, r)).await
+    }
+
+    /// Instruct the crawler to stop once the fetches in flight finish.
+    ///
+    /// The queued URLs are dropped, the reply is their number. All handles
+    /// of the crawler report it is gone afterwards.
+    pub async fn shutdown(&self) -> Result<DrainReply, CroolrError> {
+        self.send_and_wait_reply(|r| Message::Drain(true, r)).await
+    }
+
+    /// Wait until the crawler task finishes, e.g. after [`Crawler::shutdown`].
+    pub async fn join(&self) {
+        // The task owns the receiving end of the channel.
+        self.channel.closed().await
+    }
+
+    /// Instruct the crawler to send the progress of its crawls.
+    pub async fn checkpoint(&self) -> Result<Checkpoint, CroolrError> {
+        self.send_and_wait_reply(Message::Checkpoint).await
+    }
+
+    /// Load the progress saved by a checkpoint and continue its crawls.
+    ///
+    /// Meant for a fresh crawler, URLs already known to it are overwritten.
+    pub async fn restore(&self, checkpoint: Checkpoint) -> Result<(), CroolrError> {
+        self.send_and_wait_reply(|r| Message::Restore(Box::new(checkpoint), r))
+            .await
+    }
+
+    /// Main crawler message handling loop.
+    ///
+    /// The task supervises a shard for each domain with URLs to fetch, which
+    /// schedules the fetches, and keeps everything else: the discovered URLs,
+    /// the results and the state of the crawls.
+    async fn run(
+        self,
+        mut rx: mpsc::Receiver<Message>,
+        mut settings: Settings,
+        make_frontier: MakeFrontier,
+    ) {
+        let seen = self.seen.clone();
+        // The results are shared with the listings being built, a set being
+        // copied only when a result is stored while it is listed.
+        let mut data: HashMap<Domain, Arc<UrlSet>> = HashMap::new();
+        let mut summaries: HashMap<Domain, Summary> = HashMap::new();
+        let mut sessions: HashMap<Domain, SessionParams> = HashMap::new();
+        // Estimates of the memory taken, kept up to date as the URLs are
+        // discovered and their results stored.
+        let mut seen_bytes: usize = 0;
+        let mut results_bytes: HashMap<Domain, usize> = HashMap::new();
+        let mut shards = Shards::new(make_frontier, settings.fetch_limit);
+        // Fetches allowed at once given the fetch limit and the cap set by
+        // the auto-tuning, if any.
+        let effective = |limit: u32, cap: Option<u32>| cap.map_or(limit, |c| c.min(limit));
+        let mut fetch_cap = None;
+        METRICS.fetch_limit.set(settings.fetch_limit.into());
+        let mut states: HashMap<Domain, CrawlState> = HashMap::new();
+        let mut crawl_ids: HashMap<Domain, CrawlId> = HashMap::new();
+        // Crawls counted against the max number of active domains, and those
+        // waiting for room, with their seeds, in the order they were asked for.
+        let mut active: HashSet<Domain> = HashSet::new();
+        let mut pending: VecDeque<(Domain, Url)> = VecDeque::new();
+        let mut num_discovered: u64 = 0;
+        let mut draining = false;
+        let mut stopping = false;
+        let mut drain_waiters: Vec<oneshot::Sender<DrainReply>> = Vec::new();
+        let mut versions: HashMap<Domain, DataVersion> = HashMap::new();
+        let mut num_processed: u64 = 0;
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        let started = Instant::now();
+
+        while let Some(msg) = rx.recv().await {
+            METRICS.channel_occupancy.set(rx.len() as i64);
+            match msg {
+                Message::LinksFound(urls, parent) => {
+                    let depth = match &parent {
+                        Some(p) => seen.get(p).map_or(0, |d| d.depth + 1),
+                        None => 0,
+                    };
+                    if settings.max_depth.is_some_and(|max| depth > max) {
+                        continue;
+                    }
+                    let parent = parent.map(|p| seen.intern(&p));
+                    let mut found: HashMap<Domain, Vec<(Url, Discovery)>> = HashMap::new();
+                    for url in urls {
+                        let url = match &self.on_discover {
+                            Some(hook) => match hook(url, parent.as_deref()) {
+                                Some(url) => url,
+                                None => continue,
+                            },
+                            None => url,
+                        };
+                        let url = settings.trailing_slash.apply(url);
+                        let domain = match self.domain_of(&url) {
+                            Some(domain) => domain,
+                            None => continue,
+                        };
+                        let url = match sessions.get(&domain) {
+                            Some(params) => params.collapse(url),
+                            None => url,
+                        };
+                        let stopped = states.get(&domain) == Some(&CrawlState::Stopped);
+                        if stopped || seen.contains(&url) {
+                            continue;
+                        }
+                        let discovery = Discovery {
+                            seq: num_discovered,
+                            parent: parent.clone(),
+                            depth,
+                            discovered_at: Some(SystemTime::now()),
+                        };
+                        num_discovered += 1;
+                        METRICS.urls_discovered.inc();
+                        self.publish(CrawlEvent::discovered(&url, &discovery));
+                        seen_bytes += stats::discovery_bytes(&url);
+                        seen.insert(Arc::new(url.clone()), discovery.clone());
+                        found.entry(domain).or_default().push((url, discovery));
+                    }
+                    for (domain, links) in found {
+                        shards
+                            .get_or_spawn(&self, &domain, &settings, draining)
+                            .send(ShardMessage::LinksFound(links))
+                            .await;
+                    }
+                }
+                Message::Processed(url, mut info) => {
+                    if let Some(discovery) = seen.get(&url) {
+                        info.discovery = discovery;
+                    }
+                    self.publish(CrawlEvent::fetched(&url, &info));
+                    // A page followed on another host is stored under its own
+                    // URL there too, unless that one is known already.
+                    let domain = self.domain_of(&url);
+                    let landed = info
+                        .redirects
+                        .last()
+                        .filter(|landed| info.result.is_ok() && !seen.contains(landed));
+                    let landed = landed
+                        .and_then(|l| Some((l.clone(), self.domain_of(l)?)))
+                        .filter(|(_, landed)| domain.as_ref() != Some(landed));
+                    if let (Some((landed, domain)), RedirectPolicy::Follow) =
+                        (landed, settings.cross_host_redirects)
+                    {
+                        let discovery = Discovery {
+                            seq: num_discovered,
+                            parent: Some(seen.intern(&url)),
+                            depth: info.discovery.depth,
+                            discovered_at: Some(SystemTime::now()),
+                        };
+                        num_discovered += 1;
+                        METRICS.urls_discovered.inc();
+                        let landed = Arc::new(landed);
+                        seen_bytes += stats::discovery_bytes(&landed);
+                        seen.insert(landed.clone(), discovery.clone());
+                        let page = UrlInfo {
+                            redirects: Vec::new(),
+                            discovery,
+                            ..(*info).clone()
+                        };
+                        summaries
+                            .entry(domain.clone())
+                            .or_insert_with(|| Summary::new(&settings.buckets))
+                            .record(&page);
+                        let version = DataVersion {
+                            epoch,
+                            seq: num_processed,
+                            modified: SystemTime::now(),
+                        };
+                        versions.insert(domain.clone(), version);
+                        store_result(&mut data, &mut results_bytes, &domain, landed, page);
+                    }
+                    if let (Some(links), Ok(page)) = (&self.links, &info.result) {
+                        for link in page.links.iter() {
+                            if !self.scope.same_site(&url, &link) {
+                                links.check(link, &settings.user_agent);
+                            }
+                        }
+                    }
+                    if let Some(domain) = domain {
+                        let hash = info.result.as_ref().ok().and_then(|p| p.content_hash);
+                        if let Some(hash) = hash.filter(|_| settings.detect_session_params) {
+                            let params = sessions.entry(domain.clone()).or_default();
+                            if let Some(param) = params.record(&url, hash) {
+                                tracing::info!(crawl = %&*domain, param = %param, "Session parameter found");
+                            }
+                        }
+                        summaries
+                            .entry(domain.clone())
+                            .or_insert_with(|| Summary::new(&settings.buckets))
+                            .record(&info);
+                        let version = DataVersion {
+                            epoch,
+                            seq: num_processed,
+                            modified: SystemTime::now(),
+                        };
+                        versions.insert(domain.clone(), version);
+                        #[cfg(feature = "headless")]
+                        if let (Some(screenshots), Ok(_)) = (&self.screenshots, &info.result) {
+                            let _ = screenshots.capture(url.clone(), &settings.user_agent);
+                        }
+                        let stored = seen.intern(&url);
+                        store_result(&mut data, &mut results_bytes, &domain, stored, *info);
+                        // Told only now so that the result is there once the
+                        // crawl is reported to be finished.
+                        if let Some(shard) = shards.get(&domain) {
+                            shard.send(ShardMessage::Processed(url)).await;
+                        }
+                    }
+                    num_processed += 1;
+                }
+                Message::ListUrls(host, reply) => {
+                    // The crawls with no result yet have no results, rather
+                    // than being unknown.
+                    let urls = match data.get(&host) {
+                        Some(urls) => Some(urls.clone()),
+                        None => states.get(&host).map(|_| Arc::new(UrlSet::new())),
+                    };
+                    let _ = reply.send(urls);
+                }
+                Message::RobotsFetched(host, txt) => {
+                    shards
+                        .get_or_spawn(&self, &host, &settings, draining)
+                        .send(ShardMessage::RobotsFetched(txt))
+                        .await;
+                }
+                Message::Robots(host, path, reply) => {
+                    let report = match shards.get(&host) {
+                        Some(shard) => shard.request(|r| ShardMessage::Robots(path, r)).await,
+                        None => None,
+                    };
+                    let _ = reply.send(report.flatten());
+                }
+                Message::DataVersion(host, reply) => {
+                    let _ = reply.send(versions.get(&host).copied());
+                }
+                Message::CountUrls(host, reply) => {
+                    // Kept up to date by the summaries as the results come.
+                    let counts = match summaries.get(&host) {
+                        Some(summary) => Some(summary.counts()),
+                        None => states.get(&host).map(|_| Counts::default()),
+                    };
+                    let _ = reply.send(counts);
+                }
+                Message::Summary(host, reply) => {
+                    let summary = match summaries.get(&host) {
+                        Some(summary) => Some(summary.clone()),
+                        None => states.get(&host).map(|_| Summary::new(&settings.buckets)),
+                    };
+                    let _ = reply.send(summary);
+                }
+                Message::Summaries(reply) => {
+                    let mut reports: SummariesReply = summaries
+                        .iter()
+                        .map(|(d, s)| (d.clone(), s.report()))
+                        .collect();
+                    reports.sort_by(|a, b| a.0.cmp(&b.0));
+                    let _ = reply.send(reports);
+                }
+                Message::UrlInfo(url, reply) => {
+                    let info = self
+                        .domain_of(&url)
+                        .and_then(|d| data.get(&d))
+                        .and_then(|urls| urls.get(&url))
+                        .cloned();
+                    let _ = reply.send(info);
+                }
+                Message::Outlinks(page, reply) => {
+                    let outlinks = self
+                        .domain_of(&page)
+                        .and_then(|d| data.get(&d))
+                        .and_then(|urls| urls.get(&page))
+                        .map(|info| Outlinks::new(&page, info, &data, self.scope));
+                    let _ = reply.send(outlinks);
+                }
+                Message::Domains(reply) => {
+                    let names: BTreeSet<&Domain> = data.keys().chain(states.keys()).collect();
+                    let domains = names
+                        .into_iter()
+                        .map(|domain| DomainStatus {
+                            domain: domain.clone(),
+                            urls: data.get(domain).map_or(0, |urls| urls.len()),
+                            state: states.get(domain).copied(),
+                            crawl_id: crawl_ids.get(domain).cloned(),
+                        })
+                        .collect();
+                    let _ = reply.send(domains);
+                }
+                Message::Search(query, reply) => {
+                    let _ = reply.send(query.apply(data.values().map(|urls| &**urls)));
+                }
+                Message::GetSettings(reply) => {
+                    let _ = reply.send(settings.clone());
+                }
+                Message::UpdateSettings(update, reply) => {
+                    let fetch_limit = settings.fetch_limit;
+                    let result = update.apply(&mut settings).map(|()| settings.clone());
+                    if result.is_ok() {
+                        let (old, new) = (
+                            effective(fetch_limit, fetch_cap),
+                            effective(settings.fetch_limit, fetch_cap),
+                        );
+                        shards.set_fetch_limit(old, new);
+                        self.workers.resize(new as usize);
+                        METRICS.fetch_limit.set(new.into());
+                        for shard in shards.iter() {
+                            let update = Box::new(settings.clone());
+                            shard.send(ShardMessage::UpdateSettings(update)).await;
+                        }
+                    }
+                    let _ = reply.send(result);
+                }
+                Message::CapFetchLimit(cap, reply) => {
+                    let old = effective(settings.fetch_limit, fetch_cap);
+                    fetch_cap = cap;
+                    let new = effective(settings.fetch_limit, fetch_cap);
+                    shards.set_fetch_limit(old, new);
+                    self.workers.resize(new as usize);
+                    METRICS.fetch_limit.set(new.into());
+                    let _ = reply.send(());
+                }
+                Message::Stats(reply) => {
+                    let mut queues = ShardStats::default();
+                    for shard in shards.iter() {
+                        let stats = shard.request(ShardMessage::Stats).await;
+                        let stats = stats.unwrap_or_default();
+                        queues.queued += stats.queued;
+                        queues.in_flight += stats.in_flight;
+                        queues.memory_bytes += stats.memory_bytes;
+                    }
+                    let domains: HashSet<&Domain> = data.keys().chain(states.keys()).collect();
+                    let stats = Stats {
+                        uptime_secs: started.elapsed().as_secs(),
+                        domains: domains.len(),
+                        active_crawls: states
+                            .values()
+                            .filter(|s| **s == CrawlState::Running)
+                            .count(),
+                        pending_crawls: pending.len(),
+                        urls_seen: seen.len(),
+                        urls_fetched: data.values().map(|urls| urls.len()).sum(),
+                        queued: queues.queued,
+                        in_flight: queues.in_flight,
+                        memory: MemoryEstimate::new(
+                            seen_bytes,
+                            results_bytes.iter().map(|(d, b)| (d.clone(), *b)).collect(),
+                            queues.memory_bytes,
+                        ),
+                        features: stats::features(),
+                    };
+                    let _ = reply.send(stats);
+                }
+                Message::Queue(host, reply) => {
+                    let status = match shards.get(&host) {
+                        Some(shard) => shard.request(ShardMessage::Queue).await,
+                        None => None,
+                    };
+                    let _ = reply.send(status.unwrap_or_default());
+                }
+                Message::Crawl(host, seed, reply) => {
+                    let ret = match seed.map_or_else(|| url_from_host(&host), Ok) {
+                        Ok(url) => {
+                            let url = settings.trailing_slash.apply(url);
+                            // The seed is only marked seen later on, so a crawl
+                            // started just before is told by its state.
+                            if states.contains_key(&host) || seen.contains(&url) {
+                                CrawlReply::AlreadyCrawling
+                            } else {
+                                let id = CrawlId(format!("{:x}-{}", epoch, crawl_ids.len()));
+                                crawl_ids.insert(host.clone(), id.clone());
+                                versions
+                                    .entry(host.clone())
+                                    .or_insert_with(DataVersion::queued);
+                                let shard = shards.get_or_spawn(&self, &host, &settings, draining);
+                                let full = settings
+                                    .max_active_domains
+                                    .is_some_and(|max| active.len() >= max as usize);
+                                if full {
+                                    // The seed is queued, held back by the shard.
+                                    tracing::info!(crawl = %&*host, crawl_id = %id, "Crawl pending");
+                                    states.insert(host.clone(), CrawlState::Pending);
+                                    self.publish(CrawlEvent::state_changed(
+                                        &host,
+                                        CrawlState::Pending,
+                                    ));
+                                    shard.send(ShardMessage::Control(CrawlState::Pending)).await;
+                                    pending.push_back((host.clone(), url.clone()));
+                                } else {
+                                    active.insert(host.clone());
+                                    self.start_crawl(&host, &id, &mut states);
+                                    shard.send(ShardMessage::Crawl(id, url.clone())).await;
+                                }
+                                self.send_later(Message::LinksFound(vec![url], None));
+                                CrawlReply::Queued
+                            }
+                        }
+                        Err(err) => CrawlReply::MalformedHostName(err),
+                    };
+                    let _ = reply.send(ret);
+                }
+                Message::Control(host, control, reply) => {
+                    let new_state = states.get_mut(&host).map(|state| {
+                        *state = match (control, *state) {
+                            (_, CrawlState::Stopped) => CrawlState::Stopped,
+                            (Control::Stop, _) => CrawlState::Stopped,
+                            // A pending crawl is only started once there is room.
+                            (_, CrawlState::Pending) => CrawlState::Pending,
+                            (Control::Pause, _) => CrawlState::Paused,
+                            (Control::Resume, _) => CrawlState::Running,
+                        };
+                        *state
+                    });
+                    if let Some(state) = new_state {
+                        tracing::info!(
+                            crawl = %&*host,
+                            crawl_id = %crawl_ids[&host],
+                            state = ?state,
+                            "Crawl state changed"
+                        );
+                        self.publish(CrawlEvent::state_changed(&host, state));
+                        if let Some(shard) = shards.get(&host) {
+                            shard.send(ShardMessage::Control(state)).await;
+                        }
+                        if state == CrawlState::Stopped {
+                            pending.retain(|(domain, _)| *domain != host);
+                            self.send_later(Message::CrawlFinished(host));
+                        }
+                    }
+                    let _ = reply.send(new_state);
+                }
+                Message::CrawlFinished(host) => {
+                    active.remove(&host);
+                    let room = |active: &HashSet<Domain>| {
+                        settings
+                            .max_active_domains
+                            .is_none_or(|max| active.len() < max as usize)
+                    };
+                    while room(&active) {
+                        let (host, seed) = match pending.pop_front() {
+                            Some(next) => next,
+                            None => break,
+                        };
+                        let id = crawl_ids[&host].clone();
+                        active.insert(host.clone());
+                        self.start_crawl(&host, &id, &mut states);
+                        shards
+                            .get_or_spawn(&self, &host, &settings, draining)
+                            .send(ShardMessage::Crawl(id, seed))
+                            .await;
+                    }
+                }
+                Message::Wait(host, reply) => match shards.get(&host) {
+                    Some(shard) if states.contains_key(&host) => {
+                        shard.send(ShardMessage::Wait(reply)).await;
+                    }
+                    _ => {
+                        let _ = reply.send(false);
+                    }
+                },
+                Message::Drain(stop, reply) => {
+                    if !draining {
+                        draining = true;
+                        self.publish(CrawlEvent::ShuttingDown);
+                    }
+                    stopping |= stop;
+                    drain_waiters.push(reply);
+                    let mut drained = Vec::new();
+                    for shard in shards.iter() {
+                        let (sx, rx) = oneshot::channel();
+                        shard.send(ShardMessage::Drain(sx)).await;
+                        drained.push(rx);
+                    }
+                    // The shards finish their fetches while the results are
+                    // being stored here.
+                    let crawler = self.clone();
+                    tokio::task::spawn(async move {
+                        let mut queued = 0;
+                        for rx in drained {
+                            queued += rx.await.unwrap_or(0);
+                        }
+                        crawler.send(Message::Drained(queued)).await;
+                    });
+                }
+                Message::Drained(queued) => {
+                    for waiter in drain_waiters.drain(..) {
+                        let _ = waiter.send(queued);
+                    }
+                    if stopping {
+                        break;
+                    }
+                }
+                Message::Checkpoint(reply) => {
+                    let domains = states.iter().filter(|(_, s)| **s != CrawlState::Stopped);
+                    let (mut queued, mut in_flight) = (Vec::new(), Vec::new());
+                    for shard in shards.iter() {
+                        if let Some((q, f)) = shard.request(ShardMessage::Checkpoint).await {
+                            queued.extend(q);
+                            in_flight.extend(f);
+                        }
+                    }
+                    // Queued URLs are saved in the order of discovery so
+                    // that any frontier restores them alike, followed by the
+                    // URLs in flight to be fetched again on resume.
+                    let queued_url = |url: Url| Queued {
+                        discovery: seen.get(&url).unwrap_or_default(),
+                        url,
+                    };
+                    let mut frontier: Vec<Queued> = queued.into_iter().map(queued_url).collect();
+                    frontier.sort_by_key(|q| q.discovery.seq);
+                    let frontier = frontier
+                        .into_iter()
+                        .chain(in_flight.into_iter().map(queued_url));
+                    let fetched = data.values().flat_map(|urls| urls.iter());
+                    let checkpoint = Checkpoint {
+                        domains: domains.map(|(d, _)| d.clone()).collect(),
+                        fetched: fetched.map(|(u, i)| Fetched::new(u, i)).collect(),
+                        frontier: frontier.collect(),
+                    };
+                    let _ = reply.send(checkpoint);
+                }
+                Message::Restore(checkpoint, reply) => {
+                    let Checkpoint {
+                        domains,
+                        fetched,
+                        frontier,
+                    } = *checkpoint;
+                    for (url, mut info) in fetched.into_iter().map(Fetched::into_info) {
+                        num_discovered = num_discovered.max(info.discovery.seq + 1);
+                        let parent = info.discovery.parent.take();
+                        info.discovery.parent = parent.map(|p| seen.intern(&p));
+                        let url = Arc::new(url);
+                        if seen.insert(url.clone(), info.discovery.clone()).is_none() {
+                            seen_bytes += stats::discovery_bytes(&url);
+                        }
+                        if let Some(domain) = self.domain_of(&url) {
+                            summaries
+                                .entry(domain.clone())
+                                .or_insert_with(|| Summary::new(&settings.buckets))
+                                .record(&info);
+                            let version = DataVersion {
+                                epoch,
+                                seq: num_processed,
+                                modified: SystemTime::now(),
+                            };
+                            versions.insert(domain.clone(), version);
+                            store_result(&mut data, &mut results_bytes, &domain, url, info);
+                        }
+                        num_processed += 1;
+                    }
+                    // The crawls are started before their URLs are queued so
+                    // that nothing is fetched before the robots.txt files are
+                    // fetched again.
+                    let mut seeds = Vec::new();
+                    for host in domains {
+                        let host = self.site(host);
+                        let url = match url_from_host(&host) {
+                            Ok(url) if !states.contains_key(&host) => url,
+                            _ => continue,
+                        };
+                        let id = CrawlId(format!("{:x}-{}", epoch, crawl_ids.len()));
+                        versions
+                            .entry(host.clone())
+                            .or_insert_with(DataVersion::queued);
+                        let shard = shards.get_or_spawn(&self, &host, &settings, draining);
+                        let full = settings
+                            .max_active_domains
+                            .is_some_and(|max| active.len() >= max as usize);
+                        if full {
+                            states.insert(host.clone(), CrawlState::Pending);
+                            self.publish(CrawlEvent::state_changed(&host, CrawlState::Pending));
+                            shard.send(ShardMessage::Control(CrawlState::Pending)).await;
+                            pending.push_back((host.clone(), url.clone()));
+                        } else {
+                            active.insert(host.clone());
+                            self.start_crawl(&host, &id, &mut states);
+                            shard
+                                .send(ShardMessage::Crawl(id.clone(), url.clone()))
+                                .await;
+                        }
+                        crawl_ids.insert(host, id);
+                        seeds.push(url);
+                    }
+                    for Queued { url, mut discovery } in frontier {
+                        let parent = discovery.parent.take();
+                        discovery.parent = parent.map(|p| seen.intern(&p));
+                        let domain = match self.domain_of(&url) {
+                            Some(domain) => domain,
+                            None => continue,
+                        };
+                        num_discovered = num_discovered.max(discovery.seq + 1);
+                        let msg = ShardMessage::LinksFound(vec![(url.clone(), discovery.clone())]);
+                        shards
+                            .get_or_spawn(&self, &domain, &settings, draining)
+                            .send(msg)
+                            .await;
+                        if !seen.contains(&url) {
+                            seen_bytes += stats::discovery_bytes(&url);
+                        }
+                        seen.insert(Arc::new(url), discovery);
+                    }
+                    for url in seeds {
+                        if !seen.contains(&url) {
+                            self.send_later(Message::LinksFound(vec![url], None));
+                        }
+                    }
+                    let _ = reply.send(());
+                }
+            }
+        }
+        shards.close();
+    }
+
+    /// Mark given domain as crawled. Its shard is to fetch the robots.txt.
+    fn start_crawl(&self, host: &Domain, id: &CrawlId, states: &mut HashMap<Domain, CrawlState>) {
+        tracing::info!(crawl = %&**host, crawl_id = %id, "Crawl started");
+        states.insert(host.clone(), CrawlState::Running);
+        self.publish(CrawlEvent::state_changed(host, CrawlState::Running));
+    }
+
+    /// Record given URL as not fetched, e.g. when disallowed by robots.txt.
+    pub(crate) fn record_error(&self, url: Url, error: Error) {
+        let info = UrlInfo {
+            result: Err(error),
+            redirects: Vec::new(),
+            fetched_at: SystemTime::now(),
+            elapsed: Duration::ZERO,
+            discovery: Discovery::default(),
+        };
+        let crawler = self.clone();
+        tokio::task::spawn(async move {
+            crawler.send(Message::Processed(url, Box::new(info))).await;
+        });
+    }
+
+    /// Tell the crawler the crawl of given domain has nothing more to fetch.
+    pub(crate) fn crawl_finished(&self, domain: Domain) {
+        self.send_later(Message::CrawlFinished(domain));
+    }
+
+    /// Get the fetcher ready for the requests to given domain.
+    pub(crate) fn prefetch(&self, domain: &Domain) {
+        self.fetcher.prefetch(domain);
+    }
+
+    /// Fetch the robots.txt of given domain.
+    pub(crate) fn fetch_robots(
+        &self,
+        domain: Domain,
+        url: Url,
+        user_agent: &str,
+        crawl_id: &CrawlId,
+    ) {
+        let crawler = self.clone();
+        let cb_finish = |txt| async move {
+            crawler
+                .send(Message::RobotsFetched(domain, Box::new(txt)))
+                .await;
+        };
+        let fetcher = self.fetcher.clone();
+        fetch::spawn_robots(fetcher, url, user_agent.to_string(), &crawl_id.0, cb_finish);
+    }
+
+    /// Fetch given page
+    pub(crate) fn fetch(&self, url: Url, settings: &Settings, crawl_id: Option<&CrawlId>) {
+        let h_link = self.clone();
+        let parent = Arc::new(url.clone());
+        let cb_link = |mut urls: Vec<Url>| async move {
+            // The links are rewritten by the hook before they are looked up.
+            if h_link.on_discover.is_none() {
+                urls.retain(|url| !h_link.seen.contains(url));
+            }
+            if !urls.is_empty() {
+                h_link.send(Message::LinksFound(urls, Some(parent))).await;
+            }
+        };
+
+        let h_finish = self.clone();
+        let url_finish = url.clone();
+        let cb_finish = |r| async move {
+            h_finish
+                .send(Message::Processed(url_finish, Box::new(r)))
+                .await;
+        };
+
+        let crawl_id = crawl_id.map(|id| id.0.as_str());
+        let fetcher = self.fetcher.clone();
+        fetch::spawn(
+            &self.workers,
+            fetcher,
+            url,
+            settings.user_agent.clone(),
+            crawl_id,
+            settings.cross_host_redirects,
+            self.scope,
+            cb_link,
+            cb_finish,
+        );
+    }
+
+    /// Publish an event to all subscribers.
+    fn publish(&self, event: CrawlEvent) {
+        // Sending only fails if there are no subscribers, which is fine.
+        let _ = self.events.send(event);
+    }
+
+    /// Send a message to the crawler.
+    ///
+    /// Waits while the channel is full, which holds up the calling task only.
+    /// The fetch tasks find links this way, so that a crawler falling behind
+    /// slows the fetches down rather than buffering the links without bound.
+    async fn send(&self, msg: Message) {
+        let started = Instant::now();
+        // Only fails once the crawler is gone, the message is moot then.
+        let _ = self.channel.send(msg).await;
+        METRICS.record_send_wait(started.elapsed());
+    }
+
+    /// Send a message to the crawler from its own task without waiting.
+    ///
+    /// The crawler task must never wait for room in its own channel, which
+    /// only it makes.
+    fn send_later(&self, msg: Message) {
+        let crawler = self.clone();
+        tokio::task::spawn(async move { crawler.send(msg).await });
+    }
+
+    /// Send a message to the crawler and wait for reply.
+    async fn send_and_wait_reply<F, R>(&self, msg_func: F) -> Result<R, CroolrError>
+    where
+        F: FnOnce(oneshot::Sender<R>) -> Message,
+    {
+        let (sx, rx) = oneshot::channel();
+        self.channel
+            .send(msg_func(sx))
+            .await
+            .map_err(|_| CroolrError::CrawlerGone)?;
+        rx.await.map_err(|_| CroolrError::CrawlerGone)
+    }
+}
+
+/// Store the result of given URL, keeping the estimate of the memory taken
+/// by the results of its domain up to date.
+fn store_result(
+    data: &mut HashMap<Domain, Arc<UrlSet>>,
+    results_bytes: &mut HashMap<Domain, usize>,
+    domain: &Domain,
+    url: Arc<Url>,
+    info: UrlInfo,
+) {
+    let added = stats::result_bytes(&info);
+    let replaced = Arc::make_mut(data.entry(domain.clone()).or_default()).insert(url, info);
+    let bytes = results_bytes.entry(domain.clone()).or_default();
+    *bytes = (*bytes + added).saturating_sub(replaced.as_ref().map_or(0, stats::result_bytes));
+}
+
+fn url_from_host(host: &str) -> Result<Url, url::ParseError> {
+    let mut u = Url::parse("http://localhost").unwrap();
+    u.set_host(Some(host))?;
+    Ok(u)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::super::fetcher::{self, MockFetcher};
+    use super::super::robots::{Robots, RobotsFile};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_url_discovered() {
+        let crawler = Crawler::builder().build().unwrap();
+        let url = Url::parse("http://example.com/foo.png").unwrap();
+        let result = Err(Error::Fetch("test".to_string()));
+        let info = UrlInfo::from_result(result, std::time::Duration::from_millis(1));
+        crawler
+            .send(Message::Processed(url.clone(), Box::new(info)))
+            .await;
+        let ret = crawler
+            .list_urls("example.com".parse().unwrap(), ListOptions::default())
+            .await
+            .unwrap()
+            .expect("domain not present");
+        assert!(ret.total == 1, "Too many URLs present");
+        assert_eq!(*ret.urls[0].0, url);
+    }
+
+    #[tokio::test]
+    async fn test_fetched_event_published() {
+        let crawler = Crawler::builder().build().unwrap();
+        let mut events = crawler.subscribe();
+        let url = Url::parse("http://example.com/").unwrap();
+        let result = Err(Error::Fetch("test".to_string()));
+        let info = UrlInfo::from_result(result, std::time::Duration::from_millis(1));
+        crawler
+            .send(Message::Processed(url.clone(), Box::new(info)))
+            .await;
+        let event = events.recv().await.expect("no event published");
+        assert_eq!(event.name(), "fetched");
+        assert_eq!(event.domain(), Domain::from_url(&url));
+    }
+
+    #[tokio::test]
+    async fn test_crawl_control() {
+        let crawler = Crawler::builder().build().unwrap();
+        let domain: Domain = "example.invalid".parse().unwrap();
+        assert_eq!(
+            crawler.control(domain.clone(), Control::Pause).await,
+            Ok(None)
+        );
+        crawler.crawl(domain.clone()).await.unwrap();
+        let state = |c| crawler.control(domain.clone(), c);
+        assert_eq!(state(Control::Pause).await, Ok(Some(CrawlState::Paused)));
+        assert_eq!(state(Control::Resume).await, Ok(Some(CrawlState::Running)));
+        assert_eq!(state(Control::Stop).await, Ok(Some(CrawlState::Stopped)));
+        assert_eq!(state(Control::Resume).await, Ok(Some(CrawlState::Stopped)));
+        assert_eq!(crawler.queue(domain.clone()).await.unwrap().queued, 0);
+    }
+
+    #[tokio::test]
+    async fn test_domains() {
+        let crawler = Crawler::builder().build().unwrap();
+        let url = Url::parse("http://example.com/").unwrap();
+        let info = UrlInfo::from_result(Err(Error::Fetch("test".to_string())), Default::default());
+        crawler.send(Message::Processed(url, Box::new(info))).await;
+        crawler
+            .crawl("example.invalid".parse().unwrap())
+            .await
+            .unwrap();
+        let domains = crawler.domains().await.unwrap();
+        let names: Vec<&str> = domains.iter().map(|d| &*d.domain).collect();
+        assert_eq!(names, ["example.com", "example.invalid"]);
+        assert_eq!((domains[0].urls, domains[0].state), (1, None));
+        assert_eq!(domains[1].state, Some(CrawlState::Running));
+        assert!(domains[0].crawl_id.is_none());
+        assert!(domains[1].crawl_id.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_robots() {
+        let crawler = Crawler::builder().build().unwrap();
+        let mut events = crawler.subscribe();
+        let domain: Domain = "example.com".parse().unwrap();
+        assert_eq!(crawler.robots(domain.clone(), None).await.unwrap(), None);
+        let txt = RobotsTxt {
+            file: RobotsFile::Parsed(Robots::parse("User-agent: *\nDisallow: /private")),
+            fetched_at: SystemTime::now(),
+        };
+        crawler
+            .send(Message::RobotsFetched(domain.clone(), Box::new(txt)))
+            .await;
+        let path = Some("/private/x".to_string());
+        let report = crawler.robots(domain, path).await.unwrap().unwrap();
+        assert_eq!(report.status, "parsed");
+        assert!(!report.decision.unwrap().allowed);
+
+        // Disallowed URLs are recorded as failed without being fetched.
+        let url = Url::parse("http://example.com/private/x").unwrap();
+        crawler
+            .send(Message::LinksFound(vec![url.clone()], None))
+            .await;
+        while events.recv().await.unwrap().name() != "fetched" {}
+        let info = crawler.url_info(url).await.unwrap().unwrap();
+        assert!(
+            matches!(info.result, Err(Error::Disallowed(Some(ref r))) if r == "Disallow: /private")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_discovery_only() {
+        let settings = Settings {
+            discovery_only: true,
+            ..Settings::default()
+        };
+        let crawler = Crawler::builder().settings(settings).build().unwrap();
+        let mut events = crawler.subscribe();
+        let url = Url::parse("http://example.invalid/logo.png").unwrap();
+        crawler
+            .send(Message::LinksFound(vec![url.clone()], None))
+            .await;
+        while events.recv().await.unwrap().name() != "fetched" {}
+        let info = crawler.url_info(url).await.unwrap().unwrap();
+        assert!(matches!(info.result, Err(Error::NotFetched(ref r)) if r == "asset"));
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint() {
+        let crawler = Crawler::builder().build().unwrap();
+        let url = Url::parse("http://example.com/").unwrap();
+        let info = UrlInfo::from_result(Err(Error::Fetch("test".to_string())), Default::default());
+        crawler
+            .send(Message::Processed(url.clone(), Box::new(info)))
+            .await;
+        let checkpoint = crawler.checkpoint().await.unwrap();
+        assert!(checkpoint.domains.is_empty() && checkpoint.frontier.is_empty());
+        assert_eq!(checkpoint.fetched.len(), 1);
+
+        let restored = Crawler::builder().build().unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        let checkpoint = Checkpoint {
+            domains: vec![domain.clone()],
+            ..checkpoint
+        };
+        restored.restore(checkpoint).await.unwrap();
+        assert!(restored.url_info(url).await.unwrap().is_some());
+        let status = restored.queue(domain.clone()).await.unwrap();
+        assert_eq!(status.state, Some(CrawlState::Running));
+        // The seed has been fetched already.
+        assert_eq!(status.queued, 0);
+        assert!(matches!(
+            restored.crawl(domain).await,
+            Ok(CrawlReply::AlreadyCrawling)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_builder() {
+        let err = Crawler::builder().fetch_limit(0).build().err().unwrap();
+        assert!(matches!(err, CroolrError::InvalidConfig(_)));
+        assert!(Crawler::builder().user_agent("bad\nagent").build().is_err());
+        let leaves = vec!["product".to_string()];
+        assert!(Crawler::builder().discovery_only(leaves).build().is_err());
+        assert!(Crawler::builder().channel_capacity(0).build().is_err());
+
+        let crawler = Crawler::builder()
+            .fetch_limit(2)
+            .host_delay(Duration::from_millis(250))
+            .max_depth(0)
+            .build()
+            .unwrap();
+        let settings = crawler.settings().await.unwrap();
+        assert_eq!((settings.fetch_limit, settings.host_delay_ms), (2, 250));
+        let seed = Url::parse("http://example.invalid/").unwrap();
+        let link = Url::parse("http://example.invalid/deeper").unwrap();
+        crawler
+            .send(Message::LinksFound(vec![seed.clone()], None))
+            .await;
+        crawler
+            .send(Message::LinksFound(vec![link], Some(Arc::new(seed))))
+            .await;
+        assert_eq!(crawler.stats().await.unwrap().urls_seen, 1);
+    }
+
+    #[tokio::test]
+    async fn test_crawl_twice() {
+        let fetcher = MockFetcher::new()
+            .page("http://example.com/", r#"<a href="/a">A</a>"#)
+            .page("http://example.com/a", "");
+        let crawler = Crawler::builder().fetcher(fetcher).build().unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        let seed = Url::parse("http://example.com/a").unwrap();
+        let (first, second, other_seed) = tokio::join!(
+            crawler.crawl(domain.clone()),
+            crawler.crawl(domain.clone()),
+            crawler.crawl_seed(seed),
+        );
+        assert!(matches!(first, Ok(CrawlReply::Queued)));
+        assert!(matches!(second, Ok(CrawlReply::AlreadyCrawling)));
+        assert!(matches!(other_seed, Ok(CrawlReply::AlreadyCrawling)));
+        assert_eq!(crawler.wait(domain.clone()).await, Ok(true));
+        assert_eq!(crawler.count_urls(domain).await.unwrap(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_mock_crawl() {
+        let home = r#"<a href="/a">A</a> <a href="/old">Old</a> <a href="/logo.png">Logo</a>
+            <a href="/private/x">Private</a> <a href="/missing">Missing</a>"#;
+        let fetcher = MockFetcher::new()
+            .page("http://example.com/", home)
+            .page("http://example.com/a", r#"<a href="/">Home</a>"#)
+            .redirect("http://example.com/old", "http://example.com/a")
+            .document("http://example.com/logo.png", "image/png", "")
+            .document(
+                "http://example.com/robots.txt",
+                "text/plain",
+                "User-agent: *\nDisallow: /private",
+            );
+        let crawler = Crawler::builder().fetcher(fetcher).build().unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        assert_eq!(crawler.wait(domain.clone()).await, Ok(true));
+        assert_eq!(crawler.count_urls(domain).await.unwrap(), Some(6));
+
+        let info = |u: &str| crawler.url_info(Url::parse(u).unwrap());
+        let a = info("http://example.com/a").await.unwrap().unwrap();
+        assert!(a.result.is_ok());
+        let old = info("http://example.com/old").await.unwrap().unwrap();
+        assert_eq!(old.redirects, [Url::parse("http://example.com/a").unwrap()]);
+        let logo = info("http://example.com/logo.png").await.unwrap().unwrap();
+        assert!(matches!(logo.result, Err(Error::UnsupportedType(_))));
+        let private = info("http://example.com/private/x").await.unwrap().unwrap();
+        assert!(matches!(private.result, Err(Error::Disallowed(_))));
+        let missing = info("http://example.com/missing").await.unwrap().unwrap();
+        assert!(matches!(missing.result, Err(Error::Status(s)) if s.as_u16() == 404));
+    }
+
+    #[tokio::test]
+    async fn test_cross_host_redirects() {
+        let fetcher = MockFetcher::new()
+            .page(
+                "http://example.com/",
+                r#"<a href="/moved">Moved</a> <a href="/a">A</a>"#,
+            )
+            .page("http://example.com/a", "")
+            .redirect("http://example.com/moved", "http://www.example.com/new")
+            .page("http://www.example.com/new", r#"<a href="/b">B</a>"#)
+            .page("http://www.example.com/b", "");
+        let url = |u| Url::parse(u).unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        let www: Domain = "www.example.com".parse().unwrap();
+        for policy in [
+            RedirectPolicy::Follow,
+            RedirectPolicy::Record,
+            RedirectPolicy::Drop,
+        ] {
+            let crawler = Crawler::builder()
+                .cross_host_redirects(policy)
+                .fetcher(fetcher.clone())
+                .build()
+                .unwrap();
+            crawler.crawl(domain.clone()).await.unwrap();
+            assert_eq!(crawler.wait(domain.clone()).await, Ok(true));
+            let moved = crawler.url_info(url("http://example.com/moved")).await;
+            let moved = moved.unwrap().unwrap();
+            let new = crawler.url_info(url("http://www.example.com/new")).await;
+            let www_urls = crawler.count_urls(www.clone()).await.unwrap();
+            match policy {
+                // The page is attributed to its own host, where its links are
+                // followed.
+                RedirectPolicy::Follow => {
+                    assert!(moved.result.is_ok());
+                    let new = new.unwrap().unwrap();
+                    assert!(new.redirects.is_empty());
+                    assert_eq!(
+                        new.discovery.parent.as_deref(),
+                        Some(&url("http://example.com/moved"))
+                    );
+                    // The other host is not crawled itself, there is no
+                    // waiting for it to finish.
+                    let fetched = async {
+                        while crawler.count_urls(www.clone()).await.unwrap() != Some(2) {
+                            tokio::task::yield_now().await;
+                        }
+                    };
+                    tokio::time::timeout(Duration::from_secs(5), fetched)
+                        .await
+                        .unwrap();
+                }
+                RedirectPolicy::Record => {
+                    assert!(moved.result.is_ok());
+                    assert!(new.unwrap().is_none());
+                    assert_eq!(www_urls, None);
+                }
+                RedirectPolicy::Drop => {
+                    let reason = "redirected to www.example.com";
+                    assert!(matches!(moved.result, Err(Error::NotFetched(ref r)) if r == reason));
+                    assert_eq!(www_urls, None);
+                }
+            }
+            assert_eq!(crawler.count_urls(domain.clone()).await.unwrap(), Some(3));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_www() {
+        let fetcher = MockFetcher::new()
+            .page(
+                "http://example.com/",
+                r#"<a href="http://www.example.com/a">A</a> <a href="/moved">Moved</a>"#,
+            )
+            .page(
+                "http://www.example.com/a",
+                r#"<a href="http://example.com/">Home</a>"#,
+            )
+            .redirect("http://example.com/moved", "http://www.example.com/new")
+            .page("http://www.example.com/new", r#"<a href="/b">B</a>"#)
+            .page("http://www.example.com/b", "");
+        let url = |u| Url::parse(u).unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        let www: Domain = "www.example.com".parse().unwrap();
+        let crawler = Crawler::builder()
+            .merge_www(true)
+            .fetcher(fetcher)
+            .build()
+            .unwrap();
+        crawler.crawl(www.clone()).await.unwrap();
+        assert_eq!(crawler.wait(domain.clone()).await, Ok(true));
+        // Both hosts are one site, followed across and kept together.
+        assert_eq!(crawler.count_urls(domain.clone()).await.unwrap(), Some(4));
+        assert_eq!(crawler.count_urls(www).await.unwrap(), Some(4));
+        let domains = crawler.domains().await.unwrap();
+        assert_eq!(domains.len(), 1);
+        assert_eq!(domains[0].domain, domain);
+        let b = crawler.url_info(url("http://www.example.com/b")).await;
+        assert!(b.unwrap().unwrap().result.is_ok());
+        // The redirect within the site is not stored twice.
+        let new = crawler.url_info(url("http://www.example.com/new")).await;
+        assert!(new.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_session_params() {
+        let links: String = (1..=4)
+            .map(|i| format!(r#"<a href="/a?sid={}">A</a>"#, i))
+            .collect();
+        let mut fetcher = MockFetcher::new()
+            .page(
+                "http://example.com/",
+                &format!(r#"<a href="/z">Z</a>{}"#, links),
+            )
+            .page("http://example.com/z", r#"<a href="/y?sid=5">Y</a>"#)
+            .page("http://example.com/y", "");
+        for i in 1..=4 {
+            fetcher = fetcher.page(&format!("http://example.com/a?sid={}", i), "A");
+        }
+        let domain: Domain = "example.com".parse().unwrap();
+        let crawler = Crawler::builder()
+            .fetch_limit(1)
+            .fetcher(fetcher)
+            .build()
+            .unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        assert_eq!(crawler.wait(domain.clone()).await, Ok(true));
+        // The links found last are fetched first, /z after the /a pages have
+        // given the parameter away.
+        let info = |u: &str| crawler.url_info(Url::parse(u).unwrap());
+        assert!(info("http://example.com/y").await.unwrap().is_some());
+        assert!(info("http://example.com/y?sid=5").await.unwrap().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_data_version_queued() {
+        let fetcher = MockFetcher::new()
+            .page("http://example.com/", "")
+            .latency(Duration::from_secs(1));
+        let crawler = Crawler::builder().fetcher(fetcher).build().unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        let version = |d: &str| crawler.data_version(d.parse().unwrap());
+        assert_eq!(version("example.com").await, Ok(None));
+
+        // The crawl has a version before its first result, changed by it.
+        crawler.crawl(domain.clone()).await.unwrap();
+        let queued = version("example.com").await.unwrap().unwrap();
+        assert_eq!((queued.epoch, queued.seq), (0, 0));
+        assert_eq!(crawler.wait(domain).await, Ok(true));
+        let fetched = version("example.com").await.unwrap().unwrap();
+        assert_ne!(fetched.etag("json"), queued.etag("json"));
+        assert_eq!(version("example.org").await, Ok(None));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_active_domains() {
+        let fetcher = MockFetcher::new()
+            .page("http://a.example/", "")
+            .page("http://b.example/", "")
+            .latency(Duration::from_secs(1));
+        let crawler = Crawler::builder()
+            .max_active_domains(1)
+            .fetcher(fetcher)
+            .build()
+            .unwrap();
+        let domains: Vec<Domain> = ["a.example", "b.example", "c.example"]
+            .iter()
+            .map(|d| d.parse().unwrap())
+            .collect();
+        for domain in &domains {
+            crawler.crawl(domain.clone()).await.unwrap();
+        }
+        let state = |d: &Domain| crawler.queue(d.clone());
+        assert_eq!(
+            state(&domains[0]).await.unwrap().state,
+            Some(CrawlState::Running)
+        );
+        assert_eq!(
+            state(&domains[1]).await.unwrap().state,
+            Some(CrawlState::Pending)
+        );
+        assert_eq!(crawler.stats().await.unwrap().pending_crawls, 2);
+
+        // A pending crawl stays so when paused, and leaves the queue once stopped.
+        let control = |c| crawler.control(domains[2].clone(), c);
+        assert_eq!(control(Control::Pause).await, Ok(Some(CrawlState::Pending)));
+        assert_eq!(control(Control::Stop).await, Ok(Some(CrawlState::Stopped)));
+        assert_eq!(crawler.stats().await.unwrap().pending_crawls, 1);
+
+        // The second crawl starts once the first one is done.
+        assert_eq!(crawler.wait(domains[1].clone()).await, Ok(true));
+        assert_eq!(
+            state(&domains[1]).await.unwrap().state,
+            Some(CrawlState::Running)
+        );
+        let url = Url::parse("http://b.example/").unwrap();
+        assert!(crawler.url_info(url).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_discover_hook() {
+        let fetcher = MockFetcher::new()
+            .page(
+                "http://example.com/",
+                r#"<a href="/a?session=1">A</a> <a href="/logout">Log out</a>"#,
+            )
+            .page("http://example.com/a", "");
+        let crawler = Crawler::builder()
+            .fetcher(fetcher)
+            .on_discover(|mut url, _parent| {
+                url.set_query(None);
+                Some(url).filter(|u| u.path() != "/logout")
+            })
+            .build()
+            .unwrap();
+        let mut events = Box::pin(crawler.events());
+        let domain: Domain = "example.com".parse().unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        assert_eq!(crawler.wait(domain.clone()).await, Ok(true));
+        assert_eq!(crawler.count_urls(domain).await.unwrap(), Some(2));
+        let url = Url::parse("http://example.com/a").unwrap();
+        assert!(crawler.url_info(url).await.unwrap().unwrap().result.is_ok());
+
+        let mut discovered = Vec::new();
+        while let Some(event) = events.next().await {
+            match event {
+                CrawlEvent::Discovered { url, .. } => discovered.push(url.path().to_string()),
+                CrawlEvent::Fetched { .. } if discovered.len() == 2 => break,
+                _ => (),
+            }
+        }
+        assert_eq!(discovered, ["/", "/a"]);
+    }
+
+    #[tokio::test]
+    async fn test_crawl_stream() {
+        let fetcher = MockFetcher::new()
+            .page(
+                "http://example.com/",
+                r#"<a href="/a">A</a> <a href="/b">B</a>"#,
+            )
+            .page("http://example.com/a", "");
+        let crawler = Crawler::builder().fetcher(fetcher).build().unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        let results = crawler.crawl_stream(domain.clone()).await.unwrap();
+        let mut urls: Vec<_> = results
+            .map(|(url, _)| url.path().to_string())
+            .collect()
+            .await;
+        urls.sort();
+        assert_eq!(urls, ["/", "/a", "/b"]);
+
+        // The crawl has finished already.
+        let results = crawler.crawl_stream(domain).await.unwrap();
+        assert_eq!(results.collect::<Vec<_>>().await.len(), 0);
+    }
+
+    #[cfg(feature = "scripting")]
+    #[tokio::test]
+    async fn test_script() {
+        let fetcher = MockFetcher::new()
+            .page(
+                "http://example.com/",
+                r#"<title>Home</title> <a href="/a">A</a> <a href="/skip">Skip</a>"#,
+            )
+            .page("http://example.com/a", "<title>A</title>");
+        let script = Script::compile(
+            r#"
+            fn follow(url, parent) { !url.ends_with("/skip") }
+            fn extract(url, body) { #{ size: body.len() } }
+            "#,
+        )
+        .unwrap();
+        let crawler = Crawler::builder()
+            .fetcher(fetcher)
+            .script(script)
+            .build()
+            .unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        assert_eq!(crawler.wait(domain.clone()).await, Ok(true));
+        assert_eq!(crawler.count_urls(domain).await.unwrap(), Some(2));
+        let url = Url::parse("http://example.com/a").unwrap();
+        let info = crawler.url_info(url).await.unwrap().unwrap();
+        assert_eq!(info.result.unwrap().extra["size"], "16");
+
+        let settings = Settings {
+            script: Some("/nonexistent/script.rhai".into()),
+            ..Settings::default()
+        };
+        assert!(Crawler::builder().settings(settings).build().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wait() {
+        let crawler = Crawler::builder().build().unwrap();
+        let domain: Domain = "example.invalid".parse().unwrap();
+        assert_eq!(crawler.wait(domain.clone()).await, Ok(false));
+        crawler.crawl(domain.clone()).await.unwrap();
+        let wait = tokio::time::timeout(Duration::from_secs(30), crawler.wait(domain.clone()));
+        assert_eq!(wait.await, Ok(Ok(true)));
+        // The seed cannot be fetched, either due to robots.txt or on its own.
+        assert_eq!(crawler.count_urls(domain).await.unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let crawler = Crawler::builder().build().unwrap();
+        let url = Url::parse("http://example.com/").unwrap();
+        let info = UrlInfo::from_result(Err(Error::Fetch("test".to_string())), Default::default());
+        let processed = || Message::Processed(url.clone(), Box::new(info.clone()));
+        crawler.send(processed()).await;
+        let stats = crawler.stats().await.unwrap();
+        assert_eq!((stats.domains, stats.active_crawls), (1, 0));
+        assert_eq!((stats.urls_seen, stats.urls_fetched), (0, 1));
+        assert!(stats.memory.results_bytes > 0);
+        assert_eq!(stats.memory.total_bytes, stats.memory.results_bytes);
+        let domain: Domain = "example.com".parse().unwrap();
+        assert_eq!(stats.memory.domains[&domain], stats.memory.results_bytes);
+
+        // A result replaced takes no more memory.
+        crawler.send(processed()).await;
+        let again = crawler.stats().await.unwrap();
+        assert_eq!(again.memory.results_bytes, stats.memory.results_bytes);
+
+        crawler
+            .send(Message::LinksFound(vec![url.join("/a").unwrap()], None))
+            .await;
+        let stats = crawler.stats().await.unwrap();
+        assert_eq!(
+            stats.memory.seen_bytes,
+            stats::discovery_bytes(&url.join("/a").unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_settings() {
+        let crawler = Crawler::builder().build().unwrap();
+        let update = SettingsUpdate {
+            fetch_limit: Some(2),
+            ..Default::default()
+        };
+        let settings = crawler.update_settings(update).await.unwrap().unwrap();
+        assert_eq!(settings.fetch_limit, 2);
+        assert_eq!(crawler.settings().await, Ok(settings));
+        let update = SettingsUpdate {
+            fetch_limit: Some(0),
+            ..Default::default()
+        };
+        assert!(crawler.update_settings(update).await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_data_version() {
+        let crawler = Crawler::builder().build().unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        assert_eq!(crawler.data_version(domain.clone()).await, Ok(None));
+        let processed = |path| {
+            let url = Url::parse("http://example.com/")
+                .unwrap()
+                .join(path)
+                .unwrap();
+            let info =
+                UrlInfo::from_result(Err(Error::Fetch("test".to_string())), Default::default());
+            Message::Processed(url, Box::new(info))
+        };
+        crawler.send(processed("/a")).await;
+        let first = crawler.data_version(domain.clone()).await.unwrap().unwrap();
+        crawler.send(processed("/b")).await;
+        let second = crawler.data_version(domain.clone()).await.unwrap().unwrap();
+        assert_ne!(first.etag("json"), second.etag("json"));
+        assert_ne!(second.etag("json"), second.etag("csv"));
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let crawler = Crawler::builder().build().unwrap();
+        let mut events = crawler.subscribe();
+        assert_eq!(crawler.drain().await, Ok(0));
+        let event = events.recv().await.expect("no event published");
+        assert_eq!(event.name(), "shutting_down");
+
+        // Links found while draining are queued but not fetched.
+        let url = Url::parse("http://example.invalid/").unwrap();
+        crawler.send(Message::LinksFound(vec![url], None)).await;
+        assert_eq!(crawler.drain().await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let fetcher = MockFetcher::new().page("http://example.com/", "");
+        let crawler = Crawler::builder().fetcher(fetcher).build().unwrap();
+        let events = crawler.events();
+        let domain: Domain = "example.com".parse().unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        assert_eq!(crawler.shutdown().await, Ok(0));
+        crawler.join().await;
+        // The stream ends with the crawler.
+        let events: Vec<_> = events.map(|e| e.name()).collect().await;
+        assert_eq!(events.first(), Some(&"state_changed"));
+        assert_eq!(crawler.stats().await.err(), Some(CroolrError::CrawlerGone));
+        // Messages sent by finished fetches are dropped.
+        let url = Url::parse("http://example.com/a").unwrap();
+        crawler.send(Message::LinksFound(vec![url], None)).await;
+    }
+
+    /// Fetcher of the simulations, serving a mock site and recording when
+    /// each page request starts and ends.
+    struct Recorder {
+        site: MockFetcher,
+        start: Instant,
+        /// Requests as (path, start, end) in milliseconds since the start.
+        log: Arc<std::sync::Mutex<Vec<(String, u64, u64)>>>,
+    }
+
+    impl Fetcher for Recorder {
+        fn fetch<'a>(
+            &'a self,
+            request: &'a fetcher::Request,
+            redirects: &'a mut Vec<Url>,
+        ) -> futures::future::BoxFuture<'a, Result<fetcher::Response, Error>> {
+            Box::pin(async move {
+                let millis = || self.start.elapsed().as_millis() as u64;
+                let started = millis();
+                let result = self.site.fetch(request, redirects).await;
+                let entry = (request.url.path().to_string(), started, millis());
+                self.log.lock().unwrap().push(entry);
+                result
+            })
+        }
+
+        fn fetch_robots<'a>(
+            &'a self,
+            url: &'a Url,
+            user_agent: &'a str,
+        ) -> futures::future::BoxFuture<'a, RobotsFile> {
+            self.site.fetch_robots(url, user_agent)
+        }
+    }
+
+    /// A site on example.com made of pages at given paths, each linking to
+    /// the paths given with it.
+    fn sim_site(pages: &[(&str, &[&str])]) -> MockFetcher {
+        pages
+            .iter()
+            .fold(MockFetcher::new(), |site, (path, links)| {
+                let links: String = links
+                    .iter()
+                    .map(|l| format!("<a href=\"{}\">{}</a>", l, l))
+                    .collect();
+                site.page(&format!("http://example.com{}", path), &links)
+            })
+    }
+
+    /// Crawl example.com on given site in virtual time, which the calling
+    /// test must have paused, and return the page requests as (path, start,
+    /// end) in milliseconds, ordered by the start and path.
+    async fn simulate(builder: CrawlerBuilder, site: MockFetcher) -> Vec<(String, u64, u64)> {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = Recorder {
+            site,
+            start: Instant::now(),
+            log: log.clone(),
+        };
+        let crawler = builder.fetcher(recorder).build().unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        assert_eq!(crawler.wait(domain).await, Ok(true));
+        let mut log = log.lock().unwrap().clone();
+        log.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        log
+    }
+
+    fn sim_log(entries: &[(&str, u64, u64)]) -> Vec<(String, u64, u64)> {
+        entries
+            .iter()
+            .map(|(path, start, end)| (path.to_string(), *start, *end))
+            .collect()
+    }
+
+    const SIM_FAN_OUT: &[(&str, &[&str])] = &[
+        ("/", &["/a", "/b", "/c", "/d", "/e"]),
+        ("/a", &[]),
+        ("/b", &[]),
+        ("/c", &[]),
+        ("/d", &[]),
+        ("/e", &[]),
+    ];
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sim_fetch_cap() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = Recorder {
+            site: sim_site(SIM_FAN_OUT).latency(Duration::from_millis(100)),
+            start: Instant::now(),
+            log: log.clone(),
+        };
+        let crawler = Crawler::builder().fetcher(recorder).build().unwrap();
+        crawler.cap_fetch_limit(Some(1)).await.unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        // Lifting the cap lets the remaining fetches run at once.
+        crawler.cap_fetch_limit(None).await.unwrap();
+        assert_eq!(crawler.wait(domain).await, Ok(true));
+        let mut log = log.lock().unwrap().clone();
+        log.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        let expected = [
+            ("/", 0, 100),
+            ("/e", 100, 200),
+            ("/d", 200, 300),
+            ("/a", 250, 350),
+            ("/b", 250, 350),
+            ("/c", 250, 350),
+        ];
+        assert_eq!(log, sim_log(&expected));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sim_robots_with_seed() {
+        let robots = "http://example.com/robots.txt";
+        let site = sim_site(SIM_FAN_OUT)
+            .latency(Duration::from_millis(100))
+            .document(robots, "text/plain", "User-agent: *\nDisallow: /b")
+            .url_latency(robots, Duration::from_millis(300));
+        let log = simulate(Crawler::builder(), site).await;
+        // The seed is fetched along with the robots.txt, its links only once
+        // the file is in, leaving out the disallowed one.
+        let expected = [
+            ("/", 0, 100),
+            ("/a", 300, 400),
+            ("/c", 300, 400),
+            ("/d", 300, 400),
+            ("/e", 300, 400),
+        ];
+        assert_eq!(log, sim_log(&expected));
+
+        // The links of a disallowed seed are not followed.
+        let site = sim_site(SIM_FAN_OUT)
+            .document(robots, "text/plain", "User-agent: *\nDisallow: /")
+            .url_latency(robots, Duration::from_millis(300));
+        let log = simulate(Crawler::builder(), site).await;
+        assert_eq!(log, sim_log(&[("/", 0, 0)]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sim_fetch_limit() {
+        let site = sim_site(SIM_FAN_OUT).latency(Duration::from_millis(100));
+        let log = simulate(Crawler::builder().fetch_limit(2), site).await;
+        // Two at a time, the most recently discovered first.
+        let expected = [
+            ("/", 0, 100),
+            ("/d", 100, 200),
+            ("/e", 100, 200),
+            ("/b", 200, 300),
+            ("/c", 200, 300),
+            ("/a", 300, 400),
+        ];
+        assert_eq!(log, sim_log(&expected));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sim_host_delay() {
+        let site = sim_site(SIM_FAN_OUT)
+            .latency(Duration::from_millis(100))
+            .url_latency("http://example.com/e", Duration::from_millis(1000));
+        let builder = Crawler::builder()
+            .fetch_limit(4)
+            .host_delay(Duration::from_millis(250));
+        let log = simulate(builder, site).await;
+        // Fetches start the host delay apart, regardless of their latency.
+        let expected = [
+            ("/", 0, 100),
+            ("/e", 250, 1250),
+            ("/d", 500, 600),
+            ("/c", 750, 850),
+            ("/b", 1000, 1100),
+            ("/a", 1250, 1350),
+        ];
+        assert_eq!(log, sim_log(&expected));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sim_host_burst() {
+        let site = sim_site(SIM_FAN_OUT)
+            .latency(Duration::from_millis(100))
+            .url_latency("http://example.com/e", Duration::from_millis(1000));
+        let builder = Crawler::builder()
+            .fetch_limit(4)
+            .host_delay(Duration::from_millis(250))
+            .host_burst(3);
+        let log = simulate(builder, site).await;
+        // Up to 3 fetches start at once, then they are spaced again until the
+        // tokens are back.
+        let expected = [
+            ("/", 0, 100),
+            ("/d", 100, 200),
+            ("/e", 100, 1100),
+            ("/c", 250, 350),
+            ("/b", 500, 600),
+            ("/a", 750, 850),
+        ];
+        assert_eq!(log, sim_log(&expected));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sim_fairness() {
+        let site = sim_site(SIM_FAN_OUT)
+            .page(
+                "http://example.org/",
+                r#"<a href="/x">X</a> <a href="/y">Y</a>"#,
+            )
+            .page("http://example.org/x", "")
+            .page("http://example.org/y", "")
+            .latency(Duration::from_millis(100));
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = Recorder {
+            site,
+            start: Instant::now(),
+            log: log.clone(),
+        };
+        let crawler = Crawler::builder()
+            .fetch_limit(1)
+            .fetcher(recorder)
+            .build()
+            .unwrap();
+        let (com, org): (Domain, Domain) = (
+            "example.com".parse().unwrap(),
+            "example.org".parse().unwrap(),
+        );
+        crawler.crawl(com.clone()).await.unwrap();
+        crawler.crawl(org.clone()).await.unwrap();
+        assert_eq!(crawler.wait(com).await, Ok(true));
+        assert_eq!(crawler.wait(org).await, Ok(true));
+        // The domains take turns while both have URLs queued.
+        let paths: Vec<String> = log.lock().unwrap().iter().map(|e| e.0.clone()).collect();
+        assert_eq!(paths, ["/", "/", "/e", "/y", "/d", "/x", "/c", "/b", "/a"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sim_pause() {
+        let site = sim_site(SIM_FAN_OUT).latency(Duration::from_millis(100));
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = Recorder {
+            site,
+            start: Instant::now(),
+            log: log.clone(),
+        };
+        let crawler = Crawler::builder()
+            .fetch_limit(1)
+            .fetcher(recorder)
+            .build()
+            .unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        // Pause in the middle of the fetch of /d, which completes.
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        crawler
+            .control(domain.clone(), Control::Pause)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        crawler
+            .control(domain.clone(), Control::Resume)
+            .await
+            .unwrap();
+        assert_eq!(crawler.wait(domain).await, Ok(true));
+        let expected = [
+            ("/", 0, 100),
+            ("/e", 100, 200),
+            ("/d", 200, 300),
+            ("/c", 1250, 1350),
+            ("/b", 1350, 1450),
+            ("/a", 1450, 1550),
+        ];
+        assert_eq!(*log.lock().unwrap(), sim_log(&expected));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sim_channel_capacity() {
+        // The fetches wait for room in the channel without holding up the
+        // crawler.
+        let site = sim_site(SIM_FAN_OUT).latency(Duration::from_millis(100));
+        let log = simulate(Crawler::builder().channel_capacity(1), site).await;
+        let paths: Vec<&str> = log.iter().map(|e| e.0.as_str()).collect();
+        assert_eq!(paths, ["/", "/a", "/b", "/c", "/d", "/e"]);
+    }
+}
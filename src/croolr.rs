@@ -1,6 +0,0 @@
-//! Collect all the components.
-
-pub mod crawler;
-pub mod fetch;
-pub mod server;
-pub mod urlinfo;
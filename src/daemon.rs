@@ -0,0 +1,82 @@
+//! Running the server in the background on traditional init systems.
+
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::{
+    fs::{File, OpenOptions},
+    path::Path,
+};
+
+/// How to detach from the terminal.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// File to write the process ID of the daemon to.
+    pub pidfile: Option<PathBuf>,
+    /// File to append the standard output and error to, discarded if None.
+    pub log_file: Option<PathBuf>,
+}
+
+/// Fork into the background, detaching from the terminal.
+///
+/// Only the daemon returns from this, the original process exits. It must be
+/// called before any threads are started, i.e. before the async runtime. The
+/// working directory is kept so that relative paths given on the command line
+/// still work.
+#[cfg(unix)]
+pub fn daemonize(opts: &Options) -> Result<(), String> {
+    let output = |path: &Option<PathBuf>| -> Result<daemonize::Stdio, String> {
+        match path {
+            Some(path) => Ok(open_log(path)?.into()),
+            None => Ok(daemonize::Stdio::devnull()),
+        }
+    };
+    let cwd = std::env::current_dir().map_err(|e| e.to_string())?;
+    let mut daemon = daemonize::Daemonize::new()
+        .working_directory(cwd)
+        .stdout(output(&opts.log_file)?)
+        .stderr(output(&opts.log_file)?);
+    if let Some(pidfile) = &opts.pidfile {
+        daemon = daemon.pid_file(pidfile);
+    }
+    daemon.start().map_err(|e| e.to_string())
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_opts: &Options) -> Result<(), String> {
+    Err("not supported on this platform".to_string())
+}
+
+/// Remove the pidfile once the daemon is done.
+pub fn remove_pidfile(opts: &Options) {
+    if let Some(pidfile) = &opts.pidfile {
+        if let Err(e) = std::fs::remove_file(pidfile) {
+            tracing::warn!(error = %e, pidfile = %pidfile.display(), "Cannot remove pidfile");
+        }
+    }
+}
+
+#[cfg(unix)]
+fn open_log(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("cannot open {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_pidfile_removed() {
+        let pidfile = std::env::temp_dir().join(format!("croolr-test-{}.pid", std::process::id()));
+        std::fs::write(&pidfile, "1").unwrap();
+        let opts = Options {
+            pidfile: Some(pidfile.clone()),
+            log_file: None,
+        };
+        remove_pidfile(&opts);
+        assert!(!pidfile.exists());
+    }
+}
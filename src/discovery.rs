@@ -0,0 +1,128 @@
+//! Discovery-only crawls, enumerating the URL space of a site cheaply.
+//!
+//! Only the pages that may link further are fetched. Assets, told apart by the
+//! file extension in their URL, and leaf pages, matching one of the configured
+//! robots.txt style patterns, are recorded without being fetched.
+//!
+//! URLs looking like they change some state when requested, e.g. logging out
+//! or deleting something, are recorded without being fetched in every crawl,
+//! so that a crawl authenticated by the middleware does not trigger them.
+
+use super::robots::{path_of, pattern_matches};
+
+use url::Url;
+
+/// File extensions of URLs assumed not to be HTML pages.
+const ASSET_EXTENSIONS: &[&str] = &[
+    "7z", "avi", "bmp", "css", "csv", "doc", "docx", "eot", "gif", "gz", "ico", "jpeg", "jpg",
+    "js", "json", "m4a", "mov", "mp3", "mp4", "ogg", "otf", "pdf", "png", "ppt", "pptx", "rar",
+    "svg", "tar", "tgz", "tif", "tiff", "ttf", "txt", "wav", "webm", "webp", "woff", "woff2",
+    "xls", "xlsx", "xml", "zip",
+];
+
+/// Patterns of the URLs not fetched as they look like they change some state,
+/// unless configured otherwise. They are matched against the lower case path.
+pub const DEFAULT_UNSAFE_PATTERNS: &[&str] = &[
+    "*/logout",
+    "*/log-out",
+    "*/logoff",
+    "*/signout",
+    "*/sign-out",
+    "*/delete",
+    "*/remove",
+    "*/unsubscribe",
+    "*action=delete",
+    "*action=remove",
+    "*action=logout",
+];
+
+/// Check a leaf page pattern given on the command line.
+pub fn parse_pattern(pattern: &str) -> Result<String, String> {
+    match pattern.starts_with('/') || pattern.starts_with('*') {
+        true => Ok(pattern.to_string()),
+        false => Err("pattern must start with / or *".to_string()),
+    }
+}
+
+/// Why given URL is recorded without fetching it, None if it is to be fetched.
+pub fn skip_reason(url: &Url, leaf_patterns: &[String]) -> Option<String> {
+    if is_asset(url) {
+        return Some("asset".to_string());
+    }
+    let path = path_of(url);
+    leaf_patterns
+        .iter()
+        .find(|p| pattern_matches(p, &path))
+        .map(|p| format!("leaf page {}", p))
+}
+
+/// The pattern given URL matches if it looks like requesting it changes some
+/// state, None if it is safe to fetch.
+pub fn unsafe_pattern<'a>(url: &Url, unsafe_patterns: &'a [String]) -> Option<&'a str> {
+    let path = path_of(url).to_lowercase();
+    unsafe_patterns
+        .iter()
+        .find(|p| pattern_matches(p, &path))
+        .map(String::as_str)
+}
+
+fn is_asset(url: &Url) -> bool {
+    let name = url
+        .path_segments()
+        .and_then(|mut s| s.next_back())
+        .unwrap_or("");
+    match name.rsplit_once('.') {
+        Some((_, ext)) => ASSET_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_skip_reason() {
+        let skip = |u: &str| {
+            let leaves = ["/product/*".to_string(), "*.html$".to_string()];
+            skip_reason(&Url::parse(u).unwrap(), &leaves)
+        };
+        assert_eq!(skip("http://example.com/"), None);
+        assert_eq!(skip("http://example.com/blog/"), None);
+        assert_eq!(
+            skip("http://example.com/img/Logo.PNG"),
+            Some("asset".into())
+        );
+        assert_eq!(skip("http://example.com/app.js?v=2"), Some("asset".into()));
+        assert_eq!(
+            skip("http://example.com/product/42"),
+            Some("leaf page /product/*".into())
+        );
+        assert_eq!(
+            skip("http://example.com/about.html"),
+            Some("leaf page *.html$".into())
+        );
+        assert!(parse_pattern("product").is_err());
+    }
+
+    #[test]
+    fn unit_unsafe_pattern() {
+        let defaults: Vec<String> = DEFAULT_UNSAFE_PATTERNS
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let check = |u: &str| unsafe_pattern(&Url::parse(u).unwrap(), &defaults);
+        assert_eq!(check("http://example.com/"), None);
+        assert_eq!(check("http://example.com/Account/LogOut"), Some("*/logout"));
+        assert_eq!(
+            check("http://example.com/cart?id=3&action=remove"),
+            Some("*action=remove")
+        );
+        assert_eq!(check("http://example.com/posts/3/delete"), Some("*/delete"));
+        assert_eq!(check("http://example.com/undelete"), None);
+        assert_eq!(
+            unsafe_pattern(&Url::parse("http://example.com/logout").unwrap(), &[]),
+            None
+        );
+    }
+}
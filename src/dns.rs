@@ -0,0 +1,123 @@
+//! Host name resolution for the HTTP fetcher, with a short-lived cache that
+//! can be warmed up ahead of the requests.
+//!
+//! When the crawler takes on a new domain, e.g. one of many crawled at once,
+//! its name is looked up right away in the background. The requests to it
+//! then find the addresses in the cache, or wait for the lookup already in
+//! progress, instead of each starting their own.
+
+use super::metrics::METRICS;
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::hyper::client::connect::dns::Name;
+
+/// How long resolved addresses are reused.
+const TTL: Duration = Duration::from_secs(60);
+
+/// A lookup, possibly still in progress, shared by everyone asking for it.
+type Lookup = Shared<BoxFuture<'static, Result<Arc<[SocketAddr]>, String>>>;
+
+/// Failed lookup, told apart from the other errors of a request by its type.
+#[derive(Debug)]
+pub struct LookupError(String);
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+/// Resolver caching the addresses of the hosts for a while.
+#[derive(Default)]
+pub struct DnsCache {
+    /// Lookups by host name, along with the time they were started.
+    lookups: Mutex<HashMap<String, (Instant, Lookup)>>,
+}
+
+impl DnsCache {
+    pub fn new() -> Self {
+        DnsCache::default()
+    }
+
+    /// Start resolving given host name in the background, unless it has been
+    /// resolved recently.
+    pub fn prefetch(&self, host: &str) {
+        let (lookup, started) = self.lookup(host);
+        if started {
+            METRICS.dns_prefetches.inc();
+            tokio::task::spawn(lookup.map(drop));
+        }
+    }
+
+    /// The lookup of given host name and whether it has just been started,
+    /// because there was no usable one. Failed lookups are not reused.
+    fn lookup(&self, host: &str) -> (Lookup, bool) {
+        let mut lookups = self.lookups.lock().unwrap();
+        let now = Instant::now();
+        if let Some((started, lookup)) = lookups.get(host) {
+            let failed = matches!(lookup.peek(), Some(Err(_)));
+            if now.duration_since(*started) < TTL && !failed {
+                return (lookup.clone(), false);
+            }
+        }
+        lookups.retain(|_, (started, _)| now.duration_since(*started) < TTL);
+        METRICS.dns_lookups.inc();
+        let name = host.to_string();
+        let lookup = async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await
+                .map_err(|e| format!("cannot resolve {}: {}", name, e))?;
+            Ok(addrs.collect::<Vec<_>>().into())
+        }
+        .boxed()
+        .shared();
+        lookups.insert(host.to_string(), (now, lookup.clone()));
+        (lookup, true)
+    }
+}
+
+impl Resolve for DnsCache {
+    fn resolve(&self, name: Name) -> Resolving {
+        let (lookup, _) = self.lookup(name.as_str());
+        Box::pin(async move {
+            let addrs = lookup.await.map_err(LookupError)?;
+            Ok(Box::new((0..addrs.len()).map(move |i| addrs[i])) as Addrs)
+        })
+    }
+}
+
+impl fmt::Debug for DnsCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hosts = self.lookups.lock().unwrap().len();
+        f.debug_struct("DnsCache").field("hosts", &hosts).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dns_cache() {
+        let cache = DnsCache::new();
+        cache.prefetch("localhost");
+        // The prefetched lookup is reused rather than started again.
+        let (lookup, started) = cache.lookup("localhost");
+        assert!(!started);
+        let addrs = lookup.await.unwrap();
+        assert!(addrs.iter().all(|a| a.ip().is_loopback()));
+
+        let missing = cache.lookup("nonexistent.invalid").0.await;
+        assert!(missing.is_err());
+        // Failures are looked up again.
+        assert!(cache.lookup("nonexistent.invalid").1);
+    }
+}
@@ -0,0 +1,12 @@
+//! Errors reported by the crawler library.
+
+/// Error returned by the public crawler methods.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CroolrError {
+    /// The crawler task is no longer running.
+    #[error("the crawler is not running")]
+    CrawlerGone,
+    /// The crawler configuration is invalid.
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+}
@@ -0,0 +1,77 @@
+//! Events published by the crawler as it makes progress.
+
+use super::crawler::CrawlState;
+use super::urlinfo::*;
+
+use serde::Serialize;
+use url::Url;
+
+/// Something notable that happened during crawling.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum CrawlEvent {
+    /// A new URL has been discovered and scheduled for fetching.
+    Discovered {
+        url: Url,
+        parent: Option<Url>,
+        depth: u32,
+    },
+    /// A URL has been fetched and processed.
+    Fetched {
+        url: Url,
+        status: Option<u16>,
+        error: Option<String>,
+        elapsed_ms: f64,
+    },
+    /// The state of the crawl of a domain has changed.
+    StateChanged { domain: Domain, state: CrawlState },
+    /// The crawler is shutting down, no more events will follow.
+    ShuttingDown,
+}
+
+impl CrawlEvent {
+    pub fn discovered(url: &Url, discovery: &Discovery) -> Self {
+        CrawlEvent::Discovered {
+            url: url.clone(),
+            parent: discovery.parent.as_deref().cloned(),
+            depth: discovery.depth,
+        }
+    }
+
+    pub fn fetched(url: &Url, info: &UrlInfo) -> Self {
+        CrawlEvent::Fetched {
+            url: url.clone(),
+            status: info.status().map(|s| s.as_u16()),
+            error: info.result.as_ref().err().map(|e| e.to_string()),
+            elapsed_ms: info.elapsed.as_secs_f64() * 1000.0,
+        }
+    }
+
+    pub fn state_changed(domain: &Domain, state: CrawlState) -> Self {
+        CrawlEvent::StateChanged {
+            domain: domain.clone(),
+            state,
+        }
+    }
+
+    /// Short name of the event kind.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CrawlEvent::Discovered { .. } => "discovered",
+            CrawlEvent::Fetched { .. } => "fetched",
+            CrawlEvent::StateChanged { .. } => "state_changed",
+            CrawlEvent::ShuttingDown => "shutting_down",
+        }
+    }
+
+    /// The domain the event is about.
+    pub fn domain(&self) -> Option<Domain> {
+        match self {
+            CrawlEvent::Discovered { url, .. } | CrawlEvent::Fetched { url, .. } => {
+                Domain::from_url(url)
+            }
+            CrawlEvent::StateChanged { domain, .. } => Some(domain.clone()),
+            CrawlEvent::ShuttingDown => None,
+        }
+    }
+}
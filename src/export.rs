@@ -0,0 +1,305 @@
+//! Alternative representations of URL listings.
+
+use super::listing::Listing;
+use super::urlinfo::*;
+
+use futures::stream;
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use url::Url;
+use warp::hyper::Body;
+
+/// Number of URLs rendered in one piece of a streamed listing.
+const CHUNK_SIZE: usize = 1000;
+
+/// Representation of a URL listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// A single JSON document, the default.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+    /// Comma-separated values with a header line.
+    Csv,
+    /// Just the URLs, one per line.
+    Text,
+}
+
+impl Format {
+    /// Pick the format based on the Accept header.
+    ///
+    /// The first media type with a matching format wins, JSON is the default.
+    pub fn from_accept(accept: &str) -> Format {
+        accept
+            .split(',')
+            .filter_map(|item| {
+                let media_type = item.split(';').next()?.trim();
+                match media_type.to_ascii_lowercase().as_str() {
+                    "application/json" => Some(Format::Json),
+                    "application/x-ndjson" | "application/ndjson" => Some(Format::Ndjson),
+                    "text/csv" => Some(Format::Csv),
+                    "text/plain" => Some(Format::Text),
+                    _ => None,
+                }
+            })
+            .next()
+            .unwrap_or(Format::Json)
+    }
+
+    /// Name of the format as used in the `format` parameter.
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Ndjson => "ndjson",
+            Format::Csv => "csv",
+            Format::Text => "text",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Ndjson => "application/x-ndjson",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Text => "text/plain; charset=utf-8",
+        }
+    }
+
+    /// Render given listing as a response body.
+    ///
+    /// Only the JSON format includes the listing total, the other formats
+    /// contain just the URLs. These are serialized and streamed in chunks, so
+    /// that the whole document is never held in memory at once.
+    pub fn body(self, listing: Listing) -> Body {
+        Body::wrap_stream(stream::iter(self.chunks(listing).map(Ok::<_, Infallible>)))
+    }
+
+    /// Write given listing out, e.g. to the standard output.
+    pub fn write(self, listing: Listing, out: &mut impl io::Write) -> io::Result<()> {
+        self.chunks(listing)
+            .try_for_each(|chunk| out.write_all(&chunk))?;
+        match self {
+            Format::Json => writeln!(out),
+            _ => Ok(()),
+        }
+    }
+
+    /// Pieces of the rendered listing.
+    ///
+    /// The URLs are moved out of the listing a chunk at a time, so they are
+    /// freed as soon as they have been rendered.
+    fn chunks(self, listing: Listing) -> impl Iterator<Item = Vec<u8>> {
+        let (header, footer) = match self {
+            Format::Json => (
+                format!(
+                    "{{\"total\":{},\"offset\":{},\"urls\":{{",
+                    listing.total, listing.offset
+                ),
+                "}}",
+            ),
+            Format::Csv => (CSV_HEADER.to_string(), ""),
+            Format::Ndjson | Format::Text => (String::new(), ""),
+        };
+        let mut urls = listing.urls.into_iter();
+        let batches = std::iter::from_fn(move || {
+            let batch: Vec<_> = urls.by_ref().take(CHUNK_SIZE).collect();
+            (!batch.is_empty()).then_some(batch)
+        });
+        std::iter::once(header.into_bytes())
+            .chain(
+                batches
+                    .enumerate()
+                    .map(move |(i, batch)| self.render_rows(&batch, i == 0)),
+            )
+            .chain(std::iter::once(footer.as_bytes().to_vec()))
+            .filter(|chunk| !chunk.is_empty())
+    }
+
+    /// Render listed URLs, one per line, or as entries of the JSON map.
+    fn render_rows(self, urls: &[(Arc<Url>, UrlInfo)], first: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (i, (url, info)) in urls.iter().enumerate() {
+            match self {
+                Format::Json => {
+                    if i > 0 || !first {
+                        out.push(b',');
+                    }
+                    serde_json::to_writer(&mut out, url.as_str())
+                        .expect("URL serialization failed");
+                    out.push(b':');
+                    let record = UrlRecord::new(url, info);
+                    serde_json::to_writer(&mut out, &record).expect("record serialization failed");
+                }
+                Format::Ndjson => {
+                    let record = UrlRecord::new(url, info);
+                    serde_json::to_writer(&mut out, &record).expect("record serialization failed");
+                    out.push(b'\n');
+                }
+                Format::Csv => write_csv(&UrlRecord::new(url, info), &mut out),
+                Format::Text => {
+                    out.extend_from_slice(url.as_str().as_bytes());
+                    out.push(b'\n');
+                }
+            }
+        }
+        out
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Format::Json, Format::Ndjson, Format::Csv, Format::Text]
+            .iter()
+            .copied()
+            .find(|f| f.name() == s)
+            .ok_or_else(|| format!("unknown format: {}", s))
+    }
+}
+
+const CSV_HEADER: &str = "url,status,error,content_type,size,elapsed_ms\n";
+
+/// Write the main fields of given record as a CSV line.
+fn write_csv(record: &UrlRecord, out: &mut Vec<u8>) {
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    let fields = [
+        record.url.to_string(),
+        opt(record.status.map(|s| s.to_string())),
+        opt(record.error.clone()),
+        opt(record.content_type.clone()),
+        opt(record.size.map(|s| s.to_string())),
+        format!("{:.3}", record.elapsed_ms),
+    ];
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        out.extend_from_slice(csv_field(field).as_bytes());
+    }
+    out.push(b'\n');
+}
+
+/// Quote a CSV field if needed.
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn listing() -> Listing {
+        let page = Page {
+            size: 10,
+            ..Page::default()
+        };
+        let url = |p| Url::parse("http://example.com/").unwrap().join(p).unwrap();
+        let info = |r| UrlInfo::from_result(r, Duration::from_millis(2));
+        Listing {
+            total: 2,
+            offset: 0,
+            urls: vec![
+                (Arc::new(url("/a")), info(Ok(page))),
+                (
+                    Arc::new(url("/b,c")),
+                    info(Err(Error::Fetch("x \"y\"".into()))),
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn unit_format_from_accept() {
+        assert_eq!(Format::from_accept("*/*"), Format::Json);
+        assert_eq!(Format::from_accept("text/csv"), Format::Csv);
+        assert_eq!(
+            Format::from_accept("text/html, text/plain;q=0.9, */*"),
+            Format::Text
+        );
+        assert_eq!(Format::from_accept("application/x-ndjson"), Format::Ndjson);
+    }
+
+    fn render(format: Format, listing: &Listing) -> String {
+        let bytes: Vec<u8> = format.chunks(listing.clone()).flatten().collect();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn unit_format_render() {
+        let listing = listing();
+        assert_eq!(
+            render(Format::Json, &listing),
+            serde_json::to_string(&listing).unwrap()
+        );
+        assert_eq!(
+            render(Format::Json, &Listing::default()),
+            r#"{"total":0,"offset":0,"urls":{}}"#
+        );
+        assert_eq!(
+            render(Format::Text, &listing),
+            "http://example.com/a\nhttp://example.com/b,c\n"
+        );
+        assert_eq!(
+            render(Format::Csv, &listing),
+            "url,status,error,content_type,size,elapsed_ms\n\
+             http://example.com/a,200,,text/html,10,2.000\n\
+             \"http://example.com/b,c\",,\"fetch failed: x \"\"y\"\"\",,,2.000\n"
+        );
+        let ndjson = render(Format::Ndjson, &listing);
+        let lines: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["status"], 200);
+        assert_eq!(lines[1]["url"], "http://example.com/b,c");
+        let record: UrlRecord = serde_json::from_value(lines[0].clone()).unwrap();
+        assert_eq!(
+            record,
+            UrlRecord::new(&listing.urls[0].0, &listing.urls[0].1)
+        );
+    }
+
+    #[test]
+    fn unit_format_write() {
+        let listing = listing();
+        let mut out = Vec::new();
+        Format::Json.write(listing.clone(), &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["total"], 2);
+        let record: UrlRecord =
+            serde_json::from_value(json["urls"]["http://example.com/a"].clone()).unwrap();
+        assert_eq!(
+            record,
+            UrlRecord::new(&listing.urls[0].0, &listing.urls[0].1)
+        );
+        let mut out = Vec::new();
+        Format::Text.write(listing.clone(), &mut out).unwrap();
+        assert_eq!(out, render(Format::Text, &listing).as_bytes());
+        assert_eq!("csv".parse(), Ok(Format::Csv));
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn unit_format_chunks() {
+        let mut listing = listing();
+        let entry = listing.urls[0].clone();
+        listing.urls = vec![entry; CHUNK_SIZE + 1];
+        let json = render(Format::Json, &listing);
+        assert_eq!(json, serde_json::to_string(&listing).unwrap());
+        let chunks: Vec<Vec<u8>> = Format::Csv.chunks(listing).collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0], CSV_HEADER.as_bytes());
+        assert_eq!(chunks[1].split(|&b| b == b'\n').count(), CHUNK_SIZE + 1);
+        assert_eq!(chunks[2].split(|&b| b == b'\n').count(), 2);
+    }
+}
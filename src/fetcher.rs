@@ -0,0 +1,606 @@
+//! Backends doing the requests for the crawler.
+//!
+//! The crawler takes care of scheduling, link extraction and bookkeeping, a
+//! [`Fetcher`] only gets the documents. [`HttpFetcher`] gets them from the web,
+//! [`MockFetcher`] serves a site held in memory, which makes crawls
+//! deterministic and independent of the network, e.g. in tests. With the
+//! `headless` feature, `HeadlessFetcher` renders the pages got by another
+//! fetcher in a headless browser.
+
+use super::dns::{DnsCache, LookupError};
+use super::metrics::METRICS;
+use super::robots::{Robots, RobotsFile};
+use super::settings::Settings;
+use super::urlinfo::{Error, Page};
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
+use warp::hyper::client::connect::HttpInfo;
+
+/// Max number of redirects followed for a single URL.
+const MAX_REDIRECTS: usize = 10;
+
+/// Max time to wait for a robots.txt file.
+const ROBOTS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time to wait for the response to a link check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A page request about to be sent.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub url: Url,
+    pub user_agent: String,
+    /// Additional request headers, sent to each redirect location as well.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub fn new(url: Url, user_agent: &str) -> Self {
+        Request {
+            url,
+            user_agent: user_agent.to_string(),
+            headers: Vec::new(),
+        }
+    }
+}
+
+/// An HTML page as received, before the links are extracted from it.
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// The URL the page has been received from, after the redirects.
+    pub url: Url,
+    /// Details of the page, the links and other parts of the content are
+    /// filled in by the crawler.
+    pub page: Page,
+    /// The body as received, shared by everything processing it rather than
+    /// copied. Invalid UTF-8 is replaced when it is parsed.
+    pub body: Bytes,
+    /// Links to follow in addition to those in the body, e.g. found by
+    /// middleware. Relative ones are resolved against the URL.
+    pub links: Vec<String>,
+}
+
+/// Backend fetching pages and robots.txt files.
+pub trait Fetcher: Send + Sync + 'static {
+    /// Send given request.
+    ///
+    /// Redirects are followed, recording each location in `redirects`.
+    /// Responses other than successfully received HTML pages are errors.
+    fn fetch<'a>(
+        &'a self,
+        request: &'a Request,
+        redirects: &'a mut Vec<Url>,
+    ) -> BoxFuture<'a, Result<Response, Error>>;
+
+    /// Fetch the robots.txt file of the site of given URL.
+    ///
+    /// Files that cannot be fetched are reported as unavailable.
+    fn fetch_robots<'a>(&'a self, url: &'a Url, user_agent: &'a str) -> BoxFuture<'a, RobotsFile>;
+
+    /// Check whether given link to another site works, returning the status
+    /// of the response. The page is fetched by default, a response of other
+    /// type than HTML taken as working.
+    fn check<'a>(
+        &'a self,
+        url: &'a Url,
+        user_agent: &'a str,
+    ) -> BoxFuture<'a, Result<reqwest::StatusCode, Error>> {
+        Box::pin(async move {
+            let request = Request::new(url.clone(), user_agent);
+            match self.fetch(&request, &mut Vec::new()).await {
+                Ok(response) => Ok(response.page.status),
+                Err(Error::Status(status)) => Ok(status),
+                Err(Error::UnsupportedType(_)) => Ok(reqwest::StatusCode::OK),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    /// Get ready for requests to given host, e.g. by resolving its name.
+    /// Does nothing by default.
+    fn prefetch(&self, _host: &str) {}
+}
+
+/// Fetcher getting the documents from the web.
+///
+/// All requests go through one client, so connections to a site are kept
+/// open and reused by the following requests instead of being set up anew
+/// for every page. Host names are resolved through a [`DnsCache`], which the
+/// crawler warms up for the domains it takes on. Clones share the client, its
+/// connection pool and the cache.
+#[derive(Debug, Clone)]
+pub struct HttpFetcher {
+    client: reqwest::Client,
+    dns: Arc<DnsCache>,
+    connections: Arc<Connections>,
+}
+
+impl HttpFetcher {
+    /// Fetcher keeping up to given number of idle connections to each host,
+    /// which should match the number of concurrent requests.
+    ///
+    /// With zero, connections are closed after each request.
+    pub fn new(pool_size: usize) -> Self {
+        HttpFetcher::with_pool(pool_size, Settings::default().pool_idle_timeout())
+    }
+
+    /// Fetcher keeping up to given number of idle connections to each host
+    /// open for given time.
+    pub fn with_pool(pool_size: usize, idle_timeout: Duration) -> Self {
+        let dns = Arc::new(DnsCache::new());
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .pool_max_idle_per_host(pool_size)
+            .pool_idle_timeout(idle_timeout)
+            .dns_resolver(dns.clone())
+            .build()
+            .expect("HTTP client configuration is valid");
+        let connections = Arc::new(Connections {
+            last_used: Mutex::new(HashMap::new()),
+            idle_timeout,
+        });
+        HttpFetcher {
+            client,
+            dns,
+            connections,
+        }
+    }
+}
+
+/// The connections used by the requests, telling the new ones from those
+/// kept open by the pool for the metrics.
+///
+/// Connections are identified by their local and remote address. The pool
+/// closes those idle for longer than the timeout, so a connection not used
+/// since then is a new one, even if the operating system reused the port.
+#[derive(Debug)]
+struct Connections {
+    last_used: Mutex<HashMap<(SocketAddr, SocketAddr), Instant>>,
+    idle_timeout: Duration,
+}
+
+impl Connections {
+    /// Account for given response, received over a new or a reused connection.
+    fn record(&self, resp: &reqwest::Response) {
+        let info = match resp.extensions().get::<HttpInfo>() {
+            Some(info) => info,
+            None => return,
+        };
+        let now = Instant::now();
+        let mut last_used = self.last_used.lock().unwrap();
+        match last_used.insert((info.local_addr(), info.remote_addr()), now) {
+            Some(last) if now.duration_since(last) < self.idle_timeout => {
+                METRICS.connections_reused.inc()
+            }
+            _ => {
+                METRICS.connections_opened.inc();
+                if resp.url().scheme() == "https" {
+                    METRICS.tls_handshakes.inc();
+                }
+                let timeout = self.idle_timeout;
+                last_used.retain(|_, last| now.duration_since(*last) < timeout);
+            }
+        }
+    }
+}
+
+impl Default for HttpFetcher {
+    fn default() -> Self {
+        HttpFetcher::new(Settings::default().fetch_limit as usize)
+    }
+}
+
+impl Fetcher for HttpFetcher {
+    fn fetch<'a>(
+        &'a self,
+        request: &'a Request,
+        redirects: &'a mut Vec<Url>,
+    ) -> BoxFuture<'a, Result<Response, Error>> {
+        Box::pin(fetch_url(self, request, redirects))
+    }
+
+    fn fetch_robots<'a>(&'a self, url: &'a Url, user_agent: &'a str) -> BoxFuture<'a, RobotsFile> {
+        Box::pin(async move {
+            let request = Request::new(robots_url(url), user_agent);
+            let result = async {
+                let get = reqwest::Method::GET;
+                let timeout = Some(ROBOTS_TIMEOUT);
+                let (_, resp) = send(self, &request, get, timeout, &mut Vec::new()).await?;
+                let status = resp.status();
+                let body = match status.is_success() {
+                    true => Some(resp.text().await.map_err(fetch_error)?),
+                    false => None,
+                };
+                Ok::<_, Error>((status, body))
+            };
+            match result.await {
+                Ok((_, Some(body))) => RobotsFile::Parsed(Robots::parse(&body)),
+                Ok((status, _)) if status.is_client_error() => RobotsFile::Missing(status.as_u16()),
+                Ok((status, _)) => {
+                    RobotsFile::Unavailable(format!("unexpected response status: {}", status))
+                }
+                Err(Error::Status(status)) => {
+                    RobotsFile::Unavailable(format!("unexpected response status: {}", status))
+                }
+                Err(e) => RobotsFile::Unavailable(e.to_string()),
+            }
+        })
+    }
+
+    /// Send a HEAD request, or a GET one if the server does not take HEAD,
+    /// without reading the body.
+    fn check<'a>(
+        &'a self,
+        url: &'a Url,
+        user_agent: &'a str,
+    ) -> BoxFuture<'a, Result<reqwest::StatusCode, Error>> {
+        Box::pin(async move {
+            let request = Request::new(url.clone(), user_agent);
+            let (timeout, mut redirects) = (Some(CHECK_TIMEOUT), Vec::new());
+            let head = send(
+                self,
+                &request,
+                reqwest::Method::HEAD,
+                timeout,
+                &mut redirects,
+            );
+            let status = head.await?.1.status();
+            if status != reqwest::StatusCode::METHOD_NOT_ALLOWED
+                && status != reqwest::StatusCode::NOT_IMPLEMENTED
+            {
+                return Ok(status);
+            }
+            redirects.clear();
+            let get = send(
+                self,
+                &request,
+                reqwest::Method::GET,
+                timeout,
+                &mut redirects,
+            );
+            Ok(get.await?.1.status())
+        })
+    }
+
+    fn prefetch(&self, host: &str) {
+        self.dns.prefetch(host);
+    }
+}
+
+/// Send given request with given method, following the redirects and recording
+/// each location in `redirects`. Returns the final URL and its response.
+async fn send(
+    fetcher: &HttpFetcher,
+    request: &Request,
+    method: reqwest::Method,
+    timeout: Option<Duration>,
+    redirects: &mut Vec<Url>,
+) -> Result<(Url, reqwest::Response), Error> {
+    let mut url = request.url.clone();
+    loop {
+        let mut req = request
+            .headers
+            .iter()
+            .fold(
+                fetcher.client.request(method.clone(), url.clone()),
+                |req, (name, value)| req.header(name, value),
+            )
+            .header(reqwest::header::USER_AGENT, &request.user_agent);
+        if let Some(timeout) = timeout {
+            req = req.timeout(timeout);
+        }
+        let resp = req.send().await.map_err(fetch_error)?;
+        fetcher.connections.record(&resp);
+        if !resp.status().is_redirection() {
+            return Ok((url, resp));
+        }
+        let location = resp
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .and_then(|l| url.join(l).ok())
+            .ok_or(Error::Status(resp.status()))?;
+        if redirects.len() >= MAX_REDIRECTS {
+            return Err(Error::Fetch("too many redirects".to_string()));
+        }
+        redirects.push(location.clone());
+        url = location;
+    }
+}
+
+/// The error of a failed request, telling DNS failures, timeouts and TLS
+/// failures apart from the other ones by the errors it has been caused by.
+fn fetch_error(e: reqwest::Error) -> Error {
+    let causes = || std::iter::successors(std::error::Error::source(&e), |c| c.source());
+    let msg = e.to_string();
+    if e.is_timeout() {
+        Error::Timeout(msg)
+    } else if causes().any(|c| c.is::<LookupError>()) {
+        Error::Dns(msg)
+    } else if causes().any(is_tls_error) {
+        Error::Tls(msg)
+    } else {
+        Error::Fetch(msg)
+    }
+}
+
+/// Whether given cause of a failed request comes from the TLS handshake. The
+/// errors of the TLS library are not exposed, so they are known by messages.
+fn is_tls_error(cause: &(dyn std::error::Error + 'static)) -> bool {
+    let msg = cause.to_string().to_lowercase();
+    ["certificate", "ssl", "tls", "handshake"]
+        .iter()
+        .any(|word| msg.contains(word))
+}
+
+/// Fetch given URL and return its text if successful and all additional
+/// conditions have been satisfied.
+///
+/// Redirects are followed manually so that the chain can be recorded in
+/// the redirects argument.
+async fn fetch_url(
+    fetcher: &HttpFetcher,
+    request: &Request,
+    redirects: &mut Vec<Url>,
+) -> Result<Response, Error> {
+    let (url, resp) = send(fetcher, request, reqwest::Method::GET, None, redirects).await?;
+
+    // Check response status.
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(Error::Status(status));
+    }
+
+    // Check content type is html before proceeding.
+    let unsupported_type = |t: &str| Error::UnsupportedType(t.to_string());
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .ok_or_else(|| unsupported_type("unknown"))?
+        .to_str()
+        .map_err(|_| unsupported_type("unparsable"))?;
+    if !content_type.contains("html") {
+        return Err(unsupported_type(content_type));
+    }
+    let content_type = content_type.to_string();
+    let headers = resp
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    // Extract the page content.
+    let body = resp.bytes().await.map_err(fetch_error)?;
+    let page = Page {
+        status,
+        content_type,
+        size: body.len(),
+        headers,
+        ..Page::default()
+    };
+    Ok(Response {
+        url,
+        page,
+        body,
+        links: Vec::new(),
+    })
+}
+
+/// The robots.txt file of the site of given URL.
+fn robots_url(url: &Url) -> Url {
+    let mut url = url.clone();
+    url.set_path("/robots.txt");
+    url.set_query(None);
+    url
+}
+
+/// A document served by the [`MockFetcher`].
+#[derive(Debug, Clone)]
+enum Document {
+    /// A resource with given content type and body.
+    Body(String, String),
+    /// A redirect to given URL.
+    Redirect(Url),
+    /// An error response with given status.
+    Status(reqwest::StatusCode),
+}
+
+/// Fetcher serving a site held in memory.
+///
+/// URLs not added to it are not found (404), and so are the robots.txt files
+/// unless they are added as plain text documents. Responses take no time
+/// unless a latency is set, which is measured by the tokio clock, so that
+/// crawls with the time paused (`tokio::time::pause`) run in virtual time.
+#[derive(Debug, Clone, Default)]
+pub struct MockFetcher {
+    documents: HashMap<Url, Document>,
+    latency: Duration,
+    latencies: HashMap<Url, Duration>,
+}
+
+impl MockFetcher {
+    pub fn new() -> Self {
+        MockFetcher::default()
+    }
+
+    /// Serve an HTML page with given body at given URL.
+    pub fn page(self, url: &str, html: &str) -> Self {
+        self.document(url, "text/html", html)
+    }
+
+    /// Serve a document of given content type at given URL.
+    pub fn document(mut self, url: &str, content_type: &str, body: &str) -> Self {
+        let doc = Document::Body(content_type.to_string(), body.to_string());
+        self.documents.insert(parse(url), doc);
+        self
+    }
+
+    /// Redirect from given URL to another one.
+    pub fn redirect(mut self, from: &str, to: &str) -> Self {
+        self.documents
+            .insert(parse(from), Document::Redirect(parse(to)));
+        self
+    }
+
+    /// Respond to given URL with given error status.
+    pub fn status(mut self, url: &str, status: u16) -> Self {
+        let status = reqwest::StatusCode::from_u16(status).expect("valid status code");
+        self.documents.insert(parse(url), Document::Status(status));
+        self
+    }
+
+    /// Take given time to respond to each page request.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Take given time to respond to the requests for given URL, including
+    /// redirects from it. Applies to robots.txt files as well.
+    pub fn url_latency(mut self, url: &str, latency: Duration) -> Self {
+        self.latencies.insert(parse(url), latency);
+        self
+    }
+
+    fn get(&self, url: &Url) -> Document {
+        self.documents
+            .get(url)
+            .cloned()
+            .unwrap_or(Document::Status(reqwest::StatusCode::NOT_FOUND))
+    }
+}
+
+/// Parse a URL given to the mock fetcher, panicking if it is invalid.
+fn parse(url: &str) -> Url {
+    Url::parse(url).expect("valid mock URL")
+}
+
+impl Fetcher for MockFetcher {
+    fn fetch<'a>(
+        &'a self,
+        request: &'a Request,
+        redirects: &'a mut Vec<Url>,
+    ) -> BoxFuture<'a, Result<Response, Error>> {
+        Box::pin(async move {
+            let mut url = request.url.clone();
+            let latency = self.latencies.get(&url).copied();
+            let latency = latency.unwrap_or(self.latency);
+            if !latency.is_zero() {
+                tokio::time::sleep(latency).await;
+            }
+            let (content_type, body) = loop {
+                match self.get(&url) {
+                    Document::Body(content_type, body) => break (content_type, body),
+                    Document::Status(status) => return Err(Error::Status(status)),
+                    Document::Redirect(location) => {
+                        if redirects.len() >= MAX_REDIRECTS {
+                            return Err(Error::Fetch("too many redirects".to_string()));
+                        }
+                        redirects.push(location.clone());
+                        url = location;
+                    }
+                }
+            };
+            if !content_type.contains("html") {
+                return Err(Error::UnsupportedType(content_type));
+            }
+            let page = Page {
+                headers: vec![("content-type".to_string(), content_type.clone())],
+                content_type,
+                size: body.len(),
+                ..Page::default()
+            };
+            Ok(Response {
+                url,
+                page,
+                body: Bytes::from(body),
+                links: Vec::new(),
+            })
+        })
+    }
+
+    fn fetch_robots<'a>(&'a self, url: &'a Url, _user_agent: &'a str) -> BoxFuture<'a, RobotsFile> {
+        Box::pin(async move {
+            let url = robots_url(url);
+            if let Some(&latency) = self.latencies.get(&url) {
+                tokio::time::sleep(latency).await;
+            }
+            match self.get(&url) {
+                Document::Body(_, body) => RobotsFile::Parsed(Robots::parse(&body)),
+                Document::Status(s) if s.is_client_error() => RobotsFile::Missing(s.as_u16()),
+                Document::Status(s) => {
+                    RobotsFile::Unavailable(format!("unexpected response status: {}", s))
+                }
+                Document::Redirect(_) => RobotsFile::Unavailable("redirected".to_string()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_fetcher() {
+        let fetcher = MockFetcher::new()
+            .page("http://example.com/", "<a href=\"/a\">")
+            .redirect("http://example.com/old", "http://example.com/")
+            .document(
+                "http://example.com/robots.txt",
+                "text/plain",
+                "User-agent: *",
+            )
+            .document("http://example.com/logo.png", "image/png", "")
+            .status("http://example.com/broken", 500);
+        let fetch = |u: &str| {
+            let request = Request::new(parse(u), "test");
+            let fetcher = fetcher.clone();
+            async move {
+                let mut redirects = Vec::new();
+                let result = fetcher.fetch(&request, &mut redirects).await;
+                (result, redirects)
+            }
+        };
+
+        let (resp, redirects) = fetch("http://example.com/old").await;
+        let resp = resp.unwrap();
+        assert_eq!(resp.url, parse("http://example.com/"));
+        assert_eq!(resp.body, "<a href=\"/a\">");
+        assert_eq!(redirects, [parse("http://example.com/")]);
+        let (resp, _) = fetch("http://example.com/logo.png").await;
+        assert!(matches!(resp, Err(Error::UnsupportedType(t)) if t == "image/png"));
+        let (resp, _) = fetch("http://example.com/broken").await;
+        assert!(matches!(resp, Err(Error::Status(s)) if s.as_u16() == 500));
+        let (resp, _) = fetch("http://example.com/a").await;
+        assert!(matches!(resp, Err(Error::Status(s)) if s.as_u16() == 404));
+
+        let url = parse("http://example.com/x");
+        let robots = fetcher.fetch_robots(&url, "test").await;
+        assert!(matches!(robots, RobotsFile::Parsed(_)));
+        let url = parse("http://other.com/");
+        let robots = fetcher.fetch_robots(&url, "test").await;
+        assert!(matches!(robots, RobotsFile::Missing(404)));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_errors() {
+        let fetcher = HttpFetcher::new(1);
+        let fetch = |u: &str| {
+            let request = Request::new(parse(u), "test");
+            let fetcher = fetcher.clone();
+            async move { fetcher.fetch(&request, &mut Vec::new()).await }
+        };
+        let resp = fetch("http://nonexistent.invalid/").await;
+        assert!(matches!(resp, Err(Error::Dns(_))), "{:?}", resp.err());
+        // Nothing listens on port 1.
+        let resp = fetch("http://127.0.0.1:1/").await;
+        assert!(matches!(resp, Err(Error::Fetch(_))), "{:?}", resp.err());
+    }
+}
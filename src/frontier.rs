@@ -0,0 +1,559 @@
+//! The URLs waiting to be fetched and the order they are fetched in.
+//!
+//! Each crawled domain has a [`Frontier`] of its own. The crawler pushes
+//! every newly discovered URL to the frontier of its domain and pops the next
+//! one to fetch whenever a fetch slot is free, skipping the URLs that are
+//! held back, e.g. by the per-host delay. Which of the ready URLs comes next
+//! is up to the frontier:
+//!
+//! * [`MemoryFrontier`], the default, takes the most recently discovered
+//!   one, which makes the crawls go deep first. [`MemoryFrontier::fifo`]
+//!   takes the least recently discovered one instead.
+//! * [`PriorityFrontier`] takes the one with the highest priority, e.g. the
+//!   least deep one with [`PriorityFrontier::breadth_first`].
+//! * [`DiskFrontier`] takes them in the order of discovery and keeps only a
+//!   limited number of them in memory, the rest in a file.
+
+use super::stats;
+use super::urlinfo::{Discovery, Domain};
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use url::Url;
+
+/// Creates the frontier of each crawled domain, see
+/// [`CrawlerBuilder::frontier`].
+///
+/// [`CrawlerBuilder::frontier`]: super::crawler::CrawlerBuilder::frontier
+pub type MakeFrontier = Arc<dyn Fn(&Domain) -> Box<dyn Frontier> + Send + Sync>;
+
+/// Scheduling policy of the crawler, see [`CrawlerBuilder::frontier`].
+///
+/// [`CrawlerBuilder::frontier`]: super::crawler::CrawlerBuilder::frontier
+pub trait Frontier: Send + 'static {
+    /// Queue a newly discovered URL.
+    fn push(&mut self, url: Url, discovery: &Discovery);
+
+    /// Take out the next URL to fetch among those `ready` to be fetched.
+    fn pop(&mut self, ready: &mut dyn FnMut(&Url) -> bool) -> Option<Url>;
+
+    /// Drop the queued URLs not to be kept.
+    fn retain(&mut self, keep: &mut dyn FnMut(&Url) -> bool);
+
+    /// Number of queued URLs.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The queued URLs, in the order they are popped if all of them are ready.
+    fn urls(&self) -> Box<dyn Iterator<Item = Cow<'_, Url>> + '_>;
+
+    /// Whether there are queued URLs of given domain.
+    fn contains_domain(&self, domain: &Domain) -> bool {
+        self.urls()
+            .any(|u| Domain::from_url(&u).as_ref() == Some(domain))
+    }
+
+    /// Estimated size of the queued URLs held in memory.
+    fn memory_bytes(&self) -> usize {
+        self.urls().map(|u| stats::url_bytes(&u)).sum()
+    }
+}
+
+/// Frontier held in memory, the most recently discovered URLs first unless
+/// created by [`MemoryFrontier::fifo`].
+///
+/// Taking the next URL only scans the queued ones while they are held back,
+/// so a large frontier costs little as long as most of its URLs are ready.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFrontier {
+    /// URLs in the order of discovery.
+    urls: VecDeque<Url>,
+    /// URLs are popped from the front rather than the end.
+    fifo: bool,
+}
+
+impl MemoryFrontier {
+    /// Fetch the most recently discovered URLs first.
+    pub fn new() -> Self {
+        MemoryFrontier::default()
+    }
+
+    /// Fetch the URLs in the order of discovery.
+    pub fn fifo() -> Self {
+        MemoryFrontier {
+            fifo: true,
+            ..MemoryFrontier::default()
+        }
+    }
+}
+
+impl Frontier for MemoryFrontier {
+    fn push(&mut self, url: Url, _discovery: &Discovery) {
+        self.urls.push_back(url);
+    }
+
+    fn pop(&mut self, ready: &mut dyn FnMut(&Url) -> bool) -> Option<Url> {
+        // Removing is cheap close to either end, where the ready URLs are
+        // found first.
+        let idx = match self.fifo {
+            true => self.urls.iter().position(ready)?,
+            false => self.urls.iter().rposition(ready)?,
+        };
+        self.urls.remove(idx)
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&Url) -> bool) {
+        self.urls.retain(|u| keep(u));
+    }
+
+    fn len(&self) -> usize {
+        self.urls.len()
+    }
+
+    fn urls(&self) -> Box<dyn Iterator<Item = Cow<'_, Url>> + '_> {
+        match self.fifo {
+            true => Box::new(self.urls.iter().map(Cow::Borrowed)),
+            false => Box::new(self.urls.iter().rev().map(Cow::Borrowed)),
+        }
+    }
+}
+
+/// Priority of a discovered URL, the higher the sooner it is fetched.
+pub type PriorityFn = Box<dyn Fn(&Url, &Discovery) -> i64 + Send>;
+
+/// Frontier held in memory, the URLs with the highest priority first and
+/// those of the same priority in the order of discovery.
+pub struct PriorityFrontier {
+    priority: PriorityFn,
+    urls: BTreeMap<(Reverse<i64>, u64), Url>,
+    /// Number of URLs pushed so far, orders those of the same priority.
+    pushed: u64,
+}
+
+impl PriorityFrontier {
+    /// Prioritize the URLs using given function.
+    pub fn new<F>(priority: F) -> Self
+    where
+        F: Fn(&Url, &Discovery) -> i64 + Send + 'static,
+    {
+        PriorityFrontier {
+            priority: Box::new(priority),
+            urls: BTreeMap::new(),
+            pushed: 0,
+        }
+    }
+
+    /// Fetch the URLs closest to the seeds first.
+    pub fn breadth_first() -> Self {
+        PriorityFrontier::new(|_, discovery| -i64::from(discovery.depth))
+    }
+}
+
+impl Frontier for PriorityFrontier {
+    fn push(&mut self, url: Url, discovery: &Discovery) {
+        let priority = (self.priority)(&url, discovery);
+        self.urls.insert((Reverse(priority), self.pushed), url);
+        self.pushed += 1;
+    }
+
+    fn pop(&mut self, ready: &mut dyn FnMut(&Url) -> bool) -> Option<Url> {
+        let key = *self.urls.iter().find(|(_, u)| ready(u))?.0;
+        self.urls.remove(&key)
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&Url) -> bool) {
+        self.urls.retain(|_, u| keep(u));
+    }
+
+    fn len(&self) -> usize {
+        self.urls.len()
+    }
+
+    fn urls(&self) -> Box<dyn Iterator<Item = Cow<'_, Url>> + '_> {
+        Box::new(self.urls.values().map(Cow::Borrowed))
+    }
+}
+
+/// Frontier keeping at most a given number of URLs in memory and spilling
+/// the rest to a file, for crawls too large to queue in memory. URLs are
+/// fetched in the order of discovery.
+///
+/// Only the URLs in memory are considered for fetching until none of them is
+/// ready, so more than the given number of them may be held in memory when
+/// those of paused crawls pile up. The file is removed when the frontier is
+/// dropped.
+pub struct DiskFrontier {
+    path: PathBuf,
+    file: File,
+    capacity: usize,
+    /// The URLs in memory, before those in the file.
+    head: VecDeque<Url>,
+    /// Number of URLs in the file after the read position.
+    spilled: usize,
+    read_pos: u64,
+    /// Number of queued URLs of each domain.
+    domains: HashMap<Domain, usize>,
+}
+
+impl DiskFrontier {
+    /// Create a frontier spilling to given file, replacing its content.
+    pub fn create(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(DiskFrontier {
+            path,
+            file,
+            capacity: capacity.max(1),
+            head: VecDeque::new(),
+            spilled: 0,
+            read_pos: 0,
+            domains: HashMap::new(),
+        })
+    }
+
+    /// Append a URL to the file.
+    fn spill(&mut self, url: &Url) -> io::Result<()> {
+        self.file.seek(SeekFrom::End(0))?;
+        writeln!(self.file, "{}", url)
+    }
+
+    /// Read up to `limit` URLs from the file, all of them if None.
+    fn read(&mut self, limit: Option<usize>) -> io::Result<Vec<Url>> {
+        self.file.seek(SeekFrom::Start(self.read_pos))?;
+        let limit = limit.unwrap_or(self.spilled).min(self.spilled);
+        let mut reader = BufReader::new(&self.file);
+        let mut urls = Vec::with_capacity(limit);
+        let mut line = String::new();
+        while urls.len() < limit {
+            line.clear();
+            self.read_pos += reader.read_line(&mut line)? as u64;
+            match Url::parse(line.trim_end()) {
+                Ok(url) => urls.push(url),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        }
+        Ok(urls)
+    }
+
+    /// Move the next URLs from the file to memory.
+    fn refill(&mut self) {
+        match self.read(Some(self.capacity)) {
+            Ok(urls) => {
+                self.spilled -= urls.len();
+                self.head.extend(urls);
+                if self.spilled == 0 {
+                    self.discard_file();
+                }
+            }
+            Err(e) => self.lose_file(e),
+        }
+    }
+
+    /// Drop the content of the file.
+    fn discard_file(&mut self) {
+        self.spilled = 0;
+        self.read_pos = 0;
+        if let Err(e) = self.file.set_len(0) {
+            tracing::warn!(path = %self.path.display(), error = %e, "Cannot truncate the frontier file");
+        }
+    }
+
+    /// Give up on the URLs in the file after failing to read it.
+    fn lose_file(&mut self, error: io::Error) {
+        tracing::error!(
+            path = %self.path.display(),
+            error = %error,
+            lost = self.spilled,
+            "Cannot read the frontier file"
+        );
+        self.discard_file();
+        self.domains.clear();
+        for url in self.head.clone() {
+            self.count(&url, true);
+        }
+    }
+
+    /// Count given URL in or out of its domain.
+    fn count(&mut self, url: &Url, queued: bool) {
+        let domain = match Domain::from_url(url) {
+            Some(domain) => domain,
+            None => return,
+        };
+        let count = self.domains.entry(domain.clone()).or_default();
+        match queued {
+            true => *count += 1,
+            false => *count -= 1,
+        }
+        if *count == 0 {
+            self.domains.remove(&domain);
+        }
+    }
+
+    /// Queue a URL already counted in its domain.
+    fn enqueue(&mut self, url: Url) {
+        if self.spilled > 0 || self.head.len() >= self.capacity {
+            match self.spill(&url) {
+                Ok(()) => {
+                    self.spilled += 1;
+                    return;
+                }
+                Err(e) => {
+                    tracing::warn!(path = %self.path.display(), error = %e, "Cannot write the frontier file")
+                }
+            }
+        }
+        self.head.push_back(url);
+    }
+
+    /// Read the URLs in the file without taking them out.
+    fn read_spilled(&self) -> io::Result<Vec<Url>> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.read_pos))?;
+        BufReader::new(file)
+            .lines()
+            .take(self.spilled)
+            .map(|line| {
+                Url::parse(&line?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            })
+            .collect()
+    }
+}
+
+impl Frontier for DiskFrontier {
+    fn push(&mut self, url: Url, _discovery: &Discovery) {
+        self.count(&url, true);
+        self.enqueue(url);
+    }
+
+    fn pop(&mut self, ready: &mut dyn FnMut(&Url) -> bool) -> Option<Url> {
+        let mut checked = 0;
+        loop {
+            if let Some(idx) = self.head.iter().skip(checked).position(&mut *ready) {
+                let url = self.head.remove(checked + idx)?;
+                self.count(&url, false);
+                return Some(url);
+            }
+            if self.spilled == 0 {
+                return None;
+            }
+            checked = self.head.len();
+            self.refill();
+        }
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&Url) -> bool) {
+        let mut dropped = Vec::new();
+        self.head.retain(|u| {
+            let kept = keep(u);
+            if !kept {
+                dropped.push(u.clone());
+            }
+            kept
+        });
+        for url in dropped {
+            self.count(&url, false);
+        }
+        if self.spilled > 0 {
+            match self.read(None) {
+                Ok(urls) => {
+                    self.discard_file();
+                    for url in urls {
+                        match keep(&url) {
+                            true => self.enqueue(url),
+                            false => self.count(&url, false),
+                        }
+                    }
+                }
+                Err(e) => self.lose_file(e),
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.head.len() + self.spilled
+    }
+
+    fn urls(&self) -> Box<dyn Iterator<Item = Cow<'_, Url>> + '_> {
+        let spilled = match self.spilled {
+            0 => Vec::new(),
+            _ => self.read_spilled().unwrap_or_else(|e| {
+                tracing::error!(path = %self.path.display(), error = %e, "Cannot read the frontier file");
+                Vec::new()
+            }),
+        };
+        let head = self.head.iter().map(Cow::Borrowed);
+        Box::new(head.chain(spilled.into_iter().map(Cow::Owned)))
+    }
+
+    fn contains_domain(&self, domain: &Domain) -> bool {
+        self.domains.contains_key(domain)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.head.iter().map(stats::url_bytes).sum()
+    }
+}
+
+impl Drop for DiskFrontier {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::crawler::Crawler;
+    use super::super::fetcher::MockFetcher;
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    fn url(path: &str) -> Url {
+        Url::parse(&format!("http://example.com/{}", path)).unwrap()
+    }
+
+    fn discovery(depth: u32) -> Discovery {
+        Discovery {
+            depth,
+            ..Discovery::default()
+        }
+    }
+
+    /// Pop all the URLs, skipping the ones given.
+    fn drain(frontier: &mut dyn Frontier, skip: &[&str]) -> Vec<String> {
+        let mut ready = |u: &Url| !skip.contains(&&u.path()[1..]);
+        std::iter::from_fn(|| frontier.pop(&mut ready))
+            .map(|u| u.path()[1..].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn unit_memory_frontier() {
+        let mut frontier = MemoryFrontier::new();
+        for path in ["a", "b", "c"] {
+            frontier.push(url(path), &discovery(0));
+        }
+        let urls: Vec<_> = frontier.urls().map(|u| u.path().to_string()).collect();
+        assert_eq!(urls, ["/c", "/b", "/a"]);
+        assert_eq!(drain(&mut frontier, &["b"]), ["c", "a"]);
+        assert_eq!(frontier.len(), 1);
+        frontier.retain(&mut |u| u.path() != "/b");
+        assert!(frontier.is_empty());
+
+        let mut frontier = MemoryFrontier::fifo();
+        for path in ["a", "b", "c"] {
+            frontier.push(url(path), &discovery(0));
+        }
+        let urls: Vec<_> = frontier.urls().map(|u| u.path().to_string()).collect();
+        assert_eq!(urls, ["/a", "/b", "/c"]);
+        assert_eq!(drain(&mut frontier, &["b"]), ["a", "c"]);
+    }
+
+    #[test]
+    fn unit_memory_frontier_large() {
+        // Popping from either end takes constant time, so this is quick.
+        for mut frontier in [MemoryFrontier::new(), MemoryFrontier::fifo()] {
+            for i in 0..100_000 {
+                frontier.push(url(&i.to_string()), &discovery(0));
+            }
+            let popped = std::iter::from_fn(|| frontier.pop(&mut |_| true)).count();
+            assert_eq!(popped, 100_000);
+        }
+    }
+
+    #[test]
+    fn unit_priority_frontier() {
+        let mut frontier = PriorityFrontier::breadth_first();
+        for (path, depth) in [("a2", 2), ("b1", 1), ("c2", 2), ("d0", 0)] {
+            frontier.push(url(path), &discovery(depth));
+        }
+        let urls: Vec<_> = frontier.urls().map(|u| u.path().to_string()).collect();
+        assert_eq!(urls, ["/d0", "/b1", "/a2", "/c2"]);
+        assert_eq!(drain(&mut frontier, &["b1"]), ["d0", "a2", "c2"]);
+        assert_eq!(frontier.len(), 1);
+
+        let mut frontier = PriorityFrontier::new(|u, _| u.path().len() as i64);
+        for path in ["a", "bbb", "cc"] {
+            frontier.push(url(path), &discovery(0));
+        }
+        assert_eq!(drain(&mut frontier, &[]), ["bbb", "cc", "a"]);
+    }
+
+    #[test]
+    fn unit_disk_frontier() {
+        let path = std::env::temp_dir().join(format!("croolr-frontier-{}", std::process::id()));
+        let mut frontier = DiskFrontier::create(&path, 2).unwrap();
+        for path in ["a", "b", "c", "d", "e"] {
+            frontier.push(url(path), &discovery(0));
+        }
+        frontier.push(Url::parse("http://other.com/x").unwrap(), &discovery(0));
+        assert_eq!(frontier.len(), 6);
+        assert_eq!(frontier.head.len(), 2);
+        assert_eq!(frontier.memory_bytes(), 2 * stats::url_bytes(&url("a")));
+        let urls: Vec<_> = frontier.urls().map(|u| u.path().to_string()).collect();
+        assert_eq!(urls, ["/a", "/b", "/c", "/d", "/e", "/x"]);
+
+        let other: Domain = "other.com".parse().unwrap();
+        assert!(frontier.contains_domain(&other));
+        frontier.retain(&mut |u| u.host_str() != Some("other.com") && u.path() != "/d");
+        assert!(!frontier.contains_domain(&other));
+        assert_eq!(frontier.len(), 4);
+
+        // Held URLs stay in memory while the ones after them are read.
+        assert_eq!(drain(&mut frontier, &["a", "b"]), ["c", "e"]);
+        assert_eq!(frontier.len(), 2);
+        assert_eq!(drain(&mut frontier, &[]), ["a", "b"]);
+        assert!(frontier.is_empty());
+        assert!(!frontier.contains_domain(&"example.com".parse().unwrap()));
+
+        drop(frontier);
+        assert!(!path.exists());
+    }
+
+    /// Paths of the pages in the order they are fetched one by one.
+    async fn crawl_order<F>(fetcher: MockFetcher, frontier: fn() -> F) -> Vec<String>
+    where
+        F: Frontier,
+    {
+        let crawler = Crawler::builder()
+            .fetcher(fetcher)
+            .fetch_limit(1)
+            .frontier(move |_| frontier())
+            .build()
+            .unwrap();
+        let results = crawler.crawl_stream("example.com".parse().unwrap());
+        let results = results.await.unwrap();
+        results.map(|(u, _)| u.path().to_string()).collect().await
+    }
+
+    #[tokio::test]
+    async fn test_frontier_crawl() {
+        let fetcher = MockFetcher::new()
+            .page(
+                "http://example.com/",
+                r#"<a href="/a"></a> <a href="/b"></a>"#,
+            )
+            .page("http://example.com/a", r#"<a href="/a1"></a>"#)
+            .page("http://example.com/b", r#"<a href="/b1"></a>"#)
+            .page("http://example.com/a1", "")
+            .page("http://example.com/b1", "");
+        let order = crawl_order(fetcher.clone(), MemoryFrontier::new).await;
+        assert_eq!(order, ["/", "/b", "/b1", "/a", "/a1"]);
+        let order = crawl_order(fetcher.clone(), MemoryFrontier::fifo).await;
+        assert_eq!(order, ["/", "/a", "/b", "/a1", "/b1"]);
+        let order = crawl_order(fetcher, PriorityFrontier::breadth_first).await;
+        assert_eq!(order, ["/", "/a", "/b", "/a1", "/b1"]);
+    }
+}
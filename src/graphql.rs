@@ -0,0 +1,269 @@
+//! GraphQL view of the crawl data.
+
+use super::crawler::{CrawlState, Crawler, DomainStatus};
+use super::listing::{ListOptions, StatusFilter};
+use super::timefmt::w3c_datetime;
+use super::urlinfo::{Domain as DomainName, UrlInfo};
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Enum, Object, Result, SimpleObject,
+};
+use std::convert::TryFrom;
+
+/// Max nesting of a query, following links could go on forever otherwise.
+const MAX_DEPTH: usize = 16;
+
+/// Number of URLs listed by `Domain.urls` unless asked otherwise.
+const DEFAULT_LIMIT: usize = 100;
+
+pub type Schema = async_graphql::Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// Build the schema resolving queries using given crawler.
+pub fn schema(crawler: Crawler) -> Schema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(crawler)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// All the domains the crawler has data for.
+    async fn domains(&self, ctx: &Context<'_>) -> Result<Vec<Domain>> {
+        let domains = crawler(ctx).domains().await?;
+        Ok(domains.into_iter().map(Domain).collect())
+    }
+
+    /// Given domain, null if there is no data for it.
+    async fn domain(&self, ctx: &Context<'_>, name: String) -> Result<Option<Domain>> {
+        let name: DomainName = name.parse()?;
+        let domains = crawler(ctx).domains().await?;
+        Ok(domains.into_iter().find(|d| d.domain == name).map(Domain))
+    }
+
+    /// Given URL, null if it has not been fetched.
+    async fn url(&self, ctx: &Context<'_>, url: String) -> Result<Option<Url>> {
+        url_node(ctx, url::Url::parse(&url)?).await
+    }
+}
+
+/// State of a crawl.
+#[derive(Clone, Copy, PartialEq, Eq, Enum)]
+#[graphql(remote = "CrawlState")]
+enum State {
+    Running,
+    Pending,
+    Paused,
+    Stopped,
+}
+
+/// A domain with crawl results or a crawl.
+struct Domain(DomainStatus);
+
+#[Object]
+impl Domain {
+    async fn name(&self) -> &str {
+        &self.0.domain
+    }
+
+    /// Number of URLs fetched.
+    async fn url_count(&self) -> usize {
+        self.0.urls
+    }
+
+    /// The crawl of the domain, null if it has never been crawled.
+    async fn crawl(&self, ctx: &Context<'_>) -> Result<Option<CrawlRun>> {
+        let queue = crawler(ctx).queue(self.0.domain.clone()).await?;
+        Ok(queue.state.map(|state| CrawlRun {
+            state: state.into(),
+            queued: queue.queued,
+            in_flight: queue.in_flight.iter().map(url::Url::to_string).collect(),
+        }))
+    }
+
+    /// Fetched URLs ordered by URL, filtered like the REST listing.
+    async fn urls(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] offset: usize,
+        #[graphql(default_with = "DEFAULT_LIMIT")] limit: usize,
+        #[graphql(desc = "HTTP status code (404) or class (4xx)")] status: Option<String>,
+        #[graphql(name = "type", desc = "Part of the content type")] content_type: Option<String>,
+        #[graphql(desc = "Path prefix")] prefix: Option<String>,
+    ) -> Result<UrlPage> {
+        let opts = ListOptions {
+            offset,
+            limit: Some(limit),
+            status: status.map(StatusFilter::try_from).transpose()?,
+            content_type,
+            prefix,
+            ..ListOptions::default()
+        };
+        let listing = crawler(ctx).list_urls(self.0.domain.clone(), opts).await?;
+        let (total, nodes) = match listing {
+            Some(listing) => (listing.total, listing.urls),
+            None => (0, Vec::new()),
+        };
+        Ok(UrlPage {
+            total,
+            offset,
+            nodes: nodes
+                .into_iter()
+                .map(|(url, info)| Url {
+                    url: url::Url::clone(&url),
+                    info,
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Progress of the crawl of a domain.
+#[derive(SimpleObject)]
+struct CrawlRun {
+    state: State,
+    /// Number of URLs waiting to be fetched.
+    queued: usize,
+    /// URLs being fetched right now.
+    in_flight: Vec<String>,
+}
+
+/// A page of a URL listing.
+#[derive(SimpleObject)]
+struct UrlPage {
+    /// Number of URLs matching the filters.
+    total: usize,
+    offset: usize,
+    nodes: Vec<Url>,
+}
+
+/// A fetched URL.
+struct Url {
+    url: url::Url,
+    info: UrlInfo,
+}
+
+#[Object]
+impl Url {
+    async fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    async fn status(&self) -> Option<u16> {
+        self.info.status().map(|s| s.as_u16())
+    }
+
+    async fn error(&self) -> Option<String> {
+        self.info.result.as_ref().err().map(|e| e.to_string())
+    }
+
+    async fn content_type(&self) -> Option<&str> {
+        self.info.content_type()
+    }
+
+    async fn size(&self) -> Option<usize> {
+        self.info.result.as_ref().ok().map(|p| p.size)
+    }
+
+    async fn title(&self) -> Option<&str> {
+        self.page()?.title.as_deref()
+    }
+
+    async fn canonical(&self) -> Option<&str> {
+        self.page()?.canonical.as_ref().map(url::Url::as_str)
+    }
+
+    async fn fetched_at(&self) -> String {
+        w3c_datetime(self.info.fetched_at)
+    }
+
+    async fn elapsed_ms(&self) -> f64 {
+        self.info.elapsed.as_secs_f64() * 1000.0
+    }
+
+    /// Number of links followed from the seed to get here.
+    async fn depth(&self) -> u32 {
+        self.info.discovery.depth
+    }
+
+    /// The page the URL has been discovered on, null for seeds and pages not
+    /// fetched yet.
+    async fn parent(&self, ctx: &Context<'_>) -> Result<Option<Url>> {
+        match &self.info.discovery.parent {
+            Some(parent) => url_node(ctx, url::Url::clone(parent)).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Links found on the page.
+    async fn links(&self) -> Vec<Link> {
+        let links = self.page().map(|p| p.links.iter().map(Link).collect());
+        links.unwrap_or_default()
+    }
+}
+
+impl Url {
+    fn page(&self) -> Option<&super::urlinfo::Page> {
+        self.info.result.as_ref().ok()
+    }
+}
+
+/// A link from one page to another.
+struct Link(url::Url);
+
+#[Object]
+impl Link {
+    /// The URL linked to.
+    async fn target(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// The page linked to, null if it has not been fetched.
+    async fn url(&self, ctx: &Context<'_>) -> Result<Option<Url>> {
+        url_node(ctx, self.0.clone()).await
+    }
+}
+
+fn crawler<'a>(ctx: &Context<'a>) -> &'a Crawler {
+    ctx.data_unchecked::<Crawler>()
+}
+
+async fn url_node(ctx: &Context<'_>, url: url::Url) -> Result<Option<Url>> {
+    let info = crawler(ctx).url_info(url.clone()).await?;
+    Ok(info.map(|info| Url { url, info }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_graphql_query() {
+        let crawler = Crawler::builder().build().unwrap();
+        crawler
+            .crawl("example.invalid".parse().unwrap())
+            .await
+            .unwrap();
+        let query = r#"{
+            domains { name }
+            domain(name: "Example.invalid") {
+                name
+                urlCount
+                crawl { state }
+                urls(limit: 10) { total nodes { url status links { target } } }
+            }
+            url(url: "http://example.invalid/") { status }
+        }"#;
+        let response = schema(crawler).execute(query).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        assert_eq!(data["domains"][0]["name"], "example.invalid");
+        let domain = &data["domain"];
+        assert_eq!(domain["crawl"]["state"], "RUNNING");
+        // The seed may have failed to fetch already.
+        assert!(domain["urls"]["total"].as_u64().unwrap() <= 1);
+        assert!(data["url"].is_null());
+    }
+}
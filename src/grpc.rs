@@ -0,0 +1,322 @@
+//! gRPC interface mirroring the main operations of the HTTP API.
+//!
+//! The service is described in `proto/croolr.proto`. API keys are passed in
+//! the `x-api-key` metadata, just like the HTTP header.
+
+use super::api::ApiError;
+use super::auth::{self, Access, SharedKeys};
+use super::crawler::{self, CrawlReply, CrawlState, Crawler, QueueStatus};
+use super::event;
+use super::listing::{ListOptions, StatusFilter};
+use super::urlinfo::{Domain, UrlInfo};
+
+use std::convert::TryFrom;
+use std::pin::Pin;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Code, Request, Response, Status};
+
+/// Code generated from the service description.
+pub mod proto {
+    tonic::include_proto!("croolr.v1");
+}
+
+use proto::crawler_server::CrawlerServer;
+use proto::*;
+
+/// The gRPC service backed by given crawler.
+pub fn service(crawler: Crawler, keys: SharedKeys) -> CrawlerServer<Service> {
+    CrawlerServer::new(Service { crawler, keys })
+}
+
+pub struct Service {
+    crawler: Crawler,
+    keys: SharedKeys,
+}
+
+impl Service {
+    /// Check the API key of the request grants given access.
+    fn authorize<T>(&self, request: &Request<T>, access: Access) -> Result<(), ApiError> {
+        let key = request.metadata().get(auth::HEADER);
+        let key = key.and_then(|k| k.to_str().ok());
+        match self.keys.allows(key, access) {
+            true => Ok(()),
+            false => Err(ApiError::Unauthorized),
+        }
+    }
+
+    async fn crawl_status(&self, domain: Domain) -> Result<StatusResponse, Status> {
+        let queue = self.crawler.queue(domain).await.map_err(ApiError::from)?;
+        match queue.state {
+            Some(_) => Ok(status_response(queue)),
+            None => Err(ApiError::UnknownDomain.into()),
+        }
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<CrawlEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl proto::crawler_server::Crawler for Service {
+    async fn crawl(
+        &self,
+        request: Request<DomainRequest>,
+    ) -> Result<Response<CrawlResponse>, Status> {
+        self.authorize(&request, Access::Write)?;
+        let domain = parse_domain(&request.get_ref().domain)?;
+        let reply = self.crawler.crawl(domain).await.map_err(ApiError::from)?;
+        let started = match reply {
+            CrawlReply::Queued => true,
+            CrawlReply::AlreadyCrawling => false,
+            CrawlReply::MalformedHostName(e) => {
+                return Err(ApiError::InvalidDomain(e.to_string()).into())
+            }
+        };
+        Ok(Response::new(CrawlResponse { started }))
+    }
+
+    async fn list_urls(
+        &self,
+        request: Request<ListUrlsRequest>,
+    ) -> Result<Response<ListUrlsResponse>, Status> {
+        self.authorize(&request, Access::Read)?;
+        let request = request.into_inner();
+        let domain = parse_domain(&request.domain)?;
+        let status = request.status.map(StatusFilter::try_from).transpose();
+        let opts = ListOptions {
+            offset: request.offset as usize,
+            limit: request.limit.map(|l| l as usize),
+            status: status.map_err(ApiError::BadRequest)?,
+            content_type: request.content_type,
+            prefix: request.prefix,
+            ..ListOptions::default()
+        };
+        let listing = self
+            .crawler
+            .list_urls(domain, opts)
+            .await
+            .map_err(ApiError::from)?
+            .ok_or(ApiError::UnknownDomain)?;
+        Ok(Response::new(ListUrlsResponse {
+            total: listing.total as u64,
+            urls: listing.urls.iter().map(|(u, i)| url_info(u, i)).collect(),
+        }))
+    }
+
+    async fn count_urls(
+        &self,
+        request: Request<DomainRequest>,
+    ) -> Result<Response<CountUrlsResponse>, Status> {
+        self.authorize(&request, Access::Read)?;
+        let domain = parse_domain(&request.get_ref().domain)?;
+        let count = self
+            .crawler
+            .count_urls(domain)
+            .await
+            .map_err(ApiError::from)?
+            .ok_or(ApiError::UnknownDomain)?;
+        Ok(Response::new(CountUrlsResponse {
+            count: count as u64,
+        }))
+    }
+
+    async fn status(
+        &self,
+        request: Request<DomainRequest>,
+    ) -> Result<Response<StatusResponse>, Status> {
+        self.authorize(&request, Access::Read)?;
+        let domain = parse_domain(&request.get_ref().domain)?;
+        Ok(Response::new(self.crawl_status(domain).await?))
+    }
+
+    async fn control(
+        &self,
+        request: Request<ControlRequest>,
+    ) -> Result<Response<StatusResponse>, Status> {
+        use control_request::Command;
+        self.authorize(&request, Access::Write)?;
+        let request = request.get_ref();
+        let domain = parse_domain(&request.domain)?;
+        let control = match request.command() {
+            Command::Pause => crawler::Control::Pause,
+            Command::Resume => crawler::Control::Resume,
+            Command::Stop => crawler::Control::Stop,
+            Command::Unspecified => {
+                let e = ApiError::BadRequest("command not specified".to_string());
+                return Err(e.into());
+            }
+        };
+        self.crawler
+            .control(domain.clone(), control)
+            .await
+            .map_err(ApiError::from)?
+            .ok_or(ApiError::UnknownDomain)?;
+        Ok(Response::new(self.crawl_status(domain).await?))
+    }
+
+    type EventsStream = EventStream;
+
+    async fn events(
+        &self,
+        request: Request<DomainRequest>,
+    ) -> Result<Response<Self::EventsStream>, Status> {
+        self.authorize(&request, Access::Read)?;
+        let domain = self.crawler.site(parse_domain(&request.get_ref().domain)?);
+        let crawler = self.crawler.clone();
+        let stream = self.crawler.events().filter_map(move |event| {
+            if event.domain().map(|d| crawler.site(d)).as_ref() != Some(&domain) {
+                return None;
+            }
+            crawl_event(event).map(Ok)
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+impl From<ApiError> for Status {
+    fn from(e: ApiError) -> Self {
+        let code = match e {
+            ApiError::BadRequest(_) | ApiError::InvalidDomain(_) => Code::InvalidArgument,
+            ApiError::UnknownDomain | ApiError::UnknownUrl | ApiError::NoScreenshot => {
+                Code::NotFound
+            }
+            ApiError::AlreadyCrawling => Code::AlreadyExists,
+            ApiError::Unauthorized => Code::Unauthenticated,
+            ApiError::RateLimited(_) => Code::ResourceExhausted,
+            ApiError::Internal(_) => Code::Internal,
+        };
+        let message = match e.details() {
+            Some(details) => format!("{}: {}", e.message(), details),
+            None => e.message().to_string(),
+        };
+        Status::new(code, message)
+    }
+}
+
+fn parse_domain(domain: &str) -> Result<Domain, ApiError> {
+    domain
+        .parse()
+        .map_err(|e: url::ParseError| ApiError::InvalidDomain(e.to_string()))
+}
+
+fn crawl_state(state: CrawlState) -> proto::CrawlState {
+    match state {
+        CrawlState::Running => proto::CrawlState::Running,
+        CrawlState::Paused => proto::CrawlState::Paused,
+        CrawlState::Stopped => proto::CrawlState::Stopped,
+        CrawlState::Pending => proto::CrawlState::Pending,
+    }
+}
+
+fn status_response(queue: QueueStatus) -> StatusResponse {
+    let state = queue
+        .state
+        .map_or(proto::CrawlState::Unspecified, crawl_state);
+    StatusResponse {
+        state: state.into(),
+        queued: queue.queued as u64,
+        in_flight: queue.in_flight.iter().map(url::Url::to_string).collect(),
+    }
+}
+
+fn url_info(url: &url::Url, info: &UrlInfo) -> proto::UrlInfo {
+    let page = info.result.as_ref().ok();
+    proto::UrlInfo {
+        url: url.to_string(),
+        status: info.status().map(|s| s.as_u16().into()),
+        error: info.result.as_ref().err().map(|e| e.to_string()),
+        content_type: info.content_type().map(str::to_string),
+        size: page.map(|p| p.size as u64),
+        title: page.and_then(|p| p.title.clone()),
+        elapsed_ms: info.elapsed.as_secs_f64() * 1000.0,
+        depth: info.discovery.depth,
+    }
+}
+
+/// Convert a crawl event to its gRPC form, None for events not sent over gRPC.
+fn crawl_event(event: event::CrawlEvent) -> Option<CrawlEvent> {
+    use crawl_event::Event;
+    let event = match event {
+        event::CrawlEvent::Discovered { url, parent, depth } => {
+            Event::Discovered(crawl_event::Discovered {
+                url: url.to_string(),
+                parent: parent.as_ref().map(url::Url::to_string),
+                depth,
+            })
+        }
+        event::CrawlEvent::Fetched {
+            url,
+            status,
+            error,
+            elapsed_ms,
+        } => Event::Fetched(crawl_event::Fetched {
+            url: url.to_string(),
+            status: status.map(Into::into),
+            error,
+            elapsed_ms,
+        }),
+        event::CrawlEvent::StateChanged { domain, state } => {
+            Event::StateChanged(crawl_event::StateChanged {
+                domain: domain.to_string(),
+                state: crawl_state(state).into(),
+            })
+        }
+        event::CrawlEvent::ShuttingDown => return None,
+    };
+    Some(CrawlEvent { event: Some(event) })
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::auth::Keys;
+    use super::proto::crawler_server::Crawler as _;
+    use super::*;
+
+    fn domain_request(domain: &str) -> Request<DomainRequest> {
+        Request::new(DomainRequest {
+            domain: domain.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_grpc_service() {
+        let crawler = Crawler::builder().build().unwrap();
+        let service = Service {
+            crawler,
+            keys: SharedKeys::default(),
+        };
+        let status = service.count_urls(domain_request("example.invalid")).await;
+        assert_eq!(status.unwrap_err().code(), Code::NotFound);
+        let status = service.crawl(domain_request("bad domain")).await;
+        assert_eq!(status.unwrap_err().code(), Code::InvalidArgument);
+
+        let reply = service.crawl(domain_request("example.invalid")).await;
+        assert!(reply.unwrap().into_inner().started);
+        let reply = service.crawl(domain_request("example.invalid")).await;
+        assert!(!reply.unwrap().into_inner().started);
+        let control = Request::new(ControlRequest {
+            domain: "example.invalid".to_string(),
+            command: control_request::Command::Pause.into(),
+        });
+        let reply = service.control(control).await.unwrap().into_inner();
+        assert_eq!(reply.state(), proto::CrawlState::Paused);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_auth() {
+        let keys = Keys {
+            write: vec!["secret".to_string()].into_iter().collect(),
+            read: Default::default(),
+        };
+        let service = Service {
+            crawler: Crawler::builder().build().unwrap(),
+            keys: SharedKeys::new(keys),
+        };
+        let status = service.crawl(domain_request("example.invalid")).await;
+        assert_eq!(status.unwrap_err().code(), Code::Unauthenticated);
+        let mut request = domain_request("example.invalid");
+        request
+            .metadata_mut()
+            .insert(auth::HEADER, "secret".parse().unwrap());
+        assert!(service.crawl(request).await.is_ok());
+    }
+}
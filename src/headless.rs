@@ -0,0 +1,267 @@
+//! Pages rendered by a headless browser.
+//!
+//! Sites building their pages with scripts hide many of their links from the
+//! plain HTML. With `--headless`, the pages are fetched as usual by another
+//! [`Fetcher`], which gets their status, redirects and headers, and then loaded
+//! again by a [`Browser`], which runs their scripts. The links are extracted
+//! from the document as rendered. The browser is headless Chrome or Chromium
+//! unless another one is set with [`CrawlerBuilder::browser`]. It takes the
+//! screenshots of the pages as well, see [`screenshot`](super::screenshot).
+//!
+//! [`CrawlerBuilder::browser`]: super::crawler::CrawlerBuilder::browser
+
+use super::fetcher::{Fetcher, Request, Response};
+use super::robots::RobotsFile;
+use super::urlinfo::Error;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use std::path::PathBuf;
+use std::process::Output;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// Time a browser is given to load a page and render it or take its
+/// screenshot.
+const BROWSER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Size of the browser window, and so of the screenshots.
+const WINDOW_SIZE: (u32, u32) = (1280, 800);
+
+/// A headless browser loading the pages along with their scripts.
+pub trait Browser: Send + Sync + 'static {
+    /// Load given page and return its document once the scripts have run.
+    fn render<'a>(
+        &'a self,
+        url: &'a Url,
+        user_agent: &'a str,
+    ) -> BoxFuture<'a, Result<String, String>>;
+
+    /// Load given page and take its screenshot as PNG.
+    fn screenshot<'a>(
+        &'a self,
+        url: &'a Url,
+        user_agent: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<u8>, String>>;
+}
+
+/// Headless Chrome or Chromium, run for each page.
+#[derive(Debug, Clone)]
+pub struct HeadlessChrome {
+    program: PathBuf,
+}
+
+impl HeadlessChrome {
+    /// Use given browser executable, e.g. `chromium` or `google-chrome`.
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        HeadlessChrome {
+            program: program.into(),
+        }
+    }
+
+    /// Run the browser on given page with given extra arguments.
+    async fn run(&self, url: &Url, user_agent: &str, args: &[String]) -> Result<Output, String> {
+        let run = tokio::process::Command::new(&self.program)
+            .arg("--headless")
+            .arg("--disable-gpu")
+            .arg("--hide-scrollbars")
+            .arg(format!("--window-size={},{}", WINDOW_SIZE.0, WINDOW_SIZE.1))
+            .arg(format!("--user-agent={}", user_agent))
+            .args(args)
+            .arg(url.as_str())
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        match tokio::time::timeout(BROWSER_TIMEOUT, run).await {
+            Ok(output) => output.map_err(|e| format!("cannot run the browser: {}", e)),
+            Err(_) => Err("timed out".to_string()),
+        }
+    }
+}
+
+/// The error of a browser run that has not given what it has been asked for.
+fn browser_error(output: &Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = stderr.lines().last().unwrap_or_default().trim();
+    format!("browser failed ({}): {}", output.status, reason)
+}
+
+impl Browser for HeadlessChrome {
+    fn render<'a>(
+        &'a self,
+        url: &'a Url,
+        user_agent: &'a str,
+    ) -> BoxFuture<'a, Result<String, String>> {
+        Box::pin(async move {
+            let args = ["--dump-dom".to_string()];
+            let output = self.run(url, user_agent, &args).await?;
+            match output.status.success() {
+                true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+                false => Err(browser_error(&output)),
+            }
+        })
+    }
+
+    fn screenshot<'a>(
+        &'a self,
+        url: &'a Url,
+        user_agent: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<u8>, String>> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        Box::pin(async move {
+            let n = NEXT.fetch_add(1, Ordering::Relaxed);
+            let file = format!("croolr-screenshot-{}-{}.png", std::process::id(), n);
+            let path = std::env::temp_dir().join(file);
+            let arg = format!("--screenshot={}", path.display());
+            let output = self.run(url, user_agent, &[arg]).await?;
+            let png = tokio::fs::read(&path).await;
+            let _ = tokio::fs::remove_file(&path).await;
+            match png {
+                Ok(png) if output.status.success() => Ok(png),
+                _ => Err(browser_error(&output)),
+            }
+        })
+    }
+}
+
+/// Fetcher rendering the pages got by another one in a headless browser.
+///
+/// The browser loads each page fetched successfully once more, without the
+/// additional request headers, and the body is replaced by the document it
+/// renders. Pages it fails to render are kept as fetched. The robots.txt files
+/// and the link checks are left to the other fetcher.
+pub struct HeadlessFetcher {
+    fetcher: Arc<dyn Fetcher>,
+    browser: Arc<dyn Browser>,
+}
+
+impl HeadlessFetcher {
+    pub fn new(fetcher: Arc<dyn Fetcher>, browser: Arc<dyn Browser>) -> Self {
+        HeadlessFetcher { fetcher, browser }
+    }
+}
+
+impl Fetcher for HeadlessFetcher {
+    fn fetch<'a>(
+        &'a self,
+        request: &'a Request,
+        redirects: &'a mut Vec<Url>,
+    ) -> BoxFuture<'a, Result<Response, Error>> {
+        Box::pin(async move {
+            let mut response = self.fetcher.fetch(request, redirects).await?;
+            let rendered = self.browser.render(&response.url, &request.user_agent);
+            match rendered.await {
+                Ok(document) => response.body = Bytes::from(document),
+                Err(e) => tracing::warn!(url = %response.url, error = %e, "Rendering failed"),
+            }
+            Ok(response)
+        })
+    }
+
+    fn fetch_robots<'a>(&'a self, url: &'a Url, user_agent: &'a str) -> BoxFuture<'a, RobotsFile> {
+        self.fetcher.fetch_robots(url, user_agent)
+    }
+
+    fn check<'a>(
+        &'a self,
+        url: &'a Url,
+        user_agent: &'a str,
+    ) -> BoxFuture<'a, Result<reqwest::StatusCode, Error>> {
+        self.fetcher.check(url, user_agent)
+    }
+
+    fn prefetch(&self, host: &str) {
+        self.fetcher.prefetch(host)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::crawler::Crawler;
+    use super::super::fetcher::MockFetcher;
+    use super::super::urlinfo::Domain;
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_headless_chrome() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stands in for the browser, writing its arguments as the document
+        // or the screenshot.
+        let dir = std::env::temp_dir().join(format!("croolr-test-browser-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("chromium");
+        let script = "#!/bin/sh\n\
+            out=/dev/stdout\n\
+            for arg; do case $arg in --screenshot=*) out=${arg#--screenshot=};; esac; done\n\
+            [ \"$1\" = --headless ] || { echo 'not headless' >&2; exit 1; }\n\
+            echo \"$@\" > \"$out\"\n";
+        std::fs::write(&program, script).unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let url = Url::parse("http://example.com/").unwrap();
+        let chrome = HeadlessChrome::new(&program);
+        let png = chrome.screenshot(&url, "test").await.unwrap();
+        let args = String::from_utf8(png).unwrap();
+        assert!(args.contains("--user-agent=test"), "{}", args);
+        assert!(args.trim_end().ends_with("http://example.com/"), "{}", args);
+        let document = chrome.render(&url, "test").await.unwrap();
+        assert!(document.contains("--dump-dom"), "{}", document);
+
+        let missing = HeadlessChrome::new(dir.join("missing"));
+        assert!(missing.screenshot(&url, "test").await.is_err());
+        assert!(missing.render(&url, "test").await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_headless_crawl() {
+        /// Adds a link to the pages, as their scripts would, failing on some.
+        struct Browser;
+        impl super::Browser for Browser {
+            fn render<'a>(
+                &'a self,
+                url: &'a Url,
+                _user_agent: &'a str,
+            ) -> BoxFuture<'a, Result<String, String>> {
+                Box::pin(async move {
+                    match url.path() {
+                        "/" => Ok(r#"<a href="/a">A</a><a href="/b">B</a>"#.to_string()),
+                        _ => Err("crashed".to_string()),
+                    }
+                })
+            }
+
+            fn screenshot<'a>(
+                &'a self,
+                _url: &'a Url,
+                _user_agent: &'a str,
+            ) -> BoxFuture<'a, Result<Vec<u8>, String>> {
+                Box::pin(async { Err("no screen".to_string()) })
+            }
+        }
+
+        let fetcher = MockFetcher::new()
+            .page("http://example.com/", r#"<a href="/a">A</a>"#)
+            .page("http://example.com/a", "")
+            .page("http://example.com/b", r#"<a href="/c">C</a>"#)
+            .page("http://example.com/c", "");
+        let crawler = Crawler::builder()
+            .fetcher(fetcher)
+            .headless()
+            .browser(Browser)
+            .build()
+            .unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        assert_eq!(crawler.wait(domain.clone()).await, Ok(true));
+
+        // The link added by the browser is followed, and so are those of the
+        // page it fails to render.
+        let count = crawler.count_urls(domain).await.unwrap().unwrap();
+        assert_eq!(count, 4);
+    }
+}
@@ -0,0 +1,95 @@
+//! An experimental web crawler.
+//!
+//! The [`Crawler`], configured by [`Crawler::builder`], runs as a task on the
+//! tokio runtime and is controlled through a cheaply cloneable handle. It can be embedded directly, or the HTTP
+//! API the `croolr` binary serves can be started on top of it with
+//! [`server::start`]. The remaining modules hold the parts both are built from.
+//!
+//! ```no_run
+//! use croolr::{Crawler, Domain};
+//!
+//! # async fn example() -> Result<(), croolr::CroolrError> {
+//! let crawler = Crawler::builder()
+//!     .fetch_limit(4)
+//!     .max_depth(3)
+//!     .build()
+//!     .expect("valid settings");
+//! let domain: Domain = "example.com".parse().unwrap();
+//! crawler.crawl(domain.clone()).await?;
+//! crawler.wait(domain.clone()).await?;
+//! println!("{:?} URLs found", crawler.count_urls(domain).await?);
+//! # Ok(())
+//! # }
+//! ```
+
+// The OpenAPI document is a single `json!` literal, deeper than the default.
+#![recursion_limit = "256"]
+
+pub mod analyzer;
+pub mod api;
+pub mod audit;
+pub mod auth;
+pub mod autotune;
+pub mod batch;
+pub mod bench;
+pub mod caching;
+pub mod checkpoint;
+pub mod compression;
+pub mod config;
+pub mod cors;
+pub mod crawler;
+pub mod daemon;
+pub mod discovery;
+pub mod dns;
+pub mod error;
+pub mod event;
+pub mod export;
+pub mod fetch;
+pub mod fetcher;
+pub mod frontier;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "headless")]
+pub mod headless;
+pub mod linkcheck;
+pub mod listing;
+pub mod logging;
+pub mod metrics;
+pub mod middleware;
+pub mod oneshot;
+pub mod openapi;
+pub mod outlinks;
+#[cfg(feature = "wasm")]
+pub mod plugin;
+pub mod ratelimit;
+pub mod robots;
+#[cfg(feature = "headless")]
+pub mod screenshot;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod search;
+mod seen;
+pub mod server;
+mod sessions;
+pub mod settings;
+mod shard;
+pub mod sitemap;
+pub mod stats;
+pub mod summary;
+pub mod systemd;
+pub mod timefmt;
+pub mod urlinfo;
+pub mod urllist;
+pub mod wait;
+pub mod websocket;
+pub mod workers;
+
+pub use crawler::{CrawlReply, Crawler, CrawlerBuilder, DiscoverHook};
+pub use error::CroolrError;
+pub use fetcher::{Fetcher, HttpFetcher, MockFetcher};
+pub use frontier::Frontier;
+pub use middleware::Middleware;
+pub use settings::Settings;
+pub use urlinfo::{Domain, UrlInfo};
@@ -0,0 +1,394 @@
+//! Checks of the links to other sites, and the report of the broken links.
+//!
+//! The crawl follows the links within the crawled sites only. With
+//! `--check-external`, those leading elsewhere are checked instead, each once
+//! however many pages link to it, by a HEAD request (or a GET one if the
+//! server does not take HEAD). The checks of a host run one after the other,
+//! a second apart, and only a few run at once overall, so that sites nobody
+//! asked to crawl are not hammered.
+//!
+//! The broken-link report of a domain lists the links found on its pages
+//! leading to pages that failed to be fetched, and to other sites found not
+//! working by the checks.
+//!
+//! The links wait for their check in a queue per host, and the hosts take
+//! turns with a fixed set of workers, one link at a time. Links found while
+//! the queues are full are not checked, nor are they once the states of too
+//! many links are kept, the working ones being forgotten first.
+
+use super::fetcher::Fetcher;
+use super::settings::SiteScope;
+use super::urlinfo::{Error, UrlSet};
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use url::Url;
+
+/// Time between the checks of the same host.
+const HOST_DELAY: Duration = Duration::from_secs(1);
+
+/// Max number of checks running at once, i.e. of workers.
+const MAX_CHECKS: usize = 8;
+
+/// Number of hosts checked at which those idle are forgotten.
+const MAX_HOSTS: usize = 1024;
+
+/// Max number of links waiting for their check.
+const MAX_QUEUED: usize = 10_000;
+
+/// Max number of links whose state is kept.
+const MAX_LINKS: usize = 100_000;
+
+/// What is known about a link to another site.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkState {
+    /// The link is waiting for its check.
+    Pending,
+    Working,
+    Broken(Error),
+}
+
+/// Checks the links to other sites, keeping their states. The workers stop
+/// once it is dropped.
+pub struct LinkChecker {
+    shared: Arc<Shared>,
+    /// The hosts with links waiting, each at most once.
+    ready: mpsc::Sender<String>,
+}
+
+/// The state shared by the workers.
+struct Shared {
+    fetcher: Arc<dyn Fetcher>,
+    states: Mutex<HashMap<Url, LinkState>>,
+    queue: Mutex<Queue>,
+    ready: tokio::sync::Mutex<mpsc::Receiver<String>>,
+    max_queued: usize,
+    max_links: usize,
+}
+
+/// The links waiting for their check, by host.
+#[derive(Default)]
+struct Queue {
+    hosts: HashMap<String, Host>,
+    queued: usize,
+}
+
+struct Host {
+    /// The links waiting, along with the user agent to check them as.
+    links: VecDeque<(Url, String)>,
+    /// When the next check of the host may start.
+    next: Instant,
+    /// Whether the host is queued for or held by a worker.
+    scheduled: bool,
+}
+
+impl LinkChecker {
+    pub fn new(fetcher: Arc<dyn Fetcher>) -> Self {
+        LinkChecker::with_limits(fetcher, MAX_QUEUED, MAX_LINKS)
+    }
+
+    fn with_limits(fetcher: Arc<dyn Fetcher>, max_queued: usize, max_links: usize) -> Self {
+        // Every host queued has a link waiting, so sending never fails.
+        let (ready, receiver) = mpsc::channel(max_queued);
+        let shared = Arc::new(Shared {
+            fetcher,
+            states: Mutex::new(HashMap::new()),
+            queue: Mutex::new(Queue::default()),
+            ready: tokio::sync::Mutex::new(receiver),
+            max_queued,
+            max_links,
+        });
+        for _ in 0..MAX_CHECKS {
+            tokio::task::spawn(work(shared.clone(), ready.downgrade()));
+        }
+        LinkChecker { shared, ready }
+    }
+
+    /// Check given link in the background, unless it has been already or
+    /// there is no room for it.
+    pub fn check(&self, url: Url, user_agent: &str) {
+        let mut states = self.shared.states.lock().unwrap();
+        if states.contains_key(&url) {
+            return;
+        }
+        if states.len() >= self.shared.max_links {
+            states.retain(|_, state| *state != LinkState::Working);
+        }
+        let mut queue = self.shared.queue.lock().unwrap();
+        if states.len() >= self.shared.max_links || queue.queued >= self.shared.max_queued {
+            tracing::debug!(url = %url, "Link check skipped, too many links");
+            return;
+        }
+        states.insert(url.clone(), LinkState::Pending);
+        drop(states);
+        if queue.hosts.len() >= MAX_HOSTS {
+            let now = Instant::now();
+            queue
+                .hosts
+                .retain(|_, host| host.scheduled || host.next > now);
+        }
+        let name = url.host_str().unwrap_or_default().to_string();
+        let host = queue.hosts.entry(name.clone()).or_insert_with(|| Host {
+            links: VecDeque::new(),
+            next: Instant::now(),
+            scheduled: false,
+        });
+        host.links.push_back((url, user_agent.to_string()));
+        let schedule = !host.scheduled;
+        host.scheduled = true;
+        queue.queued += 1;
+        if schedule {
+            let _ = self.ready.try_send(name);
+        }
+    }
+
+    /// The state of given link, None if it has not been asked to be checked.
+    pub fn state(&self, url: &Url) -> Option<LinkState> {
+        self.shared.states.lock().unwrap().get(url).cloned()
+    }
+}
+
+/// Check the links of the hosts in turn, until the checker is dropped.
+async fn work(shared: Arc<Shared>, ready: mpsc::WeakSender<String>) {
+    loop {
+        let name = match shared.ready.lock().await.recv().await {
+            Some(name) => name,
+            None => break,
+        };
+        let next = shared.queue.lock().unwrap().hosts[&name].next;
+        tokio::time::sleep_until(next).await;
+        let link = {
+            let mut queue = shared.queue.lock().unwrap();
+            let link = queue.hosts.get_mut(&name).and_then(|h| h.links.pop_front());
+            queue.queued -= link.is_some() as usize;
+            link
+        };
+        let (url, user_agent) = match link {
+            Some(link) => link,
+            None => continue,
+        };
+        let state = match shared.fetcher.check(&url, &user_agent).await {
+            Ok(status) if status.is_client_error() || status.is_server_error() => {
+                LinkState::Broken(Error::Status(status))
+            }
+            Ok(_) => LinkState::Working,
+            Err(e) => LinkState::Broken(e),
+        };
+        shared.states.lock().unwrap().insert(url, state);
+        // The host goes back to the end of the line if it has more links.
+        let again = {
+            let mut queue = shared.queue.lock().unwrap();
+            let host = queue.hosts.get_mut(&name).expect("scheduled host is kept");
+            host.next = Instant::now() + HOST_DELAY;
+            host.scheduled = !host.links.is_empty();
+            host.scheduled
+        };
+        if again {
+            match ready.upgrade() {
+                Some(ready) => {
+                    let _ = ready.try_send(name);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// A link found not working.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BrokenLink {
+    pub url: Url,
+    /// Status of the response, if one has been received.
+    pub status: Option<u16>,
+    pub error: String,
+    /// Whether the link leads to another site.
+    pub external: bool,
+    /// The pages linking to it.
+    pub pages: BTreeSet<Url>,
+}
+
+/// The broken links found on the pages of a domain, ordered by URL.
+///
+/// The links within the site are broken if fetching them failed for a reason
+/// other than the crawl not fetching them on purpose, e.g. by robots.txt.
+/// The links to other sites are broken if their check has failed, those not
+/// checked yet are left out.
+pub fn broken_links(
+    urls: &UrlSet,
+    scope: &SiteScope,
+    checker: Option<&LinkChecker>,
+) -> Vec<BrokenLink> {
+    let mut broken: BTreeMap<Url, BrokenLink> = BTreeMap::new();
+    for (url, info) in urls {
+        let page = match &info.result {
+            Ok(page) => page,
+            Err(_) => continue,
+        };
+        for link in page.links.iter() {
+            let external = !scope.same_site(url, &link);
+            let error = match (external, checker) {
+                (false, _) => urls
+                    .get(&link)
+                    .and_then(|info| info.result.as_ref().err())
+                    .filter(|e| e.class().is_some())
+                    .cloned(),
+                (true, Some(checker)) => match checker.state(&link) {
+                    Some(LinkState::Broken(e)) => Some(e),
+                    _ => None,
+                },
+                (true, None) => None,
+            };
+            if let Some(error) = error {
+                let entry = broken.entry(link.clone()).or_insert_with(|| BrokenLink {
+                    url: link,
+                    status: match &error {
+                        Error::Status(status) => Some(status.as_u16()),
+                        _ => None,
+                    },
+                    error: error.to_string(),
+                    external,
+                    pages: BTreeSet::new(),
+                });
+                entry.pages.insert((**url).clone());
+            }
+        }
+    }
+    broken.into_values().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::fetcher::MockFetcher;
+    use super::super::urlinfo::*;
+    use super::*;
+
+    fn parse(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_link_checker() {
+        let fetcher = MockFetcher::new()
+            .page("http://other.org/", "")
+            .document("http://other.org/logo.png", "image/png", "")
+            .latency(Duration::from_millis(100));
+        let checker = Arc::new(LinkChecker::new(Arc::new(fetcher)));
+        let links = [
+            "http://other.org/",
+            "http://other.org/logo.png",
+            "http://other.org/x",
+        ];
+        for link in &links {
+            checker.check(parse(link), "test");
+        }
+        checker.check(parse(links[0]), "test");
+        assert_eq!(checker.state(&parse(links[0])), Some(LinkState::Pending));
+        assert_eq!(checker.state(&parse("http://other.org/y")), None);
+
+        // The checks of the host are a second apart.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(checker.state(&parse(links[0])), Some(LinkState::Working));
+        assert_eq!(checker.state(&parse(links[1])), Some(LinkState::Pending));
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert_eq!(checker.state(&parse(links[1])), Some(LinkState::Working));
+        let not_found = Error::Status(reqwest::StatusCode::NOT_FOUND);
+        assert_eq!(
+            checker.state(&parse(links[2])),
+            Some(LinkState::Broken(not_found))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_link_checker_limits() {
+        let fetcher = MockFetcher::new()
+            .page("http://other.org/a", "")
+            .page("http://other.org/b", "")
+            .latency(Duration::from_millis(100));
+        let checker = LinkChecker::with_limits(Arc::new(fetcher), 2, 3);
+        let links = [
+            "http://other.org/a",
+            "http://other.org/b",
+            "http://other.org/c",
+            "http://other.org/d",
+        ];
+        let state = |i: usize| checker.state(&parse(links[i]));
+
+        // The links found while the queue is full are not checked.
+        for link in &links[..3] {
+            checker.check(parse(link), "test");
+        }
+        assert_eq!(state(2), None);
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert_eq!(state(1), Some(LinkState::Working));
+
+        // Those found when it has room again are.
+        checker.check(parse(links[2]), "test");
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(matches!(state(2), Some(LinkState::Broken(_))));
+
+        // The working links are forgotten to make room for more.
+        checker.check(parse(links[3]), "test");
+        assert_eq!(state(0), None);
+        assert_eq!(state(3), Some(LinkState::Pending));
+        assert!(matches!(state(2), Some(LinkState::Broken(_))));
+    }
+
+    #[tokio::test]
+    async fn test_broken_links() {
+        let page = |links: &[&str]| Page {
+            links: links.iter().map(|l| parse(l)).collect(),
+            ..Page::default()
+        };
+        let mut urls = UrlSet::new();
+        let mut add = |url: &str, result| {
+            let info = UrlInfo::from_result(result, Duration::ZERO);
+            urls.insert(Arc::new(parse(url)), info);
+        };
+        let links = [
+            "http://example.com/a",
+            "http://example.com/gone",
+            "http://example.com/private",
+            "http://other.org/gone",
+        ];
+        add("http://example.com/", Ok(page(&links)));
+        add("http://example.com/a", Ok(page(&links[1..])));
+        let not_found = || Error::Status(reqwest::StatusCode::NOT_FOUND);
+        add("http://example.com/gone", Err(not_found()));
+        add("http://example.com/private", Err(Error::Disallowed(None)));
+
+        let scope = SiteScope::default();
+        let report = broken_links(&urls, &scope, None);
+        let pages: BTreeSet<Url> = ["http://example.com/", "http://example.com/a"]
+            .iter()
+            .map(|p| parse(p))
+            .collect();
+        let gone = BrokenLink {
+            url: parse("http://example.com/gone"),
+            status: Some(404),
+            error: not_found().to_string(),
+            external: false,
+            pages: pages.clone(),
+        };
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0], gone);
+
+        let fetcher = MockFetcher::new();
+        let checker = Arc::new(LinkChecker::new(Arc::new(fetcher)));
+        checker.check(parse("http://other.org/gone"), "test");
+        while checker.state(&parse("http://other.org/gone")) == Some(LinkState::Pending) {
+            tokio::task::yield_now().await;
+        }
+        let report = broken_links(&urls, &scope, Some(&checker));
+        let external = BrokenLink {
+            url: parse("http://other.org/gone"),
+            external: true,
+            pages,
+            ..gone.clone()
+        };
+        assert_eq!(report, [gone, external]);
+    }
+}
@@ -0,0 +1,371 @@
+//! Paginated and filtered listing of URLs discovered for a domain.
+
+use super::urlinfo::*;
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use url::Url;
+
+/// Options controlling which part of the URL set is listed.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ListOptions {
+    /// Number of URLs to skip.
+    pub offset: usize,
+    /// Max number of URLs to return. All the remaining URLs if not specified.
+    pub limit: Option<usize>,
+    /// Only list URLs with matching HTTP status.
+    pub status: Option<StatusFilter>,
+    /// Only list URLs whose content type contains given string.
+    #[serde(rename = "type")]
+    pub content_type: Option<String>,
+    /// Only list URLs whose path starts with given prefix.
+    pub prefix: Option<String>,
+    /// Key to order the listing by.
+    pub sort: SortKey,
+    /// Direction of the ordering.
+    pub order: SortOrder,
+    /// List a path with and without a trailing slash once if both give the
+    /// same page, under the URL discovered first.
+    pub merge_slashes: bool,
+}
+
+/// Key the URL listing is ordered by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// Order in which the URLs were discovered.
+    Discovered,
+    /// The URL itself, lexicographically.
+    #[default]
+    Url,
+    /// HTTP status code. URLs without a status come first.
+    Status,
+    /// Size of the page body.
+    Size,
+    /// Time it took to fetch the URL.
+    Latency,
+}
+
+/// Direction of the listing ordering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Filter on HTTP status, either an exact code (`404`) or a class (`4xx`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum StatusFilter {
+    Code(u16),
+    Class(u16),
+}
+
+impl StatusFilter {
+    fn matches(self, status: reqwest::StatusCode) -> bool {
+        match self {
+            StatusFilter::Code(code) => status.as_u16() == code,
+            StatusFilter::Class(class) => status.as_u16() / 100 == class,
+        }
+    }
+}
+
+impl TryFrom<String> for StatusFilter {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid status filter: {}", s);
+        let s_lower = s.to_ascii_lowercase();
+        match s_lower.strip_suffix("xx") {
+            Some(class) => class.parse().map(StatusFilter::Class),
+            None => s_lower.parse().map(StatusFilter::Code),
+        }
+        .map_err(|_| invalid())
+    }
+}
+
+/// A page of URLs together with their metadata.
+#[derive(Debug, Clone, Default)]
+pub struct Listing {
+    /// Total number of matching URLs, regardless of pagination.
+    pub total: usize,
+    /// Position of the first returned URL in the full listing.
+    pub offset: usize,
+    /// The URLs on this page, in listing order.
+    pub urls: Vec<(Arc<Url>, UrlInfo)>,
+}
+
+impl ListOptions {
+    /// Select a page of matching URLs from given set.
+    ///
+    /// Entries that compare equal by the sort key are ordered by URL so that
+    /// consecutive pages are stable. The total count reflects the number of
+    /// URLs matching the filters.
+    pub fn apply(&self, urls: &UrlSet) -> Listing {
+        let mut entries: Vec<_> = urls
+            .iter()
+            .filter(|(url, info)| self.matches(url, info))
+            .filter(|(url, info)| !self.merge_slashes || !merged_into_twin(urls, url, info))
+            .collect();
+        entries.sort_unstable_by(|a, b| self.compare(*a, *b).then_with(|| a.0.cmp(b.0)));
+        let total = entries.len();
+        let urls = entries
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .map(|(url, info)| (url.clone(), info.clone()))
+            .collect();
+        Listing {
+            total,
+            offset: self.offset,
+            urls,
+        }
+    }
+
+    /// Compare two entries by the requested sort key and order.
+    fn compare(
+        &self,
+        (url_a, a): (&Arc<Url>, &UrlInfo),
+        (url_b, b): (&Arc<Url>, &UrlInfo),
+    ) -> Ordering {
+        let size = |info: &UrlInfo| info.result.as_ref().map(|p| p.size).ok();
+        let ord = match self.sort {
+            SortKey::Discovered => a.discovery.seq.cmp(&b.discovery.seq),
+            SortKey::Url => url_a.cmp(url_b),
+            SortKey::Status => a.status().cmp(&b.status()),
+            SortKey::Size => size(a).cmp(&size(b)),
+            SortKey::Latency => a.elapsed.cmp(&b.elapsed),
+        };
+        match self.order {
+            SortOrder::Asc => ord,
+            SortOrder::Desc => ord.reverse(),
+        }
+    }
+
+    /// Check whether given URL passes all the filters.
+    fn matches(&self, url: &Url, info: &UrlInfo) -> bool {
+        let status_ok = match self.status {
+            Some(filter) => info.status().is_some_and(|s| filter.matches(s)),
+            None => true,
+        };
+        let type_ok = match &self.content_type {
+            Some(t) => info
+                .content_type()
+                .is_some_and(|ct| ct.contains(t.as_str())),
+            None => true,
+        };
+        let prefix_ok = match &self.prefix {
+            Some(prefix) => url.path().starts_with(prefix.as_str()),
+            None => true,
+        };
+        status_ok && type_ok && prefix_ok
+    }
+}
+
+/// The URL differing from given one only by the trailing slash of its path,
+/// None for the root.
+fn slash_twin(url: &Url) -> Option<Url> {
+    let path = url.path();
+    if path == "/" {
+        return None;
+    }
+    let mut twin = url.clone();
+    match path.strip_suffix('/') {
+        Some(stripped) => twin.set_path(stripped),
+        None => twin.set_path(&format!("{}/", path)),
+    }
+    Some(twin)
+}
+
+/// Whether given URL is left out of the listing merging the slash twins: its
+/// twin has been fetched with the same status and content, and discovered
+/// first.
+fn merged_into_twin(urls: &UrlSet, url: &Url, info: &UrlInfo) -> bool {
+    let twin = match slash_twin(url) {
+        Some(twin) => twin,
+        None => return false,
+    };
+    let twin_info = match urls.get(&twin) {
+        Some(twin_info) => twin_info,
+        None => return false,
+    };
+    let same = match (&info.result, &twin_info.result) {
+        (Ok(a), Ok(b)) => {
+            a.status == b.status && a.content_hash.is_some_and(|h| b.content_hash == Some(h))
+        }
+        _ => false,
+    };
+    same && (twin_info.discovery.seq, &twin) < (info.discovery.seq, url)
+}
+
+impl Serialize for Listing {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut map = s.serialize_map(Some(3))?;
+        map.serialize_entry("total", &self.total)?;
+        map.serialize_entry("offset", &self.offset)?;
+        map.serialize_entry("urls", &OrderedUrls(&self.urls))?;
+        map.end()
+    }
+}
+
+/// Serialize URLs as a map while preserving the listing order.
+struct OrderedUrls<'a>(&'a [(Arc<Url>, UrlInfo)]);
+
+impl Serialize for OrderedUrls<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut map = s.serialize_map(Some(self.0.len()))?;
+        for (url, info) in self.0 {
+            map.serialize_entry(url.as_str(), &UrlRecord::new(url, info))?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn url_set(n: usize) -> UrlSet {
+        (0..n)
+            .map(|i| {
+                let url = Url::parse(&format!("http://example.com/{:03}", i)).unwrap();
+                let result = Err(Error::Fetch("test".to_string()));
+                let mut info = UrlInfo::from_result(result, Duration::from_millis(1));
+                info.discovery.seq = i as u64;
+                (Arc::new(url), info)
+            })
+            .collect()
+    }
+
+    fn paths(listing: &Listing) -> Vec<&str> {
+        listing.urls.iter().map(|(u, _)| u.path()).collect()
+    }
+
+    #[test]
+    fn unit_listing_unpaginated() {
+        let listing = ListOptions::default().apply(&url_set(5));
+        assert_eq!(listing.total, 5);
+        assert_eq!(paths(&listing), ["/000", "/001", "/002", "/003", "/004"]);
+    }
+
+    #[test]
+    fn unit_listing_pages() {
+        let urls = url_set(5);
+        let opts = |offset, limit| ListOptions {
+            offset,
+            limit: Some(limit),
+            ..ListOptions::default()
+        };
+        assert_eq!(paths(&opts(0, 2).apply(&urls)), ["/000", "/001"]);
+        assert_eq!(paths(&opts(2, 2).apply(&urls)), ["/002", "/003"]);
+        assert_eq!(paths(&opts(4, 2).apply(&urls)), ["/004"]);
+        let past_end = opts(10, 2).apply(&urls);
+        assert!(past_end.urls.is_empty());
+        assert_eq!(past_end.total, 5);
+    }
+
+    #[test]
+    fn unit_listing_filters() {
+        let entry = |path: &str, result| {
+            let url = Url::parse("http://example.com")
+                .unwrap()
+                .join(path)
+                .unwrap();
+            (
+                Arc::new(url),
+                UrlInfo::from_result(result, Duration::from_millis(1)),
+            )
+        };
+        let page = |status: u16| {
+            Ok(Page {
+                status: reqwest::StatusCode::from_u16(status).unwrap(),
+                ..Page::default()
+            })
+        };
+        let urls: UrlSet = vec![
+            entry("/blog/a", page(200)),
+            entry(
+                "/blog/b",
+                Err(Error::Status(reqwest::StatusCode::NOT_FOUND)),
+            ),
+            entry("/img.png", Err(Error::UnsupportedType("image/png".into()))),
+            entry("/x", Err(Error::Status(reqwest::StatusCode::GONE))),
+        ]
+        .into_iter()
+        .collect();
+
+        let filter = |query: &str| {
+            let opts: ListOptions = serde_urlencoded::from_str(query).unwrap();
+            let listing = opts.apply(&urls);
+            assert_eq!(listing.total, listing.urls.len());
+            paths(&listing).join(" ")
+        };
+        assert_eq!(filter("status=404"), "/blog/b");
+        assert_eq!(filter("status=4xx"), "/blog/b /x");
+        assert_eq!(filter("type=html"), "/blog/a");
+        assert_eq!(filter("type=image"), "/img.png");
+        assert_eq!(filter("prefix=/blog/"), "/blog/a /blog/b");
+        assert_eq!(filter("prefix=/blog/&status=2xx"), "/blog/a");
+        assert!(serde_urlencoded::from_str::<ListOptions>("status=abc").is_err());
+    }
+
+    #[test]
+    fn unit_listing_sort() {
+        let mut urls = url_set(4);
+        for (url, info) in urls.iter_mut() {
+            let odd = url.path().ends_with(&['1', '3'][..]);
+            info.elapsed = Duration::from_millis(if odd { 0 } else { 1 });
+        }
+        let sorted = |query: &str| {
+            let opts: ListOptions = serde_urlencoded::from_str(query).unwrap();
+            paths(&opts.apply(&urls)).join(" ")
+        };
+        assert_eq!(sorted(""), "/000 /001 /002 /003");
+        assert_eq!(sorted("sort=url&order=desc"), "/003 /002 /001 /000");
+        assert_eq!(sorted("sort=discovered&order=desc"), "/003 /002 /001 /000");
+
+        // Ties are broken by URL regardless of order.
+        assert_eq!(sorted("sort=latency"), "/001 /003 /000 /002");
+        assert_eq!(sorted("sort=latency&order=desc"), "/000 /002 /001 /003");
+        assert!(serde_urlencoded::from_str::<ListOptions>("sort=bogus").is_err());
+    }
+
+    #[test]
+    fn unit_listing_merge_slashes() {
+        let page = |hash| Page {
+            content_hash: Some(hash),
+            ..Page::default()
+        };
+        let entry = |seq, path: &str, hash| {
+            let url = Url::parse("http://example.com")
+                .unwrap()
+                .join(path)
+                .unwrap();
+            let mut info = UrlInfo::from_result(Ok(page(hash)), Duration::ZERO);
+            info.discovery.seq = seq;
+            (Arc::new(url), info)
+        };
+        let urls: UrlSet = vec![
+            entry(0, "/", 1),
+            entry(1, "/docs/", 2),
+            entry(2, "/docs", 2),
+            entry(3, "/blog", 3),
+            entry(4, "/blog/", 4),
+        ]
+        .into_iter()
+        .collect();
+        let listed = |query: &str| {
+            let opts: ListOptions = serde_urlencoded::from_str(query).unwrap();
+            paths(&opts.apply(&urls)).join(" ")
+        };
+        assert_eq!(listed(""), "/ /blog /blog/ /docs /docs/");
+        // Only the twins with the same content are merged.
+        assert_eq!(listed("merge_slashes=true"), "/ /blog /blog/ /docs/");
+    }
+}
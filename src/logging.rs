@@ -0,0 +1,157 @@
+//! Logging and tracing of API requests and fetches.
+
+#[cfg(feature = "otlp")]
+use opentelemetry::trace::TracerProvider as _;
+#[cfg(feature = "otlp")]
+use opentelemetry::KeyValue;
+#[cfg(feature = "otlp")]
+use opentelemetry_otlp::WithExportConfig;
+#[cfg(feature = "otlp")]
+use opentelemetry_sdk::trace::TracerProvider;
+#[cfg(feature = "otlp")]
+use opentelemetry_sdk::{runtime, Resource};
+use std::io::IsTerminal;
+use std::str::FromStr;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Target of the messages warp logs for every request it traces.
+const WARP_TRACE: &str = "warp::filters::trace";
+
+/// Format of the log lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable text.
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format: {}", s)),
+        }
+    }
+}
+
+/// Install the global log subscriber writing to stderr.
+///
+/// The level is either a plain level like `debug` or a full filter directive
+/// like `croolr=debug,warp=info`. If an OTLP endpoint is given, the spans are
+/// exported there as OpenTelemetry traces too. The returned handle has to be
+/// shut down before exiting so that the pending spans are not lost.
+pub fn init(
+    level: &str,
+    format: LogFormat,
+    otlp_endpoint: Option<&str>,
+) -> Result<Option<Telemetry>, String> {
+    let mut filter = EnvFilter::try_new(level).map_err(|e| e.to_string())?;
+    if !level.contains(WARP_TRACE) {
+        // The request spans already carry everything warp would log there.
+        let directive = format!("{}=warn", WARP_TRACE)
+            .parse()
+            .expect("valid directive");
+        filter = filter.add_directive(directive);
+    }
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    let telemetry = otlp_endpoint.map(Telemetry::new).transpose()?;
+    #[cfg(feature = "otlp")]
+    let otel = telemetry
+        .as_ref()
+        .map(|t| tracing_opentelemetry::layer().with_tracer(t.provider.tracer("croolr")));
+    #[cfg(not(feature = "otlp"))]
+    let otel = None::<tracing_subscriber::layer::Identity>;
+    let registry = tracing_subscriber::registry().with(filter).with(otel);
+    match format {
+        LogFormat::Text => registry.with(fmt).try_init(),
+        LogFormat::Json => registry.with(fmt.json()).try_init(),
+    }
+    .map_err(|e| e.to_string())?;
+    Ok(telemetry)
+}
+
+/// Export of spans to an OpenTelemetry collector.
+pub struct Telemetry {
+    #[cfg(feature = "otlp")]
+    provider: TracerProvider,
+}
+
+#[cfg(not(feature = "otlp"))]
+impl Telemetry {
+    fn new(_endpoint: &str) -> Result<Self, String> {
+        Err("exporting traces needs croolr built with the otlp feature".to_string())
+    }
+
+    pub fn shutdown(self) {}
+}
+
+#[cfg(feature = "otlp")]
+impl Telemetry {
+    /// Set up the export to the OTLP/gRPC endpoint, e.g. `http://localhost:4317`.
+    fn new(endpoint: &str) -> Result<Self, String> {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let resource = Resource::new([KeyValue::new("service.name", "croolr")]);
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(resource)
+            .build();
+        Ok(Telemetry { provider })
+    }
+
+    /// Export the spans not exported yet and stop.
+    pub fn shutdown(self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Failed to export traces: {}", e);
+        }
+    }
+}
+
+/// Warp filter wrapping the handling of every API request in a span.
+pub fn request_spans() -> warp::trace::Trace<impl Fn(warp::trace::Info) -> tracing::Span + Clone> {
+    warp::trace(|info| {
+        tracing::info_span!(
+            "request",
+            otel.kind = "server",
+            http.method = %info.method(),
+            http.path = info.path(),
+        )
+    })
+}
+
+/// Warp filter logging every API request.
+pub fn requests() -> warp::log::Log<impl Fn(warp::log::Info) + Copy> {
+    warp::log::custom(|info| {
+        tracing::info!(
+            target: "croolr::api",
+            method = %info.method(),
+            path = info.path(),
+            status = info.status().as_u16(),
+            duration_ms = info.elapsed().as_secs_f64() * 1000.0,
+            remote = %info.remote_addr().map_or("-".into(), |a| a.to_string()),
+            "request"
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_log_format() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert_eq!("text".parse(), Ok(LogFormat::Text));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
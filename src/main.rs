@@ -1,19 +1,94 @@
-mod croolr;
-
 use structopt::StructOpt;
 
 /// An experimental web crawler.
 ///
-/// Starts a server with the following API endoints:
+/// Starts a server with the following API endoints, all of the domain ones
+/// living under /v1 (the unversioned paths are deprecated aliases):
 ///
-/// /urls/domain.com  - List URLs discovered for given domain
+/// /urls/domain.com  - List URLs discovered for given domain (JSON, NDJSON, CSV or text)
 ///
 /// /count/domain.com - Count number of discovered URLs under given domain
 ///
-/// /crawl/domain.com - Start crawling given domain
+/// /sitemap/domain.com - Sitemap of the fetched HTML pages of given domain
+///
+/// /crawl/domain.com?seed=http://domain.com:8080/ - Start crawling given domain,
+/// optionally at given URL of it
+///
+/// POST /crawl-batch - Start crawling a list of domains (JSON or one per line)
+///
+/// /pause/domain.com, /resume/domain.com, /stop/domain.com - Control the crawl
+///
+/// /domains - All the known domains with their URL counts and crawl states
+///
+/// /search?q=substring - Find URLs (and optionally page titles) across all domains
+///
+/// /stats - Global crawler statistics
+///
+/// /summary/domain.com - Result statistics for given domain
+///
+/// /wait/domain.com?timeout=30s - Wait for the crawl to finish, then get its result statistics
+///
+/// /queue/domain.com - Queued and in-flight fetches for given domain
+///
+/// /events/domain.com - Stream of crawl events for given domain (SSE)
+///
+/// /crawl-stream/domain.com - Crawl given domain, streaming the fetched URLs until it finishes (SSE)
+///
+/// /ws - Websocket for crawl events and control commands
+///
+/// /admin/config - Inspect (GET) and change (PUT) the live crawler settings
+///
+/// POST /admin/reload - Re-read the configuration file, also done on SIGHUP
+///
+/// /metrics - Prometheus metrics
+///
+/// /url?u=http://domain.com/page - Everything known about given URL
+///
+/// /outlinks/domain.com?page=http://domain.com/page - Links found on given page
+///
+/// /robots/domain.com?path=/page - The domain's robots.txt and whether a path may be crawled
+///
+/// /graphql - GraphQL queries over domains, crawls, URLs and links
+///
+/// /openapi.json, /docs - OpenAPI description of the API and its Swagger UI
+///
+/// With --grpc-port, a gRPC interface described in proto/croolr.proto is
+/// served as well.
+///
+/// With --discovery-only, assets and pages matching the --leaf patterns are
+/// listed without being fetched.
+///
+/// With --script, a Rhai script decides which links to follow and extracts
+/// custom fields from the fetched pages.
+///
+/// With --plugin, WebAssembly plugins add links to follow and metadata to the
+/// fetched pages, given croolr is built with the wasm feature.
+///
+/// With --headless, the pages are rendered by a headless browser before their
+/// links are extracted, given croolr is built with the headless feature.
+///
+/// With --seeds, the domains and URLs listed in given file are crawled on start.
+///
+/// With --checkpoint, the server saves the progress of its crawls to given file
+/// when shutting down and continues them from it on the next start.
+///
+/// With --daemon, the server runs in the background. With --systemd, it serves
+/// the sockets passed by systemd socket activation.
+///
+/// The crawl subcommand crawls a single domain or URL (or those listed by --seeds) to
+/// completion and prints the results instead of starting the server. With
+/// --resume, it saves its progress to given file and continues from it when
+/// run again after an interruption.
+///
+/// The bench subcommand crawls a mock site served in-process and reports the
+/// pages fetched per second and the fetch latency percentiles. With
+/// --no-keepalive, every page is fetched over a new connection.
 #[derive(StructOpt, Debug)]
 #[structopt(name = "croolr")]
 struct Config {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
     /// Port to bind to
     #[structopt(short, long, default_value = "3030")]
     port: u16,
@@ -22,13 +97,484 @@ struct Config {
     #[structopt(long, default_value = "127.0.0.1")]
     host_ip: std::net::IpAddr,
 
+    /// Address and port to serve the API on, may be given multiple times, overrides --host-ip and --port
+    #[structopt(long, use_delimiter = true)]
+    listen: Vec<std::net::SocketAddr>,
+
     /// Max number of concurrent web requests
-    #[structopt(long, name = "limit", default_value = "16")]
+    #[structopt(long, name = "limit", default_value = "16", global = true)]
     fetch_limit: u32,
+
+    /// Min milliseconds between starting fetches from the same host
+    #[structopt(long, default_value = "0", global = true)]
+    host_delay: u64,
+
+    /// Number of fetches from the same host that may start without the host delay between them
+    #[structopt(long, default_value = "1", global = true)]
+    host_burst: u32,
+
+    /// User agent sent with the fetches
+    #[structopt(long, default_value = croolr::settings::DEFAULT_USER_AGENT, global = true)]
+    user_agent: String,
+
+    /// Upper bounds of the per-domain response time histogram buckets in milliseconds
+    #[structopt(
+        long,
+        default_value = "10,25,50,100,250,500,1000,2500,5000,10000",
+        use_delimiter = true
+    )]
+    latency_buckets: Vec<u64>,
+
+    /// Upper bounds of the per-domain page size histogram buckets in bytes
+    #[structopt(
+        long,
+        default_value = "1024,4096,16384,65536,262144,1048576,4194304",
+        use_delimiter = true
+    )]
+    size_buckets: Vec<u64>,
+
+    /// Max number of links to follow from the seed of a crawl, unlimited if not given
+    #[structopt(long, global = true)]
+    max_depth: Option<u32>,
+
+    /// Number of messages the crawler buffers before the fetches finding links wait
+    #[structopt(long, default_value = "32", global = true)]
+    channel_capacity: usize,
+
+    /// Allow fewer concurrent fetches than the fetch limit while short of open files or memory
+    #[structopt(long, global = true)]
+    auto_fetch_limit: bool,
+
+    /// Resident memory in MiB the auto-tuned fetch limit keeps below
+    #[structopt(long, global = true, requires = "auto-fetch-limit")]
+    memory_limit: Option<u64>,
+
+    /// Max number of domains crawled at once, further crawls waiting in the pending state
+    #[structopt(long, global = true)]
+    max_active_domains: Option<u32>,
+
+    /// Max number of idle connections kept open to each host, the fetch limit if not given
+    #[structopt(long, global = true)]
+    pool_max_idle_per_host: Option<usize>,
+
+    /// Seconds idle connections are kept open for
+    #[structopt(long, default_value = "90", global = true)]
+    pool_idle_timeout: u64,
+
+    /// Max number of pages kept in memory to answer repeated requests for them
+    #[structopt(long, default_value = "0", global = true)]
+    response_cache: usize,
+
+    /// Seconds pages are answered from the response cache for
+    #[structopt(long, default_value = "60", global = true)]
+    response_cache_ttl: u64,
+
+    /// What to do with pages redirecting to another host: follow their links there, record them or drop them
+    #[structopt(long, default_value = "follow", global = true)]
+    cross_host_redirects: croolr::settings::RedirectPolicy,
+
+    /// Crawl and report www.example.com and example.com as one site
+    #[structopt(long, global = true)]
+    merge_www: bool,
+
+    /// Which ports of a host links are followed to: any, or the same as the page they are found on
+    #[structopt(long, default_value = "any", global = true)]
+    port_policy: croolr::settings::PortPolicy,
+
+    /// Turn /path and /path/ into one form before crawling them: keep them as found, add the slash or strip it
+    #[structopt(long, default_value = "keep", global = true)]
+    trailing_slash: croolr::settings::TrailingSlash,
+
+    /// Keep the query parameters found to carry sessions in the URLs rather than dropping them
+    #[structopt(long, global = true)]
+    no_session_detection: bool,
+
+    /// Check the links to other sites found on the pages, reporting those not working with the broken links
+    #[structopt(long, global = true)]
+    check_external: bool,
+
+    /// Render the pages in a headless browser before extracting their links
+    #[structopt(long, global = true)]
+    headless: bool,
+
+    /// Directory to keep a screenshot of each page in, taken by a headless browser
+    #[structopt(long, global = true, parse(from_os_str))]
+    screenshots: Option<std::path::PathBuf>,
+
+    /// Headless Chrome or Chromium executable rendering the pages and taking the screenshots
+    #[structopt(long, global = true, default_value = croolr::settings::DEFAULT_BROWSER, parse(from_os_str))]
+    browser: std::path::PathBuf,
+
+    /// Only enumerate the URLs, fetching neither assets nor leaf pages
+    #[structopt(long, global = true)]
+    discovery_only: bool,
+
+    /// Robots.txt style pattern of the leaf pages not fetched in discovery-only mode, may be given multiple times
+    #[structopt(
+        long = "leaf",
+        global = true,
+        requires = "discovery-only",
+        number_of_values = 1,
+        parse(try_from_str = croolr::discovery::parse_pattern)
+    )]
+    leaf_patterns: Vec<String>,
+
+    /// Robots.txt style pattern of the URLs not fetched as they look like they change some state, e.g. */logout, may be given multiple times, replacing the default ones
+    #[structopt(
+        long = "unsafe",
+        global = true,
+        number_of_values = 1,
+        parse(try_from_str = croolr::discovery::parse_pattern)
+    )]
+    unsafe_patterns: Vec<String>,
+
+    /// Fetch the URLs looking like they change some state as well
+    #[structopt(long, global = true, conflicts_with = "unsafe-patterns")]
+    fetch_unsafe: bool,
+
+    /// Rhai script deciding which links to follow and extracting custom fields from the pages
+    #[structopt(long, global = true, parse(from_os_str))]
+    script: Option<std::path::PathBuf>,
+
+    /// WebAssembly plugin processing the fetched pages, may be given multiple times
+    #[structopt(
+        long = "plugin",
+        global = true,
+        number_of_values = 1,
+        parse(from_os_str)
+    )]
+    plugins: Vec<std::path::PathBuf>,
+
+    /// Built-in analyzer run on the fetched pages: alt-text or headings, may be given multiple times
+    #[structopt(long = "analyzer", global = true, number_of_values = 1)]
+    analyzers: Vec<croolr::analyzer::BuiltinAnalyzer>,
+
+    /// File listing domains or URLs to start crawling at on start, one per line
+    #[structopt(long, global = true, parse(from_os_str))]
+    seeds: Option<std::path::PathBuf>,
+
+    /// TOML file with settings and API keys overriding the options, re-read on SIGHUP
+    #[structopt(long = "config", parse(from_os_str))]
+    config_file: Option<std::path::PathBuf>,
+
+    /// API key granting full access, may be given multiple times
+    #[structopt(
+        long = "api-key",
+        name = "key",
+        env = "CROOLR_API_KEYS",
+        use_delimiter = true
+    )]
+    api_keys: Vec<String>,
+
+    /// API key granting read-only access, may be given multiple times
+    #[structopt(
+        long = "read-key",
+        name = "read-key",
+        env = "CROOLR_READ_KEYS",
+        use_delimiter = true
+    )]
+    read_keys: Vec<String>,
+
+    /// Max API requests per second per client, 0 to disable rate limiting
+    #[structopt(long, default_value = "10")]
+    rate_limit: f64,
+
+    /// Number of API requests a client may burst above the rate limit
+    #[structopt(long, default_value = "20")]
+    rate_burst: u32,
+
+    /// Origin allowed to make cross-origin requests, `*` for any, may be given multiple times
+    #[structopt(long, parse(try_from_str = croolr::cors::parse_origin))]
+    cors_origin: Vec<String>,
+
+    /// HTTP methods allowed in cross-origin requests
+    #[structopt(long, default_value = "GET", use_delimiter = true)]
+    cors_methods: Vec<warp::http::Method>,
+
+    /// Never compress API responses
+    #[structopt(long)]
+    no_compression: bool,
+
+    /// Seconds to wait for fetches in progress to finish when shutting down
+    #[structopt(long, default_value = "30")]
+    shutdown_timeout: u64,
+
+    /// File to save the progress of the crawls to when shutting down and to continue them from on start
+    #[structopt(long, parse(from_os_str))]
+    checkpoint: Option<std::path::PathBuf>,
+
+    /// Serve the API on the sockets passed by systemd socket activation instead of binding
+    #[structopt(long, conflicts_with_all = &["daemon", "tls-cert"])]
+    systemd: bool,
+
+    /// Detach from the terminal and run the server in the background
+    #[structopt(long)]
+    daemon: bool,
+
+    /// File to write the process ID to when running as a daemon
+    #[structopt(long, requires = "daemon", parse(from_os_str))]
+    pidfile: Option<std::path::PathBuf>,
+
+    /// File to append the output of the daemon to, including the logs
+    #[structopt(long, requires = "daemon", parse(from_os_str))]
+    log_file: Option<std::path::PathBuf>,
+
+    /// Port to serve the gRPC interface on (plain HTTP/2) at --host-ip, disabled by default
+    #[structopt(long)]
+    grpc_port: Option<u16>,
+
+    /// PEM file with the TLS certificate chain, enables HTTPS
+    #[structopt(long, requires = "tls-key", parse(from_os_str))]
+    tls_cert: Option<std::path::PathBuf>,
+
+    /// PEM file with the TLS private key
+    #[structopt(long, requires = "tls-cert", parse(from_os_str))]
+    tls_key: Option<std::path::PathBuf>,
+
+    /// Log level or filter directives, e.g. `debug` or `croolr=debug,warp=info`
+    #[structopt(long, default_value = "info", global = true)]
+    log_level: String,
+
+    /// Format of the log lines, `text` or `json`
+    #[structopt(long, default_value = "text", global = true)]
+    log_format: croolr::logging::LogFormat,
+
+    /// OTLP/gRPC endpoint to export traces to, e.g. `http://localhost:4317`
+    #[structopt(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
 }
 
-#[tokio::main]
-async fn main() {
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Crawl a domain to completion and print one record per URL to stdout
+    Crawl {
+        /// The domain or URL to crawl, in addition to those in the --seeds file
+        #[structopt(parse(try_from_str = croolr::batch::seed_url))]
+        seed: Option<url::Url>,
+
+        /// Output format: `ndjson`, `json`, `csv` or `text`
+        #[structopt(long, default_value = "ndjson")]
+        format: croolr::export::Format,
+
+        /// Checkpoint file to continue an interrupted crawl from and to save the progress to
+        #[structopt(long, parse(from_os_str))]
+        resume: Option<std::path::PathBuf>,
+    },
+    /// Crawl a built-in mock site and report the throughput and fetch latency
+    Bench {
+        /// Number of links to deeper pages on each page of the site
+        #[structopt(long, default_value = "10")]
+        fan_out: usize,
+
+        /// Number of levels of pages below the home page
+        #[structopt(long, default_value = "3")]
+        depth: u32,
+
+        /// Open a new connection for every page instead of reusing them
+        #[structopt(long)]
+        no_keepalive: bool,
+    },
+}
+
+fn main() {
     let config = Config::from_args();
-    croolr::server::start(config.host_ip, config.port, config.fetch_limit).await;
+    let daemon = croolr::daemon::Options {
+        pidfile: config.pidfile.clone(),
+        log_file: config.log_file.clone(),
+    };
+    if config.daemon {
+        if config.command.is_some() {
+            eprintln!("The --daemon option only applies to the server");
+            std::process::exit(2);
+        }
+        // Forking has to happen before the runtime starts its threads.
+        if let Err(e) = croolr::daemon::daemonize(&daemon) {
+            eprintln!("Cannot run as a daemon: {}", e);
+            std::process::exit(1);
+        }
+    }
+    // Taken before the runtime starts its threads, as the environment
+    // variables passing them are removed.
+    let listeners = match config.systemd && config.command.is_none() {
+        true => match croolr::systemd::listeners() {
+            Ok(listeners) => listeners,
+            Err(e) => {
+                eprintln!("Socket activation failed: {}", e);
+                std::process::exit(2);
+            }
+        },
+        false => Vec::new(),
+    };
+    let runtime = tokio::runtime::Runtime::new().expect("cannot start the async runtime");
+    runtime.block_on(run(config, listeners));
+    croolr::daemon::remove_pidfile(&daemon);
+}
+
+async fn run(config: Config, listeners: Vec<std::net::TcpListener>) {
+    let otlp_endpoint = config.otlp_endpoint.as_deref();
+    let telemetry = match croolr::logging::init(&config.log_level, config.log_format, otlp_endpoint)
+    {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("Invalid logging configuration: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let buckets = match croolr::summary::Buckets::new(config.latency_buckets, config.size_buckets) {
+        Ok(buckets) => buckets,
+        Err(e) => {
+            eprintln!("Invalid histogram buckets: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let unsafe_patterns = match (config.fetch_unsafe, config.unsafe_patterns) {
+        (true, _) => Vec::new(),
+        (false, patterns) if patterns.is_empty() => croolr::discovery::DEFAULT_UNSAFE_PATTERNS
+            .iter()
+            .map(|p| p.to_string())
+            .collect(),
+        (false, patterns) => patterns,
+    };
+    let settings = croolr::settings::Settings {
+        fetch_limit: config.fetch_limit,
+        host_delay_ms: config.host_delay,
+        host_burst: config.host_burst,
+        user_agent: config.user_agent,
+        buckets,
+        discovery_only: config.discovery_only,
+        leaf_patterns: config.leaf_patterns,
+        unsafe_patterns,
+        max_depth: config.max_depth,
+        script: config.script,
+        plugins: config.plugins,
+        analyzers: config.analyzers,
+        channel_capacity: config.channel_capacity,
+        pool_max_idle_per_host: config.pool_max_idle_per_host,
+        pool_idle_timeout_secs: config.pool_idle_timeout,
+        auto_fetch_limit: config.auto_fetch_limit,
+        memory_limit_mb: config.memory_limit,
+        response_cache_size: config.response_cache,
+        response_cache_ttl_secs: config.response_cache_ttl,
+        cross_host_redirects: config.cross_host_redirects,
+        merge_www: config.merge_www,
+        port_policy: config.port_policy,
+        trailing_slash: config.trailing_slash,
+        detect_session_params: !config.no_session_detection,
+        check_external: config.check_external,
+        headless: config.headless,
+        screenshot_dir: config.screenshots,
+        browser: config.browser,
+        max_active_domains: config.max_active_domains,
+    };
+    let keys = croolr::auth::Keys {
+        write: config.api_keys.into_iter().collect(),
+        read: config.read_keys.into_iter().collect(),
+    };
+    let (settings, keys, source) = match config.config_file {
+        Some(path) => {
+            let source = croolr::config::Source {
+                path,
+                settings,
+                keys,
+            };
+            match source.load() {
+                Ok((settings, keys)) => (settings, keys, Some(source)),
+                Err(e) => {
+                    eprintln!("Invalid configuration file: {}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        None => (settings, keys, None),
+    };
+    let seeds = match &config.seeds {
+        Some(path) => match croolr::batch::read_seed_file(path) {
+            Ok(seeds) => seeds,
+            Err(e) => {
+                eprintln!("Invalid seed file: {}", e);
+                std::process::exit(2);
+            }
+        },
+        None => Vec::new(),
+    };
+    if let Some(Command::Crawl {
+        seed,
+        format,
+        resume,
+    }) = config.command
+    {
+        let seeds: Vec<_> = seed.into_iter().chain(seeds).collect();
+        if seeds.is_empty() && resume.is_none() {
+            eprintln!("Nothing to crawl, give a domain or URL, --seeds or --resume");
+            std::process::exit(2);
+        }
+        let mut stdout = std::io::stdout();
+        let checkpoint = resume.as_deref();
+        let result = croolr::oneshot::crawl(settings, seeds, checkpoint, format, &mut stdout).await;
+        if let Some(telemetry) = telemetry {
+            telemetry.shutdown();
+        }
+        if let Err(e) = result {
+            eprintln!("Crawl failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Bench {
+        fan_out,
+        depth,
+        no_keepalive,
+    }) = config.command
+    {
+        let site = croolr::bench::Site { fan_out, depth };
+        let result = croolr::bench::run(settings, site, !no_keepalive).await;
+        if let Some(telemetry) = telemetry {
+            telemetry.shutdown();
+        }
+        match result {
+            Ok(report) => print!("{}", report),
+            Err(e) => {
+                eprintln!("Benchmark failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let burst = config.rate_burst;
+    let host_ip = config.host_ip;
+    let opts = croolr::server::Options {
+        listen: match (config.systemd, config.listen.is_empty()) {
+            (true, _) => Vec::new(),
+            (false, true) => vec![(config.host_ip, config.port).into()],
+            (false, false) => config.listen,
+        },
+        listeners,
+        settings,
+        keys,
+        rate_limit: Some(config.rate_limit)
+            .filter(|&rate| rate > 0.0)
+            .map(|rate| croolr::ratelimit::Limiter::new(rate, burst)),
+        cors: croolr::cors::CorsConfig {
+            origins: config.cors_origin,
+            methods: config.cors_methods,
+        },
+        compression: !config.no_compression,
+        shutdown_timeout: std::time::Duration::from_secs(config.shutdown_timeout),
+        tls: config
+            .tls_cert
+            .zip(config.tls_key)
+            .map(|(cert, key)| croolr::server::TlsFiles { cert, key }),
+        grpc: config.grpc_port.map(|port| (host_ip, port).into()),
+        config: source,
+        seeds,
+        checkpoint: config.checkpoint,
+    };
+    let result = croolr::server::start(opts).await;
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown();
+    }
+    if let Err(e) = result {
+        eprintln!("Cannot start the server: {}", e);
+        std::process::exit(2);
+    }
 }
@@ -0,0 +1,507 @@
+//! Process-wide metrics exported in the Prometheus text format.
+
+use super::stats::MemoryEstimate;
+use super::summary::{Distribution, Report};
+use super::urlinfo::*;
+
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The global metrics registry.
+pub static METRICS: Metrics = Metrics::new();
+
+/// Error kinds fetch errors are broken down by.
+const ERROR_KINDS: [&str; 6] = [
+    "fetch",
+    "dns",
+    "timeout",
+    "tls",
+    "status",
+    "unsupported_type",
+];
+
+/// Upper bounds of the fetch latency histogram buckets in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Upper bounds of the crawler channel send wait histogram buckets in seconds.
+const SEND_WAIT_BUCKETS: [f64; 7] = [0.0001, 0.001, 0.01, 0.1, 1.0, 10.0, 60.0];
+
+/// All the metrics collected by the crawler.
+pub struct Metrics {
+    pub urls_discovered: Counter,
+    pub pages_fetched: Counter,
+    pub bytes_downloaded: Counter,
+    fetch_errors: [Counter; ERROR_KINDS.len()],
+    fetch_latency: Histogram<{ LATENCY_BUCKETS.len() }>,
+    pub queue_depth: Gauge,
+    pub in_flight: Gauge,
+    /// Fetches allowed at once, lowered by the auto-tuning under pressure.
+    pub fetch_limit: Gauge,
+    /// Messages waiting in the crawler channel.
+    pub channel_occupancy: Gauge,
+    send_wait: Histogram<{ SEND_WAIT_BUCKETS.len() }>,
+    /// Host name lookups started, the other requests are answered from the
+    /// DNS cache.
+    pub dns_lookups: Counter,
+    /// Lookups started ahead of the requests to a new domain.
+    pub dns_prefetches: Counter,
+    pub connections_opened: Counter,
+    /// Requests sent over a connection kept open by an earlier one.
+    pub connections_reused: Counter,
+    pub tls_handshakes: Counter,
+    /// Tasks running the fetches.
+    pub fetch_workers: Gauge,
+    pub fetch_panics: Counter,
+    /// Page requests answered by the response cache.
+    pub response_cache_hits: Counter,
+    pub response_cache_misses: Counter,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: Counter = Counter::new();
+        Metrics {
+            urls_discovered: Counter::new(),
+            pages_fetched: Counter::new(),
+            bytes_downloaded: Counter::new(),
+            fetch_errors: [ZERO; ERROR_KINDS.len()],
+            fetch_latency: Histogram::new(&LATENCY_BUCKETS),
+            queue_depth: Gauge::new(),
+            in_flight: Gauge::new(),
+            fetch_limit: Gauge::new(),
+            channel_occupancy: Gauge::new(),
+            send_wait: Histogram::new(&SEND_WAIT_BUCKETS),
+            dns_lookups: Counter::new(),
+            dns_prefetches: Counter::new(),
+            connections_opened: Counter::new(),
+            connections_reused: Counter::new(),
+            tls_handshakes: Counter::new(),
+            fetch_workers: Gauge::new(),
+            fetch_panics: Counter::new(),
+            response_cache_hits: Counter::new(),
+            response_cache_misses: Counter::new(),
+        }
+    }
+
+    /// Account for a message sent to the crawler, given how long it waited
+    /// for room in the channel.
+    pub fn record_send_wait(&self, wait: Duration) {
+        self.send_wait.observe(wait);
+    }
+
+    /// Account for a finished fetch.
+    pub fn record_fetch(&self, info: &UrlInfo) {
+        self.pages_fetched.inc();
+        self.fetch_latency.observe(info.elapsed);
+        match &info.result {
+            Ok(page) => self.bytes_downloaded.add(page.size as u64),
+            Err(err) => {
+                if let Some(idx) = ERROR_KINDS.iter().position(|&k| k == err.kind()) {
+                    self.fetch_errors[idx].inc();
+                }
+            }
+        }
+    }
+
+    /// Render all the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let singles: [(&str, &str, &str, &dyn Display); 16] = [
+            (
+                "urls_discovered_total",
+                "counter",
+                "URLs discovered.",
+                &self.urls_discovered.get(),
+            ),
+            (
+                "pages_fetched_total",
+                "counter",
+                "Fetches finished.",
+                &self.pages_fetched.get(),
+            ),
+            (
+                "downloaded_bytes_total",
+                "counter",
+                "Body bytes downloaded.",
+                &self.bytes_downloaded.get(),
+            ),
+            (
+                "queue_depth",
+                "gauge",
+                "URLs waiting to be fetched.",
+                &self.queue_depth.get(),
+            ),
+            (
+                "fetches_in_flight",
+                "gauge",
+                "Fetches in progress.",
+                &self.in_flight.get(),
+            ),
+            (
+                "fetch_limit",
+                "gauge",
+                "Fetches allowed at once.",
+                &self.fetch_limit.get(),
+            ),
+            (
+                "channel_occupancy",
+                "gauge",
+                "Messages waiting for the crawler.",
+                &self.channel_occupancy.get(),
+            ),
+            (
+                "dns_lookups_total",
+                "counter",
+                "Host name lookups, not counting those answered from the cache.",
+                &self.dns_lookups.get(),
+            ),
+            (
+                "dns_prefetches_total",
+                "counter",
+                "Host name lookups started ahead of the requests.",
+                &self.dns_prefetches.get(),
+            ),
+            (
+                "connections_opened_total",
+                "counter",
+                "Connections opened for the requests.",
+                &self.connections_opened.get(),
+            ),
+            (
+                "connections_reused_total",
+                "counter",
+                "Requests sent over a connection kept open.",
+                &self.connections_reused.get(),
+            ),
+            (
+                "tls_handshakes_total",
+                "counter",
+                "TLS handshakes made when opening connections.",
+                &self.tls_handshakes.get(),
+            ),
+            (
+                "fetch_workers",
+                "gauge",
+                "Tasks running the fetches.",
+                &self.fetch_workers.get(),
+            ),
+            (
+                "fetch_panics_total",
+                "counter",
+                "Fetches that panicked.",
+                &self.fetch_panics.get(),
+            ),
+            (
+                "response_cache_hits_total",
+                "counter",
+                "Page requests answered by the response cache.",
+                &self.response_cache_hits.get(),
+            ),
+            (
+                "response_cache_misses_total",
+                "counter",
+                "Page requests not found in the response cache.",
+                &self.response_cache_misses.get(),
+            ),
+        ];
+        for (name, kind, help, value) in &singles {
+            header(&mut out, name, kind, help);
+            sample(&mut out, name, "", value);
+        }
+        header(
+            &mut out,
+            "fetch_errors_total",
+            "counter",
+            "Failed fetches by error kind.",
+        );
+        for (kind, counter) in ERROR_KINDS.iter().zip(&self.fetch_errors) {
+            let labels = format!("kind=\"{}\"", kind);
+            sample(&mut out, "fetch_errors_total", &labels, counter.get());
+        }
+        header(
+            &mut out,
+            "fetch_duration_seconds",
+            "histogram",
+            "Fetch latency.",
+        );
+        self.fetch_latency
+            .render(&mut out, "fetch_duration_seconds");
+        header(
+            &mut out,
+            "channel_send_wait_seconds",
+            "histogram",
+            "Time messages waited for room in the crawler channel.",
+        );
+        self.send_wait.render(&mut out, "channel_send_wait_seconds");
+        out
+    }
+}
+
+/// Render the per-domain histograms of fetch latency and page size.
+pub fn render_domains(out: &mut String, reports: &[(Domain, Report)]) {
+    render_domain_histogram(
+        out,
+        "domain_fetch_duration_seconds",
+        "Fetch latency per domain.",
+        1000.0,
+        reports.iter().map(|(d, r)| (d, &r.response_time_ms)),
+    );
+    render_domain_histogram(
+        out,
+        "domain_page_size_bytes",
+        "Size of fetched pages per domain.",
+        1.0,
+        reports.iter().map(|(d, r)| (d, &r.page_size_bytes)),
+    );
+}
+
+/// Render the estimates of the memory taken by the crawler state.
+pub fn render_memory(out: &mut String, memory: &MemoryEstimate) {
+    let name = "memory_bytes";
+    let help = "Estimated memory taken by parts of the crawler state.";
+    header(out, name, "gauge", help);
+    let parts = [
+        ("seen", memory.seen_bytes),
+        ("results", memory.results_bytes),
+        ("queue", memory.queue_bytes),
+    ];
+    for (part, bytes) in parts {
+        sample(out, name, &format!("part=\"{}\"", part), bytes);
+    }
+    let name = "domain_results_bytes";
+    header(
+        out,
+        name,
+        "gauge",
+        "Estimated memory taken by the results per domain.",
+    );
+    for (domain, bytes) in &memory.domains {
+        sample(out, name, &format!("domain=\"{}\"", &**domain), bytes);
+    }
+}
+
+/// Render a histogram labelled by domain, dividing the values by given scale.
+fn render_domain_histogram<'a>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    scale: f64,
+    distributions: impl Iterator<Item = (&'a Domain, &'a Distribution)>,
+) {
+    header(out, name, "histogram", help);
+    let bucket = format!("{}_bucket", name);
+    for (domain, d) in distributions {
+        for b in &d.buckets {
+            let le =
+                b.le.map_or("+Inf".to_string(), |le| (le as f64 / scale).to_string());
+            let labels = format!("domain=\"{}\",le=\"{}\"", &**domain, le);
+            sample(out, &bucket, &labels, b.count);
+        }
+        let labels = format!("domain=\"{}\"", &**domain);
+        sample(out, &format!("{}_sum", name), &labels, d.sum as f64 / scale);
+        sample(out, &format!("{}_count", name), &labels, d.count);
+    }
+}
+
+/// Monotonically increasing counter.
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1)
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Value that can go up and down.
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    const fn new() -> Self {
+        Gauge(AtomicI64::new(0))
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, delta: i64) {
+        self.0.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Histogram of durations with N fixed buckets plus the implicit +Inf bucket.
+struct Histogram<const N: usize> {
+    bounds: &'static [f64; N],
+    /// Non-cumulative counts for each bucket.
+    counts: [AtomicU64; N],
+    overflow: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl<const N: usize> Histogram<N> {
+    const fn new(bounds: &'static [f64; N]) -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+        Histogram {
+            bounds,
+            counts: [ZERO; N],
+            overflow: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        match self.bounds.iter().position(|&b| secs <= b) {
+            Some(idx) => self.counts[idx].fetch_add(1, Ordering::Relaxed),
+            None => self.overflow.fetch_add(1, Ordering::Relaxed),
+        };
+        let micros = elapsed.as_micros() as u64;
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let bucket = format!("{}_bucket", name);
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count.load(Ordering::Relaxed);
+            sample(out, &bucket, &format!("le=\"{}\"", bound), cumulative);
+        }
+        cumulative += self.overflow.load(Ordering::Relaxed);
+        sample(out, &bucket, "le=\"+Inf\"", cumulative);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        sample(out, &format!("{}_sum", name), "", sum);
+        sample(out, &format!("{}_count", name), "", cumulative);
+    }
+}
+
+/// Prefix of all the metric names.
+const PREFIX: &str = "croolr_";
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {}{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}{} {}", PREFIX, name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl Display) {
+    let _ = match labels {
+        "" => writeln!(out, "{}{} {}", PREFIX, name, value),
+        _ => writeln!(out, "{}{}{{{}}} {}", PREFIX, name, labels, value),
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::summary::Summary;
+    use super::*;
+
+    #[test]
+    fn unit_metrics_render() {
+        let metrics = Metrics::new();
+        let ok = Page {
+            size: 100,
+            ..Page::default()
+        };
+        metrics.record_fetch(&UrlInfo::from_result(Ok(ok), Duration::from_millis(20)));
+        let err = Err(Error::Status(reqwest::StatusCode::NOT_FOUND));
+        metrics.record_fetch(&UrlInfo::from_result(err, Duration::from_secs(20)));
+        metrics.queue_depth.set(5);
+        metrics.record_send_wait(Duration::from_millis(5));
+
+        let text = metrics.render();
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in &[
+            "croolr_pages_fetched_total 2",
+            "croolr_downloaded_bytes_total 100",
+            "croolr_fetch_errors_total{kind=\"status\"} 1",
+            "croolr_fetch_errors_total{kind=\"fetch\"} 0",
+            "croolr_queue_depth 5",
+            "croolr_fetch_duration_seconds_bucket{le=\"0.01\"} 0",
+            "croolr_fetch_duration_seconds_bucket{le=\"0.025\"} 1",
+            "croolr_fetch_duration_seconds_bucket{le=\"10\"} 1",
+            "croolr_fetch_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "croolr_fetch_duration_seconds_sum 20.02",
+            "croolr_fetch_duration_seconds_count 2",
+            "croolr_channel_occupancy 0",
+            "croolr_channel_send_wait_seconds_bucket{le=\"0.001\"} 0",
+            "croolr_channel_send_wait_seconds_bucket{le=\"0.01\"} 1",
+            "croolr_channel_send_wait_seconds_count 1",
+        ] {
+            assert!(
+                lines.contains(expected),
+                "missing {:?} in\n{}",
+                expected,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn unit_metrics_render_memory() {
+        let domains = std::iter::once(("example.com".parse().unwrap(), 300)).collect();
+        let mut text = String::new();
+        render_memory(&mut text, &MemoryEstimate::new(100, domains, 20));
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in &[
+            "croolr_memory_bytes{part=\"seen\"} 100",
+            "croolr_memory_bytes{part=\"results\"} 300",
+            "croolr_memory_bytes{part=\"queue\"} 20",
+            "croolr_domain_results_bytes{domain=\"example.com\"} 300",
+        ] {
+            assert!(
+                lines.contains(expected),
+                "missing {:?} in\n{}",
+                expected,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn unit_metrics_render_domains() {
+        let mut summary = Summary::default();
+        let page = Page {
+            size: 2000,
+            ..Page::default()
+        };
+        summary.record(&UrlInfo::from_result(Ok(page), Duration::from_millis(20)));
+        let domain: Domain = "example.com".parse().unwrap();
+        let mut text = String::new();
+        render_domains(&mut text, &[(domain, summary.report())]);
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in &[
+            "# TYPE croolr_domain_fetch_duration_seconds histogram",
+            "croolr_domain_fetch_duration_seconds_bucket{domain=\"example.com\",le=\"0.01\"} 0",
+            "croolr_domain_fetch_duration_seconds_bucket{domain=\"example.com\",le=\"0.025\"} 1",
+            "croolr_domain_fetch_duration_seconds_sum{domain=\"example.com\"} 0.02",
+            "croolr_domain_page_size_bytes_bucket{domain=\"example.com\",le=\"1024\"} 0",
+            "croolr_domain_page_size_bytes_bucket{domain=\"example.com\",le=\"+Inf\"} 1",
+            "croolr_domain_page_size_bytes_count{domain=\"example.com\"} 1",
+        ] {
+            assert!(
+                lines.contains(expected),
+                "missing {:?} in\n{}",
+                expected,
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! Hooks run around each page fetch.
+//!
+//! Middleware is added with [`CrawlerBuilder::middleware`] and wraps the
+//! [`Fetcher`] of the crawler. Each page request passes through the
+//! [`Middleware::before_request`] hooks in the order they have been added,
+//! and the result through the [`Middleware::after_response`] hooks in the
+//! reverse order. The robots.txt requests and the link checks bypass the
+//! middleware.
+//!
+//! [`CrawlerBuilder::middleware`]: super::crawler::CrawlerBuilder::middleware
+
+use super::fetcher::{Fetcher, Request, Response};
+use super::metrics::METRICS;
+use super::robots::RobotsFile;
+use super::urlinfo::Error;
+
+use futures::future::BoxFuture;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use url::Url;
+
+/// A step of the fetch pipeline, e.g. adding credentials, caching responses
+/// or logging.
+pub trait Middleware: Send + Sync + 'static {
+    /// Adjust the request before it is sent.
+    ///
+    /// Returning a result skips the request and the hooks of the middleware
+    /// added later, e.g. when the response is served from a cache.
+    fn before_request(&self, _request: &mut Request) -> Option<Result<Response, Error>> {
+        None
+    }
+
+    /// Inspect or adjust the result of the request.
+    fn after_response(&self, _request: &Request, _result: &mut Result<Response, Error>) {}
+}
+
+/// Middleware setting a header on all the page requests, e.g. credentials.
+#[derive(Debug, Clone)]
+pub struct SetHeader {
+    name: String,
+    value: String,
+}
+
+impl SetHeader {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        SetHeader {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl Middleware for SetHeader {
+    fn before_request(&self, request: &mut Request) -> Option<Result<Response, Error>> {
+        let name = &self.name;
+        request
+            .headers
+            .retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        request
+            .headers
+            .push((self.name.clone(), self.value.clone()));
+        None
+    }
+}
+
+/// Middleware serving pages fetched a short while ago from memory, e.g. when
+/// several crawls reach the same URL or a domain is crawled again soon after.
+///
+/// Only pages received without redirects are kept, as the redirects would not
+/// be recorded for the cached ones. The least recently used pages are dropped
+/// once the cache is full.
+#[derive(Debug)]
+pub struct ResponseCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<LruEntries>,
+}
+
+/// The cached responses with the time they were received, by URL, and the
+/// URLs in the order they were last used.
+#[derive(Debug, Default)]
+struct LruEntries {
+    responses: HashMap<Url, (u64, Instant, Response)>,
+    by_use: BTreeMap<u64, Url>,
+    uses: u64,
+}
+
+impl ResponseCache {
+    /// Cache keeping up to given number of pages for given time.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        ResponseCache {
+            capacity,
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Number of pages cached, including those expired but not dropped yet.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().responses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl LruEntries {
+    /// The response for given URL received after given time, marked as used.
+    fn get(&mut self, url: &Url, received_after: Option<Instant>) -> Option<Response> {
+        let (used, received, _) = self.responses.get(url)?;
+        let (used, received) = (*used, *received);
+        self.by_use.remove(&used);
+        if received_after.is_some_and(|t| received <= t) {
+            self.responses.remove(url);
+            return None;
+        }
+        self.uses += 1;
+        self.by_use.insert(self.uses, url.clone());
+        let entry = self.responses.get_mut(url)?;
+        entry.0 = self.uses;
+        Some(entry.2.clone())
+    }
+
+    fn insert(&mut self, url: Url, response: Response, now: Instant, capacity: usize) {
+        self.uses += 1;
+        if let Some((used, _, _)) = self
+            .responses
+            .insert(url.clone(), (self.uses, now, response))
+        {
+            self.by_use.remove(&used);
+        }
+        self.by_use.insert(self.uses, url);
+        while self.responses.len() > capacity {
+            match self.by_use.pop_first() {
+                Some((_, url)) => self.responses.remove(&url),
+                None => break,
+            };
+        }
+    }
+}
+
+impl Middleware for ResponseCache {
+    fn before_request(&self, request: &mut Request) -> Option<Result<Response, Error>> {
+        let received_after = Instant::now().checked_sub(self.ttl);
+        let cached = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&request.url, received_after);
+        match cached {
+            Some(_) => METRICS.response_cache_hits.inc(),
+            None => METRICS.response_cache_misses.inc(),
+        }
+        cached.map(Ok)
+    }
+
+    fn after_response(&self, request: &Request, result: &mut Result<Response, Error>) {
+        let response = match result {
+            Ok(response) if response.url == request.url => response,
+            _ => return,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        // Pages served from the cache keep the time they were received.
+        if !entries.responses.contains_key(&request.url) {
+            let now = Instant::now();
+            entries.insert(request.url.clone(), response.clone(), now, self.capacity);
+        }
+    }
+}
+
+/// Fetcher passing the page requests through a middleware chain.
+pub(crate) struct Chain {
+    pub fetcher: Arc<dyn Fetcher>,
+    pub layers: Vec<Arc<dyn Middleware>>,
+}
+
+impl Fetcher for Chain {
+    fn fetch<'a>(
+        &'a self,
+        request: &'a Request,
+        redirects: &'a mut Vec<Url>,
+    ) -> BoxFuture<'a, Result<Response, Error>> {
+        Box::pin(async move {
+            let mut request = request.clone();
+            let mut ran = 0;
+            let mut result = None;
+            for layer in &self.layers {
+                ran += 1;
+                result = layer.before_request(&mut request);
+                if result.is_some() {
+                    break;
+                }
+            }
+            let mut result = match result {
+                Some(result) => result,
+                None => self.fetcher.fetch(&request, redirects).await,
+            };
+            for layer in self.layers[..ran].iter().rev() {
+                layer.after_response(&request, &mut result);
+            }
+            result
+        })
+    }
+
+    fn fetch_robots<'a>(&'a self, url: &'a Url, user_agent: &'a str) -> BoxFuture<'a, RobotsFile> {
+        self.fetcher.fetch_robots(url, user_agent)
+    }
+
+    fn check<'a>(
+        &'a self,
+        url: &'a Url,
+        user_agent: &'a str,
+    ) -> BoxFuture<'a, Result<reqwest::StatusCode, Error>> {
+        self.fetcher.check(url, user_agent)
+    }
+
+    fn prefetch(&self, host: &str) {
+        self.fetcher.prefetch(host)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::fetcher::MockFetcher;
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Records the order of the hook calls and the headers seen.
+    struct Trace {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+        short_circuit: bool,
+    }
+
+    impl Middleware for Trace {
+        fn before_request(&self, request: &mut Request) -> Option<Result<Response, Error>> {
+            let headers: Vec<_> = request
+                .headers
+                .iter()
+                .map(|(n, v)| format!("{}={}", n, v))
+                .collect();
+            let entry = format!("before {} [{}]", self.name, headers.join(","));
+            self.log.lock().unwrap().push(entry);
+            match self.short_circuit {
+                true => Some(Err(Error::Fetch("cached".to_string()))),
+                false => None,
+            }
+        }
+
+        fn after_response(&self, _request: &Request, result: &mut Result<Response, Error>) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("after {}", self.name));
+            if let Ok(response) = result {
+                response.body = [&response.body[..], self.name.as_bytes()].concat().into();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let trace = |name, short_circuit| -> Arc<dyn Middleware> {
+            let log = log.clone();
+            Arc::new(Trace {
+                name,
+                log,
+                short_circuit,
+            })
+        };
+        let fetcher = Arc::new(MockFetcher::new().page("http://example.com/", ""));
+        let chain = Chain {
+            fetcher: fetcher.clone(),
+            layers: vec![
+                Arc::new(SetHeader::new("Authorization", "Bearer x")),
+                trace("a", false),
+                trace("b", false),
+            ],
+        };
+        let url = Url::parse("http://example.com/").unwrap();
+        let request = Request::new(url.clone(), "test");
+        let response = chain.fetch(&request, &mut Vec::new()).await.unwrap();
+        assert_eq!(response.body, "ba");
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "before a [Authorization=Bearer x]",
+                "before b [Authorization=Bearer x]",
+                "after b",
+                "after a",
+            ]
+        );
+
+        log.lock().unwrap().clear();
+        let chain = Chain {
+            fetcher,
+            layers: vec![trace("a", true), trace("b", false)],
+        };
+        let result = chain.fetch(&request, &mut Vec::new()).await;
+        assert!(matches!(result, Err(Error::Fetch(e)) if e == "cached"));
+        assert_eq!(*log.lock().unwrap(), ["before a []", "after a"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_response_cache() {
+        let url = |path| Url::parse(&format!("http://example.com/{}", path)).unwrap();
+        let fetcher = MockFetcher::new()
+            .page("http://example.com/a", "A")
+            .page("http://example.com/b", "B")
+            .page("http://example.com/c", "C")
+            .redirect("http://example.com/r", "http://example.com/a");
+        let cache = Arc::new(ResponseCache::new(2, Duration::from_secs(10)));
+        // Requests not answered by the cache pass through the trace.
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let trace = Trace {
+            name: "fetch",
+            log: log.clone(),
+            short_circuit: false,
+        };
+        let chain = Chain {
+            fetcher: Arc::new(fetcher),
+            layers: vec![cache.clone(), Arc::new(trace)],
+        };
+        let fetch = |path| {
+            let request = Request::new(url(path), "test");
+            let chain = &chain;
+            async move { chain.fetch(&request, &mut Vec::new()).await.unwrap() }
+        };
+        let fetched = || log.lock().unwrap().len() / 2;
+
+        assert_eq!(fetch("a").await.body, "Afetch");
+        assert_eq!(fetch("a").await.body, "Afetch");
+        assert_eq!(fetched(), 1);
+        // Redirected pages are not kept.
+        fetch("r").await;
+        assert_eq!(cache.len(), 1);
+
+        // The least recently used page makes room for a new one.
+        fetch("b").await;
+        fetch("a").await;
+        fetch("c").await;
+        assert_eq!(cache.len(), 2);
+        fetch("a").await;
+        assert_eq!(fetched(), 4);
+        fetch("b").await;
+        assert_eq!(fetched(), 5);
+
+        // Pages are fetched again once they expire.
+        tokio::time::advance(Duration::from_secs(11)).await;
+        fetch("b").await;
+        assert_eq!(fetched(), 6);
+    }
+}
@@ -0,0 +1,149 @@
+//! Crawling domains without serving the API, e.g. in shell pipelines.
+
+use super::checkpoint::Checkpoint;
+use super::crawler::{CrawlReply, Crawler};
+use super::error::CroolrError;
+use super::export::Format;
+use super::listing::{ListOptions, Listing, SortKey};
+use super::settings::Settings;
+use super::urlinfo::Domain;
+
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use url::Url;
+
+/// How often the progress is saved to the checkpoint file.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Crawl the domains of given seeds to completion, starting at the seeds, and
+/// write the results out in given format, one record per URL. The domains are
+/// listed in the order of their first seeds, the URLs of each in the order
+/// they were discovered. Domains with multiple seeds are listed once.
+///
+/// With a checkpoint file, the crawl continues from the progress saved in it,
+/// if it exists, along with its domains. The progress is saved to it
+/// periodically and when interrupted by Ctrl-C, and it is removed once the
+/// crawl finishes.
+///
+/// Returns the number of URLs written.
+pub async fn crawl(
+    settings: Settings,
+    seeds: Vec<Url>,
+    checkpoint: Option<&Path>,
+    format: Format,
+    out: &mut impl io::Write,
+) -> Result<usize, String> {
+    let crawler = Crawler::builder()
+        .settings(settings)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let gone = |_| "crawler stopped unexpectedly".to_string();
+    let mut domains = Vec::new();
+    if let Some(saved) = checkpoint.map(Checkpoint::load).transpose()?.flatten() {
+        tracing::info!(
+            fetched = saved.fetched.len(),
+            queued = saved.frontier.len(),
+            "Resuming crawl"
+        );
+        domains.extend(saved.domains.iter().cloned());
+        crawler.restore(saved).await.map_err(gone)?;
+    }
+    for seed in seeds {
+        let domain = crawler
+            .domain_of(&seed)
+            .ok_or_else(|| format!("invalid seed {}: URL has no host", seed))?;
+        match crawler.crawl_seed(seed).await.map_err(gone)? {
+            CrawlReply::Queued | CrawlReply::AlreadyCrawling => domains.push(domain),
+            CrawlReply::MalformedHostName(e) => return Err(format!("invalid domain: {}", e)),
+        }
+    }
+    let mut seen = HashSet::new();
+    domains.retain(|d| seen.insert(d.clone()));
+    if domains.is_empty() {
+        return Err("nothing to crawl".to_string());
+    }
+    let finished = wait_all(&crawler, &domains);
+    match checkpoint {
+        Some(path) => {
+            tokio::pin!(finished);
+            let interrupted = tokio::signal::ctrl_c();
+            tokio::pin!(interrupted);
+            let mut interval = tokio::time::interval(CHECKPOINT_INTERVAL);
+            loop {
+                tokio::select! {
+                    result = &mut finished => break result.map_err(gone)?,
+                    _ = interval.tick() => save(&crawler, path).await?,
+                    _ = &mut interrupted => {
+                        save(&crawler, path).await?;
+                        return Err(format!(
+                            "interrupted, continue with --resume {}",
+                            path.display()
+                        ));
+                    }
+                }
+            }
+        }
+        None => finished.await.map_err(gone)?,
+    }
+    let mut urls = Vec::new();
+    for domain in domains {
+        let opts = ListOptions {
+            sort: SortKey::Discovered,
+            ..ListOptions::default()
+        };
+        let listing = crawler.list_urls(domain, opts).await.map_err(gone)?;
+        urls.extend(listing.map_or_else(Vec::new, |l| l.urls));
+    }
+    let count = urls.len();
+    let listing = Listing {
+        total: count,
+        offset: 0,
+        urls,
+    };
+    format
+        .write(listing, out)
+        .and_then(|()| out.flush())
+        .map_err(|e| format!("cannot write results: {}", e))?;
+    if let Some(path) = checkpoint {
+        if let Err(e) = std::fs::remove_file(path) {
+            tracing::warn!(error = %e, checkpoint = %path.display(), "Cannot remove checkpoint");
+        }
+    }
+    Ok(count)
+}
+
+async fn wait_all(crawler: &Crawler, domains: &[Domain]) -> Result<(), CroolrError> {
+    for domain in domains {
+        crawler.wait(domain.clone()).await?;
+    }
+    Ok(())
+}
+
+async fn save(crawler: &Crawler, path: &Path) -> Result<(), String> {
+    let checkpoint = crawler
+        .checkpoint()
+        .await
+        .map_err(|_| "crawler stopped unexpectedly".to_string())?;
+    checkpoint.save(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_oneshot_crawl() {
+        let mut out = Vec::new();
+        let seed = Url::parse("http://example.invalid/").unwrap();
+        let seeds = vec![seed.clone(), seed];
+        let count = crawl(Settings::default(), seeds, None, Format::Ndjson, &mut out)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        let record: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(record["url"], "http://example.invalid/");
+        assert!(record["error"].is_string());
+    }
+}
@@ -0,0 +1,932 @@
+//! OpenAPI description of the HTTP API.
+//!
+//! The document is maintained by hand next to the warp filters in the server
+//! module. Any change to the routes or reply formats should be reflected here.
+
+use super::auth;
+
+use serde_json::{json, Value};
+
+/// Swagger UI page rendering the OpenAPI document served at /openapi.json.
+pub const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>Croolr API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@4/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@4/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+/// Build the OpenAPI document.
+pub fn spec() -> Value {
+    let mut spec = json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Croolr",
+            "description": "A simple web crawler.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": "/v1" }],
+        // Keys are only required if configured on the server.
+        "security": [{ "ApiKey": [] }, {}],
+        "paths": {
+            "/crawl/{domain}": {
+                "get": {
+                    "summary": "Start crawling given domain",
+                    "parameters": [
+                        domain_param(),
+                        {
+                            "name": "seed",
+                            "in": "query",
+                            "description": "URL of the domain to start at instead of its root, e.g. on another port",
+                            "schema": { "type": "string", "format": "uri" },
+                        },
+                    ],
+                    "responses": with_errors(
+                        json_response(object(json!({ "status": { "type": "string" } }))),
+                        &["400", "409"],
+                    ),
+                },
+            },
+            "/crawl-batch": {
+                "post": {
+                    "summary": "Start crawling a list of domains",
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": object(json!({
+                                    "domains": { "type": "array", "items": { "type": "string" } },
+                                })),
+                            },
+                            "text/plain": {
+                                "schema": {
+                                    "type": "string",
+                                    "description": "One domain or URL per line, lines starting with # are skipped",
+                                },
+                            },
+                        },
+                    },
+                    "responses": with_errors(
+                        json_response(schema_ref("BatchResponse")),
+                        &["400"],
+                    ),
+                },
+            },
+            "/pause/{domain}": control("Pause crawling given domain"),
+            "/resume/{domain}": control("Resume crawling given domain"),
+            "/stop/{domain}": control("Stop crawling given domain"),
+            "/urls/{domain}": conditional(json!({
+                "get": {
+                    "summary": "List URLs discovered for given domain",
+                    "parameters": [
+                        domain_param(),
+                        query_param("offset", "Number of URLs to skip", json!({ "type": "integer", "minimum": 0 })),
+                        query_param("limit", "Max number of URLs to return", json!({ "type": "integer", "minimum": 0 })),
+                        query_param("status", "HTTP status code (404) or class (4xx)", json!({ "type": "string" })),
+                        query_param("type", "Substring of the content type", json!({ "type": "string" })),
+                        query_param("prefix", "Path prefix", json!({ "type": "string" })),
+                        query_param("merge_slashes", "List paths with and without a trailing slash giving the same page once", json!({ "type": "boolean", "default": false })),
+                        query_param("sort", "Sort key", json!({
+                            "type": "string",
+                            "enum": ["discovered", "url", "status", "size", "latency"],
+                            "default": "url",
+                        })),
+                        query_param("order", "Sort order", json!({
+                            "type": "string",
+                            "enum": ["asc", "desc"],
+                            "default": "asc",
+                        })),
+                        query_param("format", "Output format, overrides the Accept header", json!({
+                            "type": "string",
+                            "enum": ["json", "ndjson", "csv", "text"],
+                            "default": "json",
+                        })),
+                    ],
+                    "responses": with_errors(json!({
+                        "description": "Success",
+                        "content": {
+                            "application/json": { "schema": schema_ref("Listing") },
+                            "application/x-ndjson": { "schema": { "type": "string" } },
+                            "text/csv": { "schema": { "type": "string" } },
+                            "text/plain": { "schema": { "type": "string" } },
+                        },
+                    }), &["400", "404"]),
+                },
+            })),
+            "/count/{domain}": conditional(get_domain(
+                "Count URLs discovered for given domain",
+                json_response(object(json!({
+                    "count": { "type": "integer" },
+                    "status_classes": { "type": "object", "additionalProperties": { "type": "integer" } },
+                    "content_types": { "type": "object", "additionalProperties": { "type": "integer" } },
+                }))),
+                &["400", "404"],
+            )),
+            "/sitemap/{domain}": get_domain(
+                "Successfully fetched HTML pages of given domain as a sitemap.xml",
+                json!({
+                    "description": "Sitemap in the sitemaps.org format",
+                    "content": { "application/xml": { "schema": { "type": "string" } } },
+                }),
+                &["400", "404"],
+            ),
+            "/summary/{domain}": get_domain(
+                "Result statistics for given domain",
+                json_response(object(json!({ "summary": schema_ref("Summary") }))),
+                &["400", "404"],
+            ),
+            "/wait/{domain}": {
+                "get": {
+                    "summary": "Wait for the crawl of given domain to finish",
+                    "parameters": [
+                        domain_param(),
+                        query_param(
+                            "timeout",
+                            "Time to wait, e.g. 30s, 500ms or 2m, at most 5 minutes (30s by default)",
+                            json!({ "type": "string" }),
+                        ),
+                    ],
+                    "responses": with_errors(
+                        json_response(object(json!({
+                            "finished": { "type": "boolean" },
+                            "summary": schema_ref("Summary"),
+                        }))),
+                        &["400", "404"],
+                    ),
+                },
+            },
+            "/audit/{domain}": {
+                "get": {
+                    "summary": "SEO audit of the pages fetched for given domain",
+                    "parameters": [
+                        domain_param(),
+                        query_param(
+                            "max_depth",
+                            "Pages found more links away from the seed are reported as too deep (5 by default)",
+                            json!({ "type": "integer" }),
+                        ),
+                        query_param(
+                            "max_redirects",
+                            "URLs redirected more times are reported as long redirect chains (1 by default)",
+                            json!({ "type": "integer" }),
+                        ),
+                    ],
+                    "responses": with_errors(
+                        json_response(object(json!({ "audit": schema_ref("Audit") }))),
+                        &["400", "404"],
+                    ),
+                },
+            },
+            "/broken/{domain}": get_domain(
+                "Broken links found on the pages fetched for given domain",
+                json_response(object(json!({
+                    "broken": { "type": "array", "items": schema_ref("BrokenLink") },
+                }))),
+                &["400", "404"],
+            ),
+            "/queue/{domain}": get_domain(
+                "Queued and in-flight fetches for given domain",
+                json_response(object(json!({ "queue": schema_ref("QueueStatus") }))),
+                &["400", "404"],
+            ),
+            "/events/{domain}": get_domain(
+                "Stream of crawl events for given domain",
+                json!({
+                    "description": "Server-sent events, one per crawl event",
+                    "content": { "text/event-stream": { "schema": schema_ref("CrawlEvent") } },
+                }),
+                &["400"],
+            ),
+            "/crawl-stream/{domain}": get_domain(
+                "Crawl given domain, streaming the fetched URLs until the crawl finishes",
+                json!({
+                    "description": "Server-sent `url` events, one per fetched URL",
+                    "content": { "text/event-stream": { "schema": schema_ref("UrlRecord") } },
+                }),
+                &["400"],
+            ),
+            "/outlinks/{domain}": {
+                "get": {
+                    "summary": "Links found on a page of given domain and the state of their targets",
+                    "parameters": [
+                        domain_param(),
+                        {
+                            "name": "page",
+                            "in": "query",
+                            "description": "URL of the page",
+                            "required": true,
+                            "schema": { "type": "string", "format": "uri" },
+                        },
+                    ],
+                    "responses": with_errors(
+                        json_response(object(json!({ "outlinks": schema_ref("Outlinks") }))),
+                        &["400", "404"],
+                    ),
+                },
+            },
+            "/robots/{domain}": {
+                "get": {
+                    "summary": "The robots.txt of given domain and whether a path may be crawled",
+                    "parameters": [
+                        domain_param(),
+                        {
+                            "name": "path",
+                            "in": "query",
+                            "description": "Path to decide on, e.g. /foo?bar",
+                            "schema": { "type": "string" },
+                        },
+                    ],
+                    "responses": with_errors(
+                        json_response(object(json!({ "robots": schema_ref("RobotsReport") }))),
+                        &["400", "404"],
+                    ),
+                },
+            },
+            "/domains": {
+                "get": {
+                    "summary": "All domains the crawler has data for",
+                    "responses": with_errors(
+                        json_response(object(json!({
+                            "domains": { "type": "array", "items": schema_ref("DomainStatus") },
+                        }))),
+                        &[],
+                    ),
+                },
+            },
+            "/search": {
+                "get": {
+                    "summary": "Find URLs containing a substring across all domains",
+                    "parameters": [
+                        {
+                            "name": "q",
+                            "in": "query",
+                            "description": "Substring to look for, case-insensitive",
+                            "required": true,
+                            "schema": { "type": "string", "minLength": 1 },
+                        },
+                        query_param("titles", "Match page titles too", json!({ "type": "boolean", "default": false })),
+                        query_param("limit", "Max number of hits to return", json!({ "type": "integer", "minimum": 0, "default": 100 })),
+                    ],
+                    "responses": with_errors(
+                        json_response(object(json!({ "search": schema_ref("SearchResults") }))),
+                        &["400"],
+                    ),
+                },
+            },
+            "/stats": {
+                "get": {
+                    "summary": "Global crawler statistics",
+                    "responses": with_errors(
+                        json_response(object(json!({ "stats": schema_ref("Stats") }))),
+                        &[],
+                    ),
+                },
+            },
+            "/url": {
+                "get": {
+                    "summary": "Everything known about given URL",
+                    "parameters": [{
+                        "name": "u",
+                        "in": "query",
+                        "description": "The URL",
+                        "required": true,
+                        "schema": { "type": "string", "format": "uri" },
+                    }],
+                    "responses": with_errors(
+                        json_response(object(json!({ "url": schema_ref("UrlRecord") }))),
+                        &["404"],
+                    ),
+                },
+            },
+            "/screenshot": {
+                "get": {
+                    "summary": "Screenshot of given page, taken with --screenshots",
+                    "parameters": [{
+                        "name": "u",
+                        "in": "query",
+                        "description": "The URL of the page",
+                        "required": true,
+                        "schema": { "type": "string", "format": "uri" },
+                    }],
+                    "responses": with_errors(
+                        json!({
+                            "description": "The screenshot",
+                            "content": { "image/png": { "schema": { "type": "string", "format": "binary" } } },
+                        }),
+                        &["404"],
+                    ),
+                },
+            },
+            "/graphql": {
+                "post": {
+                    "summary": "GraphQL query over domains, crawls, URLs and the links between them",
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": object(json!({
+                                    "query": { "type": "string" },
+                                    "variables": { "type": "object" },
+                                    "operationName": { "type": "string" },
+                                })),
+                            },
+                        },
+                    },
+                    "responses": with_errors(
+                        json_response(object(json!({
+                            "data": { "type": "object", "nullable": true },
+                            "errors": { "type": "array", "items": { "type": "object" } },
+                        }))),
+                        &["400"],
+                    ),
+                },
+            },
+            "/ws": {
+                "get": {
+                    "summary": "Websocket streaming crawl events and accepting crawl commands",
+                    "responses": { "101": { "description": "Switching to the websocket protocol" } },
+                },
+            },
+            "/admin/config": {
+                "get": {
+                    "summary": "Live crawler settings",
+                    "responses": with_errors(
+                        json_response(object(json!({ "config": schema_ref("Settings") }))),
+                        &[],
+                    ),
+                },
+                "put": {
+                    "summary": "Change live crawler settings, settings not given are left as they are",
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": schema_ref("Settings") } },
+                    },
+                    "responses": with_errors(
+                        json_response(object(json!({ "config": schema_ref("Settings") }))),
+                        &["400"],
+                    ),
+                },
+            },
+            "/admin/reload": {
+                "post": {
+                    "summary": "Re-read the configuration file and apply its settings and API keys",
+                    "responses": with_errors(
+                        json_response(object(json!({ "config": schema_ref("Settings") }))),
+                        &["400"],
+                    ),
+                },
+            },
+            "/metrics": {
+                "servers": unversioned(),
+                "get": {
+                    "summary": "Prometheus metrics",
+                    "responses": {
+                        "200": {
+                            "description": "Metrics in the Prometheus text format",
+                            "content": { "text/plain": { "schema": { "type": "string" } } },
+                        },
+                    },
+                },
+            },
+            "/openapi.json": {
+                "servers": unversioned(),
+                "get": {
+                    "summary": "This document",
+                    "responses": { "200": json_response(json!({ "type": "object" })) },
+                },
+            },
+        },
+        "components": {
+            "schemas": schemas(),
+            "responses": {
+                "400": error_response("Malformed request"),
+                "404": error_response("Domain or URL not known"),
+                "409": error_response("Domain is already being crawled"),
+                "500": error_response("Internal error"),
+            },
+            "securitySchemes": {
+                "ApiKey": { "type": "apiKey", "in": "header", "name": auth::HEADER },
+            },
+        },
+    });
+    let paths = spec["paths"].as_object_mut().expect("paths object");
+    if !cfg!(feature = "graphql") {
+        paths.remove("/graphql");
+    }
+    if !cfg!(feature = "headless") {
+        paths.remove("/screenshot");
+    }
+    spec
+}
+
+/// Schemas of the reply data structures.
+fn schemas() -> Value {
+    let string = json!({ "type": "string" });
+    let nullable_string = json!({ "type": "string", "nullable": true });
+    let integer = json!({ "type": "integer" });
+    let count_map = json!({ "type": "object", "additionalProperties": integer });
+    let url_list = json!({ "type": "array", "items": string });
+    json!({
+        "Error": object(json!({ "error": schema_ref("ErrorBody") })),
+        "ErrorBody": {
+            "type": "object",
+            "required": ["code", "message"],
+            "properties": {
+                "code": {
+                    "type": "string",
+                    "enum": [
+                        "bad_request",
+                        "invalid_domain",
+                        "unknown_domain",
+                        "unknown_url",
+                        "no_screenshot",
+                        "already_crawling",
+                        "unauthorized",
+                        "rate_limited",
+                        "internal",
+                    ],
+                },
+                "message": string,
+                "details": string,
+            },
+        },
+        "Listing": object(json!({
+            "total": integer,
+            "offset": integer,
+            "urls": { "type": "object", "additionalProperties": schema_ref("UrlRecord") },
+        })),
+        "Summary": object(json!({
+            "urls": integer,
+            "status_codes": count_map,
+            "status_classes": count_map,
+            "content_types": count_map,
+            "errors": count_map,
+            "error_classes": count_map,
+            "total_bytes": integer,
+            "response_time_ms": schema_ref("Distribution"),
+            "page_size_bytes": schema_ref("Distribution"),
+        })),
+        "Audit": object(json!({
+            "pages": integer,
+            "missing_titles": url_list,
+            "missing_descriptions": url_list,
+            "duplicate_titles": {
+                "type": "object",
+                "description": "Pages by the title they share, leaving out those declaring another canonical URL",
+                "additionalProperties": url_list,
+            },
+            "non_canonical_duplicates": {
+                "type": "array",
+                "description": "Groups of pages with the same content not declaring the same canonical URL",
+                "items": url_list,
+            },
+            "deep_pages": {
+                "type": "array",
+                "items": object(json!({ "url": string, "depth": integer })),
+            },
+            "redirect_chains": {
+                "type": "array",
+                "items": object(json!({ "url": string, "redirects": url_list })),
+            },
+        })),
+        "BrokenLink": object(json!({
+            "url": string,
+            "status": { "type": "integer", "nullable": true },
+            "error": string,
+            "external": { "type": "boolean", "description": "Whether the link leads to another site" },
+            "pages": { "type": "array", "description": "Pages linking to it", "items": string },
+        })),
+        "Distribution": object(json!({
+            "count": integer,
+            "sum": integer,
+            "avg": { "type": "number" },
+            "p50": integer,
+            "p90": integer,
+            "p95": integer,
+            "p99": integer,
+            "max": integer,
+            "buckets": {
+                "type": "array",
+                "description": "Cumulative histogram, the last bucket has no upper bound",
+                "items": object(json!({
+                    "le": { "type": "integer", "nullable": true },
+                    "count": integer,
+                })),
+            },
+        })),
+        "CrawlState": { "type": "string", "enum": ["running", "pending", "paused", "stopped"] },
+        "BatchResponse": object(json!({
+            "accepted": integer,
+            "rejected": integer,
+            "results": {
+                "type": "array",
+                "items": object(json!({
+                    "seed": string,
+                    "url": { "type": "string", "format": "uri" },
+                    "accepted": { "type": "boolean" },
+                    "error": schema_ref("ErrorBody"),
+                })),
+            },
+        })),
+        "DomainStatus": object(json!({
+            "domain": string,
+            "urls": integer,
+            "state": schema_ref("CrawlState"),
+            "crawl_id": nullable_string,
+        })),
+        "Outlinks": object(json!({
+            "page": string,
+            "status": { "type": "integer", "nullable": true },
+            "redirects": { "type": "array", "items": string },
+            "internal": integer,
+            "external": integer,
+            "nofollow": integer,
+            "links": {
+                "type": "array",
+                "items": object(json!({
+                    "url": string,
+                    "internal": { "type": "boolean" },
+                    "nofollow": { "type": "boolean" },
+                    "status": { "type": "integer", "nullable": true },
+                    "error": nullable_string,
+                    "redirects_to": nullable_string,
+                })),
+            },
+        })),
+        "RobotsReport": object(json!({
+            "status": {
+                "type": "string",
+                "enum": ["pending", "parsed", "missing", "unavailable"],
+            },
+            "fetched_at": { "type": "string", "format": "date-time" },
+            "details": string,
+            "body": string,
+            "decision": object(json!({
+                "path": string,
+                "allowed": { "type": "boolean" },
+                "rule": nullable_string,
+            })),
+        })),
+        "SearchResults": object(json!({
+            "total": integer,
+            "hits": {
+                "type": "array",
+                "items": object(json!({
+                    "url": string,
+                    "status": { "type": "integer", "nullable": true },
+                    "title": nullable_string,
+                })),
+            },
+        })),
+        "Settings": object(json!({
+            "fetch_limit": { "type": "integer", "minimum": 1 },
+            "host_delay_ms": { "type": "integer", "minimum": 0 },
+            "host_burst": { "type": "integer", "minimum": 1 },
+            "user_agent": string,
+            "buckets": {
+                "type": "object",
+                "description": "Histogram bucket bounds, fixed at startup",
+                "readOnly": true,
+                "properties": {
+                    "latency_ms": { "type": "array", "items": integer },
+                    "size_bytes": { "type": "array", "items": integer },
+                },
+            },
+            "discovery_only": {
+                "type": "boolean",
+                "description": "Whether assets and leaf pages are listed without fetching them, fixed at startup",
+                "readOnly": true,
+            },
+            "max_depth": {
+                "type": "integer",
+                "nullable": true,
+                "description": "Max number of links followed from the seed, fixed at startup",
+                "readOnly": true,
+            },
+            "leaf_patterns": {
+                "type": "array",
+                "items": string,
+                "description": "Patterns of the leaf pages in discovery-only mode, fixed at startup",
+                "readOnly": true,
+            },
+            "unsafe_patterns": {
+                "type": "array",
+                "items": string,
+                "description": "Patterns of the URLs recorded without being fetched as they look like they change some state, fixed at startup",
+                "readOnly": true,
+            },
+            "script": {
+                "type": "string",
+                "nullable": true,
+                "description": "Path of the script deciding which links to follow and extracting fields from the pages, fixed at startup",
+                "readOnly": true,
+            },
+            "plugins": {
+                "type": "array",
+                "items": string,
+                "description": "Paths of the WebAssembly plugins processing the fetched pages, fixed at startup",
+                "readOnly": true,
+            },
+            "analyzers": {
+                "type": "array",
+                "items": { "type": "string", "enum": ["alt-text", "headings"] },
+                "description": "Built-in analyzers run on the fetched pages, fixed at startup",
+                "readOnly": true,
+            },
+            "channel_capacity": {
+                "type": "integer",
+                "minimum": 1,
+                "description": "Number of messages the crawler buffers, fixed at startup",
+                "readOnly": true,
+            },
+            "pool_max_idle_per_host": {
+                "type": "integer",
+                "nullable": true,
+                "description": "Max number of idle connections kept open to each host, the fetch limit if null, fixed at startup",
+                "readOnly": true,
+            },
+            "pool_idle_timeout_secs": {
+                "type": "integer",
+                "description": "Seconds idle connections are kept open for, fixed at startup",
+                "readOnly": true,
+            },
+            "auto_fetch_limit": {
+                "type": "boolean",
+                "description": "Whether fewer fetches than the limit are allowed while short of open files or memory, fixed at startup",
+                "readOnly": true,
+            },
+            "memory_limit_mb": {
+                "type": "integer",
+                "nullable": true,
+                "description": "Resident memory in MiB the auto-tuned fetch limit keeps below, fixed at startup",
+                "readOnly": true,
+            },
+            "max_active_domains": {
+                "type": "integer",
+                "nullable": true,
+                "description": "Max number of domains crawled at once, further crawls wait in the pending state, fixed at startup",
+                "readOnly": true,
+            },
+            "response_cache_size": {
+                "type": "integer",
+                "description": "Max number of pages kept to answer repeated requests, none if 0, fixed at startup",
+                "readOnly": true,
+            },
+            "response_cache_ttl_secs": {
+                "type": "integer",
+                "description": "Seconds pages are answered from the response cache for, fixed at startup",
+                "readOnly": true,
+            },
+            "cross_host_redirects": {
+                "type": "string",
+                "enum": ["follow", "record", "drop"],
+                "description": "What is done with pages redirecting to another host, fixed at startup",
+                "readOnly": true,
+            },
+            "merge_www": {
+                "type": "boolean",
+                "description": "Whether www.example.com and example.com are crawled as one site, fixed at startup",
+                "readOnly": true,
+            },
+            "port_policy": {
+                "type": "string",
+                "enum": ["any", "same"],
+                "description": "Which ports of a host links are followed to, fixed at startup",
+                "readOnly": true,
+            },
+            "trailing_slash": {
+                "type": "string",
+                "enum": ["keep", "add", "strip"],
+                "description": "Form paths with or without a trailing slash are turned into before they are crawled, fixed at startup",
+                "readOnly": true,
+            },
+            "detect_session_params": {
+                "type": "boolean",
+                "description": "Whether query parameters found to carry sessions are dropped from the URLs found, fixed at startup",
+                "readOnly": true,
+            },
+            "headless": {
+                "type": "boolean",
+                "description": "Whether the pages are rendered in a headless browser before their links are extracted, fixed at startup",
+                "readOnly": true,
+            },
+            "screenshot_dir": {
+                "type": "string",
+                "nullable": true,
+                "description": "Directory the screenshots of the pages are kept in, none are taken if null, needs headless, fixed at startup",
+                "readOnly": true,
+            },
+            "browser": {
+                "type": "string",
+                "description": "Headless Chrome or Chromium executable rendering the pages and taking the screenshots, fixed at startup",
+                "readOnly": true,
+            },
+            "check_external": {
+                "type": "boolean",
+                "description": "Whether the links to other sites are checked and reported by /broken when not working, fixed at startup",
+                "readOnly": true,
+            },
+        })),
+        "Stats": object(json!({
+            "uptime_secs": integer,
+            "domains": integer,
+            "active_crawls": integer,
+            "pending_crawls": integer,
+            "urls_seen": integer,
+            "urls_fetched": integer,
+            "queued": integer,
+            "in_flight": integer,
+            "memory": object(json!({
+                "seen_bytes": integer,
+                "results_bytes": integer,
+                "queue_bytes": integer,
+                "total_bytes": integer,
+                "domains": count_map,
+            })),
+            "features": {
+                "type": "array",
+                "items": string,
+                "description": "Optional features croolr has been built with, e.g. grpc",
+            },
+        })),
+        "QueueStatus": object(json!({
+            "state": schema_ref("CrawlState"),
+            "queued": integer,
+            "sample": { "type": "array", "items": string },
+            "in_flight": { "type": "array", "items": string },
+        })),
+        "UrlRecord": object(json!({
+            "url": string,
+            "status": { "type": "integer", "nullable": true },
+            "error": nullable_string,
+            "content_type": nullable_string,
+            "size": { "type": "integer", "nullable": true },
+            "headers": {
+                "type": "array",
+                "items": { "type": "array", "items": string, "minItems": 2, "maxItems": 2 },
+            },
+            "redirects": { "type": "array", "items": string },
+            "parent": nullable_string,
+            "depth": integer,
+            "discovered": integer,
+            "discovered_at": { "type": "integer", "nullable": true },
+            "fetched_at": integer,
+            "elapsed_ms": { "type": "number" },
+            "canonical": nullable_string,
+            "title": nullable_string,
+            "links": { "type": "array", "items": string },
+            "nofollow": { "type": "array", "items": string },
+            "extra": {
+                "type": "object",
+                "additionalProperties": string,
+                "description": "Custom fields extracted by the user script and plugins",
+            },
+            "findings": {
+                "type": "array",
+                "description": "Problems found in the page by the analyzers",
+                "items": object(json!({ "analyzer": string, "message": string })),
+            },
+        })),
+        "CrawlEvent": {
+            "type": "object",
+            "required": ["event"],
+            "properties": {
+                "event": { "type": "string", "enum": ["discovered", "fetched", "state_changed"] },
+                "url": string,
+                "parent": nullable_string,
+                "depth": integer,
+                "status": { "type": "integer", "nullable": true },
+                "error": nullable_string,
+                "elapsed_ms": { "type": "number" },
+                "domain": string,
+                "state": schema_ref("CrawlState"),
+            },
+        },
+    })
+}
+
+/// Server override for paths living outside of the versioned API.
+fn unversioned() -> Value {
+    json!([{ "url": "/" }])
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn object(properties: Value) -> Value {
+    json!({ "type": "object", "properties": properties })
+}
+
+fn json_response(schema: Value) -> Value {
+    json!({
+        "description": "Success",
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn domain_param() -> Value {
+    json!({
+        "name": "domain",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string" },
+    })
+}
+
+fn query_param(name: &str, description: &str, schema: Value) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "description": description,
+        "schema": schema,
+    })
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema_ref("Error") } },
+    })
+}
+
+/// Responses of an operation given the success response and error status codes.
+fn with_errors(response: Value, errors: &[&str]) -> Value {
+    let mut responses = json!({ "200": response, "500": { "$ref": "#/components/responses/500" } });
+    for code in errors {
+        responses[code] = json!({ "$ref": format!("#/components/responses/{}", code) });
+    }
+    responses
+}
+
+/// A GET operation taking the domain as the only parameter.
+fn get_domain(summary: &str, response: Value, errors: &[&str]) -> Value {
+    json!({
+        "get": {
+            "summary": summary,
+            "parameters": [domain_param()],
+            "responses": with_errors(response, errors),
+        },
+    })
+}
+
+/// Mark a GET operation as supporting conditional requests by ETag.
+fn conditional(mut path: Value) -> Value {
+    let op = &mut path["get"];
+    op["parameters"].as_array_mut().unwrap().push(json!({
+        "name": "If-None-Match",
+        "in": "header",
+        "description": "ETag of a previous reply, 304 is returned if the data have not changed",
+        "schema": { "type": "string" },
+    }));
+    op["responses"]["304"] = json!({ "description": "The data have not changed" });
+    path
+}
+
+fn control(summary: &str) -> Value {
+    get_domain(
+        summary,
+        json_response(object(json!({ "state": schema_ref("CrawlState") }))),
+        &["400", "404"],
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_openapi_references_resolve() {
+        let spec = spec();
+        let text = spec.to_string();
+        for kind in &["schemas", "responses"] {
+            let defined = spec["components"][kind].as_object().unwrap();
+            let prefix = format!("\"#/components/{}/", kind);
+            for reference in text.split(&prefix).skip(1) {
+                let name = &reference[..reference.find('"').unwrap()];
+                assert!(defined.contains_key(name), "unresolved {} {}", kind, name);
+            }
+        }
+    }
+
+    #[test]
+    fn unit_openapi_paths_have_responses() {
+        let spec = spec();
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            let op = match item.get("post") {
+                Some(op) => op,
+                None => &item["get"],
+            };
+            assert!(op["responses"].is_object(), "{} has no responses", path);
+            for param in op["parameters"].as_array().into_iter().flatten() {
+                assert!(param.is_object(), "{} has a malformed parameter", path);
+            }
+        }
+    }
+}
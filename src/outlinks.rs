@@ -0,0 +1,136 @@
+//! Report on the links found on a single page.
+
+use super::settings::SiteScope;
+use super::urlinfo::{Domain, UrlInfo, UrlSet};
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use url::Url;
+
+/// Links found on a page, along with what is known about their targets.
+#[derive(Debug, Clone, Serialize)]
+pub struct Outlinks {
+    pub page: Url,
+    /// HTTP status of the page, if a response has been received.
+    pub status: Option<u16>,
+    /// Redirects followed when fetching the page, in order.
+    pub redirects: Vec<Url>,
+    /// Number of links to the same host.
+    pub internal: usize,
+    /// Number of links to other hosts.
+    pub external: usize,
+    /// Number of links marked `rel="nofollow"`.
+    pub nofollow: usize,
+    /// The links in the order they appear on the page.
+    pub links: Vec<Outlink>,
+}
+
+/// A link found on a page.
+#[derive(Debug, Clone, Serialize)]
+pub struct Outlink {
+    pub url: Url,
+    /// Whether the link points to the same host as the page.
+    pub internal: bool,
+    pub nofollow: bool,
+    /// HTTP status of the target, None if it has not been fetched or there
+    /// was no response.
+    pub status: Option<u16>,
+    /// Why fetching the target failed.
+    pub error: Option<String>,
+    /// Where the target redirects to, if it does.
+    pub redirects_to: Option<Url>,
+}
+
+impl Outlinks {
+    /// Build the report for given page using the results fetched so far,
+    /// the scope telling which links are internal and where the results are.
+    pub fn new(
+        page: &Url,
+        info: &UrlInfo,
+        data: &HashMap<Domain, Arc<UrlSet>>,
+        scope: SiteScope,
+    ) -> Self {
+        let (links, nofollow): (Vec<Url>, HashSet<Url>) = match &info.result {
+            Ok(p) => (p.links.iter().collect(), p.nofollow.iter().collect()),
+            Err(_) => (Vec::new(), HashSet::new()),
+        };
+        let links: Vec<Outlink> = links
+            .into_iter()
+            .map(|url| {
+                let target = scope
+                    .domain_of(&url)
+                    .and_then(|d| data.get(&d))
+                    .and_then(|urls| urls.get(&url));
+                Outlink {
+                    internal: scope.same_site(&url, page),
+                    nofollow: nofollow.contains(&url),
+                    status: target.and_then(|t| t.status()).map(|s| s.as_u16()),
+                    error: target.and_then(|t| t.result.as_ref().err().map(|e| e.to_string())),
+                    redirects_to: target.and_then(|t| t.redirects.last().cloned()),
+                    url,
+                }
+            })
+            .collect();
+        let internal = links.iter().filter(|l| l.internal).count();
+        Outlinks {
+            page: page.clone(),
+            status: info.status().map(|s| s.as_u16()),
+            redirects: info.redirects.clone(),
+            internal,
+            external: links.len() - internal,
+            nofollow: links.iter().filter(|l| l.nofollow).count(),
+            links,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::urlinfo::{Error, Page};
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn unit_outlinks() {
+        let url = |s: &str| Url::parse(s).unwrap();
+        let page = Page {
+            links: vec![
+                url("http://example.com/a"),
+                url("http://example.com/b"),
+                url("http://other.org/"),
+            ]
+            .into(),
+            nofollow: vec![url("http://other.org/")].into(),
+            ..Page::default()
+        };
+        let info = |r| UrlInfo::from_result(r, Duration::ZERO);
+        let mut moved = info(Err(Error::Status(reqwest::StatusCode::NOT_FOUND)));
+        moved.redirects.push(url("http://example.com/gone"));
+        let mut urls = UrlSet::new();
+        urls.insert(Arc::new(url("http://example.com/a")), moved);
+        let mut data = HashMap::new();
+        data.insert(
+            Domain::from_url(&url("http://example.com/")).unwrap(),
+            Arc::new(urls),
+        );
+
+        let report = Outlinks::new(
+            &url("http://example.com/"),
+            &info(Ok(page)),
+            &data,
+            SiteScope::default(),
+        );
+        assert_eq!(report.status, Some(200));
+        assert_eq!(
+            (report.internal, report.external, report.nofollow),
+            (2, 1, 1)
+        );
+        let a = &report.links[0];
+        assert_eq!(a.status, Some(404));
+        assert_eq!(a.redirects_to, Some(url("http://example.com/gone")));
+        assert!(a.error.is_some());
+        assert_eq!(report.links[1].status, None);
+        assert!(!report.links[2].internal && report.links[2].nofollow);
+    }
+}
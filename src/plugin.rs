@@ -0,0 +1,209 @@
+//! WebAssembly plugins processing the fetched pages, with the `wasm` feature.
+//!
+//! A plugin is a WebAssembly module (binary or text format) exporting:
+//!
+//! * `memory`, its linear memory;
+//! * `alloc(len: i32) -> i32`, reserving `len` bytes for an input;
+//! * `process(url: i32, url_len: i32, body: i32, body_len: i32) -> i64`,
+//!   given the URL and the body of a page as UTF-8 strings and returning the
+//!   location of its output as `ptr << 32 | len`.
+//!
+//! The output is a JSON object with optional `links` to follow, e.g.
+//! `["/next"]`, and `metadata` fields recorded with the page, e.g.
+//! `{ "lang": "en" }`. Plugins are sandboxed: nothing is imported into them,
+//! each page is processed by a fresh instance, and both the instructions run
+//! and the memory taken are limited.
+
+use super::fetcher::{Request, Response};
+use super::middleware::Middleware;
+use super::urlinfo::Error;
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use url::Url;
+use wasmi::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// Max number of instructions, roughly, run to process a single page unless
+/// set otherwise.
+const DEFAULT_FUEL: u64 = 100_000_000;
+
+/// Max size of the memory of a plugin instance.
+const MAX_MEMORY: usize = 64 << 20;
+
+/// A loaded plugin.
+pub struct Plugin {
+    name: String,
+    engine: Engine,
+    module: Module,
+    fuel: u64,
+}
+
+/// What a plugin has found in a page.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Output {
+    #[serde(default)]
+    pub links: Vec<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, serde_json::Value>,
+}
+
+impl Plugin {
+    /// Compile a plugin from its code, named for the log messages.
+    pub fn new(name: &str, code: &[u8]) -> Result<Self, String> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module =
+            Module::new(&engine, code).map_err(|e| format!("invalid plugin {}: {}", name, e))?;
+        Ok(Plugin {
+            name: name.to_string(),
+            engine,
+            module,
+            fuel: DEFAULT_FUEL,
+        })
+    }
+
+    /// Limit the number of instructions run to process a single page.
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    /// Read and compile a plugin file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let code = std::fs::read(path)
+            .map_err(|e| format!("cannot read plugin {}: {}", path.display(), e))?;
+        Plugin::new(&path.display().to_string(), &code)
+    }
+
+    /// Run the plugin on given page.
+    pub fn process(&self, url: &Url, body: &[u8]) -> Result<Output, String> {
+        let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits: &mut StoreLimits| limits);
+        store.set_fuel(self.fuel).map_err(|e| e.to_string())?;
+        let instance = Linker::new(&self.engine)
+            .instantiate_and_start(&mut store, &self.module)
+            .map_err(|e| e.to_string())?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("no memory exported")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|e| e.to_string())?;
+        let process = instance
+            .get_typed_func::<(i32, i32, i32, i32), i64>(&store, "process")
+            .map_err(|e| e.to_string())?;
+
+        let pass = |store: &mut Store<StoreLimits>, data: &[u8]| {
+            let len = i32::try_from(data.len()).map_err(|_| "input too large".to_string())?;
+            let ptr = alloc.call(&mut *store, len).map_err(|e| e.to_string())?;
+            memory
+                .write(&mut *store, ptr as u32 as usize, data)
+                .map_err(|e| e.to_string())?;
+            Ok::<_, String>((ptr, len))
+        };
+        let (url_ptr, url_len) = pass(&mut store, url.as_str().as_bytes())?;
+        let (body_ptr, body_len) = pass(&mut store, body)?;
+        let output = process
+            .call(&mut store, (url_ptr, url_len, body_ptr, body_len))
+            .map_err(|e| e.to_string())?;
+
+        let (ptr, len) = ((output >> 32) as u32 as usize, output as u32 as usize);
+        if len > memory.data_size(&store) {
+            return Err("output out of bounds".to_string());
+        }
+        let mut output = vec![0; len];
+        memory
+            .read(&store, ptr, &mut output)
+            .map_err(|e| e.to_string())?;
+        serde_json::from_slice(&output).map_err(|e| format!("invalid output: {}", e))
+    }
+}
+
+impl Middleware for Plugin {
+    fn after_response(&self, _request: &Request, result: &mut Result<Response, Error>) {
+        let response = match result {
+            Ok(response) => response,
+            Err(_) => return,
+        };
+        match self.process(&response.url, &response.body) {
+            Ok(output) => {
+                response.links.extend(output.links);
+                let fields = output.metadata.into_iter().map(|(name, value)| {
+                    let value = match value {
+                        serde_json::Value::String(s) => s,
+                        value => value.to_string(),
+                    };
+                    (name, value)
+                });
+                response.page.extra.extend(fields);
+            }
+            Err(e) => {
+                tracing::warn!(plugin = %self.name, url = %response.url, error = %e, "Plugin failed")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::crawler::Crawler;
+    use super::super::fetcher::MockFetcher;
+    use super::super::urlinfo::Domain;
+    use super::*;
+
+    /// Plugin taking the page body as its output.
+    const ECHO: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 0))
+          (func (export "alloc") (param $len i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get $len))))
+          (func (export "process")
+                (param $url i32) (param $url_len i32) (param $body i32) (param $body_len i32)
+                (result i64)
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $body)) (i64.const 32))
+              (i64.extend_i32_u (local.get $body_len)))))
+    "#;
+
+    #[test]
+    fn unit_plugin() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let echo = Plugin::new("echo", ECHO.as_bytes()).unwrap();
+        let output = echo
+            .process(&url, br#"{"links": ["/a"], "metadata": {"n": 1}}"#)
+            .unwrap();
+        assert_eq!(output.links, ["/a"]);
+        assert_eq!(output.metadata["n"], 1);
+        assert_eq!(echo.process(&url, b"{}").unwrap(), Output::default());
+        assert!(echo.process(&url, b"not json").is_err());
+
+        assert!(Plugin::new("bad", b"(module").is_err());
+        let spin = ECHO.replace("(i64.or", "(loop $l (br $l)) (i64.or");
+        let spin = Plugin::new("spin", spin.as_bytes()).unwrap().fuel(10_000);
+        assert!(spin.process(&url, b"{}").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_plugin_crawl() {
+        let body = r#"{"links": ["/found"], "metadata": {"lang": "en"}}"#;
+        let fetcher = MockFetcher::new().page("http://example.com/", body);
+        let crawler = Crawler::builder()
+            .fetcher(fetcher)
+            .middleware(Plugin::new("echo", ECHO.as_bytes()).unwrap())
+            .build()
+            .unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        assert_eq!(crawler.wait(domain.clone()).await, Ok(true));
+        assert_eq!(crawler.count_urls(domain).await.unwrap(), Some(2));
+        let info = crawler.url_info(Url::parse("http://example.com/").unwrap());
+        let page = info.await.unwrap().unwrap().result.unwrap();
+        assert_eq!(page.extra["lang"], "en");
+    }
+}
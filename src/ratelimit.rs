@@ -0,0 +1,140 @@
+//! Per-client rate limiting of API requests.
+//!
+//! Each client gets a token bucket that refills at a steady rate up to the
+//! burst size. Clients are identified by their API key if they present a valid
+//! one, by their IP address otherwise.
+
+use super::api::ApiError;
+use super::auth::{self, SharedKeys};
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::{Filter, Rejection};
+
+/// Number of tracked clients above which idle buckets are dropped.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Token bucket rate limiter keyed by client.
+#[derive(Debug)]
+pub struct Limiter {
+    /// Tokens added per second.
+    rate: f64,
+    /// Capacity of each bucket.
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+    }
+}
+
+impl Limiter {
+    /// Allow `rate` requests per second on average with bursts of up to `burst`.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Limiter {
+            rate,
+            burst: f64::from(burst.max(1)),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token from the client's bucket.
+    ///
+    /// Returns the time until a token becomes available if the bucket is empty.
+    pub fn check(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let (rate, burst) = (self.rate, self.burst);
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, b| {
+                b.refill(now, rate, burst);
+                b.tokens < burst
+            });
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.refill(now, rate, burst);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+/// Warp filter rejecting requests of clients over their rate limit.
+///
+/// Passes everything through if there is no limiter.
+pub fn limit(
+    limiter: &Option<Arc<Limiter>>,
+    keys: &SharedKeys,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let limiter = limiter.clone();
+    let keys = keys.clone();
+    warp::header::optional::<String>(auth::HEADER)
+        .and(warp::addr::remote())
+        .and_then(move |key: Option<String>, addr: Option<SocketAddr>| {
+            let result = match &limiter {
+                Some(limiter) => {
+                    let client = match key {
+                        Some(key) if keys.contains(&key) => format!("key:{}", key),
+                        _ => format!(
+                            "ip:{}",
+                            addr.map_or("unknown".into(), |a| a.ip().to_string())
+                        ),
+                    };
+                    limiter.check(&client, Instant::now())
+                }
+                None => Ok(()),
+            };
+            async move {
+                result
+                    .map_err(|retry_after| warp::reject::custom(ApiError::RateLimited(retry_after)))
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_ratelimit_burst_and_refill() {
+        let limiter = Limiter::new(2.0, 3);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check("a", start).is_ok());
+        }
+        let wait = limiter.check("a", start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+
+        // Other clients have their own bucket.
+        assert!(limiter.check("b", start).is_ok());
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check("a", later).is_ok());
+        assert!(limiter.check("a", later).is_err());
+
+        // Refill is capped at the burst size.
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.check("a", much_later).is_ok());
+        }
+        assert!(limiter.check("a", much_later).is_err());
+    }
+}
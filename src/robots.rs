@@ -0,0 +1,357 @@
+//! Parsing and evaluation of robots.txt files.
+
+use super::timefmt::w3c_datetime;
+
+use serde::Serialize;
+use std::time::SystemTime;
+use url::Url;
+
+/// Max size of a robots.txt file taken into account, the rest is ignored.
+pub const MAX_SIZE: usize = 500 * 1024;
+
+/// Outcome of fetching the robots.txt file of a domain.
+#[derive(Debug, Clone)]
+pub enum RobotsFile {
+    /// The file has been fetched and parsed.
+    Parsed(Robots),
+    /// There is no robots.txt (4xx status), everything may be crawled.
+    Missing(u16),
+    /// The file could not be fetched (5xx status or network error), nothing
+    /// may be crawled.
+    Unavailable(String),
+}
+
+/// Fetched robots.txt of a domain.
+#[derive(Debug, Clone)]
+pub struct RobotsTxt {
+    pub file: RobotsFile,
+    pub fetched_at: SystemTime,
+}
+
+/// Parsed robots.txt file.
+#[derive(Debug, Clone, Default)]
+pub struct Robots {
+    /// The file as fetched, up to MAX_SIZE.
+    pub body: String,
+    groups: Vec<Group>,
+}
+
+/// Rules applying to a set of user agents.
+#[derive(Debug, Clone, Default)]
+struct Group {
+    /// Lowercase user agent tokens, `*` for everyone.
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+/// A single Allow or Disallow line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub allow: bool,
+    pub pattern: String,
+}
+
+/// Whether a path may be crawled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Decision {
+    pub path: String,
+    pub allowed: bool,
+    /// The robots.txt line deciding the matter, None if no rule applies.
+    pub rule: Option<String>,
+}
+
+/// Robots.txt status as reported by the API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RobotsReport {
+    /// One of `pending`, `parsed`, `missing` and `unavailable`.
+    pub status: &'static str,
+    /// When the file has been fetched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetched_at: Option<String>,
+    /// HTTP status of a missing file, or why the file is unavailable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    /// The file as fetched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Decision on the path asked about, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decision: Option<Decision>,
+}
+
+impl Rule {
+    fn line(&self) -> String {
+        match self.allow {
+            true => format!("Allow: {}", self.pattern),
+            false => format!("Disallow: {}", self.pattern),
+        }
+    }
+
+    /// Check whether the rule pattern matches given path.
+    fn matches(&self, path: &str) -> bool {
+        pattern_matches(&self.pattern, path)
+    }
+}
+
+impl Robots {
+    /// Parse the robots.txt file.
+    ///
+    /// Unknown lines are ignored. Consecutive User-agent lines start a group
+    /// that shares the rules following them.
+    pub fn parse(body: &str) -> Robots {
+        let mut body = body.to_string();
+        if body.len() > MAX_SIZE {
+            let mut end = MAX_SIZE;
+            while !body.is_char_boundary(end) {
+                end -= 1;
+            }
+            body.truncate(end);
+        }
+        let mut groups: Vec<Group> = Vec::new();
+        let mut in_agents = false;
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+                None => continue,
+            };
+            match key.as_str() {
+                "user-agent" => {
+                    if !in_agents {
+                        groups.push(Group::default());
+                        in_agents = true;
+                    }
+                    let agent = value.split('/').next().unwrap_or("").trim();
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(agent.to_ascii_lowercase());
+                    }
+                }
+                "allow" | "disallow" => {
+                    in_agents = false;
+                    // Rules before any User-agent line apply to nobody.
+                    let group = match groups.last_mut() {
+                        Some(group) => group,
+                        None => continue,
+                    };
+                    // An empty Disallow means everything is allowed.
+                    if !value.is_empty() {
+                        group.rules.push(Rule {
+                            allow: key == "allow",
+                            pattern: value.to_string(),
+                        });
+                    }
+                }
+                _ => in_agents = false,
+            }
+        }
+        Robots { body, groups }
+    }
+
+    /// Rules applying to given user agent.
+    ///
+    /// These are the rules of all the groups naming the agent, or of the `*`
+    /// groups if there are none.
+    fn rules_for(&self, user_agent: &str) -> Vec<&Rule> {
+        let token = product_token(user_agent);
+        let select = |matches: &dyn Fn(&str) -> bool| -> Vec<&Rule> {
+            self.groups
+                .iter()
+                .filter(|g| g.agents.iter().any(|a| matches(a)))
+                .flat_map(|g| &g.rules)
+                .collect()
+        };
+        let named = select(&|a| !a.is_empty() && a != "*" && a == token);
+        match named.is_empty() {
+            true => select(&|a| a == "*"),
+            false => named,
+        }
+    }
+
+    /// Decide whether given path may be crawled.
+    ///
+    /// The most specific (longest) matching rule wins, Allow wins ties.
+    pub fn check(&self, user_agent: &str, path: &str) -> Decision {
+        let rule = self
+            .rules_for(user_agent)
+            .into_iter()
+            .filter(|r| r.matches(path))
+            .max_by_key(|r| (r.pattern.len(), r.allow));
+        Decision {
+            path: path.to_string(),
+            allowed: rule.is_none_or(|r| r.allow),
+            rule: rule.map(Rule::line),
+        }
+    }
+}
+
+impl RobotsTxt {
+    /// Decide whether given path may be crawled.
+    pub fn check(&self, user_agent: &str, path: &str) -> Decision {
+        match &self.file {
+            RobotsFile::Parsed(robots) => robots.check(user_agent, path),
+            RobotsFile::Missing(_) => Decision {
+                path: path.to_string(),
+                allowed: true,
+                rule: None,
+            },
+            RobotsFile::Unavailable(_) => Decision {
+                path: path.to_string(),
+                allowed: false,
+                rule: None,
+            },
+        }
+    }
+
+    /// Decide whether given URL may be crawled.
+    pub fn allows(&self, user_agent: &str, url: &Url) -> Decision {
+        self.check(user_agent, &path_of(url))
+    }
+
+    /// Describe the file and optionally the decision on given path.
+    pub fn report(&self, user_agent: &str, path: Option<&str>) -> RobotsReport {
+        let (status, details, body) = match &self.file {
+            RobotsFile::Parsed(robots) => ("parsed", None, Some(robots.body.clone())),
+            RobotsFile::Missing(status) => ("missing", Some(status.to_string()), None),
+            RobotsFile::Unavailable(e) => ("unavailable", Some(e.clone()), None),
+        };
+        RobotsReport {
+            status,
+            fetched_at: Some(w3c_datetime(self.fetched_at)),
+            details,
+            body,
+            decision: path.map(|p| self.check(user_agent, p)),
+        }
+    }
+}
+
+impl RobotsReport {
+    /// Report on a robots.txt file which is still being fetched.
+    pub fn pending() -> Self {
+        RobotsReport {
+            status: "pending",
+            fetched_at: None,
+            details: None,
+            body: None,
+            decision: None,
+        }
+    }
+}
+
+/// The path and query of the URL, as matched by robots.txt rules.
+pub fn path_of(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+}
+
+/// Check whether a robots.txt style pattern matches given path.
+///
+/// Supports the `*` wildcard and the `$` end anchor.
+pub fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let mut rest = match path.strip_prefix(parts[0]) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let last = match parts.len() {
+        1 => return !anchored || rest.is_empty(),
+        n => parts[n - 1],
+    };
+    // Every other part follows a wildcard, so the leftmost match is the best.
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    match anchored {
+        true => rest.ends_with(last),
+        false => rest.contains(last),
+    }
+}
+
+/// The name of the crawler in given user agent, e.g. `croolr` in `croolr/0.1`.
+fn product_token(user_agent: &str) -> String {
+    let token = user_agent.split(['/', ' ']).next().unwrap_or("");
+    token.to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ROBOTS: &str = "
+        # comment
+        User-agent: *
+        Disallow: /private/
+        Allow: /private/public.html
+        Disallow: /*.pdf$
+
+        User-agent: croolr
+        User-agent: otherbot
+        Disallow: /nocroolr
+        Disallow:
+
+        User-agent: evilbot
+        Disallow: /
+    ";
+
+    #[test]
+    fn unit_rule_matches() {
+        let rule = |pattern: &str| Rule {
+            allow: false,
+            pattern: pattern.to_string(),
+        };
+        assert!(rule("/a").matches("/a/b"));
+        assert!(!rule("/a").matches("/b"));
+        assert!(rule("/*.pdf$").matches("/x/y.pdf"));
+        assert!(!rule("/*.pdf$").matches("/x/y.pdf?z"));
+        assert!(rule("/*.pdf").matches("/x/y.pdf?z"));
+        assert!(rule("/a$").matches("/a"));
+        assert!(!rule("/a$").matches("/ab"));
+        assert!(rule("/a*b*c").matches("/axxbyyc/d"));
+    }
+
+    #[test]
+    fn unit_robots_check() {
+        let robots = Robots::parse(ROBOTS);
+        let check = |agent, path| robots.check(agent, path).allowed;
+        assert!(!check("somebot", "/private/x"));
+        assert!(check("somebot", "/private/public.html"));
+        assert!(!check("somebot", "/doc.pdf"));
+        assert!(check("somebot", "/nocroolr"));
+        // Named groups replace the * group.
+        assert!(!check("croolr/0.1.0", "/nocroolr/page"));
+        assert!(check("croolr/0.1.0", "/private/x"));
+        assert!(check("OtherBot", "/private/x"));
+        assert!(!check("evilbot", "/anything"));
+        let decision = robots.check("somebot", "/private/x");
+        assert_eq!(decision.rule.as_deref(), Some("Disallow: /private/"));
+    }
+
+    #[test]
+    fn unit_robots_file() {
+        let robots = |file| RobotsTxt {
+            file,
+            fetched_at: SystemTime::UNIX_EPOCH,
+        };
+        let url = Url::parse("http://example.com/x?y=1").unwrap();
+        assert_eq!(path_of(&url), "/x?y=1");
+        assert!(
+            robots(RobotsFile::Missing(404))
+                .allows("croolr", &url)
+                .allowed
+        );
+        let unavailable = robots(RobotsFile::Unavailable("timeout".into()));
+        assert!(!unavailable.allows("croolr", &url).allowed);
+        let report = unavailable.report("croolr", Some("/x"));
+        assert_eq!(report.status, "unavailable");
+        assert_eq!(report.fetched_at.as_deref(), Some("1970-01-01T00:00:00Z"));
+        assert!(!report.decision.unwrap().allowed);
+    }
+}
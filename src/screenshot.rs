@@ -0,0 +1,252 @@
+//! Screenshots of the crawled pages taken by a headless browser.
+//!
+//! With `--screenshots DIR`, each page fetched successfully is loaded again by
+//! the [`Browser`] of the [`headless`](super::headless) backend, and a PNG
+//! screenshot of it is kept in the directory, named by the hash of its URL.
+//! `/screenshot?u=...` serves it. The browser loads the pages along with their
+//! assets on its own, outside of the per-host delay of the crawl, so only a
+//! couple of screenshots are taken at once. The directory is kept across
+//! restarts.
+
+use super::fetch::content_hash;
+use super::headless::Browser;
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use url::Url;
+
+/// Max number of screenshots taken at once.
+const MAX_SCREENSHOTS: usize = 2;
+
+/// Max number of pages waiting for their screenshot.
+const QUEUE_SIZE: usize = 256;
+
+/// The directory the screenshots are kept in.
+#[derive(Debug, Clone)]
+pub struct ScreenshotStore {
+    dir: PathBuf,
+}
+
+impl ScreenshotStore {
+    /// Keep the screenshots in given directory, created if missing.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, String> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+        Ok(ScreenshotStore { dir })
+    }
+
+    fn path(&self, url: &Url) -> PathBuf {
+        let hash = content_hash(url.as_str().as_bytes());
+        self.dir.join(format!("{:016x}.png", hash))
+    }
+
+    /// The screenshot of given page, None if none has been taken.
+    pub async fn get(&self, url: &Url) -> Result<Option<Vec<u8>>, String> {
+        match tokio::fs::read(self.path(url)).await {
+            Ok(png) => Ok(Some(png)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("cannot read the screenshot: {}", e)),
+        }
+    }
+
+    /// Keep given screenshot, replacing the previous one of the page.
+    pub async fn put(&self, url: &Url, png: &[u8]) -> Result<(), String> {
+        // Written aside first so that a screenshot is never served half done.
+        let path = self.path(url);
+        let partial = path.with_extension("part");
+        let write = async {
+            tokio::fs::write(&partial, png).await?;
+            tokio::fs::rename(&partial, &path).await
+        };
+        write
+            .await
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+}
+
+/// Takes the screenshots of the pages in the background and keeps them.
+///
+/// The pages are queued for a few worker tasks, which take the screenshots
+/// one at a time each. Pages coming when the queue is full are skipped, so
+/// that a crawl outpacing the browser does not pile them up.
+pub struct Screenshots {
+    store: ScreenshotStore,
+    queue: mpsc::Sender<(Url, String)>,
+}
+
+impl Screenshots {
+    /// Start the workers taking the screenshots with given browser.
+    pub fn new(browser: Arc<dyn Browser>, store: ScreenshotStore) -> Self {
+        Screenshots::with_queue(browser, store, QUEUE_SIZE)
+    }
+
+    fn with_queue(browser: Arc<dyn Browser>, store: ScreenshotStore, size: usize) -> Self {
+        let (queue, pages) = mpsc::channel(size);
+        let pages = Arc::new(Mutex::new(pages));
+        for _ in 0..MAX_SCREENSHOTS {
+            tokio::task::spawn(work(browser.clone(), store.clone(), pages.clone()));
+        }
+        Screenshots { store, queue }
+    }
+
+    /// Queue the screenshot of given page, returning whether it has been
+    /// queued rather than skipped.
+    pub fn capture(&self, url: Url, user_agent: &str) -> bool {
+        match self.queue.try_send((url, user_agent.to_string())) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full((url, _))) => {
+                tracing::debug!(url = %url, "Screenshot skipped, too many pages waiting");
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// The screenshot of given page, None if none has been taken.
+    pub async fn get(&self, url: &Url) -> Result<Option<Vec<u8>>, String> {
+        self.store.get(url).await
+    }
+}
+
+/// Take the screenshots of the queued pages until the queue is dropped.
+async fn work(
+    browser: Arc<dyn Browser>,
+    store: ScreenshotStore,
+    pages: Arc<Mutex<mpsc::Receiver<(Url, String)>>>,
+) {
+    loop {
+        let (url, user_agent) = match pages.lock().await.recv().await {
+            Some(page) => page,
+            None => break,
+        };
+        let taken = match browser.screenshot(&url, &user_agent).await {
+            Ok(png) => store.put(&url, &png).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = taken {
+            tracing::warn!(url = %url, error = %e, "Screenshot failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::crawler::Crawler;
+    use super::super::fetcher::MockFetcher;
+    use super::super::urlinfo::Domain;
+    use super::*;
+    use futures::future::BoxFuture;
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = format!("croolr-test-{}-{}", name, std::process::id());
+        std::env::temp_dir().join(dir)
+    }
+
+    /// The pages of the site crawled by the tests.
+    const SITE: [(&str, &str); 3] = [
+        (
+            "/",
+            r#"<a href="/a">A</a><a href="/broken">B</a><a href="/missing">C</a>"#,
+        ),
+        ("/a", ""),
+        ("/broken", ""),
+    ];
+
+    /// Renders the pages of the site, taking the URL for their screenshot,
+    /// failing on some.
+    struct Browser;
+
+    impl super::Browser for Browser {
+        fn render<'a>(
+            &'a self,
+            url: &'a Url,
+            _user_agent: &'a str,
+        ) -> BoxFuture<'a, Result<String, String>> {
+            Box::pin(async move {
+                let page = SITE.iter().find(|(path, _)| *path == url.path());
+                page.map(|(_, html)| html.to_string())
+                    .ok_or_else(|| "not found".to_string())
+            })
+        }
+
+        fn screenshot<'a>(
+            &'a self,
+            url: &'a Url,
+            _user_agent: &'a str,
+        ) -> BoxFuture<'a, Result<Vec<u8>, String>> {
+            Box::pin(async move {
+                match url.path() {
+                    "/broken" => Err("crashed".to_string()),
+                    _ => Ok(url.as_str().as_bytes().to_vec()),
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_screenshots_crawl() {
+        let fetcher = SITE
+            .iter()
+            .fold(MockFetcher::new(), |fetcher, (path, html)| {
+                fetcher.page(&format!("http://example.com{}", path), html)
+            })
+            .status("http://example.com/missing", 404);
+        let dir = temp_dir("screenshots");
+        let crawler = Crawler::builder()
+            .fetcher(fetcher)
+            .headless()
+            .screenshots(&dir)
+            .browser(Browser)
+            .build()
+            .unwrap();
+        let domain: Domain = "example.com".parse().unwrap();
+        crawler.crawl(domain.clone()).await.unwrap();
+        assert_eq!(crawler.wait(domain).await, Ok(true));
+
+        let url = |path| {
+            Url::parse("http://example.com/")
+                .unwrap()
+                .join(path)
+                .unwrap()
+        };
+        let screenshot = |path| crawler.screenshot(url(path));
+        while screenshot("/a").await.unwrap().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            screenshot("/").await,
+            Ok(Some(b"http://example.com/".to_vec()))
+        );
+        // Neither the failed screenshots nor those of the pages not found
+        // are kept.
+        assert_eq!(screenshot("/broken").await, Ok(None));
+        let missing = crawler.url_info(url("/missing")).await.unwrap().unwrap();
+        assert_eq!(missing.status(), Some(reqwest::StatusCode::NOT_FOUND));
+        assert_eq!(screenshot("/missing").await, Ok(None));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Screenshots are only taken by the headless browser backend.
+        let crawler = Crawler::builder().screenshots(&dir).build();
+        assert!(crawler.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_screenshots_queue_full() {
+        let dir = temp_dir("screenshots-queue");
+        let store = ScreenshotStore::new(&dir).unwrap();
+        let screenshots = Screenshots::with_queue(Arc::new(Browser), store, 1);
+        // The workers do not get to run before the test yields.
+        let url = Url::parse("http://example.com/").unwrap();
+        assert!(screenshots.capture(url.clone(), "test"));
+        assert!(!screenshots.capture(url.join("/a").unwrap(), "test"));
+        while screenshots.get(&url).await.unwrap().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(screenshots.capture(url.join("/a").unwrap(), "test"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
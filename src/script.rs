@@ -0,0 +1,161 @@
+//! User scripts deciding which links to follow and extracting data from the
+//! fetched pages.
+//!
+//! Scripts are written in [Rhai](https://rhai.rs) and may define either of
+//! these functions:
+//!
+//! * `follow(url, parent)` returns whether to follow a link to `url` found on
+//!   the `parent` page, which is `()` for the crawl seeds.
+//! * `extract(url, body)` returns a map of custom fields recorded with the
+//!   page, e.g. `#{ price: body.index_of("$") }`.
+//!
+//! Scripts failing at runtime are logged, the link is followed and no fields
+//! are extracted then.
+
+use super::fetcher::{Request, Response};
+use super::middleware::Middleware;
+use super::urlinfo::Error;
+
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::collections::BTreeMap;
+use std::path::Path;
+use url::Url;
+
+/// Max number of operations of a single script call, to stop runaway loops.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A compiled user script.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    has_follow: bool,
+    has_extract: bool,
+}
+
+impl Script {
+    /// Compile a script from its source code.
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine
+            .compile(source)
+            .map_err(|e| format!("invalid script: {}", e))?;
+        let defines = |name| {
+            ast.iter_functions()
+                .any(|f| f.name == name && f.params.len() == 2)
+        };
+        let has_follow = defines("follow");
+        let has_extract = defines("extract");
+        if !has_follow && !has_extract {
+            return Err(
+                "the script defines neither follow(url, parent) nor extract(url, body)".to_string(),
+            );
+        }
+        Ok(Script {
+            engine,
+            ast,
+            has_follow,
+            has_extract,
+        })
+    }
+
+    /// Read and compile a script file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read script {}: {}", path.display(), e))?;
+        Script::compile(&source)
+    }
+
+    /// Whether to follow a link to given URL.
+    pub fn follow(&self, url: &Url, parent: Option<&Url>) -> bool {
+        if !self.has_follow {
+            return true;
+        }
+        let parent = parent.map_or(Dynamic::UNIT, |p| p.to_string().into());
+        let args = (url.to_string(), parent);
+        match self
+            .engine
+            .call_fn::<bool>(&mut Scope::new(), &self.ast, "follow", args)
+        {
+            Ok(follow) => follow,
+            Err(e) => {
+                tracing::warn!(%url, error = %e, "Script follow() failed");
+                true
+            }
+        }
+    }
+
+    /// The custom fields of given page.
+    pub fn extract(&self, url: &Url, body: &str) -> BTreeMap<String, String> {
+        if !self.has_extract {
+            return BTreeMap::new();
+        }
+        let args = (url.to_string(), body.to_string());
+        match self
+            .engine
+            .call_fn::<Map>(&mut Scope::new(), &self.ast, "extract", args)
+        {
+            Ok(fields) => fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            Err(e) => {
+                tracing::warn!(%url, error = %e, "Script extract() failed");
+                BTreeMap::new()
+            }
+        }
+    }
+}
+
+impl Middleware for Script {
+    fn after_response(&self, _request: &Request, result: &mut Result<Response, Error>) {
+        if let Ok(response) = result {
+            let body = String::from_utf8_lossy(&response.body);
+            let fields = self.extract(&response.url, &body);
+            response.page.extra.extend(fields);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_script() {
+        assert!(Script::compile("fn follow(url) { true }").is_err());
+        assert!(Script::compile("fn follow(url, parent) {").is_err());
+
+        let script = Script::compile(
+            r#"
+            fn follow(url, parent) { parent == () || !url.contains("/private/") }
+            fn extract(url, body) {
+                let start = body.index_of("<h1>");
+                if start < 0 { return #{}; }
+                #{ heading: body.sub_string(start + 4, body.index_of("</h1>") - start - 4), len: body.len() }
+            }
+            "#,
+        )
+        .unwrap();
+        let url = |u| Url::parse(u).unwrap();
+        let home = url("http://example.com/");
+        assert!(script.follow(&url("http://example.com/private/x"), None));
+        assert!(!script.follow(&url("http://example.com/private/x"), Some(&home)));
+        assert!(script.follow(&url("http://example.com/public"), Some(&home)));
+
+        let fields = script.extract(&home, "<h1>Hello</h1>");
+        let expected = [("heading", "Hello"), ("len", "14")];
+        let expected: BTreeMap<_, _> = expected
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(fields, expected);
+        assert!(script.extract(&home, "no heading").is_empty());
+
+        // Failing scripts follow the links and extract nothing.
+        let script =
+            Script::compile("fn follow(url, parent) { x } fn extract(url, body) { 1 }").unwrap();
+        assert!(script.follow(&home, None));
+        assert!(script.extract(&home, "").is_empty());
+    }
+}
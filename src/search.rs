@@ -0,0 +1,121 @@
+//! Search for URLs across all the crawled domains.
+
+use super::urlinfo::*;
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use url::Url;
+
+/// Default max number of search hits returned.
+const DEFAULT_LIMIT: usize = 100;
+
+/// Search parameters.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SearchQuery {
+    /// Substring to look for, case-insensitive.
+    pub q: String,
+    /// Search the page titles too, not just the URLs.
+    #[serde(default)]
+    pub titles: bool,
+    /// Max number of hits to return.
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    DEFAULT_LIMIT
+}
+
+/// URLs matching a search, ordered by URL.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SearchResults {
+    /// Total number of matching URLs, regardless of the limit.
+    pub total: usize,
+    pub hits: Vec<SearchHit>,
+}
+
+/// A single URL matching a search.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub url: Url,
+    pub status: Option<u16>,
+    pub title: Option<String>,
+}
+
+impl SearchQuery {
+    /// Find matching URLs in given URL sets.
+    pub fn apply<'a>(&self, sets: impl Iterator<Item = &'a UrlSet>) -> SearchResults {
+        let needle = self.q.to_lowercase();
+        let title = |info: &'a UrlInfo| info.result.as_ref().ok().and_then(|p| p.title.as_ref());
+        let matches = |url: &Url, info: &'a UrlInfo| {
+            url.as_str().to_lowercase().contains(&needle)
+                || (self.titles && title(info).is_some_and(|t| t.to_lowercase().contains(&needle)))
+        };
+        let mut found: Vec<(&Arc<Url>, &UrlInfo)> = sets
+            .flatten()
+            .filter(|(url, info)| matches(url, info))
+            .collect();
+        found.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        let total = found.len();
+        found.truncate(self.limit);
+        let hits = found
+            .into_iter()
+            .map(|(url, info)| SearchHit {
+                url: Url::clone(url),
+                status: info.status().map(|s| s.as_u16()),
+                title: title(info).cloned(),
+            })
+            .collect();
+        SearchResults { total, hits }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn url_set(urls: &[(&str, Option<&str>)]) -> UrlSet {
+        let info = |title: Option<&str>| {
+            let page = Page {
+                title: title.map(str::to_string),
+                ..Page::default()
+            };
+            UrlInfo::from_result(Ok(page), Duration::ZERO)
+        };
+        urls.iter()
+            .map(|(url, title)| (Arc::new(Url::parse(url).unwrap()), info(*title)))
+            .collect()
+    }
+
+    #[test]
+    fn unit_search() {
+        let a = url_set(&[
+            ("http://a.com/blog/rust", None),
+            ("http://a.com/about", Some("About the Blog")),
+        ]);
+        let b = url_set(&[("http://b.com/BLOG/", None), ("http://b.com/", None)]);
+        let search = |q: &str, titles, limit| {
+            let query = SearchQuery {
+                q: q.to_string(),
+                titles,
+                limit,
+            };
+            let results = query.apply([&a, &b].iter().copied());
+            let hits: Vec<String> = results.hits.iter().map(|h| h.url.to_string()).collect();
+            (results.total, hits)
+        };
+        assert_eq!(
+            search("blog", false, 10),
+            (
+                2,
+                vec!["http://a.com/blog/rust".into(), "http://b.com/BLOG/".into()]
+            )
+        );
+        assert_eq!(search("blog", true, 10).0, 3);
+        assert_eq!(
+            search("blog", true, 1),
+            (3, vec!["http://a.com/about".into()])
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! The set of URLs discovered by the crawler, shared with the fetch tasks.
+//!
+//! Only the crawler task adds to the set, keeping the order of discovery, but
+//! the fetch tasks look the links they find up in it too. Links already known
+//! are dropped right away instead of all of them going through the crawler
+//! task, which with many domains crawled at once would hold up the fetches.
+//! The set is split into shards by the hash of the URL, each behind its own
+//! lock, so that the lookups rarely wait for each other.
+
+use super::urlinfo::Discovery;
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::{Arc, RwLock};
+use url::Url;
+
+/// Number of shards of the set.
+const SHARDS: usize = 64;
+
+type Shard = RwLock<HashMap<Arc<Url>, Discovery>>;
+
+/// Discovered URLs along with how they have been discovered.
+pub struct SeenSet {
+    shards: Box<[Shard]>,
+    hasher: RandomState,
+}
+
+impl SeenSet {
+    pub fn new() -> Self {
+        SeenSet {
+            shards: (0..SHARDS).map(|_| Shard::default()).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard(&self, url: &Url) -> &Shard {
+        &self.shards[self.hasher.hash_one(url) as usize % self.shards.len()]
+    }
+
+    pub fn contains(&self, url: &Url) -> bool {
+        self.shard(url).read().unwrap().contains_key(url)
+    }
+
+    pub fn get(&self, url: &Url) -> Option<Discovery> {
+        self.shard(url).read().unwrap().get(url).cloned()
+    }
+
+    /// Add given URL, returning how it had been discovered before if it had.
+    pub fn insert(&self, url: Arc<Url>, discovery: Discovery) -> Option<Discovery> {
+        self.shard(&url).write().unwrap().insert(url, discovery)
+    }
+
+    /// The copy of given URL shared by the crawler state, a new one if the URL
+    /// has not been discovered.
+    pub fn intern(&self, url: &Url) -> Arc<Url> {
+        match self.shard(url).read().unwrap().get_key_value(url) {
+            Some((shared, _)) => shared.clone(),
+            None => Arc::new(url.clone()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.read().unwrap().len()).sum()
+    }
+}
+
+impl Default for SeenSet {
+    fn default() -> Self {
+        SeenSet::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_seen_set() {
+        let seen = SeenSet::new();
+        let urls: Vec<Url> = (0..100)
+            .map(|i| Url::parse(&format!("http://example.com/{}", i)).unwrap())
+            .collect();
+        for (seq, url) in urls.iter().enumerate() {
+            let discovery = Discovery {
+                seq: seq as u64,
+                ..Default::default()
+            };
+            assert!(seen.insert(Arc::new(url.clone()), discovery).is_none());
+        }
+        assert_eq!(seen.len(), 100);
+        assert!(seen.contains(&urls[42]));
+        assert_eq!(seen.get(&urls[42]).unwrap().seq, 42);
+        assert!(!seen.contains(&Url::parse("http://example.com/x").unwrap()));
+
+        // The shared copy is handed out once the URL is in.
+        let shared = seen.intern(&urls[7]);
+        assert!(Arc::ptr_eq(&shared, &seen.intern(&urls[7])));
+        assert!(seen.insert(shared, Discovery::default()).is_some());
+        assert_eq!(seen.len(), 100);
+    }
+}
@@ -0,0 +1,1014 @@
+//! The top-level serever.
+
+use super::api::*;
+use super::audit::AuditOptions;
+use super::auth::{self, Access, SharedKeys};
+use super::batch::{self, BatchRequest};
+use super::caching;
+use super::checkpoint::Checkpoint;
+use super::compression;
+use super::config;
+use super::cors::CorsConfig;
+use super::crawler::{Control, Crawler, DataVersion};
+use super::event::CrawlEvent;
+use super::export::Format;
+#[cfg(feature = "graphql")]
+use super::graphql;
+#[cfg(feature = "grpc")]
+use super::grpc;
+use super::listing::ListOptions;
+use super::logging;
+use super::metrics::{self, METRICS};
+use super::openapi;
+use super::ratelimit::{self, Limiter};
+use super::search::SearchQuery;
+use super::settings::{Settings, SettingsUpdate};
+use super::sitemap;
+use super::urlinfo::{Domain, UrlRecord};
+use super::wait::WaitQuery;
+use super::websocket;
+
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+use warp::Filter;
+
+type JsonReply = Result<warp::reply::Json, warp::reject::Rejection>;
+
+/// Server configuration.
+#[derive(Debug)]
+pub struct Options {
+    /// Addresses to serve the API on, all sharing the same crawler.
+    pub listen: Vec<SocketAddr>,
+    /// Sockets already listening to serve the API on, e.g. passed by systemd.
+    /// These are served over plain HTTP only.
+    pub listeners: Vec<std::net::TcpListener>,
+    /// Initial crawler settings.
+    pub settings: Settings,
+    /// API keys clients have to present.
+    pub keys: auth::Keys,
+    /// Per-client request rate limit, no limit if None.
+    pub rate_limit: Option<Limiter>,
+    /// Cross-origin requests allowed from browsers.
+    pub cors: CorsConfig,
+    /// Compress responses for clients accepting it.
+    pub compression: bool,
+    /// Time to wait for fetches in flight to finish when shutting down.
+    pub shutdown_timeout: Duration,
+    /// Serve HTTPS with given certificate and key instead of plain HTTP.
+    pub tls: Option<TlsFiles>,
+    /// Address to serve the gRPC interface on, not served if None.
+    pub grpc: Option<SocketAddr>,
+    /// Configuration file to re-read on SIGHUP and /admin/reload, if any.
+    pub config: Option<config::Source>,
+    /// URLs to start crawling at right away, each of its domain.
+    pub seeds: Vec<url::Url>,
+    /// File the progress is saved to on shutdown and continued from on
+    /// start, if any.
+    pub checkpoint: Option<PathBuf>,
+}
+
+/// Paths to the PEM encoded TLS certificate chain and private key.
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Start the server, returning once it has been shut down by SIGINT or
+/// SIGTERM.
+///
+/// Fails if the crawler settings are invalid.
+pub async fn start(opts: Options) -> Result<(), String> {
+    serve(opts, shutdown_signal()).await
+}
+
+/// Start the server, returning once it has been shut down after given future
+/// resolves, e.g. in tests.
+///
+/// Fails if the crawler settings are invalid.
+pub async fn serve(opts: Options, stop_signal: impl Future<Output = ()>) -> Result<(), String> {
+    #[cfg(not(feature = "grpc"))]
+    if opts.grpc.is_some() {
+        return Err("gRPC needs croolr built with the grpc feature".to_string());
+    }
+    let crawler = Crawler::builder()
+        .settings(opts.settings)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let checkpoint = opts.checkpoint.as_deref();
+    if let Some(saved) = checkpoint.map(Checkpoint::load).transpose()?.flatten() {
+        tracing::info!(
+            fetched = saved.fetched.len(),
+            queued = saved.frontier.len(),
+            "Resuming crawls"
+        );
+        crawler
+            .restore(saved)
+            .await
+            .map_err(|_| "crawler stopped unexpectedly".to_string())?;
+    }
+    for seed in &opts.seeds {
+        if crawler.crawl_seed(seed.clone()).await.is_err() {
+            tracing::error!("Crawler stopped before the seeds were queued");
+        }
+    }
+    if !opts.seeds.is_empty() {
+        tracing::info!(seeds = opts.seeds.len(), "Seeds queued");
+    }
+    let keys = SharedKeys::new(opts.keys);
+    let limiter = opts.rate_limit.map(Arc::new);
+    let limit = ratelimit::limit(&limiter, &keys);
+    let read = limit.clone().and(auth::require(&keys, Access::Read));
+    let write = limit.and(auth::require(&keys, Access::Write));
+
+    let crawl = warp::path!("crawl" / String)
+        .and(write.clone())
+        .and(warp::query::<CrawlQuery>())
+        .and(with_cloned(&crawler))
+        .and_then(handle_crawl);
+
+    let crawl_stream = warp::path!("crawl-stream" / String)
+        .and(write.clone())
+        .and(with_cloned(&crawler))
+        .and_then(handle_crawl_stream);
+
+    let crawl_batch = warp::post()
+        .and(warp::path!("crawl-batch"))
+        .and(write.clone())
+        .and(warp::body::content_length_limit(batch::MAX_BODY_SIZE))
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::bytes())
+        .and(with_cloned(&crawler))
+        .and_then(handle_crawl_batch);
+
+    let count = warp::path!("count" / String)
+        .and(read.clone())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(with_cloned(&crawler))
+        .and_then(handle_count);
+
+    let urls = warp::path!("urls" / String)
+        .and(read.clone())
+        .and(warp::query::<ListOptions>())
+        .and(warp::query::<FormatQuery>())
+        .and(warp::header::optional::<String>("accept"))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(with_cloned(&crawler))
+        .and_then(handle_urls);
+
+    let sitemap = warp::path!("sitemap" / String)
+        .and(read.clone())
+        .and(with_cloned(&crawler))
+        .and_then(handle_sitemap);
+
+    let audit = warp::path!("audit" / String)
+        .and(read.clone())
+        .and(warp::query::<AuditOptions>())
+        .and(with_cloned(&crawler))
+        .and_then(handle_audit);
+
+    let broken = warp::path!("broken" / String)
+        .and(read.clone())
+        .and(with_cloned(&crawler))
+        .and_then(handle_broken);
+
+    let domains = warp::path!("domains")
+        .and(read.clone())
+        .and(with_cloned(&crawler))
+        .and_then(handle_domains);
+
+    let search = warp::path!("search")
+        .and(read.clone())
+        .and(warp::query::<SearchQuery>())
+        .and(with_cloned(&crawler))
+        .and_then(handle_search);
+
+    let stats = warp::path!("stats")
+        .and(read.clone())
+        .and(with_cloned(&crawler))
+        .and_then(handle_stats);
+
+    let summary = warp::path!("summary" / String)
+        .and(read.clone())
+        .and(with_cloned(&crawler))
+        .and_then(handle_summary);
+
+    let queue = warp::path!("queue" / String)
+        .and(read.clone())
+        .and(with_cloned(&crawler))
+        .and_then(handle_queue);
+
+    let wait = warp::path!("wait" / String)
+        .and(read.clone())
+        .and(warp::query::<WaitQuery>())
+        .and(with_cloned(&crawler))
+        .and_then(handle_wait);
+
+    let events = warp::path!("events" / String)
+        .and(read.clone())
+        .and(with_cloned(&crawler))
+        .and_then(handle_events);
+
+    let control = warp::path!(Control / String)
+        .and(write.clone())
+        .and(with_cloned(&crawler))
+        .and_then(handle_control);
+
+    let ws = warp::path!("ws")
+        .and(write.clone())
+        .and(warp::ws())
+        .and(with_cloned(&crawler))
+        .map(|ws: warp::ws::Ws, crawler| ws.on_upgrade(|socket| websocket::serve(socket, crawler)));
+
+    let get_config = warp::get()
+        .and(with_cloned(&crawler))
+        .and_then(handle_get_config);
+    let put_config = warp::put()
+        .and(warp::body::json())
+        .and(with_cloned(&crawler))
+        .and_then(handle_put_config);
+    let config = warp::path!("admin" / "config")
+        .and(write.clone())
+        .and(get_config.or(put_config).unify());
+
+    let reloader = Reloader {
+        source: opts.config.map(Arc::new),
+        crawler: crawler.clone(),
+        keys: keys.clone(),
+    };
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(reloader.clone()));
+
+    let reload = warp::path!("admin" / "reload")
+        .and(warp::post())
+        .and(write.clone())
+        .and(with_cloned(&reloader))
+        .and_then(handle_reload);
+
+    let metrics = warp::path!("metrics")
+        .and(read.clone())
+        .and(with_cloned(&crawler))
+        .and_then(handle_metrics);
+
+    let robots = warp::path!("robots" / String)
+        .and(read.clone())
+        .and(warp::query::<RobotsQuery>())
+        .and(with_cloned(&crawler))
+        .and_then(handle_robots);
+
+    let url = warp::path!("url")
+        .and(read.clone())
+        .and(warp::query::<UrlQuery>())
+        .and(with_cloned(&crawler))
+        .and_then(handle_url);
+
+    let outlinks = warp::path!("outlinks" / String)
+        .and(read.clone())
+        .and(warp::query::<OutlinksQuery>())
+        .and(with_cloned(&crawler))
+        .and_then(handle_outlinks);
+
+    let spec = warp::path!("openapi.json").map(|| warp::reply::json(&openapi::spec()));
+
+    let docs = warp::path!("docs").map(|| warp::reply::html(openapi::SWAGGER_UI));
+
+    let front = warp::path::end().map(|| "Nothing to see here");
+
+    let api = crawl
+        .or(crawl_stream)
+        .or(crawl_batch)
+        .or(urls)
+        .or(count)
+        .or(sitemap)
+        .or(audit)
+        .or(broken)
+        .or(domains)
+        .or(search)
+        .or(stats)
+        .or(summary)
+        .or(queue)
+        .or(wait)
+        .or(events)
+        .or(ws)
+        .or(url)
+        .or(outlinks)
+        .or(robots)
+        .or(config)
+        .or(reload)
+        .or(control);
+
+    #[cfg(feature = "graphql")]
+    let api = api.or(warp::path!("graphql")
+        .and(read.clone())
+        .and(async_graphql_warp::graphql(graphql::schema(
+            crawler.clone(),
+        )))
+        .and_then(handle_graphql));
+
+    #[cfg(feature = "headless")]
+    let api = api.or(warp::path!("screenshot")
+        .and(read.clone())
+        .and(warp::query::<UrlQuery>())
+        .and(with_cloned(&crawler))
+        .and_then(handle_screenshot));
+
+    // Unversioned paths from before /v1 are kept as deprecated aliases.
+    let v1 = warp::path("v1").and(api.clone());
+    let legacy = api.map(|reply| warp::reply::with_header(reply, "deprecation", "true"));
+
+    let routes = front
+        .or(metrics)
+        .or(spec)
+        .or(docs)
+        .or(v1)
+        .or(legacy)
+        .recover(handle_rejection)
+        .map(warp::Reply::into_response)
+        .boxed();
+
+    let routes = match opts.compression {
+        true => compression::compress(routes, is_streaming),
+        false => routes,
+    };
+
+    let routes = match opts.cors.build() {
+        Some(cors) => routes.with(cors).map(warp::Reply::into_response).boxed(),
+        None => routes,
+    };
+
+    let routes = routes
+        .with(logging::requests())
+        .with(logging::request_spans());
+
+    let (stop, stop_rx) = tokio::sync::watch::channel(());
+    // Resolves once all the servers are asked to stop.
+    let stopped = || {
+        let mut stop_rx = stop_rx.clone();
+        async move {
+            let _ = stop_rx.changed().await;
+        }
+    };
+    // The inherited sockets are checked before anything is served.
+    let inherited = opts
+        .listeners
+        .into_iter()
+        .map(|listener| {
+            listener.set_nonblocking(true)?;
+            let addr = listener.local_addr()?;
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            let incoming = tokio_stream::wrappers::TcpListenerStream::new(listener);
+            tracing::info!(%addr, "Serving API on inherited socket");
+            let server = warp::serve(routes.clone());
+            Ok(server.serve_incoming_with_graceful_shutdown(incoming, stopped()))
+        })
+        .collect::<Result<Vec<_>, std::io::Error>>()
+        .map_err(|e| format!("cannot serve on inherited socket: {}", e))?;
+    let tls = opts.tls;
+    let servers: Vec<_> = opts
+        .listen
+        .iter()
+        .map(|&addr| {
+            tracing::info!(%addr, "Serving API");
+            match &tls {
+                Some(tls) => {
+                    let server = warp::serve(routes.clone())
+                        .tls()
+                        .cert_path(&tls.cert)
+                        .key_path(&tls.key);
+                    tokio::spawn(server.bind_with_graceful_shutdown(addr, stopped()).1)
+                }
+                None => {
+                    let server = warp::serve(routes.clone());
+                    tokio::spawn(server.bind_with_graceful_shutdown(addr, stopped()).1)
+                }
+            }
+        })
+        .chain(inherited.into_iter().map(tokio::spawn))
+        .collect();
+
+    #[cfg(feature = "grpc")]
+    let grpc = opts.grpc.map(|addr| {
+        let service = grpc::service(crawler.clone(), keys.clone());
+        let server = tonic::transport::Server::builder().add_service(service);
+        let stopped = stopped();
+        tracing::info!(%addr, "Serving gRPC");
+        tokio::spawn(async move {
+            if let Err(e) = server.serve_with_shutdown(addr, stopped).await {
+                tracing::error!(error = %e, "gRPC server failed");
+            }
+        })
+    });
+    #[cfg(not(feature = "grpc"))]
+    let grpc: Option<tokio::task::JoinHandle<()>> = None;
+
+    stop_signal.await;
+    tracing::info!("Shutting down, waiting for fetches in progress to finish");
+    let _ = stop.send(());
+    // New requests are no longer accepted. Event streams are closed once the
+    // crawler announces it is shutting down. The progress is saved once the
+    // fetches in flight finish, or when they time out, in which case they are
+    // part of the frontier and get fetched again on the next start.
+    let deadline = tokio::time::Instant::now() + opts.shutdown_timeout;
+    if tokio::time::timeout_at(deadline, crawler.drain())
+        .await
+        .is_err()
+    {
+        tracing::warn!("Timed out waiting for fetches to finish");
+    }
+    if let Some(path) = checkpoint {
+        match save_checkpoint(&crawler, path).await {
+            Ok(()) => tracing::info!(checkpoint = %path.display(), "Progress saved"),
+            Err(e) => tracing::error!(error = %e, "Cannot save progress"),
+        }
+    }
+    let shutdown = async {
+        let drained = crawler.shutdown().await;
+        crawler.join().await;
+        for server in servers {
+            let _ = server.await;
+        }
+        if let Some(grpc) = grpc {
+            let _ = grpc.await;
+        }
+        drained
+    };
+    match tokio::time::timeout_at(deadline, shutdown).await {
+        Ok(Ok(unfetched)) => tracing::info!(unfetched, "Crawler stopped"),
+        Ok(Err(e)) => tracing::error!(error = %e, "Crawler failed"),
+        Err(_) => tracing::warn!("Timed out waiting for the crawler to stop"),
+    }
+    Ok(())
+}
+
+/// Save the progress of the crawls to given file.
+async fn save_checkpoint(crawler: &Crawler, path: &std::path::Path) -> Result<(), String> {
+    let checkpoint = crawler
+        .checkpoint()
+        .await
+        .map_err(|_| "crawler stopped unexpectedly".to_string())?;
+    checkpoint.save(path)
+}
+
+/// Resolve once the process is asked to terminate by SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let interrupt = tokio::signal::ctrl_c();
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("cannot handle SIGTERM");
+        tokio::select! {
+            _ = interrupt => (),
+            _ = terminate.recv() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = interrupt.await;
+}
+
+/// Check whether given request path belongs to a streaming endpoint.
+fn is_streaming(path: &str) -> bool {
+    let path = path.strip_prefix("/v1").unwrap_or(path);
+    path.starts_with("/events/") || path.starts_with("/crawl-stream/") || path == "/ws"
+}
+
+/// Handle GET on the /admin/config entry point.
+async fn handle_get_config(crawler: Crawler) -> JsonReply {
+    let config = crawler.settings().await.map_err(ApiError::from)?;
+    Ok(warp::reply::json(&ConfigResponse { config }))
+}
+
+/// Handle PUT on the /admin/config entry point.
+async fn handle_put_config(update: SettingsUpdate, crawler: Crawler) -> JsonReply {
+    let config = crawler
+        .update_settings(update)
+        .await
+        .map_err(ApiError::from)?
+        .map_err(ApiError::BadRequest)?;
+    Ok(warp::reply::json(&ConfigResponse { config }))
+}
+
+/// Applies the configuration file to the running server.
+#[derive(Clone)]
+struct Reloader {
+    source: Option<Arc<config::Source>>,
+    crawler: Crawler,
+    keys: SharedKeys,
+}
+
+impl Reloader {
+    /// Re-read the configuration file, put the keys it gives in effect and
+    /// update the crawler settings. Crawls in progress carry on.
+    async fn reload(&self) -> Result<Settings, ApiError> {
+        let source = self.source.as_ref().ok_or_else(|| {
+            ApiError::BadRequest("no configuration file has been given".to_string())
+        })?;
+        let (settings, keys) = source.load().map_err(ApiError::Internal)?;
+        let update = SettingsUpdate {
+            fetch_limit: Some(settings.fetch_limit),
+            host_delay_ms: Some(settings.host_delay_ms),
+            host_burst: Some(settings.host_burst),
+            user_agent: Some(settings.user_agent),
+        };
+        let settings = self
+            .crawler
+            .update_settings(update)
+            .await?
+            .map_err(ApiError::Internal)?;
+        self.keys.replace(keys);
+        Ok(settings)
+    }
+}
+
+/// Reload the configuration whenever the process receives SIGHUP.
+#[cfg(unix)]
+async fn reload_on_hangup(reloader: Reloader) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = signal(SignalKind::hangup()).expect("cannot handle SIGHUP");
+    while hangup.recv().await.is_some() {
+        match reloader.reload().await {
+            Ok(settings) => tracing::info!(
+                fetch_limit = settings.fetch_limit,
+                host_delay_ms = settings.host_delay_ms,
+                host_burst = settings.host_burst,
+                "Configuration reloaded"
+            ),
+            Err(e) => tracing::error!(
+                error = e.message(),
+                details = e.details().as_deref(),
+                "Cannot reload configuration"
+            ),
+        }
+    }
+}
+
+/// Handle the /admin/reload entry point.
+async fn handle_reload(reloader: Reloader) -> JsonReply {
+    let config = reloader.reload().await?;
+    Ok(warp::reply::json(&ConfigResponse { config }))
+}
+
+/// Turn rejections raised by the API filters into error replies.
+async fn handle_rejection(err: warp::Rejection) -> Result<warp::reply::Response, warp::Rejection> {
+    use warp::Reply;
+
+    let bad_request;
+    let api_error = if let Some(api_error) = err.find::<ApiError>() {
+        api_error
+    } else if let Some(e) = err.find::<warp::reject::InvalidQuery>() {
+        bad_request = ApiError::BadRequest(e.to_string());
+        &bad_request
+    } else if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        bad_request = ApiError::BadRequest(e.to_string());
+        &bad_request
+    } else if let Some(e) = graphql_bad_request(&err) {
+        bad_request = ApiError::BadRequest(e);
+        &bad_request
+    } else {
+        return Err(err);
+    };
+    let reply = warp::reply::json(&api_error.response());
+    let mut reply = warp::reply::with_status(reply, api_error.status()).into_response();
+    if let ApiError::RateLimited(retry_after) = api_error {
+        let secs = retry_after_secs(*retry_after).into();
+        reply.headers_mut().insert("retry-after", secs);
+    }
+    Ok(reply)
+}
+
+/// Details of a malformed GraphQL request, if it is one.
+fn graphql_bad_request(err: &warp::Rejection) -> Option<String> {
+    #[cfg(feature = "graphql")]
+    if let Some(e) = err.find::<async_graphql_warp::GraphQLBadRequest>() {
+        return Some(e.to_string());
+    }
+    #[cfg(not(feature = "graphql"))]
+    let _ = err;
+    None
+}
+
+/// Query parameters of the /crawl/domain.com entry point.
+#[derive(Deserialize)]
+struct CrawlQuery {
+    /// URL to start at instead of the root of the domain.
+    seed: Option<url::Url>,
+}
+
+/// Handle the /crawl/domain.com entry point.
+async fn handle_crawl(domain: String, query: CrawlQuery, crawler: Crawler) -> JsonReply {
+    let domain = parse_domain(&domain)?;
+    let reply = match query.seed {
+        Some(seed) if crawler.domain_of(&seed) != Some(crawler.site(domain.clone())) => {
+            let msg = "the seed is not a URL of the domain".to_string();
+            return Err(ApiError::BadRequest(msg).into());
+        }
+        Some(seed) => crawler.crawl_seed(seed).await,
+        None => crawler.crawl(domain).await,
+    };
+    Ok(warp::reply::json(&crawl_response(
+        reply.map_err(ApiError::from)?,
+    )?))
+}
+
+/// Handle the /crawl-batch entry point.
+///
+/// Takes either a JSON body with a list of domains or a plain text seed list,
+/// one domain or URL per line. The crawl of a URL starts at it, like with
+/// `/crawl?seed=`.
+async fn handle_crawl_batch(
+    content_type: Option<String>,
+    body: warp::hyper::body::Bytes,
+    crawler: Crawler,
+) -> JsonReply {
+    let bad_request = |e: &dyn std::fmt::Display| ApiError::BadRequest(e.to_string());
+    let seeds = match content_type {
+        Some(t) if t.starts_with("application/json") => {
+            let request: BatchRequest =
+                serde_json::from_slice(&body).map_err(|e| bad_request(&e))?;
+            request.domains
+        }
+        _ => batch::parse_seed_list(std::str::from_utf8(&body).map_err(|e| bad_request(&e))?),
+    };
+    if seeds.len() > batch::MAX_SEEDS {
+        let msg = format!("at most {} seeds allowed", batch::MAX_SEEDS);
+        return Err(ApiError::BadRequest(msg).into());
+    }
+    let mut results = Vec::with_capacity(seeds.len());
+    for seed in seeds {
+        let (url, outcome) = match batch::seed_url(&seed) {
+            Ok(url) => {
+                let reply = crawler.crawl_seed(url.clone()).await;
+                (Some(url), crawl_response(reply.map_err(ApiError::from)?))
+            }
+            Err(e) => (None, Err(ApiError::InvalidDomain(e))),
+        };
+        results.push(BatchResult {
+            seed,
+            url,
+            accepted: outcome.is_ok(),
+            error: outcome.err().map(|e| e.response().error),
+        });
+    }
+    let accepted = results.iter().filter(|r| r.accepted).count();
+    Ok(warp::reply::json(&BatchResponse {
+        accepted,
+        rejected: results.len() - accepted,
+        results,
+    }))
+}
+
+/// Handle the /pause/domain.com, /resume/domain.com and /stop/domain.com entry points.
+async fn handle_control(control: Control, domain: String, crawler: Crawler) -> JsonReply {
+    let state = crawler
+        .control(parse_domain(&domain)?, control)
+        .await
+        .map_err(ApiError::from)?
+        .ok_or(ApiError::UnknownDomain)?;
+    Ok(warp::reply::json(&ControlResponse { state }))
+}
+
+/// Handle the /count/domain.com entry point.
+async fn handle_count(
+    domain: String,
+    if_none_match: Option<String>,
+    crawler: Crawler,
+) -> Result<warp::reply::Response, warp::reject::Rejection> {
+    use warp::Reply;
+
+    let domain = parse_domain(&domain)?;
+    let version = data_version(&crawler, &domain).await?;
+    let etag = version.etag("count");
+    if let Some(reply) = caching::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(reply);
+    }
+    let counts = crawler
+        .url_counts(domain)
+        .await
+        .map_err(ApiError::from)?
+        .ok_or(ApiError::UnknownDomain)?;
+    let response = CountResponse {
+        count: counts.total,
+        status_classes: counts.status_classes,
+        content_types: counts.content_types,
+    };
+    let reply = warp::reply::json(&response).into_response();
+    Ok(caching::with_validators(reply, &version, &etag))
+}
+
+/// Version of the data collected for given domain.
+///
+/// It is taken before the data themselves so that the data sent are never
+/// older than the version, at worst the client fetches them again.
+async fn data_version(crawler: &Crawler, domain: &Domain) -> Result<DataVersion, ApiError> {
+    crawler
+        .data_version(domain.clone())
+        .await
+        .map_err(ApiError::from)?
+        .ok_or(ApiError::UnknownDomain)
+}
+
+/// Explicit choice of the /urls output format.
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    format: Option<Format>,
+}
+
+/// Handle the /urls/domain.com?offset=0&limit=100 entry point.
+///
+/// The output format is given by the `format` parameter or the Accept header.
+async fn handle_urls(
+    domain: String,
+    opts: ListOptions,
+    query: FormatQuery,
+    accept: Option<String>,
+    if_none_match: Option<String>,
+    crawler: Crawler,
+) -> Result<warp::reply::Response, warp::reject::Rejection> {
+    use warp::Reply;
+
+    let format = query
+        .format
+        .unwrap_or_else(|| accept.as_deref().map_or(Format::Json, Format::from_accept));
+    let domain = parse_domain(&domain)?;
+    let version = data_version(&crawler, &domain).await?;
+    let etag = version.etag(format.name());
+    if let Some(reply) = caching::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(reply);
+    }
+    let listing = crawler
+        .list_urls(domain, opts)
+        .await
+        .map_err(ApiError::from)?
+        .ok_or(ApiError::UnknownDomain)?;
+    let total = listing.total;
+    let reply = warp::reply::with_header(
+        warp::reply::Response::new(format.body(listing)),
+        "content-type",
+        format.content_type(),
+    );
+    let reply = match format {
+        Format::Json => reply.into_response(),
+        _ => warp::reply::with_header(reply, "x-total-count", total).into_response(),
+    };
+    Ok(caching::with_validators(reply, &version, &etag))
+}
+
+/// Handle the /sitemap/domain.com entry point.
+async fn handle_sitemap(
+    domain: String,
+    crawler: Crawler,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let listing = crawler
+        .list_urls(parse_domain(&domain)?, sitemap::list_options())
+        .await
+        .map_err(ApiError::from)?
+        .ok_or(ApiError::UnknownDomain)?;
+    let content_type = "application/xml; charset=utf-8";
+    Ok(warp::reply::with_header(
+        sitemap::render(&listing),
+        "content-type",
+        content_type,
+    ))
+}
+
+/// Handle the /audit/domain.com entry point.
+async fn handle_audit(domain: String, opts: AuditOptions, crawler: Crawler) -> JsonReply {
+    let audit = crawler
+        .audit(parse_domain(&domain)?, opts)
+        .await
+        .map_err(ApiError::from)?
+        .ok_or(ApiError::UnknownDomain)?;
+    Ok(warp::reply::json(&AuditResponse { audit }))
+}
+
+/// Handle the /broken/domain.com entry point.
+async fn handle_broken(domain: String, crawler: Crawler) -> JsonReply {
+    let broken = crawler
+        .broken_links(parse_domain(&domain)?)
+        .await
+        .map_err(ApiError::from)?
+        .ok_or(ApiError::UnknownDomain)?;
+    Ok(warp::reply::json(&BrokenLinksResponse { broken }))
+}
+
+/// Handle the /domains entry point.
+async fn handle_domains(crawler: Crawler) -> JsonReply {
+    let domains = crawler.domains().await.map_err(ApiError::from)?;
+    Ok(warp::reply::json(&DomainsResponse { domains }))
+}
+
+/// Handle the /search?q=substring entry point.
+async fn handle_search(query: SearchQuery, crawler: Crawler) -> JsonReply {
+    if query.q.is_empty() {
+        return Err(ApiError::BadRequest("empty search query".to_string()).into());
+    }
+    let search = crawler.search(query).await.map_err(ApiError::from)?;
+    Ok(warp::reply::json(&SearchResponse { search }))
+}
+
+/// Handle the /stats entry point.
+async fn handle_stats(crawler: Crawler) -> JsonReply {
+    let stats = crawler.stats().await.map_err(ApiError::from)?;
+    Ok(warp::reply::json(&StatsResponse { stats }))
+}
+
+/// Handle the /metrics entry point.
+async fn handle_metrics(crawler: Crawler) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut text = METRICS.render();
+    let reports = crawler.summaries().await.map_err(ApiError::from)?;
+    metrics::render_domains(&mut text, &reports);
+    let stats = crawler.stats().await.map_err(ApiError::from)?;
+    metrics::render_memory(&mut text, &stats.memory);
+    let content_type = "text/plain; version=0.0.4";
+    Ok(warp::reply::with_header(text, "content-type", content_type))
+}
+
+/// Handle the /summary/domain.com entry point.
+async fn handle_summary(domain: String, crawler: Crawler) -> JsonReply {
+    let summary = crawler
+        .summary(parse_domain(&domain)?)
+        .await
+        .map_err(ApiError::from)?
+        .ok_or(ApiError::UnknownDomain)?
+        .report();
+    Ok(warp::reply::json(&SummaryResponse { summary }))
+}
+
+/// Handle the /queue/domain.com entry point.
+async fn handle_queue(domain: String, crawler: Crawler) -> JsonReply {
+    let queue = crawler
+        .queue(parse_domain(&domain)?)
+        .await
+        .map_err(ApiError::from)?;
+    if queue.state.is_none() {
+        return Err(ApiError::UnknownDomain.into());
+    }
+    Ok(warp::reply::json(&QueueResponse { queue }))
+}
+
+/// Handle the /wait/domain.com?timeout=30s entry point.
+///
+/// Blocks until the crawl has nothing more to fetch or the timeout elapses,
+/// then replies with the result statistics.
+async fn handle_wait(domain: String, query: WaitQuery, crawler: Crawler) -> JsonReply {
+    let domain = parse_domain(&domain)?;
+    let wait = tokio::time::timeout(query.timeout.0, crawler.wait(domain.clone()));
+    let finished = match wait.await {
+        Ok(crawling) => match crawling.map_err(ApiError::from)? {
+            true => true,
+            false => return Err(ApiError::UnknownDomain.into()),
+        },
+        Err(_) => false,
+    };
+    let summary = crawler
+        .summary(domain)
+        .await
+        .map_err(ApiError::from)?
+        .unwrap_or_default()
+        .report();
+    Ok(warp::reply::json(&WaitResponse { finished, summary }))
+}
+
+/// Handle the /events/domain.com entry point.
+///
+/// Streams crawl events concerning given domain as server-sent events.
+async fn handle_events(
+    domain: String,
+    crawler: Crawler,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let domain = crawler.site(parse_domain(&domain)?);
+    let handle = crawler.clone();
+    let stream = crawler.events().filter_map(move |event| {
+        if event.domain().map(|d| handle.site(d)).as_ref() != Some(&domain) {
+            return None;
+        }
+        Some(sse_event(&event))
+    });
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)))
+}
+
+/// Handle the /crawl-stream/domain.com entry point.
+///
+/// Crawls given domain, streaming the fetched URLs as server-sent events
+/// until the crawl has nothing more to fetch.
+async fn handle_crawl_stream(
+    domain: String,
+    crawler: Crawler,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let domain = parse_domain(&domain)?;
+    let results = crawler.crawl_stream(domain).await.map_err(ApiError::from)?;
+    let stream = results.map(|(url, info)| {
+        warp::sse::Event::default()
+            .event("url")
+            .json_data(UrlRecord::new(&url, &info))
+    });
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)))
+}
+
+/// Convert a crawl event to a server-sent event.
+fn sse_event(event: &CrawlEvent) -> Result<warp::sse::Event, serde_json::Error> {
+    warp::sse::Event::default()
+        .event(event.name())
+        .json_data(event)
+}
+
+/// Query parameters of the /url entry point.
+#[derive(Deserialize)]
+struct UrlQuery {
+    u: url::Url,
+}
+
+/// Handle the /url?u=http://domain.com/page entry point.
+async fn handle_url(query: UrlQuery, crawler: Crawler) -> JsonReply {
+    let url = query.u;
+    let info = crawler
+        .url_info(url.clone())
+        .await
+        .map_err(ApiError::from)?
+        .ok_or(ApiError::UnknownUrl)?;
+    let detail = UrlRecord::new(&url, &info);
+    Ok(warp::reply::json(&UrlResponse { url: detail }))
+}
+
+/// Handle the /screenshot?u=http://domain.com/page entry point.
+#[cfg(feature = "headless")]
+async fn handle_screenshot(
+    query: UrlQuery,
+    crawler: Crawler,
+) -> Result<warp::reply::Response, warp::reject::Rejection> {
+    use warp::Reply;
+
+    let png = crawler
+        .screenshot(query.u)
+        .await
+        .map_err(ApiError::Internal)?
+        .ok_or(ApiError::NoScreenshot)?;
+    Ok(warp::reply::with_header(png, "content-type", "image/png").into_response())
+}
+
+/// Query parameters of the /outlinks/domain.com entry point.
+#[derive(Deserialize)]
+struct OutlinksQuery {
+    page: url::Url,
+}
+
+/// Handle the /outlinks/domain.com?page=http://domain.com/page entry point.
+async fn handle_outlinks(domain: String, query: OutlinksQuery, crawler: Crawler) -> JsonReply {
+    let domain = parse_domain(&domain)?;
+    let page = query.page;
+    if crawler.domain_of(&page) != Some(crawler.site(domain.clone())) {
+        let details = format!("page {} is not on {}", page, &*domain);
+        return Err(ApiError::BadRequest(details).into());
+    }
+    let outlinks = crawler
+        .outlinks(page)
+        .await
+        .map_err(ApiError::from)?
+        .ok_or(ApiError::UnknownUrl)?;
+    Ok(warp::reply::json(&OutlinksResponse { outlinks }))
+}
+
+/// Handle the /graphql entry point.
+#[cfg(feature = "graphql")]
+async fn handle_graphql(
+    (schema, request): (graphql::Schema, async_graphql::Request),
+) -> Result<async_graphql_warp::GraphQLResponse, Infallible> {
+    Ok(schema.execute(request).await.into())
+}
+
+/// Query parameters of the /robots/domain.com entry point.
+#[derive(Deserialize)]
+struct RobotsQuery {
+    /// Path to decide on, e.g. `/foo?bar`.
+    path: Option<String>,
+}
+
+/// Handle the /robots/domain.com?path=/foo entry point.
+async fn handle_robots(domain: String, query: RobotsQuery, crawler: Crawler) -> JsonReply {
+    let robots = crawler
+        .robots(parse_domain(&domain)?, query.path)
+        .await
+        .map_err(ApiError::from)?
+        .ok_or(ApiError::UnknownDomain)?;
+    Ok(warp::reply::json(&RobotsResponse { robots }))
+}
+
+/// Parse the domain given in the request path.
+fn parse_domain(domain: &str) -> Result<Domain, ApiError> {
+    domain
+        .parse()
+        .map_err(|e: url::ParseError| ApiError::InvalidDomain(e.to_string()))
+}
+
+/// Warp filter to pass constant data to handlers by cloning them each time.
+fn with_cloned<T: Clone + Send>(
+    x: &T,
+) -> impl warp::Filter<Extract = (T,), Error = Infallible> + Clone {
+    let x = x.clone();
+    warp::any().map(move || x.clone())
+}
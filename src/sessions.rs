@@ -0,0 +1,109 @@
+//! Detection of the query parameters carrying sessions.
+//!
+//! Sites keeping the session in a query parameter give each visit other URLs
+//! for the same pages, and a crawl following them never ends. A parameter is
+//! taken for such one once the pages differing only in its value have come
+//! with the same content a few times, and never with other content. From then
+//! on the parameter is dropped from the URLs found on the domain, collapsing
+//! each family of them into the one URL without it.
+
+use super::fetch::retain_params;
+
+use std::collections::{BTreeSet, HashMap};
+use url::Url;
+
+/// Number of pages found the same as another one of their family before the
+/// parameter is taken for a session.
+const CONFIRMATIONS: u32 = 3;
+
+/// Max number of families tracked per domain, to bound the memory taken.
+const MAX_FAMILIES: usize = 10_000;
+
+/// What has been found out about the parameters of a domain.
+#[derive(Debug, Default)]
+pub struct SessionParams {
+    /// Parameters found to carry sessions.
+    detected: BTreeSet<String>,
+    /// Number of pages found the same as and different from the first one
+    /// of their family, by parameter.
+    evidence: HashMap<String, (u32, u32)>,
+    /// The value of the parameter and the content hash of the first page of
+    /// each family, by the parameter and the URL without it.
+    families: HashMap<(String, Url), (String, u64)>,
+}
+
+impl SessionParams {
+    /// Take note of the content of given page, returning the parameter found
+    /// to carry sessions thanks to it, if any.
+    pub fn record(&mut self, url: &Url, content_hash: u64) -> Option<String> {
+        let mut found = None;
+        for (name, value) in url.query_pairs() {
+            let (same, different) = self.evidence.entry(name.to_string()).or_default();
+            if *different > 0 || self.detected.contains(&*name) {
+                continue;
+            }
+            let mut family = url.clone();
+            retain_params(&mut family, |n| n != name);
+            match self.families.get(&(name.to_string(), family.clone())) {
+                Some((first, _)) if *first == value => continue,
+                Some((_, hash)) if *hash == content_hash => *same += 1,
+                Some(_) => *different += 1,
+                None => {
+                    if self.families.len() < MAX_FAMILIES {
+                        let first = (value.to_string(), content_hash);
+                        self.families.insert((name.to_string(), family), first);
+                    }
+                    continue;
+                }
+            }
+            if *same >= CONFIRMATIONS && *different == 0 {
+                self.detected.insert(name.to_string());
+                found = Some(name.to_string());
+            }
+        }
+        if let Some(name) = &found {
+            self.families.retain(|(param, _), _| param != name);
+        }
+        found
+    }
+
+    /// Given URL without the parameters carrying sessions.
+    pub fn collapse(&self, mut url: Url) -> Url {
+        if !self.detected.is_empty() {
+            retain_params(&mut url, |name| !self.detected.contains(name));
+        }
+        url
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_session_params() {
+        let url = |u: &str| Url::parse(u).unwrap();
+        let mut params = SessionParams::default();
+        // The same page whatever the session, a different one per item.
+        for (sid, item) in [("a", 1), ("b", 2), ("c", 1), ("d", 2)] {
+            let page = url(&format!("http://example.com/p?item={}&sid={}", item, sid));
+            assert_eq!(params.record(&page, item), None);
+        }
+        let found = params.record(&url("http://example.com/p?item=1&sid=e"), 1);
+        assert_eq!(found.as_deref(), Some("sid"));
+        assert_eq!(
+            params.collapse(url("http://example.com/q?sid=x&item=3")),
+            url("http://example.com/q?item=3")
+        );
+
+        // A parameter once changing the content is never taken for a session.
+        let mut params = SessionParams::default();
+        params.record(&url("http://example.com/p?v=1"), 1);
+        params.record(&url("http://example.com/p?v=2"), 2);
+        for v in 3..10 {
+            params.record(&url(&format!("http://example.com/p?v={}", v)), 1);
+        }
+        let page = url("http://example.com/p?v=1");
+        assert_eq!(params.collapse(page.clone()), page);
+    }
+}
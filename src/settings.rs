@@ -0,0 +1,413 @@
+//! Crawler settings that can be changed while it is running.
+
+use super::analyzer::BuiltinAnalyzer;
+use super::discovery;
+use super::summary::Buckets;
+use super::urlinfo::Domain;
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use url::Url;
+
+/// User agent sent with the fetches unless configured otherwise.
+pub const DEFAULT_USER_AGENT: &str = concat!("croolr/", env!("CARGO_PKG_VERSION"));
+
+/// Browser rendering the pages unless configured otherwise.
+pub const DEFAULT_BROWSER: &str = "chromium";
+
+/// Live crawler settings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Settings {
+    /// Max number of concurrent web requests.
+    pub fetch_limit: u32,
+    /// Min delay between starting fetches from the same host, in milliseconds.
+    pub host_delay_ms: u64,
+    /// Number of fetches from the same host that may start without the delay
+    /// between them after the host has been left alone for a while.
+    pub host_burst: u32,
+    /// User agent header sent with the fetches.
+    pub user_agent: String,
+    /// Histogram buckets of the per-domain statistics, cannot be changed.
+    pub buckets: Buckets,
+    /// Record assets and leaf pages without fetching them, cannot be changed.
+    pub discovery_only: bool,
+    /// Patterns of the leaf pages in discovery-only mode, cannot be changed.
+    pub leaf_patterns: Vec<String>,
+    /// Patterns of the URLs recorded without being fetched as they look like
+    /// they change some state, cannot be changed.
+    pub unsafe_patterns: Vec<String>,
+    /// Max number of links followed from the seed, unlimited if None, cannot
+    /// be changed.
+    pub max_depth: Option<u32>,
+    /// Script deciding which links to follow and extracting custom fields
+    /// from the pages, cannot be changed.
+    pub script: Option<PathBuf>,
+    /// WebAssembly plugins processing the fetched pages, cannot be changed.
+    pub plugins: Vec<PathBuf>,
+    /// Built-in analyzers run on the fetched pages, cannot be changed.
+    pub analyzers: Vec<BuiltinAnalyzer>,
+    /// Number of messages the crawler task buffers before their senders have
+    /// to wait, cannot be changed.
+    pub channel_capacity: usize,
+    /// Max number of idle connections kept open to each host, the fetch limit
+    /// if None, cannot be changed.
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Seconds an idle connection is kept open for, cannot be changed.
+    pub pool_idle_timeout_secs: u64,
+    /// Allow fewer fetches at once than the fetch limit while the process
+    /// runs short of open files or memory, cannot be changed.
+    pub auto_fetch_limit: bool,
+    /// Resident memory in MiB the fetch limit tuning keeps below, cannot be
+    /// changed.
+    pub memory_limit_mb: Option<u64>,
+    /// Max number of pages kept in memory to answer repeated requests, none
+    /// if 0, cannot be changed.
+    pub response_cache_size: usize,
+    /// Seconds the pages are answered from the cache for, cannot be changed.
+    pub response_cache_ttl_secs: u64,
+    /// What is done with the pages redirecting to another host, cannot be
+    /// changed.
+    pub cross_host_redirects: RedirectPolicy,
+    /// Crawl and report `www.example.com` and `example.com` as one site,
+    /// cannot be changed.
+    pub merge_www: bool,
+    /// Whether the links to other ports of the host are followed, cannot be
+    /// changed.
+    pub port_policy: PortPolicy,
+    /// Form the paths with or without a trailing slash are turned into before
+    /// they are crawled, cannot be changed.
+    pub trailing_slash: TrailingSlash,
+    /// Find the query parameters carrying sessions and drop them from the
+    /// URLs found, cannot be changed.
+    pub detect_session_params: bool,
+    /// Check the links to other sites found on the pages, cannot be changed.
+    pub check_external: bool,
+    /// Render the pages in a headless browser before extracting their links,
+    /// cannot be changed.
+    pub headless: bool,
+    /// Directory the screenshots of the pages are kept in, none taken if
+    /// None, cannot be changed.
+    pub screenshot_dir: Option<PathBuf>,
+    /// Headless Chrome or Chromium executable rendering the pages and taking
+    /// the screenshots, cannot be changed.
+    pub browser: PathBuf,
+    /// Max number of domains crawled at once, the crawls asked for beyond it
+    /// being pending until others finish, cannot be changed.
+    pub max_active_domains: Option<u32>,
+}
+
+/// What is done with a page redirecting to another host, e.g. from
+/// `example.com` to `www.example.com` or to a CDN.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectPolicy {
+    /// Follow the links of the page within the host it has landed on. The
+    /// page is stored under its own URL on that host as well.
+    #[default]
+    Follow,
+    /// Store the page under the redirecting URL without following its links.
+    Record,
+    /// Record the redirecting URL as not fetched, leaving the page out.
+    Drop,
+}
+
+impl FromStr for RedirectPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "follow" => Ok(RedirectPolicy::Follow),
+            "record" => Ok(RedirectPolicy::Record),
+            "drop" => Ok(RedirectPolicy::Drop),
+            _ => Err(format!("unknown redirect policy: {}", s)),
+        }
+    }
+}
+
+/// Which ports of a host are taken for the same site, e.g. whether the
+/// links from `example.com` to `example.com:8080` are followed.
+///
+/// The results are kept under the host name whatever the port.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortPolicy {
+    /// All the ports of the host are the same site.
+    #[default]
+    Any,
+    /// Only the port of the page the links are found on, the default port of
+    /// the scheme if not given.
+    Same,
+}
+
+impl FromStr for PortPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(PortPolicy::Any),
+            "same" => Ok(PortPolicy::Same),
+            _ => Err(format!("unknown port policy: {}", s)),
+        }
+    }
+}
+
+/// Which of `/path` and `/path/` the discovered URLs are turned into, for
+/// the sites serving the same page under both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    /// The URLs are crawled as they are found.
+    #[default]
+    Keep,
+    /// A slash is added to the paths not ending with one, unless their last
+    /// segment looks like a file name.
+    Add,
+    /// The trailing slash is removed from the paths other than the root.
+    Strip,
+}
+
+impl TrailingSlash {
+    /// Given URL in the chosen form.
+    pub fn apply(self, mut url: Url) -> Url {
+        let path = url.path();
+        let path = match self {
+            TrailingSlash::Add if !path.ends_with('/') => {
+                let name = path.rsplit('/').next().unwrap_or_default();
+                (!name.contains('.')).then(|| format!("{}/", path))
+            }
+            TrailingSlash::Strip if path.len() > 1 => path.strip_suffix('/').map(str::to_string),
+            _ => None,
+        };
+        if let Some(path) = path {
+            url.set_path(&path);
+        }
+        url
+    }
+}
+
+impl FromStr for TrailingSlash {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(TrailingSlash::Keep),
+            "add" => Ok(TrailingSlash::Add),
+            "strip" => Ok(TrailingSlash::Strip),
+            _ => Err(format!("unknown trailing slash form: {}", s)),
+        }
+    }
+}
+
+/// The settings deciding which hosts make up a site: whose links are
+/// followed, which redirects go elsewhere and which results are kept together.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SiteScope {
+    pub merge_www: bool,
+    pub port_policy: PortPolicy,
+}
+
+impl SiteScope {
+    /// The domain given one is crawled and reported as.
+    pub fn site(&self, domain: Domain) -> Domain {
+        domain.site(self.merge_www)
+    }
+
+    /// The domain the results of given URL are kept under.
+    pub fn domain_of(&self, url: &Url) -> Option<Domain> {
+        Domain::from_url(url).map(|d| self.site(d))
+    }
+
+    /// Whether given URLs are of the same site.
+    pub fn same_site(&self, a: &Url, b: &Url) -> bool {
+        let same_port = match self.port_policy {
+            PortPolicy::Any => true,
+            PortPolicy::Same => a.port_or_known_default() == b.port_or_known_default(),
+        };
+        let same_host = match (Domain::from_url(a), Domain::from_url(b)) {
+            (Some(a), Some(b)) => a.equivalent(&b, self.merge_www),
+            _ => a.host() == b.host(),
+        };
+        same_host && same_port
+    }
+}
+
+impl Settings {
+    pub fn host_delay(&self) -> Duration {
+        Duration::from_millis(self.host_delay_ms)
+    }
+
+    /// Max number of idle connections kept open to each host.
+    pub fn pool_size(&self) -> usize {
+        self.pool_max_idle_per_host
+            .unwrap_or(self.fetch_limit as usize)
+    }
+
+    pub fn pool_idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.pool_idle_timeout_secs)
+    }
+
+    pub fn response_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.response_cache_ttl_secs)
+    }
+
+    pub fn site_scope(&self) -> SiteScope {
+        SiteScope {
+            merge_www: self.merge_www,
+            port_policy: self.port_policy,
+        }
+    }
+
+    /// Why given URL is not to be fetched, None if it is.
+    pub fn skip_reason(&self, url: &Url) -> Option<String> {
+        if let Some(pattern) = discovery::unsafe_pattern(url, &self.unsafe_patterns) {
+            return Some(format!("unsafe URL {}", pattern));
+        }
+        match self.discovery_only {
+            true => discovery::skip_reason(url, &self.leaf_patterns),
+            false => None,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            fetch_limit: 16,
+            host_delay_ms: 0,
+            host_burst: 1,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            buckets: Buckets::default(),
+            discovery_only: false,
+            leaf_patterns: Vec::new(),
+            unsafe_patterns: discovery::DEFAULT_UNSAFE_PATTERNS
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            max_depth: None,
+            script: None,
+            plugins: Vec::new(),
+            analyzers: Vec::new(),
+            channel_capacity: 32,
+            pool_max_idle_per_host: None,
+            pool_idle_timeout_secs: 90,
+            auto_fetch_limit: false,
+            memory_limit_mb: None,
+            response_cache_size: 0,
+            response_cache_ttl_secs: 60,
+            cross_host_redirects: RedirectPolicy::Follow,
+            merge_www: false,
+            port_policy: PortPolicy::Any,
+            trailing_slash: TrailingSlash::Keep,
+            detect_session_params: true,
+            check_external: false,
+            headless: false,
+            screenshot_dir: None,
+            browser: PathBuf::from(DEFAULT_BROWSER),
+            max_active_domains: None,
+        }
+    }
+}
+
+/// Change of the settings. Settings not mentioned are left as they are.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SettingsUpdate {
+    pub fetch_limit: Option<u32>,
+    pub host_delay_ms: Option<u64>,
+    pub host_burst: Option<u32>,
+    pub user_agent: Option<String>,
+}
+
+impl SettingsUpdate {
+    /// Check the new values are valid.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.fetch_limit == Some(0) {
+            return Err("fetch limit must be at least 1".to_string());
+        }
+        if self.host_burst == Some(0) {
+            return Err("host burst must be at least 1".to_string());
+        }
+        if let Some(user_agent) = &self.user_agent {
+            reqwest::header::HeaderValue::from_str(user_agent)
+                .map_err(|_| "user agent is not a valid header value".to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Apply the update to given settings, if it is valid.
+    pub fn apply(self, settings: &mut Settings) -> Result<(), String> {
+        self.validate()?;
+        if let Some(fetch_limit) = self.fetch_limit {
+            settings.fetch_limit = fetch_limit;
+        }
+        if let Some(host_delay_ms) = self.host_delay_ms {
+            settings.host_delay_ms = host_delay_ms;
+        }
+        if let Some(host_burst) = self.host_burst {
+            settings.host_burst = host_burst;
+        }
+        if let Some(user_agent) = self.user_agent {
+            settings.user_agent = user_agent;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_settings_update() {
+        let mut settings = Settings::default();
+        let update: SettingsUpdate = serde_json::from_str(r#"{"host_delay_ms": 500}"#).unwrap();
+        update.apply(&mut settings).unwrap();
+        assert_eq!(settings.host_delay(), Duration::from_millis(500));
+        assert_eq!(settings.fetch_limit, 16);
+
+        let update = SettingsUpdate {
+            fetch_limit: Some(0),
+            user_agent: Some("bot".to_string()),
+            ..Default::default()
+        };
+        assert!(update.apply(&mut settings).is_err());
+        assert_eq!(settings.user_agent, DEFAULT_USER_AGENT);
+
+        let update = SettingsUpdate {
+            user_agent: Some("bad\nagent".to_string()),
+            ..Default::default()
+        };
+        assert!(update.validate().is_err());
+        let update: SettingsUpdate = serde_json::from_str(r#"{"host_burst": 0}"#).unwrap();
+        assert!(update.validate().is_err());
+        assert!(serde_json::from_str::<SettingsUpdate>(r#"{"limit": 1}"#).is_err());
+    }
+
+    #[test]
+    fn unit_trailing_slash() {
+        let apply = |form: TrailingSlash, u: &str| form.apply(Url::parse(u).unwrap()).to_string();
+        let add = TrailingSlash::Add;
+        assert_eq!(
+            apply(add, "http://example.com/docs?v=1"),
+            "http://example.com/docs/?v=1"
+        );
+        assert_eq!(
+            apply(add, "http://example.com/docs/"),
+            "http://example.com/docs/"
+        );
+        assert_eq!(
+            apply(add, "http://example.com/a.html"),
+            "http://example.com/a.html"
+        );
+        let strip = TrailingSlash::Strip;
+        assert_eq!(
+            apply(strip, "http://example.com/docs/"),
+            "http://example.com/docs"
+        );
+        assert_eq!(apply(strip, "http://example.com/"), "http://example.com/");
+        let keep = TrailingSlash::Keep;
+        assert_eq!(
+            apply(keep, "http://example.com/docs/"),
+            "http://example.com/docs/"
+        );
+    }
+}
@@ -0,0 +1,668 @@
+//! Per-domain crawl actors.
+//!
+//! The crawler task supervises a shard for each domain it has URLs of. The
+//! shard owns the frontier of the domain, its robots.txt and the token bucket
+//! spacing the fetches from the host, and starts the fetches of its URLs,
+//! each taking one of the fetch slots shared by all the shards once it has a
+//! token. The slots are handed out in the order they are asked for, so a
+//! domain with plenty of queued URLs cannot starve the others. The fetch
+//! results go to the crawler task, which stores them and tells the shard the
+//! URL is done.
+
+use super::crawler::{
+    CrawlId, CrawlState, Crawler, DrainReply, QueueStatus, RobotsReply, WaitReply,
+};
+use super::frontier::{Frontier, MakeFrontier};
+use super::metrics::METRICS;
+use super::robots::{RobotsReport, RobotsTxt};
+use super::settings::Settings;
+use super::stats;
+use super::urlinfo::{Discovery, Domain, Error};
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use url::Url;
+
+/// Max number of queued URLs included in the queue status.
+const QUEUE_SAMPLE_SIZE: usize = 20;
+
+/// Messages a shard is capable of reacting to, mostly forwarded by the
+/// crawler task.
+#[derive(Debug)]
+pub(crate) enum ShardMessage {
+    /// Queue newly discovered URLs.
+    LinksFound(Vec<(Url, Discovery)>),
+    /// The result of given URL has been stored.
+    Processed(Url),
+    /// Crawl the domain from given seed, fetching the robots.txt of its site
+    /// along with it.
+    Crawl(CrawlId, Url),
+    /// Store the robots.txt file fetched for the domain.
+    RobotsFetched(Box<RobotsTxt>),
+    /// Get the robots.txt status, optionally with the decision on given path.
+    Robots(Option<String>, oneshot::Sender<RobotsReply>),
+    /// Use the new settings.
+    UpdateSettings(Box<Settings>),
+    /// Change the state of the crawl.
+    Control(CrawlState),
+    /// A fetch slot asked for is free.
+    Slot(FetchSlot),
+    /// Wake up to start fetches held back by the per-host delay.
+    Wake,
+    /// Get the fetch queue status.
+    Queue(oneshot::Sender<QueueStatus>),
+    /// Reply once the crawl has nothing more to fetch.
+    Wait(oneshot::Sender<WaitReply>),
+    /// Stop starting new fetches, reply once all the fetches in flight finish.
+    Drain(oneshot::Sender<DrainReply>),
+    /// Get the size of the queue.
+    Stats(oneshot::Sender<ShardStats>),
+    /// Get the queued URLs and the URLs in flight, to save them.
+    Checkpoint(oneshot::Sender<(Vec<Url>, Vec<Url>)>),
+}
+
+/// Size of the queue of a shard.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ShardStats {
+    pub queued: usize,
+    pub in_flight: usize,
+    /// Estimated size of the queued URLs and those in flight.
+    pub memory_bytes: usize,
+}
+
+/// A handle to a shard.
+#[derive(Debug, Clone)]
+pub(crate) struct Shard {
+    channel: mpsc::Sender<ShardMessage>,
+}
+
+impl Shard {
+    /// Start a shard crawling given domain.
+    fn spawn(
+        crawler: Crawler,
+        domain: Domain,
+        settings: Settings,
+        frontier: Box<dyn Frontier>,
+        slots: FetchSlots,
+        draining: bool,
+    ) -> Shard {
+        let (sx, rx) = mpsc::channel(32);
+        let state = State {
+            domain,
+            crawler,
+            channel: sx.downgrade(),
+            settings,
+            frontier,
+            crawl_id: None,
+            state: None,
+            robots: None,
+            seed: None,
+            held_links: Vec::new(),
+            in_flight: HashMap::new(),
+            throttle: Throttle::default(),
+            slots,
+            slot: None,
+            slot_requested: false,
+            draining,
+            drain_waiters: Vec::new(),
+            wait_waiters: Vec::new(),
+            finish_reported: false,
+            reported: (0, 0),
+        };
+        tokio::task::spawn(state.run(rx));
+        Shard { channel: sx }
+    }
+
+    /// Send a message to the shard.
+    pub async fn send(&self, msg: ShardMessage) {
+        // Only fails once the shard is gone, the message is moot then.
+        let _ = self.channel.send(msg).await;
+    }
+
+    /// Send a message to the shard and wait for reply, None if it is gone.
+    pub async fn request<R>(
+        &self,
+        msg_func: impl FnOnce(oneshot::Sender<R>) -> ShardMessage,
+    ) -> Option<R> {
+        let (sx, rx) = oneshot::channel();
+        self.channel.send(msg_func(sx)).await.ok()?;
+        rx.await.ok()
+    }
+}
+
+/// The shards of all the domains.
+pub(crate) struct Shards {
+    shards: HashMap<Domain, Shard>,
+    make_frontier: MakeFrontier,
+    slots: FetchSlots,
+}
+
+impl Shards {
+    pub fn new(make_frontier: MakeFrontier, fetch_limit: u32) -> Self {
+        Shards {
+            shards: HashMap::new(),
+            make_frontier,
+            slots: FetchSlots::new(fetch_limit as usize),
+        }
+    }
+
+    /// The shard of given domain, if there is one.
+    pub fn get(&self, domain: &Domain) -> Option<&Shard> {
+        self.shards.get(domain)
+    }
+
+    /// The shard of given domain, started if there is none yet.
+    pub fn get_or_spawn(
+        &mut self,
+        crawler: &Crawler,
+        domain: &Domain,
+        settings: &Settings,
+        draining: bool,
+    ) -> &Shard {
+        let Shards {
+            shards,
+            make_frontier,
+            slots,
+        } = self;
+        shards.entry(domain.clone()).or_insert_with(|| {
+            crawler.prefetch(domain);
+            let frontier = make_frontier(domain);
+            let (settings, slots) = (settings.clone(), slots.clone());
+            Shard::spawn(
+                crawler.clone(),
+                domain.clone(),
+                settings,
+                frontier,
+                slots,
+                draining,
+            )
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Shard> {
+        self.shards.values()
+    }
+
+    /// Change the number of fetch slots.
+    pub fn set_fetch_limit(&self, old: u32, new: u32) {
+        self.slots.resize(old as usize, new as usize);
+    }
+
+    /// Stop handing out fetch slots, letting the shards finish.
+    pub fn close(&self) {
+        self.slots.semaphore.close();
+    }
+}
+
+/// The slots for fetches in flight, shared by all the shards.
+#[derive(Debug, Clone)]
+struct FetchSlots {
+    semaphore: Arc<Semaphore>,
+    /// Number of slots to take away as soon as they are released, after the
+    /// limit has been lowered.
+    debt: Arc<AtomicUsize>,
+}
+
+/// A slot taken for a fetch, freed when dropped.
+#[derive(Debug)]
+pub(crate) struct FetchSlot {
+    permit: Option<OwnedSemaphorePermit>,
+    debt: Arc<AtomicUsize>,
+}
+
+impl FetchSlots {
+    fn new(limit: usize) -> Self {
+        FetchSlots {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            debt: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Take a slot if one is free right away.
+    fn try_take(&self) -> Option<FetchSlot> {
+        let permit = self.semaphore.clone().try_acquire_owned().ok()?;
+        Some(self.slot(permit))
+    }
+
+    /// Wait for a slot to be free, None once there are no more of them.
+    async fn take(&self) -> Option<FetchSlot> {
+        let permit = self.semaphore.clone().acquire_owned().await.ok()?;
+        Some(self.slot(permit))
+    }
+
+    fn slot(&self, permit: OwnedSemaphorePermit) -> FetchSlot {
+        FetchSlot {
+            permit: Some(permit),
+            debt: self.debt.clone(),
+        }
+    }
+
+    /// Change the number of slots from `old` to `new`.
+    fn resize(&self, old: usize, new: usize) {
+        if new > old {
+            let mut added = new - old;
+            // Slots still owed are no longer to be taken away.
+            let _ = self
+                .debt
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |debt| {
+                    let paid = debt.min(added);
+                    added -= paid;
+                    Some(debt - paid)
+                });
+            self.semaphore.add_permits(added);
+        } else {
+            let removed = old - new;
+            let forgotten = self.semaphore.forget_permits(removed);
+            self.debt.fetch_add(removed - forgotten, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Drop for FetchSlot {
+    fn drop(&mut self) {
+        let owed = self
+            .debt
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |d| d.checked_sub(1));
+        if let (Ok(_), Some(permit)) = (owed, self.permit.take()) {
+            permit.forget();
+        }
+    }
+}
+
+/// Spacing of fetches from the domain, a token bucket refilled with a token
+/// every host delay and holding up to the burst size of them.
+///
+/// Rather than counting the tokens, the bucket keeps the time it would be
+/// full again if no burst was allowed, which takes no rounding.
+#[derive(Debug, Default)]
+struct Throttle {
+    /// When the bucket is full again with a burst of 1, None if it is full.
+    full_at: Option<Instant>,
+    /// A wake-up message is on its way.
+    wake_scheduled: bool,
+}
+
+impl Throttle {
+    /// Time from which the next URL may be fetched, None if it may be fetched
+    /// right away.
+    fn ready_at(&self, delay: Duration, burst: u32) -> Option<Instant> {
+        if delay.is_zero() {
+            return None;
+        }
+        self.full_at?.checked_sub(delay * burst.saturating_sub(1))
+    }
+
+    /// Take a token for a fetch started at given time.
+    fn started(&mut self, now: Instant, delay: Duration) {
+        let full_at = self.full_at.map_or(now, |t| t.max(now));
+        self.full_at = Some(full_at + delay);
+    }
+}
+
+/// State of a shard, owned by its task.
+struct State {
+    domain: Domain,
+    /// Handle of the crawler the fetches report to.
+    crawler: Crawler,
+    /// The shard's own channel, for the wake-ups and slots to come.
+    channel: mpsc::WeakSender<ShardMessage>,
+    settings: Settings,
+    frontier: Box<dyn Frontier>,
+    crawl_id: Option<CrawlId>,
+    /// State of the crawl, None if the domain is not being crawled itself,
+    /// e.g. when it has only been reached by a redirect.
+    state: Option<CrawlState>,
+    /// The robots.txt of the domain, None if it is not being crawled and
+    /// Some(None) while the file is being fetched.
+    robots: Option<Option<RobotsTxt>>,
+    /// The seed of the crawl, which is fetched without waiting for the
+    /// robots.txt. The links found on it are held in `held_links` until the
+    /// file is in, and dropped if it turns out to disallow the seed.
+    seed: Option<Url>,
+    held_links: Vec<(Url, Discovery)>,
+    /// URLs being fetched with their fetch slots, freed once the result is
+    /// stored, and URLs being recorded as not fetched.
+    in_flight: HashMap<Url, Option<FetchSlot>>,
+    throttle: Throttle,
+    slots: FetchSlots,
+    /// A free slot not used yet.
+    slot: Option<FetchSlot>,
+    /// A slot has been asked for and is on its way.
+    slot_requested: bool,
+    draining: bool,
+    drain_waiters: Vec<oneshot::Sender<DrainReply>>,
+    wait_waiters: Vec<oneshot::Sender<WaitReply>>,
+    /// The crawler has been told the crawl has nothing more to fetch.
+    finish_reported: bool,
+    /// Queue depth and fetches in flight added to the metrics.
+    reported: (i64, i64),
+}
+
+impl State {
+    /// Shard message handling loop.
+    async fn run(mut self, mut rx: mpsc::Receiver<ShardMessage>) {
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                ShardMessage::LinksFound(links) => {
+                    if self.state != Some(CrawlState::Stopped) {
+                        let follow_seed = self.follow_seed_links();
+                        for (url, discovery) in links {
+                            let from_seed = self.seed.is_some()
+                                && discovery.parent.as_deref() == self.seed.as_ref();
+                            match follow_seed {
+                                None if from_seed => self.held_links.push((url, discovery)),
+                                Some(false) if from_seed => (),
+                                _ => self.frontier.push(url, &discovery),
+                            }
+                        }
+                        self.dispatch();
+                    }
+                }
+                ShardMessage::Processed(url) => {
+                    self.in_flight.remove(&url);
+                    if self.draining && self.in_flight.is_empty() {
+                        for waiter in self.drain_waiters.drain(..) {
+                            let _ = waiter.send(self.frontier.len());
+                        }
+                    }
+                    self.dispatch();
+                }
+                ShardMessage::Crawl(id, seed) => {
+                    self.state = Some(CrawlState::Running);
+                    self.robots = Some(None);
+                    self.seed = Some(seed.clone());
+                    let user_agent = &self.settings.user_agent;
+                    self.crawler
+                        .fetch_robots(self.domain.clone(), seed, user_agent, &id);
+                    self.crawl_id = Some(id);
+                    self.finish_reported = false;
+                    self.dispatch();
+                }
+                ShardMessage::RobotsFetched(txt) => {
+                    self.robots = Some(Some(*txt));
+                    let held = std::mem::take(&mut self.held_links);
+                    if self.follow_seed_links() == Some(true) {
+                        for (url, discovery) in held {
+                            self.frontier.push(url, &discovery);
+                        }
+                    } else if !held.is_empty() {
+                        tracing::info!(
+                            crawl = %&*self.domain,
+                            "Seed disallowed by robots.txt, not following its links"
+                        );
+                    }
+                    self.dispatch();
+                }
+                ShardMessage::Robots(path, reply) => {
+                    let report = self.robots.as_ref().map(|txt| match txt {
+                        Some(txt) => txt.report(&self.settings.user_agent, path.as_deref()),
+                        None => RobotsReport::pending(),
+                    });
+                    let _ = reply.send(report);
+                }
+                ShardMessage::UpdateSettings(settings) => {
+                    self.settings = *settings;
+                    self.dispatch();
+                }
+                ShardMessage::Control(state) => {
+                    self.state = Some(state);
+                    if state == CrawlState::Stopped {
+                        self.frontier.retain(&mut |_| false);
+                        self.held_links.clear();
+                    }
+                    self.dispatch();
+                }
+                ShardMessage::Slot(slot) => {
+                    self.slot_requested = false;
+                    self.slot = Some(slot);
+                    self.dispatch();
+                }
+                ShardMessage::Wake => {
+                    self.throttle.wake_scheduled = false;
+                    self.dispatch();
+                }
+                ShardMessage::Queue(reply) => {
+                    let mut queued = self.frontier.urls();
+                    let sample: Vec<Url> = queued
+                        .by_ref()
+                        .take(QUEUE_SAMPLE_SIZE)
+                        .map(Cow::into_owned)
+                        .collect();
+                    let status = QueueStatus {
+                        state: self.state,
+                        queued: sample.len() + queued.count(),
+                        sample,
+                        in_flight: self.in_flight.keys().cloned().collect(),
+                    };
+                    let _ = reply.send(status);
+                }
+                ShardMessage::Wait(reply) => self.wait_waiters.push(reply),
+                ShardMessage::Drain(reply) => {
+                    self.draining = true;
+                    self.slot = None;
+                    match self.in_flight.is_empty() {
+                        true => {
+                            let _ = reply.send(self.frontier.len());
+                        }
+                        false => self.drain_waiters.push(reply),
+                    }
+                }
+                ShardMessage::Stats(reply) => {
+                    let in_flight_bytes: usize = self.in_flight.keys().map(stats::url_bytes).sum();
+                    let _ = reply.send(ShardStats {
+                        queued: self.frontier.len(),
+                        in_flight: self.in_flight.len(),
+                        memory_bytes: self.frontier.memory_bytes() + in_flight_bytes,
+                    });
+                }
+                ShardMessage::Checkpoint(reply) => {
+                    let held = self.held_links.iter().map(|(url, _)| url.clone());
+                    let queued = self.frontier.urls().map(Cow::into_owned).chain(held);
+                    let queued = queued.collect();
+                    let in_flight = self.in_flight.keys().cloned().collect();
+                    let _ = reply.send((queued, in_flight));
+                }
+            }
+            self.notify_finished();
+            self.report();
+        }
+        METRICS.queue_depth.add(-self.reported.0);
+        METRICS.in_flight.add(-self.reported.1);
+    }
+
+    /// Start fetching queued URLs while there are free fetch slots.
+    ///
+    /// Nothing is fetched while the crawl is paused, pending or draining, nor
+    /// while its robots.txt is still being fetched apart from the seed, and
+    /// the fetches start at least the per-host delay apart. A wake-up is
+    /// scheduled for when the delay is over, and a slot is asked for when
+    /// there are none free. URLs disallowed by robots.txt, and assets and leaf
+    /// pages in discovery-only mode, are recorded as failed without being
+    /// fetched.
+    fn dispatch(&mut self) {
+        let held =
+            self.draining || matches!(self.state, Some(CrawlState::Paused | CrawlState::Pending));
+        let robots_pending = matches!(self.robots, Some(None));
+        let (delay, burst) = (self.settings.host_delay(), self.settings.host_burst);
+        let now = Instant::now();
+        while !held && !self.frontier.is_empty() {
+            let throttled = self
+                .throttle
+                .ready_at(delay, burst)
+                .is_some_and(|t| t > now);
+            let slot = match self.slot.take().or_else(|| self.slots.try_take()) {
+                Some(slot) => slot,
+                None => {
+                    if !throttled {
+                        self.request_slot();
+                    }
+                    break;
+                }
+            };
+            // URLs not to be fetched need not wait for the host delay.
+            let (settings, seed) = (&self.settings, self.seed.as_ref());
+            let mut ready = |u: &Url| {
+                (!robots_pending || Some(u) == seed)
+                    && (!throttled || settings.skip_reason(u).is_some())
+            };
+            let url = match self.frontier.pop(&mut ready) {
+                Some(url) => url,
+                None => break,
+            };
+            let txt = self.robots.as_ref().and_then(Option::as_ref);
+            if let Some(decision) = txt.map(|txt| txt.allows(&settings.user_agent, &url)) {
+                if !decision.allowed {
+                    // Counted as in flight until recorded, so that the crawl
+                    // does not look finished in the meantime.
+                    self.in_flight.insert(url.clone(), None);
+                    self.crawler
+                        .record_error(url, Error::Disallowed(decision.rule));
+                    self.slot = Some(slot);
+                    continue;
+                }
+            }
+            if let Some(reason) = settings.skip_reason(&url) {
+                self.in_flight.insert(url.clone(), None);
+                self.crawler.record_error(url, Error::NotFetched(reason));
+                self.slot = Some(slot);
+                continue;
+            }
+            self.throttle.started(now, delay);
+            self.in_flight.insert(url.clone(), Some(slot));
+            self.crawler
+                .fetch(url, &self.settings, self.crawl_id.as_ref());
+        }
+        // A slot not needed right now is left to the other shards.
+        self.slot = None;
+        if !held && !self.frontier.is_empty() && !self.throttle.wake_scheduled {
+            if let Some(at) = self.throttle.ready_at(delay, burst).filter(|&t| t > now) {
+                self.throttle.wake_scheduled = true;
+                let channel = self.channel.clone();
+                tokio::task::spawn(async move {
+                    tokio::time::sleep_until(at).await;
+                    if let Some(channel) = channel.upgrade() {
+                        let _ = channel.send(ShardMessage::Wake).await;
+                    }
+                });
+            }
+        }
+    }
+
+    /// Whether to follow the links found on the seed, None while the
+    /// robots.txt is being fetched.
+    fn follow_seed_links(&self) -> Option<bool> {
+        match (&self.robots, &self.seed) {
+            (Some(None), _) => None,
+            (Some(Some(txt)), Some(seed)) => {
+                Some(txt.allows(&self.settings.user_agent, seed).allowed)
+            }
+            _ => Some(true),
+        }
+    }
+
+    /// Ask for a fetch slot, to be sent to the shard once free.
+    fn request_slot(&mut self) {
+        if self.slot_requested {
+            return;
+        }
+        self.slot_requested = true;
+        let (slots, channel) = (self.slots.clone(), self.channel.clone());
+        tokio::task::spawn(async move {
+            if let (Some(slot), Some(channel)) = (slots.take().await, channel.upgrade()) {
+                let _ = channel.send(ShardMessage::Slot(slot)).await;
+            }
+        });
+    }
+
+    /// Notify those waiting for the crawl if it has nothing more to fetch,
+    /// and the crawler the first time it has not, to start a pending crawl.
+    fn notify_finished(&mut self) {
+        let finished = !matches!(self.robots, Some(None))
+            && self.state != Some(CrawlState::Pending)
+            && self.frontier.is_empty()
+            && self.in_flight.is_empty();
+        if finished && !self.finish_reported && self.state == Some(CrawlState::Running) {
+            self.finish_reported = true;
+            self.crawler.crawl_finished(self.domain.clone());
+        }
+        if self.wait_waiters.is_empty() {
+            return;
+        }
+        if finished {
+            for waiter in self.wait_waiters.drain(..) {
+                let _ = waiter.send(true);
+            }
+        } else {
+            // Waiters which have given up are dropped.
+            self.wait_waiters.retain(|w| !w.is_closed());
+        }
+    }
+
+    /// Add the changes of the queue depth and fetches in flight to the
+    /// metrics, which sum them over all the shards.
+    fn report(&mut self) {
+        let current = (self.frontier.len() as i64, self.in_flight.len() as i64);
+        METRICS.queue_depth.add(current.0 - self.reported.0);
+        METRICS.in_flight.add(current.1 - self.reported.1);
+        self.reported = current;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_throttle() {
+        let mut throttle = Throttle::default();
+        let delay = Duration::from_secs(1);
+        let now = Instant::now();
+        assert_eq!(throttle.ready_at(delay, 1), None);
+        throttle.started(now, delay);
+        assert_eq!(throttle.ready_at(delay, 1), Some(now + delay));
+        assert_eq!(throttle.ready_at(Duration::ZERO, 1), None);
+
+        // A burst of 3 starts right away, then one per delay.
+        let mut throttle = Throttle::default();
+        for _ in 0..3 {
+            assert!(throttle.ready_at(delay, 3).is_none_or(|t| t <= now));
+            throttle.started(now, delay);
+        }
+        assert_eq!(throttle.ready_at(delay, 3), Some(now + delay));
+        // Tokens come back over time, up to the burst size.
+        let later = now + delay * 10;
+        throttle.started(later, delay);
+        throttle.started(later, delay);
+        assert!(throttle.ready_at(delay, 3).unwrap() <= later);
+        throttle.started(later, delay);
+        assert_eq!(throttle.ready_at(delay, 3), Some(later + delay));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_slots() {
+        let slots = FetchSlots::new(2);
+        let a = slots.try_take().unwrap();
+        let b = slots.try_take().unwrap();
+        assert!(slots.try_take().is_none());
+
+        // Slots in use are taken away once released.
+        slots.resize(2, 1);
+        drop(a);
+        assert!(slots.try_take().is_none());
+        drop(b);
+        let c = slots.try_take().unwrap();
+        assert!(slots.try_take().is_none());
+
+        slots.resize(1, 3);
+        let d = slots.try_take().unwrap();
+        let _e = slots.try_take().unwrap();
+        assert!(slots.try_take().is_none());
+        drop((c, d));
+        assert!(slots.try_take().is_some());
+    }
+}
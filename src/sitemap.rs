@@ -0,0 +1,82 @@
+//! Rendering of crawl results as a sitemap.xml.
+
+use super::listing::{ListOptions, Listing, StatusFilter};
+use super::timefmt::w3c_datetime;
+
+use std::fmt::Write;
+
+/// Max number of URLs a single sitemap may contain.
+pub const MAX_URLS: usize = 50_000;
+
+/// Listing options selecting the URLs that belong to the sitemap, a page
+/// found with and without a trailing slash only once.
+pub fn list_options() -> ListOptions {
+    ListOptions {
+        limit: Some(MAX_URLS),
+        status: Some(StatusFilter::Class(2)),
+        content_type: Some("html".to_string()),
+        merge_slashes: true,
+        ..ListOptions::default()
+    }
+}
+
+/// Render listed URLs as a sitemap.
+///
+/// Pages declaring a different canonical URL are left out, the canonical one
+/// is listed on its own if it has been fetched.
+pub fn render(listing: &Listing) -> String {
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    ));
+    for (url, info) in &listing.urls {
+        let canonical = info.result.as_ref().ok().and_then(|p| p.canonical.as_ref());
+        if canonical.is_some_and(|c| c != &**url) {
+            continue;
+        }
+        let _ = write!(
+            out,
+            "  <url>\n    <loc>{}</loc>\n    <lastmod>{}</lastmod>\n  </url>\n",
+            escaper::encode_minimal(url.as_str()),
+            w3c_datetime(info.fetched_at),
+        );
+    }
+    out.push_str("</urlset>\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::urlinfo::*;
+    use super::*;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+    use url::Url;
+
+    #[test]
+    fn unit_render_sitemap() {
+        let page = |canonical: Option<&str>| Page {
+            size: 10,
+            canonical: canonical.map(|c| Url::parse(c).unwrap()),
+            ..Page::default()
+        };
+        let entry = |url: &str, page| {
+            let mut info = UrlInfo::from_result(Ok(page), Duration::ZERO);
+            info.fetched_at = UNIX_EPOCH;
+            (Arc::new(Url::parse(url).unwrap()), info)
+        };
+        let listing = Listing {
+            total: 2,
+            offset: 0,
+            urls: vec![
+                entry("http://example.com/?a=1&b=2", page(None)),
+                entry("http://example.com/b", page(Some("http://example.com/"))),
+            ],
+        };
+        let xml = render(&listing);
+        assert!(xml.contains("<loc>http://example.com/?a=1&amp;b=2</loc>"));
+        assert!(xml.contains("<lastmod>1970-01-01T00:00:00Z</lastmod>"));
+        assert!(!xml.contains("http://example.com/b"));
+        assert!(xml.ends_with("</urlset>\n"));
+    }
+}
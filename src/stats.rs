@@ -0,0 +1,150 @@
+//! Global statistics of the crawler.
+
+use super::urlinfo::*;
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::mem::size_of;
+use std::sync::Arc;
+use url::Url;
+
+/// Overview of the whole crawler.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Stats {
+    /// Seconds since the crawler has been started.
+    pub uptime_secs: u64,
+    /// Number of domains with results or crawl state.
+    pub domains: usize,
+    /// Number of crawls currently running, i.e. neither paused nor stopped.
+    pub active_crawls: usize,
+    /// Number of crawls waiting for the others to finish.
+    pub pending_crawls: usize,
+    /// Number of distinct URLs discovered.
+    pub urls_seen: usize,
+    /// Number of URLs fetched, successfully or not.
+    pub urls_fetched: usize,
+    /// Number of URLs waiting to be fetched.
+    pub queued: usize,
+    /// Number of fetches in progress.
+    pub in_flight: usize,
+    /// Rough estimate of the memory taken by the crawler state.
+    pub memory: MemoryEstimate,
+    /// Optional cargo features croolr has been built with.
+    pub features: Vec<&'static str>,
+}
+
+/// Estimated number of bytes taken by parts of the crawler state.
+///
+/// Only the data itself is accounted for, allocator and hash table overhead
+/// is not included. The crawler keeps the estimates of the discovered URLs
+/// and the results up to date as they are stored, the queues are measured
+/// when asked for.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MemoryEstimate {
+    /// The set of discovered URLs.
+    pub seen_bytes: usize,
+    /// Fetch results.
+    pub results_bytes: usize,
+    /// The fetch queue and the fetches in flight.
+    pub queue_bytes: usize,
+    /// Sum of the above.
+    pub total_bytes: usize,
+    /// Fetch results by domain.
+    pub domains: BTreeMap<Domain, usize>,
+}
+
+impl MemoryEstimate {
+    pub fn new(seen_bytes: usize, domains: BTreeMap<Domain, usize>, queue_bytes: usize) -> Self {
+        let results_bytes = domains.values().sum();
+        MemoryEstimate {
+            seen_bytes,
+            results_bytes,
+            queue_bytes,
+            total_bytes: seen_bytes + results_bytes + queue_bytes,
+            domains,
+        }
+    }
+}
+
+/// Optional cargo features enabled in this build.
+pub fn features() -> Vec<&'static str> {
+    let features = [
+        ("graphql", cfg!(feature = "graphql")),
+        ("grpc", cfg!(feature = "grpc")),
+        ("headless", cfg!(feature = "headless")),
+        ("otlp", cfg!(feature = "otlp")),
+        ("scripting", cfg!(feature = "scripting")),
+        ("wasm", cfg!(feature = "wasm")),
+    ];
+    features
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Estimated size of a URL.
+pub fn url_bytes(url: &Url) -> usize {
+    size_of::<Url>() + url.as_str().len()
+}
+
+/// Estimated size of a discovered URL entry, the URL itself being shared by
+/// the results and the discovery of the pages it links to.
+pub fn discovery_bytes(url: &Url) -> usize {
+    url_bytes(url) + size_of::<Arc<Url>>() + size_of::<Discovery>()
+}
+
+/// Estimated size of a fetch result entry.
+pub fn result_bytes(info: &UrlInfo) -> usize {
+    let page = info.result.as_ref().ok().map_or(0, |page| {
+        let headers: usize = page.headers.iter().map(|(k, v)| k.len() + v.len()).sum();
+        page.content_type.len()
+            + headers
+            + page.headers.len() * size_of::<(String, String)>()
+            + page.canonical.as_ref().map_or(0, url_bytes)
+            + page.title.as_ref().map_or(0, String::len)
+            + page.links.bytes()
+            + page.nofollow.bytes()
+            + page
+                .extra
+                .iter()
+                .map(|(k, v)| k.len() + v.len())
+                .sum::<usize>()
+    });
+    let redirects: usize = info.redirects.iter().map(url_bytes).sum();
+    size_of::<Arc<Url>>() + size_of::<UrlInfo>() + page + redirects
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn unit_memory_estimate() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let failed = UrlInfo::from_result(Err(Error::Fetch("x".into())), Duration::ZERO);
+        let page = Page {
+            headers: vec![("server".into(), "test".into())],
+            links: vec![url.clone(), url.clone()].into(),
+            ..Page::default()
+        };
+        let fetched = UrlInfo::from_result(Ok(page), Duration::ZERO);
+        let base = result_bytes(&failed);
+        assert!(base > url_bytes(&url));
+        assert!(result_bytes(&fetched) > base + url.as_str().len() + 19);
+        let domains = [("a.com", 2), ("b.com", 3)]
+            .iter()
+            .map(|(d, b)| (d.parse().unwrap(), *b))
+            .collect();
+        let memory = MemoryEstimate::new(1, domains, 4);
+        assert_eq!((memory.results_bytes, memory.total_bytes), (5, 10));
+    }
+
+    #[test]
+    fn unit_features() {
+        let features = features();
+        assert_eq!(features.contains(&"grpc"), cfg!(feature = "grpc"));
+        assert_eq!(features.contains(&"wasm"), cfg!(feature = "wasm"));
+    }
+}
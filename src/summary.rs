@@ -0,0 +1,460 @@
+//! Per-domain statistics about crawl results.
+
+use super::urlinfo::*;
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Default upper bounds of the response time histogram buckets in milliseconds.
+const LATENCY_BUCKETS_MS: &[u64] = &[10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Default upper bounds of the page size histogram buckets in bytes.
+const SIZE_BUCKETS: &[u64] = &[
+    1 << 10,
+    4 << 10,
+    16 << 10,
+    64 << 10,
+    256 << 10,
+    1 << 20,
+    4 << 20,
+];
+
+/// Number of subdivisions of each power of two in the digests of the values,
+/// their percentiles are within 1/64 of the exact ones.
+const DIGEST_PRECISION: u64 = 128;
+
+/// Upper bounds of the histogram buckets, fixed when the crawler starts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Buckets {
+    /// Response time bucket bounds in milliseconds.
+    pub latency_ms: Vec<u64>,
+    /// Page size bucket bounds in bytes.
+    pub size_bytes: Vec<u64>,
+}
+
+impl Buckets {
+    /// Check the bounds are non-empty and strictly increasing.
+    pub fn new(latency_ms: Vec<u64>, size_bytes: Vec<u64>) -> Result<Self, String> {
+        for (name, bounds) in [("latency", &latency_ms), ("size", &size_bytes)].iter() {
+            if bounds.is_empty() || bounds.windows(2).any(|w| w[0] >= w[1]) {
+                return Err(format!("{} buckets must be increasing", name));
+            }
+        }
+        Ok(Buckets {
+            latency_ms,
+            size_bytes,
+        })
+    }
+}
+
+impl Default for Buckets {
+    fn default() -> Self {
+        Buckets {
+            latency_ms: LATENCY_BUCKETS_MS.to_vec(),
+            size_bytes: SIZE_BUCKETS.to_vec(),
+        }
+    }
+}
+
+/// Summary statistics of the crawl results for a single domain.
+///
+/// The statistics are updated incrementally as pages are processed so that
+/// producing a report does not require walking all the URLs.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    urls: usize,
+    statuses: BTreeMap<u16, usize>,
+    status_classes: BTreeMap<String, usize>,
+    content_types: BTreeMap<String, usize>,
+    errors: BTreeMap<&'static str, usize>,
+    error_classes: BTreeMap<&'static str, usize>,
+    bytes: u64,
+    latency: Histogram,
+    sizes: Histogram,
+}
+
+/// Serializable snapshot of the summary statistics.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub urls: usize,
+    pub status_codes: BTreeMap<u16, usize>,
+    /// Number of responses by status class, e.g. `2xx`.
+    pub status_classes: BTreeMap<String, usize>,
+    pub content_types: BTreeMap<String, usize>,
+    pub errors: BTreeMap<&'static str, usize>,
+    /// Number of failures by what is wrong with the site, see
+    /// [`Error::class`].
+    pub error_classes: BTreeMap<&'static str, usize>,
+    pub total_bytes: u64,
+    /// Response times of all the fetches, in milliseconds.
+    pub response_time_ms: Distribution,
+    /// Body sizes of the fetched pages, in bytes.
+    pub page_size_bytes: Distribution,
+}
+
+/// Numbers of processed URLs of a domain, see [`Summary::counts`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Counts {
+    pub total: usize,
+    /// Number of responses by status class, e.g. `2xx`.
+    pub status_classes: BTreeMap<String, usize>,
+    /// Number of responses by media type.
+    pub content_types: BTreeMap<String, usize>,
+}
+
+/// Statistics of a measured value.
+///
+/// The percentiles are not bound to the buckets, they are estimated from a
+/// finer digest of the values within 1/64 of the exact ones.
+#[derive(Debug, Clone, Serialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub sum: u64,
+    pub avg: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub max: u64,
+    /// Cumulative histogram, the last bucket has no upper bound.
+    pub buckets: Vec<Bucket>,
+}
+
+/// Number of values less than or equal to the upper bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Bucket {
+    /// Upper bound, None for infinity.
+    pub le: Option<u64>,
+    pub count: usize,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Summary::new(&Buckets::default())
+    }
+}
+
+impl Summary {
+    pub fn new(buckets: &Buckets) -> Self {
+        Summary {
+            urls: 0,
+            statuses: BTreeMap::new(),
+            status_classes: BTreeMap::new(),
+            content_types: BTreeMap::new(),
+            errors: BTreeMap::new(),
+            error_classes: BTreeMap::new(),
+            bytes: 0,
+            latency: Histogram::new(&buckets.latency_ms),
+            sizes: Histogram::new(&buckets.size_bytes),
+        }
+    }
+
+    /// Account for a processed URL.
+    pub fn record(&mut self, info: &UrlInfo) {
+        self.urls += 1;
+        self.latency.record(info.elapsed.as_millis() as u64);
+        if let Some(status) = info.status() {
+            *self.statuses.entry(status.as_u16()).or_default() += 1;
+            let class = format!("{}xx", status.as_u16() / 100);
+            *self.status_classes.entry(class).or_default() += 1;
+        }
+        if let Some(content_type) = info.content_type() {
+            *self
+                .content_types
+                .entry(media_type(content_type))
+                .or_default() += 1;
+        }
+        match &info.result {
+            Ok(page) => {
+                self.bytes += page.size as u64;
+                self.sizes.record(page.size as u64);
+            }
+            Err(err) => {
+                *self.errors.entry(err.kind()).or_default() += 1;
+                if let Some(class) = err.class() {
+                    *self.error_classes.entry(class).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    /// The numbers of URLs, without the distributions of the full report.
+    pub fn counts(&self) -> Counts {
+        Counts {
+            total: self.urls,
+            status_classes: self.status_classes.clone(),
+            content_types: self.content_types.clone(),
+        }
+    }
+
+    /// Produce a report of the current statistics.
+    pub fn report(&self) -> Report {
+        Report {
+            urls: self.urls,
+            status_codes: self.statuses.clone(),
+            status_classes: self.status_classes.clone(),
+            content_types: self.content_types.clone(),
+            errors: self.errors.clone(),
+            error_classes: self.error_classes.clone(),
+            total_bytes: self.bytes,
+            response_time_ms: self.latency.distribution(),
+            page_size_bytes: self.sizes.distribution(),
+        }
+    }
+}
+
+/// Histogram with fixed buckets.
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: Vec<u64>,
+    /// Counts for each bucket plus one overflow bucket.
+    counts: Vec<usize>,
+    /// Counts by [`digest_index`], for the percentiles.
+    digest: BTreeMap<u16, usize>,
+    total: usize,
+    sum: u64,
+    max: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &[u64]) -> Self {
+        Histogram {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len() + 1],
+            digest: BTreeMap::new(),
+            total: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+
+    fn record(&mut self, value: u64) {
+        let idx = self
+            .bounds
+            .iter()
+            .position(|&b| value <= b)
+            .unwrap_or(self.bounds.len());
+        self.counts[idx] += 1;
+        *self.digest.entry(digest_index(value)).or_default() += 1;
+        self.total += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+    }
+
+    /// Estimate given percentile (0.0 - 1.0) as the upper bound of the digest
+    /// range it falls into.
+    fn percentile(&self, q: f64) -> u64 {
+        let rank = ((q * self.total as f64).ceil() as usize).max(1);
+        let mut seen = 0;
+        for (&idx, count) in self.digest.iter() {
+            seen += count;
+            if seen >= rank {
+                return digest_bound(idx).min(self.max);
+            }
+        }
+        0
+    }
+
+    fn distribution(&self) -> Distribution {
+        let avg = match self.total {
+            0 => 0.0,
+            n => self.sum as f64 / n as f64,
+        };
+        let bounds = self.bounds.iter().map(|&b| Some(b)).chain(Some(None));
+        let mut cumulative = 0;
+        let buckets = bounds
+            .zip(&self.counts)
+            .map(|(le, count)| {
+                cumulative += count;
+                Bucket {
+                    le,
+                    count: cumulative,
+                }
+            })
+            .collect();
+        Distribution {
+            count: self.total,
+            sum: self.sum,
+            avg,
+            p50: self.percentile(0.5),
+            p90: self.percentile(0.9),
+            p95: self.percentile(0.95),
+            p99: self.percentile(0.99),
+            max: self.max,
+            buckets,
+        }
+    }
+}
+
+/// Index of the digest range given value falls into. Values below
+/// [`DIGEST_PRECISION`] have a range each, each power of two above is split
+/// into half as many ranges.
+fn digest_index(value: u64) -> u16 {
+    if value < DIGEST_PRECISION {
+        return value as u16;
+    }
+    let half = DIGEST_PRECISION / 2;
+    let shift = 63 - value.leading_zeros() - half.trailing_zeros();
+    (DIGEST_PRECISION + (shift as u64 - 1) * half + (value >> shift) - half) as u16
+}
+
+/// The largest value of the digest range of given index.
+fn digest_bound(idx: u16) -> u64 {
+    let idx = idx as u64;
+    if idx < DIGEST_PRECISION {
+        return idx;
+    }
+    let half = DIGEST_PRECISION / 2;
+    let shift = (idx - DIGEST_PRECISION) / half + 1;
+    let sub = (idx - DIGEST_PRECISION) % half + half;
+    (sub << shift) | ((1 << shift) - 1)
+}
+
+/// Extract the media type from a content type header, dropping parameters.
+fn media_type(content_type: &str) -> String {
+    let t = content_type.split(';').next().unwrap_or_default();
+    t.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn page(status: u16, content_type: &str, size: usize, ms: u64) -> UrlInfo {
+        let page = Page {
+            status: reqwest::StatusCode::from_u16(status).unwrap(),
+            content_type: content_type.to_string(),
+            size,
+            ..Page::default()
+        };
+        UrlInfo::from_result(Ok(page), Duration::from_millis(ms))
+    }
+
+    fn error(err: Error, ms: u64) -> UrlInfo {
+        UrlInfo::from_result(Err(err), Duration::from_millis(ms))
+    }
+
+    #[test]
+    fn unit_summary_counts() {
+        let mut summary = Summary::default();
+        summary.record(&page(200, "text/html; charset=UTF-8", 100, 20));
+        summary.record(&page(200, "text/html", 50, 40));
+        summary.record(&error(Error::Status(reqwest::StatusCode::NOT_FOUND), 5));
+        summary.record(&error(Error::UnsupportedType("image/png".into()), 5));
+        summary.record(&error(Error::Fetch("timeout".into()), 30));
+        summary.record(&error(Error::Timeout("timeout".into()), 30));
+        summary.record(&error(Error::Dns("no such host".into()), 0));
+        summary.record(&error(Error::Status(reqwest::StatusCode::BAD_GATEWAY), 5));
+        summary.record(&error(Error::NotFetched("asset".into()), 0));
+
+        let report = summary.report();
+        assert_eq!(report.urls, 9);
+        assert_eq!(report.total_bytes, 150);
+        assert_eq!(report.status_codes.get(&200), Some(&2));
+        assert_eq!(report.status_codes.get(&404), Some(&1));
+        assert_eq!(report.status_classes.get("2xx"), Some(&2));
+        assert_eq!(report.status_classes.get("4xx"), Some(&1));
+        assert_eq!(report.status_classes.get("5xx"), Some(&1));
+        assert_eq!(report.content_types.get("text/html"), Some(&2));
+        assert_eq!(report.content_types.get("image/png"), Some(&1));
+        assert_eq!(report.errors.get("status"), Some(&2));
+        assert_eq!(report.errors.get("fetch"), Some(&1));
+        assert_eq!(report.errors.get("unsupported_type"), Some(&1));
+        assert_eq!(report.errors.get("timeout"), Some(&1));
+        let classes: Vec<_> = report.error_classes.into_iter().collect();
+        assert_eq!(
+            classes,
+            [
+                ("4xx", 1),
+                ("5xx", 1),
+                ("dns", 1),
+                ("other", 1),
+                ("timeout", 1)
+            ]
+        );
+        assert!((report.response_time_ms.avg - 15.0).abs() < 1e-9);
+        assert_eq!(report.page_size_bytes.count, 2);
+        assert_eq!(report.page_size_bytes.max, 100);
+
+        let counts = summary.counts();
+        assert_eq!(counts.total, 9);
+        assert_eq!(counts.status_classes, report.status_classes);
+        assert_eq!(counts.content_types, report.content_types);
+    }
+
+    #[test]
+    fn unit_summary_percentiles() {
+        let mut summary = Summary::default();
+        for ms in 1..=100 {
+            summary.record(&page(200, "text/html", 0, ms));
+        }
+        let latency = summary.report().response_time_ms;
+        assert_eq!(latency.p50, 50);
+        assert_eq!(latency.p90, 90);
+        assert_eq!(latency.p95, 95);
+        assert_eq!(latency.p99, 99);
+        assert_eq!(latency.max, 100);
+
+        // Larger values are estimated within 1/64.
+        let mut summary = Summary::default();
+        for ms in 1..=1000 {
+            summary.record(&page(200, "text/html", 0, ms * 10));
+        }
+        let latency = summary.report().response_time_ms;
+        for (p, exact) in [
+            (latency.p50, 5000),
+            (latency.p95, 9500),
+            (latency.p99, 9900),
+        ]
+        .iter()
+        {
+            assert!(
+                *p >= *exact && *p - *exact <= *exact / 64,
+                "{} vs {}",
+                p,
+                exact
+            );
+        }
+    }
+
+    #[test]
+    fn unit_digest() {
+        for value in (0..1 << 20).chain([u64::MAX / 3, u64::MAX].iter().copied()) {
+            let idx = digest_index(value);
+            assert!(digest_bound(idx) >= value);
+            assert!(idx == 0 || digest_bound(idx - 1) < value);
+        }
+    }
+
+    #[test]
+    fn unit_summary_buckets() {
+        let buckets = Buckets::new(vec![10, 100], vec![50]).unwrap();
+        let mut summary = Summary::new(&buckets);
+        summary.record(&page(200, "text/html", 20, 5));
+        summary.record(&page(200, "text/html", 80, 50));
+        summary.record(&error(Error::Fetch("timeout".into()), 500));
+        let report = summary.report();
+        let counts = |d: &Distribution| {
+            d.buckets
+                .iter()
+                .map(|b| (b.le, b.count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            counts(&report.response_time_ms),
+            [(Some(10), 1), (Some(100), 2), (None, 3)]
+        );
+        assert_eq!(counts(&report.page_size_bytes), [(Some(50), 1), (None, 2)]);
+        assert_eq!(report.page_size_bytes.sum, 100);
+        assert!(Buckets::new(vec![10, 10], vec![1]).is_err());
+        assert!(Buckets::new(vec![10], vec![]).is_err());
+    }
+
+    #[test]
+    fn unit_summary_empty() {
+        let latency = Summary::default().report().response_time_ms;
+        assert_eq!(latency.p50, 0);
+        assert_eq!(latency.avg, 0.0);
+    }
+}
@@ -0,0 +1,70 @@
+//! Socket activation by systemd.
+//!
+//! The listening sockets are passed as file descriptors starting at 3, their
+//! number in the `LISTEN_FDS` environment variable and the process they are
+//! meant for in `LISTEN_PID`, as described in sd_listen_fds(3).
+
+use std::net::TcpListener;
+
+/// The first file descriptor passed by systemd.
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Take over the listening sockets passed by systemd.
+///
+/// The environment variables are removed so that they are not inherited by
+/// child processes. Returns an error if there are no sockets to take over.
+#[cfg(unix)]
+pub fn listeners() -> Result<Vec<TcpListener>, String> {
+    use std::os::unix::io::FromRawFd;
+
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    let count = listen_fds(pid.as_deref(), fds.as_deref(), std::process::id())?;
+    let listeners = (0..count as i32)
+        // SAFETY: systemd hands the descriptors over to this process, which
+        // takes them over exactly once.
+        .map(|i| unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START + i) })
+        .collect();
+    Ok(listeners)
+}
+
+#[cfg(not(unix))]
+pub fn listeners() -> Result<Vec<TcpListener>, String> {
+    Err("not supported on this platform".to_string())
+}
+
+/// Number of sockets passed to the process with given ID, according to the
+/// values of `LISTEN_PID` and `LISTEN_FDS`.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn listen_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> Result<usize, String> {
+    let (pid, fds) = match pid.zip(fds) {
+        Some(vars) => vars,
+        None => return Err("no sockets passed by systemd".to_string()),
+    };
+    if pid.parse() != Ok(own_pid) {
+        return Err(format!("sockets passed to process {}", pid));
+    }
+    match fds.parse() {
+        Ok(0) => Err("no sockets passed by systemd".to_string()),
+        Ok(count) => Ok(count),
+        Err(_) => Err(format!("invalid LISTEN_FDS: {}", fds)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_listen_fds() {
+        assert_eq!(listen_fds(Some("42"), Some("2"), 42), Ok(2));
+        assert!(listen_fds(Some("41"), Some("2"), 42).is_err());
+        assert!(listen_fds(Some("42"), Some("0"), 42).is_err());
+        assert!(listen_fds(Some("42"), Some("x"), 42).is_err());
+        assert!(listen_fds(None, None, 42).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! Formatting of timestamps in the formats used by the web.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Format the time in the W3C datetime format, e.g. `2021-04-01T12:30:00Z`.
+pub fn w3c_datetime(time: SystemTime) -> String {
+    let (days, secs) = split_days(time);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Format the time as an HTTP date, e.g. `Thu, 01 Apr 2021 12:30:00 GMT`.
+pub fn http_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (days, secs) = split_days(time);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Split the time to whole days since the Unix epoch and seconds of the day.
+fn split_days(time: SystemTime) -> (i64, u64) {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    ((secs / 86400) as i64, secs % 86400)
+}
+
+/// Convert days since the Unix epoch to a (year, month, day) date.
+///
+/// This is the algorithm from http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn unit_w3c_datetime() {
+        assert_eq!(w3c_datetime(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let time = UNIX_EPOCH + Duration::from_secs(1_614_556_800 + 3723);
+        assert_eq!(w3c_datetime(time), "2021-03-01T01:02:03Z");
+        let time = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(w3c_datetime(time), "2000-02-29T00:00:00Z");
+    }
+
+    #[test]
+    fn unit_http_date() {
+        assert_eq!(http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+}
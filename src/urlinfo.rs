@@ -0,0 +1,360 @@
+//! Data structures that hold information about URLs.
+
+use super::analyzer::Finding;
+use super::urllist::UrlList;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::{Host, Url};
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Error {
+    Fetch(String),
+    /// The host name could not be resolved.
+    Dns(String),
+    /// The server has not answered in time.
+    Timeout(String),
+    /// The TLS handshake has failed, e.g. on an invalid certificate.
+    Tls(String),
+    Status(#[serde(with = "status_code")] reqwest::StatusCode),
+    UnsupportedType(String),
+    /// The URL is disallowed by robots.txt, with the deciding rule if any.
+    Disallowed(Option<String>),
+    /// The URL is not fetched in a discovery-only crawl, for given reason.
+    NotFetched(String),
+}
+
+impl Error {
+    /// Short name of the error kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Fetch(_) => "fetch",
+            Error::Dns(_) => "dns",
+            Error::Timeout(_) => "timeout",
+            Error::Tls(_) => "tls",
+            Error::Status(_) => "status",
+            Error::UnsupportedType(_) => "unsupported_type",
+            Error::Disallowed(_) => "robots",
+            Error::NotFetched(_) => "not_fetched",
+        }
+    }
+
+    /// Class of the error telling what is wrong with the site: `dns`,
+    /// `timeout`, `tls`, `4xx`, `5xx` or `other`. None for the URLs not
+    /// fetched on purpose or of content not crawled.
+    pub fn class(&self) -> Option<&'static str> {
+        match self {
+            Error::Dns(_) => Some("dns"),
+            Error::Timeout(_) => Some("timeout"),
+            Error::Tls(_) => Some("tls"),
+            Error::Status(s) if s.is_client_error() => Some("4xx"),
+            Error::Status(s) if s.is_server_error() => Some("5xx"),
+            Error::Fetch(_) | Error::Status(_) => Some("other"),
+            Error::UnsupportedType(_) | Error::Disallowed(_) | Error::NotFetched(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Fetch(e) => write!(f, "fetch failed: {}", e),
+            Error::Dns(e) => write!(f, "DNS lookup failed: {}", e),
+            Error::Timeout(e) => write!(f, "timed out: {}", e),
+            Error::Tls(e) => write!(f, "TLS failed: {}", e),
+            Error::Status(s) => write!(f, "unexpected response status: {}", s),
+            Error::UnsupportedType(t) => write!(f, "unsupported content type: {}", t),
+            Error::Disallowed(Some(rule)) => write!(f, "disallowed by robots.txt: {}", rule),
+            Error::Disallowed(None) => write!(f, "disallowed by robots.txt"),
+            Error::NotFetched(reason) => write!(f, "not fetched: {}", reason),
+        }
+    }
+}
+
+/// Details about a successfully fetched page.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Page {
+    #[serde(with = "status_code")]
+    pub status: reqwest::StatusCode,
+    pub content_type: String,
+    /// Size of the page body in bytes.
+    pub size: usize,
+    /// Response headers as (name, value) pairs.
+    pub headers: Vec<(String, String)>,
+    /// Canonical URL declared by the page.
+    pub canonical: Option<Url>,
+    /// Content of the `<title>` element, if any.
+    pub title: Option<String>,
+    /// Content of the `<meta name="description">` tag, if any.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// All http(s) links found in the page, deduplicated and without fragments.
+    pub links: UrlList,
+    /// Links marked `rel="nofollow"`, a subset of links.
+    pub nofollow: UrlList,
+    /// Custom fields extracted by the user script.
+    #[serde(default)]
+    pub extra: BTreeMap<String, String>,
+    /// Hash of the page body, telling pages with the same content apart from
+    /// the others. None if the body has not been seen, e.g. when restored.
+    #[serde(default)]
+    pub content_hash: Option<u64>,
+    /// Problems found by the analyzers.
+    #[serde(default)]
+    pub findings: Vec<Finding>,
+}
+
+/// An empty HTML page with status 200, to be filled in.
+impl Default for Page {
+    fn default() -> Self {
+        Page {
+            status: reqwest::StatusCode::OK,
+            content_type: "text/html".to_string(),
+            size: 0,
+            headers: Vec::new(),
+            canonical: None,
+            title: None,
+            description: None,
+            links: UrlList::new(),
+            nofollow: UrlList::new(),
+            extra: BTreeMap::new(),
+            content_hash: None,
+            findings: Vec::new(),
+        }
+    }
+}
+
+pub type FetchResult = Result<Page, Error>;
+
+/// How a URL has been discovered by the crawler.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Discovery {
+    /// Sequence number reflecting the order in which URLs were discovered.
+    pub seq: u64,
+    /// The page the URL has been found on. None for crawl seeds.
+    pub parent: Option<Arc<Url>>,
+    /// Number of links followed from the seed to get to the URL.
+    pub depth: u32,
+    /// When the URL has been discovered, if known.
+    #[serde(default)]
+    pub discovered_at: Option<SystemTime>,
+}
+
+/// Stores metadata about an URL.
+#[derive(Debug, Clone)]
+pub struct UrlInfo {
+    pub result: FetchResult,
+    /// Redirects followed to get the result, in order.
+    pub redirects: Vec<Url>,
+    /// Time when fetching started.
+    pub fetched_at: SystemTime,
+    /// Time it took to fetch the URL.
+    pub elapsed: Duration,
+    /// Filled in by the crawler once the URL is processed.
+    pub discovery: Discovery,
+}
+
+impl UrlInfo {
+    /// HTTP status code of the response, if one has been received.
+    pub fn status(&self) -> Option<reqwest::StatusCode> {
+        match &self.result {
+            Ok(page) => Some(page.status),
+            Err(Error::Status(status)) => Some(*status),
+            Err(_) => None,
+        }
+    }
+
+    /// Content type of the response, if known.
+    pub fn content_type(&self) -> Option<&str> {
+        match &self.result {
+            Ok(page) => Some(&page.content_type),
+            Err(Error::UnsupportedType(t)) => Some(t),
+            Err(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl UrlInfo {
+    /// Construct URL info with given result, no redirects and default discovery.
+    pub fn from_result(result: FetchResult, elapsed: Duration) -> Self {
+        UrlInfo {
+            result,
+            redirects: Vec::new(),
+            fetched_at: SystemTime::now(),
+            elapsed,
+            discovery: Discovery::default(),
+        }
+    }
+}
+
+/// Results of the fetched URLs, keyed by the URLs shared with the rest of the
+/// crawler state.
+pub type UrlSet = HashMap<Arc<Url>, UrlInfo>;
+
+/// Everything known about a single URL, as reported by the API and written
+/// by the exports.
+///
+/// Times are in seconds since the Unix epoch.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UrlRecord {
+    pub url: Url,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub content_type: Option<String>,
+    pub size: Option<usize>,
+    pub headers: Vec<(String, String)>,
+    pub redirects: Vec<Url>,
+    pub parent: Option<Url>,
+    pub depth: u32,
+    /// Sequence number reflecting the order in which URLs were discovered.
+    pub discovered: u64,
+    pub discovered_at: Option<u64>,
+    pub fetched_at: u64,
+    pub elapsed_ms: f64,
+    pub canonical: Option<Url>,
+    pub title: Option<String>,
+    pub links: Vec<Url>,
+    pub nofollow: Vec<Url>,
+    /// Custom fields extracted by the user script and plugins.
+    pub extra: BTreeMap<String, String>,
+    /// Problems found by the analyzers.
+    #[serde(default)]
+    pub findings: Vec<Finding>,
+}
+
+impl UrlRecord {
+    pub fn new(url: &Url, info: &UrlInfo) -> Self {
+        let page = info.result.as_ref().ok();
+        let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        UrlRecord {
+            url: url.clone(),
+            status: info.status().map(|s| s.as_u16()),
+            error: info.result.as_ref().err().map(|e| e.to_string()),
+            content_type: info.content_type().map(str::to_string),
+            size: page.map(|p| p.size),
+            headers: page.map(|p| p.headers.clone()).unwrap_or_default(),
+            redirects: info.redirects.clone(),
+            parent: info.discovery.parent.as_deref().cloned(),
+            depth: info.discovery.depth,
+            discovered: info.discovery.seq,
+            discovered_at: info.discovery.discovered_at.map(secs),
+            fetched_at: secs(info.fetched_at),
+            elapsed_ms: info.elapsed.as_secs_f64() * 1000.0,
+            canonical: page.and_then(|p| p.canonical.clone()),
+            title: page.and_then(|p| p.title.clone()),
+            links: page.map(|p| p.links.iter().collect()).unwrap_or_default(),
+            nofollow: page
+                .map(|p| p.nofollow.iter().collect())
+                .unwrap_or_default(),
+            extra: page.map(|p| p.extra.clone()).unwrap_or_default(),
+            findings: page.map(|p| p.findings.clone()).unwrap_or_default(),
+        }
+    }
+}
+
+/// Domain name, normalized as by UTS-46: lower case, with internationalized
+/// names in their ASCII (punycode) form, so that `münchen.de` and
+/// `xn--mnchen-3ya.de` are the same domain.
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "String")]
+pub struct Domain(String);
+
+impl Domain {
+    pub fn from_host<S: AsRef<str>>(h: &Host<S>) -> Self {
+        Domain(h.to_string())
+    }
+
+    /// Domain of given URL, if it has a host.
+    pub fn from_url(url: &Url) -> Option<Self> {
+        url.host().map(|h| Self::from_host(&h))
+    }
+
+    /// The domain this one is crawled and reported as. With `merge_www` a
+    /// leading `www.` is dropped, `www.example.com` being taken for the same
+    /// site as `example.com`.
+    pub fn site(self, merge_www: bool) -> Self {
+        match self.0.strip_prefix("www.") {
+            Some(apex) if merge_www && apex.contains('.') => Domain(apex.to_string()),
+            _ => self,
+        }
+    }
+
+    /// Whether given domain is the same site as this one.
+    pub fn equivalent(&self, other: &Domain, merge_www: bool) -> bool {
+        self == other || self.clone().site(merge_www) == other.clone().site(merge_www)
+    }
+}
+
+impl FromStr for Domain {
+    type Err = url::ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        url::Host::parse(s).map(|h| Self::from_host(&h))
+    }
+}
+
+impl std::convert::TryFrom<String> for Domain {
+    type Error = url::ParseError;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::ops::Deref for Domain {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.0.deref()
+    }
+}
+
+/// Status codes stored as plain numbers.
+mod status_code {
+    use reqwest::StatusCode;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(status: &StatusCode, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u16(status.as_u16())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<StatusCode, D::Error> {
+        StatusCode::from_u16(u16::deserialize(d)?).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn unit_domain_case_insensitive() {
+        assert!(Domain::from_str("eXamPle.coM") == "ExamPle.Com".parse())
+    }
+
+    #[test]
+    fn unit_domain_idn() {
+        let punycode = Domain::from_str("xn--mnchen-3ya.de").unwrap();
+        assert_eq!(Domain::from_str("münchen.de"), Ok(punycode.clone()));
+        assert_eq!(Domain::from_str("MÜNCHEN.de"), Ok(punycode.clone()));
+        // As found in the API paths.
+        assert_eq!(Domain::from_str("m%C3%BCnchen.de"), Ok(punycode.clone()));
+        let url = Url::parse("http://münchen.de/karte").unwrap();
+        assert_eq!(Domain::from_url(&url), Some(punycode));
+    }
+
+    #[test]
+    fn unit_domain_site() {
+        let domain = |d: &str| Domain::from_str(d).unwrap();
+        let www = domain("WWW.example.com");
+        assert_eq!(www.clone().site(true), domain("example.com"));
+        assert_eq!(www.clone().site(false), www);
+        assert_eq!(domain("www.com").site(true), domain("www.com"));
+        assert!(www.equivalent(&domain("example.com"), true));
+        assert!(!www.equivalent(&domain("example.com"), false));
+        assert!(!www.equivalent(&domain("www.example.org"), true));
+    }
+}
@@ -0,0 +1,197 @@
+//! Compact storage of the links found on the pages.
+//!
+//! Links of a page mostly point to the same site and share long prefixes with
+//! each other, and for large sites they make up most of the crawl results.
+//! The list keeps them front-coded in a single buffer: each URL is stored as
+//! the length of the prefix it shares with the previous one, followed by the
+//! rest of it. The URLs are decoded again while iterating over the list.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::iter::FromIterator;
+use std::mem::size_of;
+use url::Url;
+
+/// A list of URLs in the order they were added.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct UrlList {
+    /// Shared prefix length, length of the rest and the rest of each URL.
+    buf: Vec<u8>,
+    /// Number of URLs in the list.
+    len: usize,
+}
+
+impl UrlList {
+    pub fn new() -> Self {
+        UrlList::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append given URL at the end of the list. The list is decoded to find
+    /// the last URL, collecting the list at once is cheaper.
+    pub fn push(&mut self, url: &Url) {
+        let last = self.iter_str().last().unwrap_or_default();
+        self.append(&last, url.as_str());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Url> + '_ {
+        self.iter_str()
+            .map(|s| Url::parse(&s).expect("stored URLs are valid"))
+    }
+
+    pub fn contains(&self, url: &Url) -> bool {
+        self.iter_str().any(|s| s == url.as_str())
+    }
+
+    /// Estimated size of the list in bytes.
+    pub fn bytes(&self) -> usize {
+        size_of::<Self>() + self.buf.capacity()
+    }
+
+    /// Append a URL following given last one.
+    fn append(&mut self, last: &str, url: &str) {
+        let shared = common_prefix(last, url);
+        write_len(&mut self.buf, shared);
+        write_len(&mut self.buf, url.len() - shared);
+        self.buf.extend_from_slice(&url.as_bytes()[shared..]);
+        self.len += 1;
+    }
+
+    /// The URLs as strings.
+    fn iter_str(&self) -> impl Iterator<Item = String> + '_ {
+        let mut rest = &self.buf[..];
+        let mut url = String::new();
+        std::iter::from_fn(move || {
+            if rest.is_empty() {
+                return None;
+            }
+            let shared = read_len(&mut rest);
+            let len = read_len(&mut rest);
+            let (suffix, tail) = rest.split_at(len);
+            rest = tail;
+            url.truncate(shared);
+            url.push_str(std::str::from_utf8(suffix).expect("stored URLs are valid"));
+            Some(url.clone())
+        })
+    }
+}
+
+/// Length in bytes of the common prefix of given strings, at a character
+/// boundary.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i)
+}
+
+/// Write a length in the variable length encoding, 7 bits per byte.
+fn write_len(buf: &mut Vec<u8>, mut len: usize) {
+    while len >= 0x80 {
+        buf.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    buf.push(len as u8);
+}
+
+/// Read a length written by `write_len`, advancing the buffer past it.
+fn read_len(buf: &mut &[u8]) -> usize {
+    let mut len = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[0];
+        *buf = &buf[1..];
+        len |= ((byte & 0x7f) as usize) << shift;
+        if byte < 0x80 {
+            return len;
+        }
+        shift += 7;
+    }
+}
+
+impl FromIterator<Url> for UrlList {
+    fn from_iter<I: IntoIterator<Item = Url>>(iter: I) -> Self {
+        let mut list = UrlList::new();
+        let mut last = String::new();
+        for url in iter {
+            list.append(&last, url.as_str());
+            last.clear();
+            last.push_str(url.as_str());
+        }
+        list.buf.shrink_to_fit();
+        list
+    }
+}
+
+impl From<Vec<Url>> for UrlList {
+    fn from(urls: Vec<Url>) -> Self {
+        urls.into_iter().collect()
+    }
+}
+
+impl fmt::Debug for UrlList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter_str()).finish()
+    }
+}
+
+impl Serialize for UrlList {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(self.iter_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for UrlList {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        Vec::<Url>::deserialize(d).map(UrlList::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stats::url_bytes;
+
+    #[test]
+    fn unit_url_list() {
+        let urls: Vec<Url> = [
+            "http://example.com/docs/guide/intro",
+            "http://example.com/docs/guide/install",
+            "http://example.com/",
+            "https://other.org/ü/päge",
+            "https://other.org/ü/pöge",
+            "http://example.com/docs/guide/install",
+        ]
+        .iter()
+        .map(|u| Url::parse(u).unwrap())
+        .collect();
+        let mut list: UrlList = urls[..5].to_vec().into();
+        list.push(&urls[5]);
+        assert_eq!(list.len(), urls.len());
+        assert_eq!(list.iter().collect::<Vec<_>>(), urls);
+        assert!(list.contains(&urls[3]));
+        assert!(!list.contains(&Url::parse("http://example.com/docs").unwrap()));
+
+        let json = serde_json::to_string(&list).unwrap();
+        assert_eq!(json, serde_json::to_string(&urls).unwrap());
+        assert_eq!(serde_json::from_str::<UrlList>(&json).unwrap(), list);
+        assert!(UrlList::new().iter().next().is_none());
+    }
+
+    #[test]
+    fn unit_url_list_size() {
+        let urls: Vec<Url> = (0..1000)
+            .map(|i| Url::parse(&format!("https://www.example.com/articles/2020/{}", i)).unwrap())
+            .collect();
+        let list: UrlList = urls.iter().cloned().collect();
+        let plain: usize = urls.iter().map(url_bytes).sum();
+        assert!(list.bytes() * 5 < plain, "{} vs {}", list.bytes(), plain);
+    }
+}
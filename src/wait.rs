@@ -0,0 +1,66 @@
+//! Waiting for a crawl to finish.
+
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// Time to wait unless asked otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest time a client may wait.
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Query parameters of the /wait/domain.com entry point.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct WaitQuery {
+    pub timeout: Timeout,
+}
+
+/// How long to wait, e.g. `30s`, `500ms` or `2m`. Plain numbers are seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Timeout(pub Duration);
+
+impl Default for Timeout {
+    fn default() -> Self {
+        Timeout(DEFAULT_TIMEOUT)
+    }
+}
+
+impl TryFrom<String> for Timeout {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid timeout: {}", s);
+        let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        let timeout = match &s[digits.len()..] {
+            "ms" => Duration::from_millis(value),
+            "" | "s" => Duration::from_secs(value),
+            "m" => Duration::from_secs(value.saturating_mul(60)),
+            _ => return Err(invalid()),
+        };
+        match timeout <= MAX_TIMEOUT {
+            true => Ok(Timeout(timeout)),
+            false => Err(format!("timeout exceeds {}s", MAX_TIMEOUT.as_secs())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_parse_timeout() {
+        let parse = |s: &str| Timeout::try_from(s.to_string()).map(|t| t.0);
+        assert_eq!(parse("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse("45"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse("10h").is_err());
+        assert!(parse("s").is_err());
+        assert!(parse("-1s").is_err());
+        assert!(parse("6m").is_err());
+    }
+}
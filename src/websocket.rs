@@ -0,0 +1,100 @@
+//! WebSocket API for following and controlling crawls over a single connection.
+//!
+//! The server pushes all crawl events to the client as JSON objects tagged
+//! with an `event` field. The client sends commands of the form
+//! `{"command": "crawl", "domain": "example.com"}` where the command is one of
+//! `crawl`, `pause`, `resume` or `stop`. Each command is answered by an object
+//! with a `reply` field.
+
+use super::api::{crawl_response, ApiError};
+use super::crawler::{Control, CrawlState, Crawler};
+use super::event::CrawlEvent;
+use super::urlinfo::Domain;
+
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use warp::ws::{Message, WebSocket};
+
+/// Command sent by the client.
+#[derive(Debug, Deserialize)]
+struct Request {
+    command: Command,
+    domain: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Command {
+    Crawl,
+    Pause,
+    Resume,
+    Stop,
+}
+
+/// Serve a single websocket connection until either side closes it.
+pub async fn serve(ws: WebSocket, crawler: Crawler) {
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    let mut events = crawler.subscribe();
+
+    loop {
+        let reply = tokio::select! {
+            event = events.recv() => match event {
+                Ok(CrawlEvent::ShuttingDown) => {
+                    let _ = ws_tx.send(Message::text(json!(CrawlEvent::ShuttingDown).to_string())).await;
+                    break;
+                }
+                Ok(event) => json!(event),
+                Err(RecvError::Lagged(n)) => json!({ "lagged": n }),
+                Err(RecvError::Closed) => break,
+            },
+            msg = ws_rx.next() => match msg {
+                Some(Ok(msg)) if msg.is_close() => break,
+                Some(Ok(msg)) => match msg.to_str() {
+                    Ok(text) => handle_request(text, &crawler).await,
+                    // Ping, pong and binary messages are ignored.
+                    Err(()) => continue,
+                },
+                Some(Err(_)) | None => break,
+            },
+        };
+        if ws_tx.send(Message::text(reply.to_string())).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Execute a client command and produce the reply.
+async fn handle_request(text: &str, crawler: &Crawler) -> serde_json::Value {
+    match execute(text, crawler).await {
+        Ok(reply) => json!({ "reply": reply }),
+        Err(e) => json!({ "reply": e.response() }),
+    }
+}
+
+async fn execute(text: &str, crawler: &Crawler) -> Result<serde_json::Value, ApiError> {
+    let request: Request =
+        serde_json::from_str(text).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    let domain: Domain = request
+        .domain
+        .parse()
+        .map_err(|e: url::ParseError| ApiError::InvalidDomain(e.to_string()))?;
+    let d = domain.clone();
+    let result = match request.command {
+        Command::Crawl => json!(crawl_response(crawler.crawl(d).await?)?.status()),
+        Command::Pause => json!(control(crawler, d, Control::Pause).await?),
+        Command::Resume => json!(control(crawler, d, Control::Resume).await?),
+        Command::Stop => json!(control(crawler, d, Control::Stop).await?),
+    };
+    Ok(json!({ "command": request.command, "domain": domain, "result": result }))
+}
+
+async fn control(
+    crawler: &Crawler,
+    domain: Domain,
+    control: Control,
+) -> Result<CrawlState, ApiError> {
+    let state = crawler.control(domain, control).await?;
+    state.ok_or(ApiError::UnknownDomain)
+}
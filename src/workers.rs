@@ -0,0 +1,173 @@
+//! A pool of tasks running the fetches of the pages.
+//!
+//! Rather than a task being spawned for each page, the fetches are queued for
+//! a set of worker tasks, as many as the fetches allowed at once. The number
+//! of tasks stays bounded however many URLs the shards hand out, and the pool
+//! follows the fetch limit as it is changed. A fetch that panics takes down
+//! neither its worker nor the crawler, it is only logged and counted.
+
+use super::metrics::METRICS;
+
+use futures::future::{BoxFuture, FutureExt};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+type Job = BoxFuture<'static, ()>;
+
+/// Handle to the pool, the workers stop once all the handles are dropped.
+#[derive(Debug, Clone)]
+pub struct WorkerPool {
+    jobs: mpsc::UnboundedSender<Job>,
+    shared: Arc<Shared>,
+}
+
+/// The state shared by the workers.
+#[derive(Debug)]
+struct Shared {
+    queue: Mutex<mpsc::UnboundedReceiver<Job>>,
+    /// Number of workers wanted.
+    target: AtomicUsize,
+    /// Number of workers running, brought down to the target by the workers
+    /// in excess as they finish their jobs.
+    running: AtomicUsize,
+}
+
+impl WorkerPool {
+    /// Start a pool with given number of workers.
+    pub fn new(workers: usize) -> Self {
+        let (jobs, queue) = mpsc::unbounded_channel();
+        let pool = WorkerPool {
+            jobs,
+            shared: Arc::new(Shared {
+                queue: Mutex::new(queue),
+                target: AtomicUsize::new(0),
+                running: AtomicUsize::new(0),
+            }),
+        };
+        pool.resize(workers);
+        pool
+    }
+
+    /// Number of workers running.
+    pub fn workers(&self) -> usize {
+        self.shared.running.load(Ordering::SeqCst)
+    }
+
+    /// Change the number of workers. New ones are started right away, those
+    /// in excess stop once done with their current job.
+    pub fn resize(&self, workers: usize) {
+        self.shared.target.store(workers, Ordering::SeqCst);
+        loop {
+            let running = self.shared.running.load(Ordering::SeqCst);
+            if running >= workers {
+                break;
+            }
+            let started = self.shared.running.compare_exchange(
+                running,
+                running + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+            if started.is_ok() {
+                METRICS.fetch_workers.add(1);
+                tokio::task::spawn(work(self.shared.clone()));
+            }
+        }
+    }
+
+    /// Queue given job for the next free worker.
+    pub fn submit(&self, job: Job) {
+        // The workers only go away with the last handle, never before.
+        let _ = self.jobs.send(job);
+    }
+}
+
+/// Run the jobs of the pool until there are no more or the worker is not
+/// needed any longer.
+async fn work(shared: Arc<Shared>) {
+    loop {
+        let running = shared.running.load(Ordering::SeqCst);
+        if running > shared.target.load(Ordering::SeqCst) {
+            let stopped = shared.running.compare_exchange(
+                running,
+                running - 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+            if stopped.is_ok() {
+                break;
+            }
+            continue;
+        }
+        let job = shared.queue.lock().await.recv().await;
+        let job = match job {
+            Some(job) => job,
+            None => {
+                shared.running.fetch_sub(1, Ordering::SeqCst);
+                break;
+            }
+        };
+        if let Err(panic) = AssertUnwindSafe(job).catch_unwind().await {
+            METRICS.fetch_panics.inc();
+            tracing::error!(panic = panic_message(&*panic), "fetch panicked");
+        }
+    }
+    METRICS.fetch_workers.add(-1);
+}
+
+/// The message a panic has been raised with.
+pub fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(msg) => msg,
+        None => panic.downcast_ref::<String>().map_or("", String::as_str),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_worker_pool() {
+        let pool = WorkerPool::new(2);
+        assert_eq!(pool.workers(), 2);
+
+        // Jobs beyond the number of workers wait for one to be free.
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..3 {
+            let done = done.clone();
+            pool.submit(
+                async move {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    done.fetch_add(1, Ordering::SeqCst);
+                }
+                .boxed(),
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(done.load(Ordering::SeqCst), 2);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(done.load(Ordering::SeqCst), 3);
+
+        // A panicking job leaves the worker running.
+        pool.submit(async { panic!("broken page") }.boxed());
+        let (sx, rx) = oneshot::channel();
+        pool.submit(async move { sx.send(()).unwrap() }.boxed());
+        rx.await.unwrap();
+        assert_eq!(pool.workers(), 2);
+
+        // Workers in excess stop after their next job.
+        pool.resize(1);
+        for _ in 0..2 {
+            pool.submit(async {}.boxed());
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(pool.workers(), 1);
+        pool.resize(3);
+        assert_eq!(pool.workers(), 3);
+    }
+}
@@ -0,0 +1,260 @@
+//! End-to-end tests of the HTTP API crawling a fake site served locally.
+
+use croolr::checkpoint::Checkpoint;
+use croolr::server::{self, Options};
+use croolr::Settings;
+
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use warp::Filter;
+
+/// Number of pages linked from the home page of the fake site.
+const PAGES: usize = 8;
+
+/// Number of requests being served at once.
+#[derive(Default)]
+struct Gauge {
+    current: AtomicUsize,
+    max: AtomicUsize,
+}
+
+impl Gauge {
+    fn enter(&self) {
+        let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max.fetch_max(current, Ordering::SeqCst);
+    }
+
+    fn leave(&self) {
+        self.current.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Serve a site with a home page linking to the other pages, each of them
+/// taking a while to respond. There is no robots.txt.
+fn fake_site(gauge: Arc<Gauge>) -> SocketAddr {
+    let pages = warp::path::tail().and_then(move |tail: warp::path::Tail| {
+        let gauge = gauge.clone();
+        async move {
+            gauge.enter();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            gauge.leave();
+            let body = match tail.as_str() {
+                "" => (0..PAGES)
+                    .map(|i| format!("<a href=\"/p{}\">{}</a>", i, i))
+                    .collect(),
+                page if page.starts_with('p') => "<p>Nothing here</p>".to_string(),
+                _ => return Err(warp::reject::not_found()),
+            };
+            Ok(warp::reply::html(body))
+        }
+    });
+    let (addr, server) = warp::serve(pages).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    addr
+}
+
+/// Server options with given fetch limit, serving on a local port.
+fn options(fetch_limit: u32) -> Options {
+    Options {
+        listen: Vec::new(),
+        listeners: vec![std::net::TcpListener::bind("127.0.0.1:0").unwrap()],
+        settings: Settings {
+            fetch_limit,
+            ..Settings::default()
+        },
+        keys: Default::default(),
+        rate_limit: None,
+        cors: Default::default(),
+        compression: false,
+        shutdown_timeout: Duration::from_secs(5),
+        tls: None,
+        grpc: None,
+        config: None,
+        seeds: Vec::new(),
+        checkpoint: None,
+    }
+}
+
+/// Start the API server with given fetch limit, returning its address and a
+/// sender shutting it down.
+fn start_server(fetch_limit: u32) -> (SocketAddr, oneshot::Sender<()>) {
+    let (addr, stop, _) = spawn_server(options(fetch_limit));
+    (addr, stop)
+}
+
+/// Start the API server with given options, also returning the server task.
+fn spawn_server(
+    opts: Options,
+) -> (
+    SocketAddr,
+    oneshot::Sender<()>,
+    JoinHandle<Result<(), String>>,
+) {
+    let addr = opts.listeners[0].local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(server::serve(opts, async {
+        let _ = stopped.await;
+    }));
+    (addr, stop, server)
+}
+
+async fn get(api: SocketAddr, path: &str) -> Value {
+    let resp = reqwest::get(format!("http://{}/v1{}", api, path))
+        .await
+        .unwrap();
+    assert!(
+        resp.status().is_success(),
+        "GET {}: {}",
+        path,
+        resp.status()
+    );
+    serde_json::from_str(&resp.text().await.unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn test_crawl_fake_site() {
+    let gauge = Arc::new(Gauge::default());
+    let site = fake_site(gauge.clone());
+    let (api, stop) = start_server(3);
+
+    let seed = format!("http://{}/", site);
+    let crawl = get(api, &format!("/crawl/127.0.0.1?seed={}", seed)).await;
+    assert_eq!(crawl["status"], "Queued");
+    let wait = get(api, "/wait/127.0.0.1?timeout=30s").await;
+    assert_eq!(wait["finished"], true);
+
+    // The home page and the pages linked from it are fetched, at most the
+    // fetch limit at a time.
+    let count = get(api, "/count/127.0.0.1").await;
+    assert_eq!(count["count"], PAGES + 1);
+    assert_eq!(count["status_classes"]["2xx"], PAGES + 1);
+    assert_eq!(gauge.max.load(Ordering::SeqCst), 3);
+
+    let urls = get(api, "/urls/127.0.0.1?sort=url").await;
+    assert_eq!(urls["total"], PAGES + 1);
+    let urls = urls["urls"].as_object().unwrap();
+    assert!(urls.contains_key(&seed));
+    assert!(urls.contains_key(&format!("http://{}/p{}", site, PAGES - 1)));
+    assert!(urls.values().all(|info| info["status"] == 200));
+
+    let _ = stop.send(());
+}
+
+#[tokio::test]
+async fn test_results_before_first_fetch() {
+    let site = fake_site(Arc::new(Gauge::default()));
+    let (api, stop) = start_server(1);
+
+    // The crawl is known before anything is fetched, with no results yet.
+    let crawl = get(api, &format!("/crawl/127.0.0.1?seed=http://{}/", site)).await;
+    assert_eq!(crawl["status"], "Queued");
+    let resp = reqwest::get(format!("http://{}/v1/count/127.0.0.1", api))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+    assert!(resp.headers().contains_key("etag"));
+    let count: Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert!(count["count"].as_u64().unwrap() <= PAGES as u64 + 1);
+    get(api, "/urls/127.0.0.1").await;
+    get(api, "/summary/127.0.0.1").await;
+
+    let resp = reqwest::get(format!("http://{}/v1/count/example.com", api))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+    let _ = stop.send(());
+}
+
+#[tokio::test]
+async fn test_crawl_seed_of_other_domain() {
+    let (api, stop) = start_server(1);
+    let resp = reqwest::get(format!(
+        "http://{}/v1/crawl/example.com?seed=http://example.org/",
+        api
+    ))
+    .await
+    .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+    let _ = stop.send(());
+}
+
+#[tokio::test]
+async fn test_checkpoint_on_stop() {
+    let site = fake_site(Arc::new(Gauge::default()));
+    let path = std::env::temp_dir().join(format!(
+        "croolr-test-server-checkpoint-{}.json",
+        std::process::id()
+    ));
+    let opts = || Options {
+        checkpoint: Some(path.clone()),
+        ..options(2)
+    };
+    let (api, stop, server) = spawn_server(opts());
+    get(api, &format!("/crawl/127.0.0.1?seed=http://{}/", site)).await;
+    let wait = get(api, "/wait/127.0.0.1?timeout=30s").await;
+    assert_eq!(wait["finished"], true);
+
+    // The results are saved once the server stops.
+    let _ = stop.send(());
+    server.await.unwrap().unwrap();
+    let saved = Checkpoint::load(&path).unwrap().unwrap();
+    assert_eq!(saved.domains.len(), 1);
+    assert_eq!(saved.fetched.len(), PAGES + 1);
+
+    // And they are back on the next start.
+    let (api, stop, server) = spawn_server(opts());
+    let count = get(api, "/count/127.0.0.1").await;
+    assert_eq!(count["status_classes"]["2xx"], PAGES + 1);
+    let _ = stop.send(());
+    server.await.unwrap().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_seeds_with_path_and_port() {
+    let site = fake_site(Arc::new(Gauge::default()));
+    let seed = format!("http://{}/p3", site);
+    let (api, stop, _) = spawn_server(Options {
+        seeds: vec![seed.parse().unwrap()],
+        ..options(1)
+    });
+
+    // Only the seed is fetched, at its port rather than the default one.
+    let wait = get(api, "/wait/127.0.0.1?timeout=30s").await;
+    assert_eq!(wait["finished"], true);
+    let urls = get(api, "/urls/127.0.0.1").await;
+    assert_eq!(urls["total"], 1);
+    assert_eq!(urls["urls"][&seed]["status"], 200);
+    let _ = stop.send(());
+}
+
+#[tokio::test]
+async fn test_crawl_batch_seed_with_path_and_port() {
+    let site = fake_site(Arc::new(Gauge::default()));
+    let (api, stop) = start_server(1);
+
+    let seed = format!("http://{}/p3", site);
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/v1/crawl-batch", api))
+        .header("content-type", "text/plain")
+        .body(format!("{}\nexa mple.com\n", seed))
+        .send()
+        .await
+        .unwrap();
+    let batch: Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(batch["accepted"], 1);
+    assert_eq!(batch["results"][0]["url"], seed);
+    assert_eq!(batch["results"][1]["error"]["code"], "invalid_domain");
+
+    let wait = get(api, "/wait/127.0.0.1?timeout=30s").await;
+    assert_eq!(wait["finished"], true);
+    let urls = get(api, "/urls/127.0.0.1").await;
+    assert_eq!(urls["total"], 1);
+    assert_eq!(urls["urls"][&seed]["status"], 200);
+    let _ = stop.send(());
+}